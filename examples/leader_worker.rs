@@ -0,0 +1,112 @@
+//! Leader-only cron: three workers share a backend and only the leader runs
+//! the job. Halfway through the leader is drained and the job moves on to the
+//! next oldest worker.
+//!
+//! The workers run in one process over an in-process `MemoryBackend`, or over
+//! the Redis server at `INSTANCES_REDIS_URL` if set; with a shared backend each
+//! would usually be its own process.
+//!
+//! ```sh
+//! cargo run --example leader_worker --features signals
+//! INSTANCES_REDIS_URL=redis://127.0.0.1:6379 cargo run --example leader_worker --features backend-redis
+//! ```
+
+#[cfg(feature = "backend-redis")]
+use std::env;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use instances_rs::backends::memory::MemoryBackend;
+#[cfg(feature = "backend-redis")]
+use instances_rs::backends::redis::RedisBackend;
+use instances_rs::backends::Backend;
+use instances_rs::config::Builder;
+use instances_rs::events::InstancesEvent;
+use instances_rs::models::{InstanceRole, LeaderStrategy, Registration};
+use instances_rs::Instances;
+
+const UPDATE_INTERVAL: Duration = Duration::from_millis(200);
+const ROUNDS: usize = 12;
+
+type SharedBackend = Box<dyn Backend<Registration<String>> + Send + Sync>;
+
+type Worker = Arc<Instances<SharedBackend, String>>;
+
+fn main() {
+    let connect = connector();
+    let mut workers: Vec<Worker> = (0..3)
+        .map(|n| {
+            let worker = Builder::default()
+                .with_backend(connect())
+                .with_update_interval(UPDATE_INTERVAL)
+                .with_leader_strategy(LeaderStrategy::Oldest)
+                .with_tombstones(true)
+                .with_info_extractor(move || format!("worker-{}", n))
+                .build();
+            worker
+                .wait_for_first_update(Duration::from_secs(1))
+                .unwrap();
+            worker
+        })
+        .collect();
+    // Lets every worker see the others before electing.
+    thread::sleep(UPDATE_INTERVAL * 2);
+    // A real deployment drains on SIGTERM instead of by hand, e.g. on a
+    // rolling restart.
+    #[cfg(feature = "signals")]
+    for worker in &workers {
+        worker
+            .install_signal_handlers(Duration::from_secs(2))
+            .unwrap();
+    }
+    let events = workers.last().unwrap().subscribe();
+
+    for round in 0..ROUNDS {
+        if let Some(leader) = workers.iter().find(|w| is_leader(w)) {
+            let name = &leader.get_instance_info().unwrap().data;
+            println!("round {}: {} runs the job", round, name);
+        }
+
+        if round == ROUNDS / 2 {
+            let position = workers.iter().position(is_leader).unwrap();
+            let leader = workers.remove(position);
+            println!("draining {}", leader.get_instance_info().unwrap().data);
+            leader.drain(UPDATE_INTERVAL * 3).unwrap();
+        }
+
+        for event in events.try_iter() {
+            match event {
+                InstancesEvent::LeaderChanged(leader) => println!("leader changed: {:?}", leader),
+                InstancesEvent::MemberLeft(id, reason) => println!("{} left: {:?}", id, reason),
+                _ => {}
+            }
+        }
+        thread::sleep(UPDATE_INTERVAL);
+    }
+}
+
+/// Connects a worker to the cluster's backend: the Redis server at
+/// `INSTANCES_REDIS_URL` if set, each worker opening its own connection as
+/// separate processes would, or else an in-process `MemoryBackend` shared by
+/// the workers.
+fn connector() -> Box<dyn Fn() -> SharedBackend> {
+    #[cfg(feature = "backend-redis")]
+    if let Ok(url) = env::var("INSTANCES_REDIS_URL") {
+        return Box::new(move || {
+            Box::new(
+                RedisBackend::connect(&url, UPDATE_INTERVAL * 5)
+                    .unwrap()
+                    .with_prefix("leader_worker"),
+            )
+        });
+    }
+    let backend = MemoryBackend::new();
+    Box::new(move || Box::new(backend.clone()))
+}
+
+fn is_leader(worker: &Worker) -> bool {
+    worker
+        .get_instance_info()
+        .is_some_and(|info| info.role == InstanceRole::Leader)
+}
@@ -0,0 +1,154 @@
+//! Partitioned queue consumption: three consumers split the partitions of a
+//! queue between the active members. When one of them is drained, the others
+//! pick up its partitions on their next update.
+//!
+//! The consumers run in one process over an in-process `MemoryBackend`, or over
+//! the Redis server at `INSTANCES_REDIS_URL` if set; with a shared backend each
+//! would usually be its own process.
+//!
+//! ```sh
+//! cargo run --example sharded_consumer --features signals
+//! INSTANCES_REDIS_URL=redis://127.0.0.1:6379 cargo run --example sharded_consumer --features backend-redis
+//! ```
+
+use std::collections::VecDeque;
+#[cfg(feature = "backend-redis")]
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use instances_rs::backends::memory::MemoryBackend;
+#[cfg(feature = "backend-redis")]
+use instances_rs::backends::redis::RedisBackend;
+use instances_rs::backends::Backend;
+use instances_rs::config::Builder;
+use instances_rs::events::InstancesEvent;
+use instances_rs::models::{InstanceStatus, Registration};
+use instances_rs::partitioning::PartitionManager;
+use instances_rs::Instances;
+
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+const PARTITIONS: u32 = 12;
+const MESSAGES_PER_PARTITION: u32 = 20;
+
+type SharedBackend = Box<dyn Backend<Registration<String>> + Send + Sync>;
+
+type Consumer = Arc<Instances<SharedBackend, String>>;
+type Queue = Arc<Vec<Mutex<VecDeque<u32>>>>;
+
+fn main() {
+    let queue: Queue = Arc::new(
+        (0..PARTITIONS)
+            .map(|_| Mutex::new((0..MESSAGES_PER_PARTITION).collect()))
+            .collect(),
+    );
+    let connect = connector();
+    let consumers: Vec<Consumer> = (0..3)
+        .map(|n| {
+            let consumer = Builder::default()
+                .with_backend(connect())
+                .with_update_interval(UPDATE_INTERVAL)
+                .with_tombstones(true)
+                .with_extension(PartitionManager::new(PARTITIONS))
+                .with_info_extractor(move || format!("consumer-{}", n))
+                .build();
+            consumer
+                .wait_for_first_update(Duration::from_secs(1))
+                .unwrap();
+            #[cfg(feature = "signals")]
+            consumer
+                .install_signal_handlers(Duration::from_secs(2))
+                .unwrap();
+            consumer
+        })
+        .collect();
+    // Lets every consumer see the others before splitting the partitions.
+    thread::sleep(UPDATE_INTERVAL * 2);
+
+    let handles: Vec<_> = consumers
+        .iter()
+        .map(|consumer| {
+            let consumer = consumer.clone();
+            let queue = queue.clone();
+            thread::spawn(move || consume(&consumer, &queue))
+        })
+        .collect();
+
+    thread::sleep(UPDATE_INTERVAL * 5);
+    let drained = &consumers[0];
+    println!("draining {}", drained.get_instance_info().unwrap().data);
+    drained.drain(UPDATE_INTERVAL * 3).unwrap();
+
+    for (consumer, handle) in consumers.iter().zip(handles) {
+        let consumed = handle.join().unwrap();
+        println!(
+            "{} consumed {} messages",
+            consumer.get_instance_info().unwrap().data,
+            consumed
+        );
+    }
+}
+
+/// Connects a consumer to the cluster's backend: the Redis server at
+/// `INSTANCES_REDIS_URL` if set, each consumer opening its own connection as
+/// separate processes would, or else an in-process `MemoryBackend` shared by
+/// the consumers.
+fn connector() -> Box<dyn Fn() -> SharedBackend> {
+    #[cfg(feature = "backend-redis")]
+    if let Ok(url) = env::var("INSTANCES_REDIS_URL") {
+        return Box::new(move || {
+            Box::new(
+                RedisBackend::connect(&url, UPDATE_INTERVAL * 5)
+                    .unwrap()
+                    .with_prefix("sharded_consumer"),
+            )
+        });
+    }
+    let backend = MemoryBackend::new();
+    Box::new(move || Box::new(backend.clone()))
+}
+
+/// Consumes the partitions owned by the consumer until the queue is empty or
+/// the consumer is drained, returning how many messages it consumed.
+fn consume(consumer: &Consumer, queue: &Queue) -> usize {
+    let events = consumer.subscribe();
+    let mut consumed = 0;
+    loop {
+        let Some(owned) = owned_partitions(consumer) else {
+            return consumed;
+        };
+        for partition in &owned {
+            if queue[*partition as usize]
+                .lock()
+                .unwrap()
+                .pop_front()
+                .is_some()
+            {
+                consumed += 1;
+            }
+        }
+        if queue.iter().all(|p| p.lock().unwrap().is_empty()) {
+            return consumed;
+        }
+
+        for event in events.try_iter() {
+            if let InstancesEvent::MemberLeft(id, reason) = event {
+                println!("{} left ({:?}), rebalancing", id, reason);
+            }
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// The partitions the `PartitionManager` assigned to the consumer, every
+/// consumer computes the same assignment from the same listing. `None` once
+/// the consumer is no longer active.
+fn owned_partitions(consumer: &Consumer) -> Option<Vec<u32>> {
+    let info = consumer.get_instance_info()?;
+    if info.status != InstanceStatus::Active {
+        return None;
+    }
+    let partitions = consumer.extension::<PartitionManager>()?;
+    Some(partitions.owned_partitions())
+}
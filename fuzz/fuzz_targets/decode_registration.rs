@@ -0,0 +1,9 @@
+#![no_main]
+
+use instances_rs::backends::codec;
+use libfuzzer_sys::fuzz_target;
+use serde_json::Value;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = codec::decode::<Value>(data);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use instances_rs::backends::codec;
+use libfuzzer_sys::fuzz_target;
+
+// Typed payloads go through the derived `Deserialize` implementations.
+fuzz_target!(|data: &[u8]| {
+    let _ = codec::decode::<String>(data);
+    let _ = codec::decode::<Vec<u64>>(data);
+});
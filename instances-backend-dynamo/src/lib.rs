@@ -0,0 +1,703 @@
+//! A backend storing the heartbeats in a DynamoDB table, keeping the cost of
+//! the heartbeats predictable.
+//!
+//! Connects with the AWS SDK, configured from the environment as the AWS CLI
+//! is (`AWS_REGION`, the credentials chain, and `AWS_ENDPOINT_URL` to reach
+//! DynamoDB local). The SDK's calls run on a runtime owned by the backend.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use aws_config::retry::RetryConfig;
+use aws_config::BehaviorVersion;
+use aws_sdk_dynamodb::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_sdk_dynamodb::types::{
+    AttributeDefinition, AttributeValue, BillingMode, ConsumedCapacity, KeySchemaElement, KeyType,
+    KeysAndAttributes, ReturnConsumedCapacity, ReturnValue, ScalarAttributeType, TableStatus,
+    TimeToLiveSpecification,
+};
+use aws_sdk_dynamodb::Client;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::runtime::Runtime;
+use tracing::debug;
+use uuid::Uuid;
+
+use instances_core::backends::{codec, Backend, Capabilities, ConnectionError, InstanceRecord};
+use instances_core::random::{RandomSource, SystemRandom};
+use instances_core::time::HeartbeatTime;
+
+use pace::Pace;
+
+mod pace;
+
+/// The key of the item listing the members, not a UUID so no instance
+/// collides with it.
+const MEMBERS_KEY: &str = "members";
+/// BatchGetItem's limit.
+const BATCH_SIZE: usize = 100;
+const MAX_ATTEMPTS: u32 = 5;
+const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+/// An item is 400KB at most, the attributes other than the data included.
+const MAX_PAYLOAD_SIZE: usize = 399 * 1024;
+const THROTTLING_CODES: [&str; 3] = [
+    "ProvisionedThroughputExceededException",
+    "ThrottlingException",
+    "RequestLimitExceeded",
+];
+
+type Item = HashMap<String, AttributeValue>;
+
+/// The capacity consumed by a backend and how it adapted to the throttling.
+/// A handle: its clones keep reading the backend's counters after the
+/// backend moved into the instance.
+#[derive(Clone, Default, Debug)]
+pub struct CapacityMetrics(Arc<Counters>);
+
+#[derive(Default, Debug)]
+struct Counters {
+    read_milliunits: AtomicU64,
+    write_milliunits: AtomicU64,
+    throttled_requests: AtomicU64,
+    skipped_writes: AtomicU64,
+    cached_listings: AtomicU64,
+}
+
+impl CapacityMetrics {
+    /// The read capacity units consumed.
+    pub fn read_units(&self) -> f64 {
+        self.0.read_milliunits.load(Ordering::Relaxed) as f64 / 1000.0
+    }
+
+    /// The write capacity units consumed.
+    pub fn write_units(&self) -> f64 {
+        self.0.write_milliunits.load(Ordering::Relaxed) as f64 / 1000.0
+    }
+
+    /// The requests DynamoDB throttled, retried or not, unprocessed keys of
+    /// a BatchGetItem included.
+    pub fn throttled_requests(&self) -> u64 {
+        self.0.throttled_requests.load(Ordering::Relaxed)
+    }
+
+    /// The updates not written because the record didn't change and was
+    /// written recently, while throttled.
+    pub fn skipped_writes(&self) -> u64 {
+        self.0.skipped_writes.load(Ordering::Relaxed)
+    }
+
+    /// The listings served from the previous one, while throttled.
+    pub fn cached_listings(&self) -> u64 {
+        self.0.cached_listings.load(Ordering::Relaxed)
+    }
+
+    fn consumed<'a>(&self, kind: Kind, capacity: impl IntoIterator<Item = &'a ConsumedCapacity>) {
+        let units: f64 = capacity
+            .into_iter()
+            .filter_map(ConsumedCapacity::capacity_units)
+            .sum();
+        let counter = match kind {
+            Kind::Read => &self.0.read_milliunits,
+            Kind::Write => &self.0.write_milliunits,
+        };
+        counter.fetch_add((units * 1000.0).round() as u64, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Read,
+    Write,
+}
+
+/// What the backend remembers between requests to pace them.
+struct State {
+    writes: Pace,
+    reads: Pace,
+    /// The data last written per instance, and when.
+    written: HashMap<Uuid, (String, Instant)>,
+    /// The items last listed, and when.
+    listing: Option<(Instant, Vec<Item>)>,
+}
+
+/// Stores each instance as an item, keyed by its id, holding its serialized
+/// data, its registration time and its latest heartbeat. Items not updated
+/// for `ttl` aren't listed anymore, and expire through the table's TTL on
+/// `expires_at`.
+///
+/// The ids are kept in a `members` item, so a listing reads it and then the
+/// records with BatchGetItem, never scanning the table. A listing drops the
+/// ids whose records expired, deleting the records left, so the members item
+/// only holds the active instances, some ten thousand at most.
+///
+/// Throttled requests are retried with backoff. While DynamoDB throttles the
+/// backend, it writes the unchanged records and reads the table less often,
+/// never less than three times per `ttl`, and goes back to every update once
+/// the requests go through. See [`DynamoBackend::metrics`] for the consumed
+/// capacity.
+pub struct DynamoBackend<T> {
+    client: Client,
+    runtime: Runtime,
+    table: String,
+    ttl: Duration,
+    state: Mutex<State>,
+    metrics: CapacityMetrics,
+    _data: PhantomData<fn() -> T>,
+}
+
+impl<T> DynamoBackend<T> {
+    /// Connects to the table with the configuration of the environment,
+    /// listing the records updated within `ttl`. The SDK doesn't retry: the
+    /// backend does, to adapt to the throttling.
+    pub fn connect(table: impl Into<String>, ttl: Duration) -> io::Result<Self> {
+        let runtime = runtime()?;
+        let config = runtime.block_on(
+            aws_config::defaults(BehaviorVersion::latest())
+                .retry_config(RetryConfig::standard().with_max_attempts(1))
+                .load(),
+        );
+        Ok(Self::with_runtime(
+            Client::new(&config),
+            runtime,
+            table.into(),
+            ttl,
+        ))
+    }
+
+    /// Uses `client` for the table, listing the records updated within
+    /// `ttl`. Better disable its retries, the backend retries itself.
+    pub fn new(client: Client, table: impl Into<String>, ttl: Duration) -> io::Result<Self> {
+        Ok(Self::with_runtime(client, runtime()?, table.into(), ttl))
+    }
+
+    fn with_runtime(client: Client, runtime: Runtime, table: String, ttl: Duration) -> Self {
+        DynamoBackend {
+            client,
+            runtime,
+            table,
+            ttl,
+            state: Mutex::new(State {
+                writes: Pace::new(ttl / 3),
+                reads: Pace::new(ttl / 3),
+                written: HashMap::new(),
+                listing: None,
+            }),
+            metrics: CapacityMetrics::default(),
+            _data: PhantomData,
+        }
+    }
+
+    /// The capacity consumed by the backend.
+    pub fn metrics(&self) -> CapacityMetrics {
+        self.metrics.clone()
+    }
+
+    /// Creates the table, on demand, with the TTL on `expires_at`, unless it
+    /// exists. Usually done once when provisioning, e.g. for DynamoDB local.
+    pub fn create_table(&self) -> Result<(), ConnectionError> {
+        let failed = |e: String| ConnectionError::FailedToUpdate(e);
+        let id = AttributeDefinition::builder()
+            .attribute_name("id")
+            .attribute_type(ScalarAttributeType::S)
+            .build()
+            .map_err(|e| failed(e.to_string()))?;
+        let key = KeySchemaElement::builder()
+            .attribute_name("id")
+            .key_type(KeyType::Hash)
+            .build()
+            .map_err(|e| failed(e.to_string()))?;
+        let created = self.runtime.block_on(
+            self.client
+                .create_table()
+                .table_name(&self.table)
+                .attribute_definitions(id)
+                .key_schema(key)
+                .billing_mode(BillingMode::PayPerRequest)
+                .send(),
+        );
+        match created {
+            Ok(_) => {}
+            Err(error) if error.code() == Some("ResourceInUseException") => return Ok(()),
+            Err(error) => return Err(failed(describe(error))),
+        }
+        for attempt in 0..MAX_ATTEMPTS * 10 {
+            let described = self
+                .runtime
+                .block_on(self.client.describe_table().table_name(&self.table).send())
+                .map_err(|e| failed(describe(e)))?;
+            let status = described.table().and_then(|table| table.table_status());
+            if status == Some(&TableStatus::Active) {
+                break;
+            }
+            thread::sleep(backoff(attempt.min(4)));
+        }
+        let ttl = TimeToLiveSpecification::builder()
+            .enabled(true)
+            .attribute_name("expires_at")
+            .build()
+            .map_err(|e| failed(e.to_string()))?;
+        self.runtime
+            .block_on(
+                self.client
+                    .update_time_to_live()
+                    .table_name(&self.table)
+                    .time_to_live_specification(ttl)
+                    .send(),
+            )
+            .map_err(|e| failed(describe(e)))?;
+        Ok(())
+    }
+
+    /// Sends the request built by `request`, retrying it with backoff while
+    /// it's throttled, and paces the requests of its kind accordingly.
+    fn send<R, E, F, Fut>(&self, kind: Kind, request: F) -> Result<R, String>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<R, SdkError<E>>>,
+        E: ProvideErrorMetadata + std::error::Error + 'static,
+    {
+        let mut attempt = 0;
+        loop {
+            match self.runtime.block_on(request()) {
+                Ok(output) => {
+                    self.pace(kind, Pace::went_through);
+                    return Ok(output);
+                }
+                Err(error) if is_throttling(&error) => {
+                    self.throttled(kind);
+                    attempt += 1;
+                    if attempt == MAX_ATTEMPTS {
+                        return Err(describe(error));
+                    }
+                    debug!(
+                        "Throttled by DynamoDB, retrying. Cause: {}",
+                        DisplayErrorContext(&error)
+                    );
+                    thread::sleep(backoff(attempt));
+                }
+                Err(error) => return Err(describe(error)),
+            }
+        }
+    }
+
+    fn throttled(&self, kind: Kind) {
+        self.metrics
+            .0
+            .throttled_requests
+            .fetch_add(1, Ordering::Relaxed);
+        self.pace(kind, Pace::throttled);
+    }
+
+    fn pace(&self, kind: Kind, change: impl FnOnce(&mut Pace)) {
+        let mut state = self.lock();
+        match kind {
+            Kind::Read => change(&mut state.reads),
+            Kind::Write => change(&mut state.writes),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn member_ids(&self) -> Result<Vec<String>, String> {
+        let output = self.send(Kind::Read, || {
+            self.client
+                .get_item()
+                .table_name(&self.table)
+                .key("id", AttributeValue::S(MEMBERS_KEY.to_string()))
+                .consistent_read(true)
+                .return_consumed_capacity(ReturnConsumedCapacity::Total)
+                .send()
+        })?;
+        self.metrics
+            .consumed(Kind::Read, output.consumed_capacity());
+        match output.item().and_then(|item| item.get("ids")) {
+            Some(AttributeValue::Ss(ids)) => Ok(ids.clone()),
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// Adds (with `ADD`) or removes (with `DELETE`) `ids` from the members.
+    fn change_members(&self, action: &str, ids: Vec<String>) -> Result<(), String> {
+        let output = self.send(Kind::Write, || {
+            self.client
+                .update_item()
+                .table_name(&self.table)
+                .key("id", AttributeValue::S(MEMBERS_KEY.to_string()))
+                .update_expression(format!("{} #ids :ids", action))
+                .expression_attribute_names("#ids", "ids")
+                .expression_attribute_values(":ids", AttributeValue::Ss(ids.clone()))
+                .return_consumed_capacity(ReturnConsumedCapacity::Total)
+                .send()
+        })?;
+        self.metrics
+            .consumed(Kind::Write, output.consumed_capacity());
+        Ok(())
+    }
+
+    /// The records of `ids`, BATCH_SIZE at a time, retrying the keys left
+    /// unprocessed.
+    fn batch_get(&self, ids: &[String]) -> Result<Vec<Item>, String> {
+        let mut items = vec![];
+        for chunk in ids.chunks(BATCH_SIZE) {
+            let mut keys: Vec<Item> = chunk.iter().map(|id| key(id)).collect();
+            let mut attempt = 0;
+            while !keys.is_empty() {
+                let request = KeysAndAttributes::builder()
+                    .set_keys(Some(keys))
+                    .consistent_read(true)
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                let output = self.send(Kind::Read, || {
+                    self.client
+                        .batch_get_item()
+                        .request_items(&self.table, request.clone())
+                        .return_consumed_capacity(ReturnConsumedCapacity::Total)
+                        .send()
+                })?;
+                self.metrics
+                    .consumed(Kind::Read, output.consumed_capacity());
+                if let Some(found) = output.responses().and_then(|r| r.get(&self.table)) {
+                    items.extend(found.iter().cloned());
+                }
+                keys = output
+                    .unprocessed_keys()
+                    .and_then(|unprocessed| unprocessed.get(&self.table))
+                    .map(|unprocessed| unprocessed.keys().to_vec())
+                    .unwrap_or_default();
+                if !keys.is_empty() {
+                    self.throttled(Kind::Read);
+                    attempt += 1;
+                    if attempt == MAX_ATTEMPTS {
+                        return Err(format!("{} keys left unprocessed.", keys.len()));
+                    }
+                    thread::sleep(backoff(attempt));
+                }
+            }
+        }
+        Ok(items)
+    }
+
+    /// The records of the members, or the previous ones if they were read
+    /// recently while throttled.
+    fn items(&self) -> Result<Vec<Item>, String> {
+        {
+            let state = self.lock();
+            if let Some((at, items)) = &state.listing {
+                if state.reads.is_recent(*at) {
+                    self.metrics
+                        .0
+                        .cached_listings
+                        .fetch_add(1, Ordering::Relaxed);
+                    return Ok(items.clone());
+                }
+            }
+        }
+        let ids = self.member_ids()?;
+        let items = self.batch_get(&ids)?;
+        let oldest = HeartbeatTime::now()
+            .as_millis()
+            .saturating_sub(self.ttl.as_millis() as u64);
+        if let Err(error) = self.drop_members(&ids, &items, oldest) {
+            debug!("Failed to drop the expired members: {}", error);
+        }
+        self.lock().listing = Some((Instant::now(), items.clone()));
+        Ok(items)
+    }
+
+    /// Deletes the records of `items` whose heartbeat is older than
+    /// `older_than`, unless they were refreshed meanwhile, and takes them
+    /// out of the members with the ids whose records already expired.
+    /// Returns how many it dropped.
+    fn drop_members(
+        &self,
+        ids: &[String],
+        items: &[Item],
+        older_than: u64,
+    ) -> Result<usize, String> {
+        let alive: Vec<&str> = items
+            .iter()
+            .filter_map(|item| fields(item))
+            .filter(|(_, _, heartbeat_at, _)| *heartbeat_at >= older_than)
+            .map(|(id, _, _, _)| id)
+            .collect();
+        let mut pruned = vec![];
+        for id in ids.iter().filter(|id| !alive.contains(&id.as_str())) {
+            let deleted = self.send(Kind::Write, || {
+                self.client
+                    .delete_item()
+                    .table_name(&self.table)
+                    .key("id", AttributeValue::S(id.clone()))
+                    .condition_expression("attribute_not_exists(id) OR heartbeat_at < :older_than")
+                    .expression_attribute_values(
+                        ":older_than",
+                        AttributeValue::N(older_than.to_string()),
+                    )
+                    .return_consumed_capacity(ReturnConsumedCapacity::Total)
+                    .send()
+            });
+            match deleted {
+                Ok(output) => {
+                    self.metrics
+                        .consumed(Kind::Write, output.consumed_capacity());
+                    pruned.push(id.clone());
+                }
+                // Refreshed since the listing.
+                Err(error) if error.contains("ConditionalCheckFailed") => {}
+                Err(error) => return Err(error),
+            }
+        }
+        if !pruned.is_empty() {
+            self.change_members("DELETE", pruned.clone())?;
+            self.lock().listing = None;
+        }
+        Ok(pruned.len())
+    }
+}
+
+impl<T> Backend<T> for DynamoBackend<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// Skips the write while throttled if the record is unchanged and was
+    /// written recently. The instance adds itself to the members before
+    /// every write: a listing may drop its id meanwhile, seeing no record
+    /// yet, and it's then back by the next write.
+    fn update_instance_info(&self, instance_id: Uuid, data: T) -> Result<(), ConnectionError> {
+        let failed = |e: String| ConnectionError::FailedToUpdate(e);
+        let data = serde_json::to_string(&data).map_err(|e| failed(e.to_string()))?;
+        let changed = {
+            let state = self.lock();
+            match state.written.get(&instance_id) {
+                Some((written, at)) if *written == data && state.writes.is_recent(*at) => {
+                    self.metrics
+                        .0
+                        .skipped_writes
+                        .fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+                Some((written, _)) => *written != data,
+                None => true,
+            }
+        };
+        let id = instance_id.to_string();
+        self.change_members("ADD", vec![id.clone()])
+            .map_err(failed)?;
+        let now = HeartbeatTime::now().as_millis();
+        let expires_at = (now + self.ttl.as_millis() as u64) / 1000 + 1;
+        let output = self
+            .send(Kind::Write, || {
+                self.client
+                    .update_item()
+                    .table_name(&self.table)
+                    .key("id", AttributeValue::S(id.clone()))
+                    .update_expression(
+                        "SET registered_at = if_not_exists(registered_at, :now), \
+                         heartbeat_at = :now, #data = :data, expires_at = :expires_at",
+                    )
+                    .expression_attribute_names("#data", "data")
+                    .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+                    .expression_attribute_values(":data", AttributeValue::S(data.clone()))
+                    .expression_attribute_values(
+                        ":expires_at",
+                        AttributeValue::N(expires_at.to_string()),
+                    )
+                    .return_values(ReturnValue::UpdatedOld)
+                    .return_consumed_capacity(ReturnConsumedCapacity::Total)
+                    .send()
+            })
+            .map_err(failed)?;
+        self.metrics
+            .consumed(Kind::Write, output.consumed_capacity());
+        let existed = output
+            .attributes()
+            .is_some_and(|old| old.contains_key("heartbeat_at"));
+        let mut state = self.lock();
+        state.written.insert(instance_id, (data, Instant::now()));
+        if changed || !existed {
+            state.listing = None;
+        }
+        Ok(())
+    }
+
+    fn list_active_instances(&self) -> Result<Vec<InstanceRecord<T>>, ConnectionError> {
+        Ok(codec::decode_records(self.list_encoded_instances()?))
+    }
+
+    fn list_encoded_instances(&self) -> Result<Vec<InstanceRecord<Vec<u8>>>, ConnectionError> {
+        let failed = |e: String| ConnectionError::FailedToRetrieve(e);
+        let oldest = HeartbeatTime::now()
+            .as_millis()
+            .saturating_sub(self.ttl.as_millis() as u64);
+        let mut records = vec![];
+        for item in self.items().map_err(failed)? {
+            let Some((id, registered_at, heartbeat_at, data)) = fields(&item) else {
+                return Err(failed("Unexpected item.".to_string()));
+            };
+            if heartbeat_at <= oldest {
+                continue;
+            }
+            let id = id.parse::<Uuid>().map_err(|e| failed(e.to_string()))?;
+            records.push(
+                InstanceRecord::new(
+                    id,
+                    HeartbeatTime::from_millis(registered_at),
+                    data.as_bytes().to_vec(),
+                )
+                .with_heartbeat_at(HeartbeatTime::from_millis(heartbeat_at)),
+            );
+        }
+        Ok(records)
+    }
+
+    fn deregister_instance(&self, instance_id: Uuid) -> Result<(), ConnectionError> {
+        let failed = |e: String| ConnectionError::FailedToDeregister(e);
+        let id = instance_id.to_string();
+        let output = self
+            .send(Kind::Write, || {
+                self.client
+                    .delete_item()
+                    .table_name(&self.table)
+                    .key("id", AttributeValue::S(id.clone()))
+                    .return_consumed_capacity(ReturnConsumedCapacity::Total)
+                    .send()
+            })
+            .map_err(failed)?;
+        self.metrics
+            .consumed(Kind::Write, output.consumed_capacity());
+        self.change_members("DELETE", vec![id]).map_err(failed)?;
+        let mut state = self.lock();
+        state.written.remove(&instance_id);
+        state.listing = None;
+        Ok(())
+    }
+
+    fn max_payload_size(&self) -> Option<usize> {
+        Some(MAX_PAYLOAD_SIZE)
+    }
+
+    fn identity(&self) -> String {
+        match self.client.config().region() {
+            Some(region) => format!("DynamoDB(dynamodb://{}, {})", self.table, region),
+            None => format!("DynamoDB(dynamodb://{})", self.table),
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        capabilities.deregister = true;
+        capabilities.heartbeat_timestamps = true;
+        capabilities.expiry = true;
+        capabilities.pruning = true;
+        capabilities
+    }
+
+    /// Deletes the records not refreshed for `max_age`, as a listing does
+    /// for `ttl`. The heartbeats are taken from the instances' clocks, the
+    /// cutoff from this one's.
+    fn prune_expired(&self, max_age: Duration) -> Result<usize, ConnectionError> {
+        let failed = |e: String| ConnectionError::FailedToUpdate(e);
+        let now = HeartbeatTime::now().as_millis();
+        let older_than = now.saturating_sub(max_age.as_millis() as u64);
+        let ids = self.member_ids().map_err(failed)?;
+        let items = self.batch_get(&ids).map_err(failed)?;
+        self.drop_members(&ids, &items, older_than).map_err(failed)
+    }
+
+    fn health_check(&self) -> Result<(), ConnectionError> {
+        self.send(Kind::Read, || {
+            self.client.describe_table().table_name(&self.table).send()
+        })
+        .map(|_| ())
+        .map_err(ConnectionError::FailedToRetrieve)
+    }
+}
+
+fn runtime() -> io::Result<Runtime> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+}
+
+fn key(id: &str) -> Item {
+    HashMap::from([("id".to_string(), AttributeValue::S(id.to_string()))])
+}
+
+/// The id, registration time, heartbeat and data of a record.
+fn fields(item: &Item) -> Option<(&str, u64, u64, &str)> {
+    let number = |name: &str| item.get(name)?.as_n().ok()?.parse::<u64>().ok();
+    let string = |name: &str| item.get(name)?.as_s().ok().map(String::as_str);
+    Some((
+        string("id")?,
+        number("registered_at")?,
+        number("heartbeat_at")?,
+        string("data")?,
+    ))
+}
+
+fn is_throttling<E: ProvideErrorMetadata>(error: &SdkError<E>) -> bool {
+    error
+        .code()
+        .is_some_and(|code| THROTTLING_CODES.contains(&code))
+}
+
+/// The error with its causes, which the SDK's errors only show this way.
+fn describe<E: std::error::Error + 'static>(error: E) -> String {
+    DisplayErrorContext(error).to_string()
+}
+
+/// Exponential, with full jitter.
+fn backoff(attempt: u32) -> Duration {
+    let max = RETRY_BACKOFF * 2u32.pow(attempt);
+    Duration::from_millis(SystemRandom.below(max.as_millis() as u64 + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_the_fields_of_a_record() {
+        let mut item = key("id-1");
+        item.insert(
+            "registered_at".to_string(),
+            AttributeValue::N("10".to_string()),
+        );
+        item.insert(
+            "heartbeat_at".to_string(),
+            AttributeValue::N("20".to_string()),
+        );
+        item.insert("data".to_string(), AttributeValue::S("{}".to_string()));
+
+        assert_eq!(Some(("id-1", 10, 20, "{}")), fields(&item));
+        item.remove("heartbeat_at");
+        assert_eq!(None, fields(&item));
+    }
+
+    #[test]
+    fn should_add_up_the_consumed_capacity() {
+        let metrics = CapacityMetrics::default();
+        let capacity = |units| ConsumedCapacity::builder().capacity_units(units).build();
+
+        metrics.consumed(Kind::Read, &[capacity(0.5), capacity(1.0)]);
+        metrics.consumed(Kind::Write, Some(&capacity(2.0)));
+        metrics.consumed(Kind::Write, None);
+
+        assert_eq!(1.5, metrics.read_units());
+        assert_eq!(2.0, metrics.clone().write_units());
+    }
+
+    #[test]
+    fn should_back_off_within_the_exponential_bound() {
+        for attempt in 0..MAX_ATTEMPTS {
+            assert!(backoff(attempt) <= RETRY_BACKOFF * 2u32.pow(attempt));
+        }
+    }
+}
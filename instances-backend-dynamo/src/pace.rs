@@ -0,0 +1,74 @@
+//! How often the backend writes unchanged records and reads the table,
+//! slowing down while DynamoDB throttles it and speeding back up after.
+
+use std::time::{Duration, Instant};
+
+/// The first step when throttled.
+const MIN_PACE: Duration = Duration::from_millis(500);
+
+/// The shortest time between two requests of a kind, zero while nothing is
+/// throttled: doubled on every throttled request, up to `max`, and halved on
+/// every request that went through.
+#[derive(PartialEq, Debug)]
+pub(crate) struct Pace {
+    every: Duration,
+    max: Duration,
+}
+
+impl Pace {
+    pub(crate) fn new(max: Duration) -> Self {
+        Pace {
+            every: Duration::ZERO,
+            max,
+        }
+    }
+
+    pub(crate) fn throttled(&mut self) {
+        self.every = (self.every * 2).max(MIN_PACE).min(self.max);
+    }
+
+    pub(crate) fn went_through(&mut self) {
+        self.every /= 2;
+        if self.every < MIN_PACE {
+            self.every = Duration::ZERO;
+        }
+    }
+
+    /// Whether a request made at `last` is recent enough to skip another one.
+    pub(crate) fn is_recent(&self, last: Instant) -> bool {
+        last.elapsed() < self.every
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_slow_down_while_throttled_and_recover_after() {
+        let mut pace = Pace::new(Duration::from_secs(3));
+
+        pace.throttled();
+        assert_eq!(MIN_PACE, pace.every);
+        pace.throttled();
+        pace.throttled();
+        pace.throttled();
+        assert_eq!(Duration::from_secs(3), pace.every);
+
+        pace.went_through();
+        assert_eq!(Duration::from_millis(1500), pace.every);
+        pace.went_through();
+        pace.went_through();
+        assert_eq!(Duration::ZERO, pace.every);
+    }
+
+    #[test]
+    fn should_skip_only_while_slowed_down() {
+        let mut pace = Pace::new(Duration::from_secs(3));
+        let last = Instant::now();
+
+        assert!(!pace.is_recent(last));
+        pace.throttled();
+        assert!(pace.is_recent(last));
+    }
+}
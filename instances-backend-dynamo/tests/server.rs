@@ -0,0 +1,154 @@
+//! Checks against a real DynamoDB, configured from the environment: run
+//! DynamoDB local and point `AWS_ENDPOINT_URL` at it, with any `AWS_REGION`
+//! and credentials. They create tables with random names: run them with
+//! `cargo test -p instances-backend-dynamo -- --ignored`.
+
+use std::thread;
+use std::time::Duration;
+
+use aws_config::BehaviorVersion;
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_dynamodb::Client;
+use instances_backend_dynamo::DynamoBackend;
+use instances_core::backends::{conformance, Backend};
+use instances_core::models::Registration;
+use uuid::Uuid;
+
+fn backend<T>(table: &str, ttl: Duration) -> DynamoBackend<T> {
+    let backend = DynamoBackend::connect(table, ttl).unwrap();
+    backend.create_table().unwrap();
+    backend
+}
+
+fn random_table() -> String {
+    format!("instances_{}", Uuid::new_v4().to_simple())
+}
+
+#[test]
+#[ignore = "needs DynamoDB"]
+fn should_pass_the_conformance_checks() {
+    let fresh = || backend::<Registration<String>>(&random_table(), Duration::from_secs(10));
+
+    conformance::lists_registered_instances(&fresh());
+    conformance::keeps_registration_time(&fresh());
+    conformance::advances_heartbeat(&fresh());
+    conformance::removes_deregistered_instances(&fresh());
+    conformance::passes_health_check(&fresh());
+    conformance::expires_unrefreshed_records(
+        &backend::<Registration<String>>(&random_table(), Duration::from_millis(500)),
+        Duration::from_millis(500),
+    );
+}
+
+#[test]
+#[ignore = "needs DynamoDB"]
+fn should_prune_only_the_expired_records() {
+    let backend = backend::<String>(&random_table(), Duration::from_secs(10));
+    let (expired, active) = (Uuid::new_v4(), Uuid::new_v4());
+
+    backend
+        .update_instance_info(expired, "expired".to_string())
+        .unwrap();
+    thread::sleep(Duration::from_millis(40));
+    backend
+        .update_instance_info(active, "active".to_string())
+        .unwrap();
+
+    assert_eq!(Ok(1), backend.prune_expired(Duration::from_millis(20)));
+    let records = backend.list_active_instances().unwrap();
+    assert_eq!(
+        vec![active],
+        records.iter().map(|record| record.id).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+#[ignore = "needs DynamoDB"]
+fn should_drop_the_expired_members_while_listing() {
+    let backend = backend::<String>(&random_table(), Duration::from_millis(200));
+    let (expired, active) = (Uuid::new_v4(), Uuid::new_v4());
+
+    backend
+        .update_instance_info(expired, "expired".to_string())
+        .unwrap();
+    thread::sleep(Duration::from_millis(300));
+    backend
+        .update_instance_info(active, "active".to_string())
+        .unwrap();
+    backend.list_active_instances().unwrap();
+
+    // Dropped by the listing, there's nothing left to prune.
+    assert_eq!(Ok(0), backend.prune_expired(Duration::from_millis(200)));
+    backend
+        .update_instance_info(expired, "back".to_string())
+        .unwrap();
+    let mut ids: Vec<_> = backend
+        .list_active_instances()
+        .unwrap()
+        .iter()
+        .map(|record| record.id)
+        .collect();
+    ids.sort();
+    let mut expected = vec![expired, active];
+    expected.sort();
+    assert_eq!(expected, ids);
+}
+
+/// Takes `id` out of the members item, as a listing does between a joining
+/// instance adding its id and writing its record.
+fn drop_member(table: &str, id: Uuid) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let config = aws_config::defaults(BehaviorVersion::latest()).load().await;
+        Client::new(&config)
+            .update_item()
+            .table_name(table)
+            .key("id", AttributeValue::S("members".to_string()))
+            .update_expression("DELETE #ids :ids")
+            .expression_attribute_names("#ids", "ids")
+            .expression_attribute_values(":ids", AttributeValue::Ss(vec![id.to_string()]))
+            .send()
+            .await
+            .unwrap();
+    });
+}
+
+#[test]
+#[ignore = "needs DynamoDB"]
+fn should_add_back_a_member_dropped_while_joining() {
+    let table = random_table();
+    let backend = backend::<String>(&table, Duration::from_secs(10));
+    let id = Uuid::new_v4();
+
+    backend
+        .update_instance_info(id, "joining".to_string())
+        .unwrap();
+    drop_member(&table, id);
+    backend
+        .update_instance_info(id, "joined".to_string())
+        .unwrap();
+
+    let records = backend.list_active_instances().unwrap();
+    assert_eq!(
+        vec![id],
+        records.iter().map(|record| record.id).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+#[ignore = "needs DynamoDB"]
+fn should_count_the_consumed_capacity() {
+    let backend = backend::<String>(&random_table(), Duration::from_secs(10));
+    let metrics = backend.metrics();
+
+    backend
+        .update_instance_info(Uuid::new_v4(), "data".to_string())
+        .unwrap();
+    backend.list_active_instances().unwrap();
+
+    assert!(metrics.write_units() > 0.0);
+    assert!(metrics.read_units() > 0.0);
+}
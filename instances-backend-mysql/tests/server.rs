@@ -0,0 +1,95 @@
+//! Checks against a real server, at `INSTANCES_MYSQL_URL` or
+//! `mysql://root@127.0.0.1:3306/mysql`. They create tables with random
+//! names: point them at a disposable database and run them with
+//! `cargo test -p instances-backend-mysql -- --ignored`.
+
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use instances_backend_mysql::MySqlBackend;
+use instances_core::backends::{conformance, Backend};
+use instances_core::models::Registration;
+use uuid::Uuid;
+
+fn backend<T>(table: &str, ttl: Duration) -> MySqlBackend<T> {
+    let url =
+        env::var("INSTANCES_MYSQL_URL").unwrap_or("mysql://root@127.0.0.1:3306/mysql".to_string());
+    MySqlBackend::connect(&url, ttl).unwrap().with_table(table)
+}
+
+fn random_table() -> String {
+    format!("instances_{}", Uuid::new_v4().to_simple())
+}
+
+#[test]
+#[ignore = "needs a MySQL server"]
+fn should_pass_the_conformance_checks() {
+    let fresh = || backend::<Registration<String>>(&random_table(), Duration::from_secs(10));
+
+    conformance::lists_registered_instances(&fresh());
+    conformance::keeps_registration_time(&fresh());
+    conformance::advances_heartbeat(&fresh());
+    conformance::removes_deregistered_instances(&fresh());
+    conformance::passes_health_check(&fresh());
+    conformance::expires_unrefreshed_records(
+        &backend::<Registration<String>>(&random_table(), Duration::from_millis(500)),
+        Duration::from_millis(500),
+    );
+}
+
+#[test]
+#[ignore = "needs a MySQL server"]
+fn should_designate_the_oldest_registration() {
+    let table = random_table();
+    let first = backend::<String>(&table, Duration::from_secs(10));
+    let second = backend::<String>(&table, Duration::from_secs(10));
+    let (first_id, second_id) = (Uuid::new_v4(), Uuid::new_v4());
+    let leaders = |backend: &MySqlBackend<String>| -> Vec<Uuid> {
+        let records = backend.list_active_instances().unwrap();
+        records
+            .iter()
+            .filter(|record| record.designated_leader)
+            .map(|record| record.id)
+            .collect()
+    };
+
+    first
+        .update_instance_info(first_id, "first".to_string())
+        .unwrap();
+    second
+        .update_instance_info(second_id, "second".to_string())
+        .unwrap();
+    assert_eq!(vec![first_id], leaders(&first));
+    assert_eq!(vec![first_id], leaders(&second));
+
+    first.deregister_instance(first_id).unwrap();
+
+    assert_eq!(vec![second_id], leaders(&first));
+}
+
+#[test]
+#[ignore = "needs a MySQL server"]
+fn should_prune_the_expired_records() {
+    let backend = backend::<String>(&random_table(), Duration::from_secs(10));
+    backend
+        .update_instance_info(Uuid::new_v4(), "data".to_string())
+        .unwrap();
+
+    assert_eq!(Ok(0), backend.prune_expired(Duration::from_secs(5)));
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(Ok(1), backend.prune_expired(Duration::from_millis(10)));
+    assert!(backend.list_active_instances().unwrap().is_empty());
+}
+
+#[test]
+#[ignore = "needs a MySQL server"]
+fn should_store_the_data_verbatim() {
+    let backend = backend::<String>(&random_table(), Duration::from_secs(10));
+    let id = Uuid::new_v4();
+    let data = r"O'Brien \' ? ; DROP TABLE instances; --".to_string();
+
+    backend.update_instance_info(id, data.clone()).unwrap();
+
+    assert_eq!(data, backend.list_active_instances().unwrap()[0].data);
+}
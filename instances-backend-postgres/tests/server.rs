@@ -0,0 +1,109 @@
+//! Checks against a real server, at `INSTANCES_POSTGRES_URL` or
+//! `postgres://postgres@127.0.0.1:5432/postgres`. They create tables with
+//! random names: point them at a disposable database and run them with
+//! `cargo test -p instances-backend-postgres -- --ignored`.
+
+use std::env;
+use std::thread;
+use std::time::Duration;
+
+use instances_backend_postgres::PostgresBackend;
+use instances_core::backends::{conformance, Backend};
+use instances_core::models::Registration;
+use uuid::Uuid;
+
+fn url() -> String {
+    env::var("INSTANCES_POSTGRES_URL")
+        .unwrap_or("postgres://postgres@127.0.0.1:5432/postgres".to_string())
+}
+
+fn backend<T>(table: &str, ttl: Duration) -> PostgresBackend<T> {
+    PostgresBackend::connect(&url(), ttl)
+        .unwrap()
+        .with_table(table)
+}
+
+fn random_table() -> String {
+    format!("instances_{}", Uuid::new_v4().to_simple())
+}
+
+#[test]
+#[ignore = "needs a PostgreSQL server"]
+fn should_pass_the_conformance_checks() {
+    let fresh = || backend::<Registration<String>>(&random_table(), Duration::from_secs(10));
+
+    conformance::lists_registered_instances(&fresh());
+    conformance::keeps_registration_time(&fresh());
+    conformance::advances_heartbeat(&fresh());
+    conformance::removes_deregistered_instances(&fresh());
+    conformance::holds_leases_exclusively(&fresh());
+    conformance::passes_health_check(&fresh());
+    conformance::expires_unrefreshed_records(
+        &backend::<Registration<String>>(&random_table(), Duration::from_millis(500)),
+        Duration::from_millis(500),
+    );
+}
+
+#[test]
+#[ignore = "needs a PostgreSQL server"]
+fn should_designate_the_holder_of_the_advisory_lock() {
+    let table = random_table();
+    let key = rand_key();
+    let first = backend::<String>(&table, Duration::from_secs(10)).with_advisory_lock(key);
+    let second = backend::<String>(&table, Duration::from_secs(10)).with_advisory_lock(key);
+    let (first_id, second_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+    first
+        .update_instance_info(first_id, "first".to_string())
+        .unwrap();
+    second
+        .update_instance_info(second_id, "second".to_string())
+        .unwrap();
+    let leaders = |backend: &PostgresBackend<String>| -> Vec<Uuid> {
+        let records = backend.list_active_instances().unwrap();
+        records
+            .iter()
+            .filter(|record| record.designated_leader)
+            .map(|record| record.id)
+            .collect()
+    };
+    assert_eq!(vec![first_id], leaders(&second));
+
+    first.deregister_instance(first_id).unwrap();
+    second
+        .update_instance_info(second_id, "second".to_string())
+        .unwrap();
+
+    assert_eq!(vec![second_id], leaders(&first));
+}
+
+#[test]
+#[ignore = "needs a PostgreSQL server"]
+fn should_prune_the_expired_records() {
+    let backend = backend::<String>(&random_table(), Duration::from_secs(10));
+    backend
+        .update_instance_info(Uuid::new_v4(), "data".to_string())
+        .unwrap();
+
+    assert_eq!(Ok(0), backend.prune_expired(Duration::from_secs(5)));
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(Ok(1), backend.prune_expired(Duration::from_millis(10)));
+    assert!(backend.list_active_instances().unwrap().is_empty());
+}
+
+#[test]
+#[ignore = "needs a PostgreSQL server"]
+fn should_store_the_data_verbatim() {
+    let backend = backend::<String>(&random_table(), Duration::from_secs(10));
+    let id = Uuid::new_v4();
+    let data = r"O'Brien \' $1 ; DROP TABLE instances; --".to_string();
+
+    backend.update_instance_info(id, data.clone()).unwrap();
+
+    assert_eq!(data, backend.list_active_instances().unwrap()[0].data);
+}
+
+/// A key no other run uses.
+fn rand_key() -> i64 {
+    Uuid::new_v4().as_u128() as i64
+}
@@ -0,0 +1,72 @@
+//! Checks against a real server, at `INSTANCES_REDIS_URL` or
+//! `redis://127.0.0.1:6379`. They write records under random prefixes:
+//! point them at a disposable server and run them with
+//! `cargo test -p instances-backend-redis -- --ignored`.
+
+use std::env;
+use std::time::Duration;
+
+use instances_backend_redis::RedisBackend;
+use instances_core::backends::Backend;
+use uuid::Uuid;
+
+fn backend(ttl: Duration) -> RedisBackend<String> {
+    let url = env::var("INSTANCES_REDIS_URL").unwrap_or("redis://127.0.0.1:6379".to_string());
+    RedisBackend::connect(&url, ttl)
+        .unwrap()
+        .with_prefix(Uuid::new_v4().to_string())
+}
+
+#[test]
+#[ignore = "needs a Redis server"]
+fn should_list_the_ttl_left() {
+    let backend = backend(Duration::from_secs(10));
+    let id = Uuid::new_v4();
+
+    backend
+        .update_instance_info(id, "data".to_string())
+        .unwrap();
+
+    let listed = backend.list_active_instances().unwrap();
+    let ttl = listed[0].extensions["ttl_ms"].as_i64().unwrap();
+    assert!(ttl > 9000 && ttl <= 10000);
+    assert!(listed[0].heartbeat_at.is_some());
+}
+
+#[test]
+#[ignore = "needs a Redis server"]
+fn should_keep_the_leader_override_out_of_the_listing() {
+    let backend = backend(Duration::from_secs(10));
+    let id = Uuid::new_v4();
+    backend
+        .update_instance_info(id, "data".to_string())
+        .unwrap();
+
+    backend.store_leader_override(Some(id)).unwrap();
+
+    assert_eq!(Ok(Some(id)), backend.load_leader_override());
+    assert_eq!(1, backend.list_active_instances().unwrap().len());
+    backend.store_leader_override(None).unwrap();
+    assert_eq!(Ok(None), backend.load_leader_override());
+}
+
+#[test]
+#[ignore = "needs a Redis server"]
+fn should_push_the_joins_and_departures() {
+    let backend = backend(Duration::from_secs(10));
+    let changes = backend.watch_changes().unwrap();
+    let id = Uuid::new_v4();
+    let pushed = || changes.recv_timeout(Duration::from_secs(2));
+
+    backend
+        .update_instance_info(id, "data".to_string())
+        .unwrap();
+    assert_eq!(Ok(id), pushed());
+    backend
+        .update_instance_info(id, "data".to_string())
+        .unwrap();
+    backend.deregister_instance(id).unwrap();
+
+    // The heartbeat isn't pushed, the departure is.
+    assert_eq!(Ok(id), pushed());
+}
@@ -0,0 +1,9 @@
+//! Runs the backend conformance checks against real Redis, PostgreSQL, MySQL
+//! and DynamoDB local servers, each started in a container by the test. It
+//! needs Docker and is off by default:
+//!
+//! ```text
+//! cargo test -p instances-containers --features containers
+//! ```
+//!
+//! The crate holds only the tests, in `tests/containers.rs`.
@@ -0,0 +1,142 @@
+//! The conformance checks against a container per backend, the records of
+//! every check under a fresh table or prefix.
+
+#![cfg(feature = "containers")]
+
+use std::time::Duration;
+
+use aws_sdk_dynamodb::config::retry::RetryConfig;
+use aws_sdk_dynamodb::config::{BehaviorVersion, Credentials, Region};
+use aws_sdk_dynamodb::Client;
+use instances_backend_dynamo::DynamoBackend;
+use instances_backend_mysql::MySqlBackend;
+use instances_backend_postgres::PostgresBackend;
+use instances_backend_redis::RedisBackend;
+use instances_core::backends::{conformance, Backend};
+use instances_core::models::Registration;
+use testcontainers::core::wait::LogWaitStrategy;
+use testcontainers::core::{IntoContainerPort, WaitFor};
+use testcontainers::runners::SyncRunner;
+use testcontainers::{Container, GenericImage, ImageExt};
+use uuid::Uuid;
+
+const TTL: Duration = Duration::from_secs(10);
+
+/// How long an unrefreshed record may stay listed in the expiry check.
+const EXPIRY: Duration = Duration::from_millis(500);
+
+/// Runs every check, each against the backend built by `fresh` with the TTL
+/// it is given.
+fn check<B, F>(fresh: F)
+where
+    B: Backend<Registration<String>> + Sync,
+    F: Fn(Duration) -> B,
+{
+    conformance::lists_registered_instances(&fresh(TTL));
+    conformance::keeps_registration_time(&fresh(TTL));
+    conformance::advances_heartbeat(&fresh(TTL));
+    conformance::removes_deregistered_instances(&fresh(TTL));
+    conformance::rejects_instances_over_the_limit(&fresh(TTL));
+    conformance::admits_concurrent_registrations_up_to_the_limit(&fresh(TTL));
+    conformance::compares_and_sets_counters(&fresh(TTL));
+    conformance::applies_deltas(&fresh(TTL));
+    conformance::holds_leases_exclusively(&fresh(TTL));
+    conformance::executes_batches_in_order(&fresh(TTL));
+    conformance::passes_health_check(&fresh(TTL));
+    conformance::expires_unrefreshed_records(&fresh(EXPIRY), EXPIRY);
+}
+
+fn random_name() -> String {
+    format!("instances_{}", Uuid::new_v4().to_simple())
+}
+
+fn address(container: &Container<GenericImage>, port: u16) -> String {
+    format!(
+        "{}:{}",
+        container.get_host().unwrap(),
+        container.get_host_port_ipv4(port).unwrap()
+    )
+}
+
+#[test]
+fn should_conform_on_redis() {
+    let container = GenericImage::new("redis", "7.2")
+        .with_exposed_port(6379.tcp())
+        .with_wait_for(WaitFor::message_on_stdout("Ready to accept connections"))
+        .with_cmd(["redis-server", "--notify-keyspace-events", "Kgx"])
+        .start()
+        .unwrap();
+    let url = format!("redis://{}/0", address(&container, 6379));
+
+    check(|ttl| {
+        RedisBackend::connect(&url, ttl)
+            .unwrap()
+            .with_prefix(random_name())
+    });
+}
+
+#[test]
+fn should_conform_on_postgres() {
+    // The server restarts once initialized, logging that it is ready twice.
+    let container = GenericImage::new("postgres", "16")
+        .with_exposed_port(5432.tcp())
+        .with_wait_for(WaitFor::log(
+            LogWaitStrategy::stderr("database system is ready to accept connections").with_times(2),
+        ))
+        .with_env_var("POSTGRES_PASSWORD", "postgres")
+        .start()
+        .unwrap();
+    let url = format!(
+        "postgres://postgres:postgres@{}/postgres",
+        address(&container, 5432)
+    );
+
+    check(|ttl| {
+        PostgresBackend::connect(&url, ttl)
+            .unwrap()
+            .with_table(&random_name())
+    });
+}
+
+#[test]
+fn should_conform_on_mysql() {
+    // The server initializing the data directory listens on port 0.
+    let container = GenericImage::new("mysql", "8.4")
+        .with_exposed_port(3306.tcp())
+        .with_wait_for(WaitFor::log(LogWaitStrategy::stdout_or_stderr(
+            "port: 3306  MySQL Community Server",
+        )))
+        .with_env_var("MYSQL_ALLOW_EMPTY_PASSWORD", "yes")
+        .start()
+        .unwrap();
+    let url = format!("mysql://root@{}/mysql", address(&container, 3306));
+
+    check(|ttl| {
+        MySqlBackend::connect(&url, ttl)
+            .unwrap()
+            .with_table(&random_name())
+    });
+}
+
+#[test]
+fn should_conform_on_dynamodb_local() {
+    let container = GenericImage::new("amazon/dynamodb-local", "2.5.2")
+        .with_exposed_port(8000.tcp())
+        .with_wait_for(WaitFor::message_on_stdout("CorsParams"))
+        .start()
+        .unwrap();
+    let config = aws_sdk_dynamodb::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("us-east-1"))
+        .endpoint_url(format!("http://{}", address(&container, 8000)))
+        .credentials_provider(Credentials::new("local", "local", None, None, "test"))
+        .retry_config(RetryConfig::disabled())
+        .build();
+    let client = Client::from_conf(config);
+
+    check(|ttl| {
+        let backend = DynamoBackend::new(client.clone(), random_name(), ttl).unwrap();
+        backend.create_table().unwrap();
+        backend
+    });
+}
@@ -0,0 +1,381 @@
+//! Alerts on the conditions needing an operator, delivered to an
+//! [`AlertSink`] such as the [`WebhookSink`]. See [`Alerts`].
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::extension::{Extension, TickContext};
+use crate::sync::MutexExt;
+use crate::{Backend, InstanceStatus, Instances, Registration};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Updates missed, in update intervals, before the daemon is reported as
+/// stalled.
+const STALLED_AFTER_INTERVALS: u32 = 3;
+
+/// A condition reported by the [`Alerts`], serialized as
+/// `{"alert": "<kind>", "instance_id": ..., ...}`.
+#[derive(Serialize, PartialEq, Eq, Clone, Debug)]
+#[serde(tag = "alert", rename_all = "snake_case")]
+pub enum Alert {
+    /// No update completed for `stalled_for_ms`: the instance may be dropped
+    /// from the cluster while it's still running.
+    DaemonStalled {
+        instance_id: Uuid,
+        stalled_for_ms: u64,
+    },
+    /// The instance stopped being the leader without draining.
+    LeadershipLost { instance_id: Uuid },
+    /// Fewer members than the quorum are listed, after the cluster had it.
+    QuorumLost {
+        instance_id: Uuid,
+        members: usize,
+        quorum: usize,
+    },
+}
+
+/// Where the [`Alerts`] are delivered. Called from the update daemon and the
+/// alerts' watchdog, so it must not block.
+pub trait AlertSink: Send + Sync + 'static {
+    fn alert(&self, alert: &Alert);
+}
+
+impl<F> AlertSink for F
+where
+    F: Fn(&Alert) + Send + Sync + 'static,
+{
+    fn alert(&self, alert: &Alert) {
+        self(alert)
+    }
+}
+
+#[derive(Default)]
+struct AlertState {
+    was_leader: bool,
+    had_quorum: bool,
+    stalled: bool,
+}
+
+/// Reports the critical conditions of the instance to `sink`, each once until
+/// it clears: the update daemon stalling, the leadership lost unexpectedly
+/// and, with [`Alerts::with_quorum`], the quorum lost. Registered with
+/// `Builder::with_alerts`. The stalls are also checked by a watchdog thread,
+/// every update interval, so a dead or hung daemon is reported too.
+pub struct Alerts {
+    sink: Box<dyn AlertSink>,
+    quorum: Option<usize>,
+    state: Mutex<AlertState>,
+}
+
+impl Alerts {
+    pub fn new(sink: impl AlertSink) -> Self {
+        Alerts {
+            sink: Box::new(sink),
+            quorum: None,
+            state: Mutex::new(AlertState::default()),
+        }
+    }
+
+    /// Alerts when fewer than `members` instances are listed.
+    pub fn with_quorum(mut self, members: usize) -> Self {
+        self.quorum = Some(members);
+        self
+    }
+
+    /// Starts the watchdog of the service's alerts, if registered. It stops
+    /// once the daemon is stopped or the service dropped.
+    pub(crate) fn watch<B, T>(service: &Arc<Instances<B, T>>)
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+        B: Backend<Registration<T>> + Send + Sync + 'static,
+    {
+        if service.extension::<Alerts>().is_none() {
+            return;
+        }
+        let service = service.downgrade();
+        thread::spawn(move || loop {
+            let interval = match service.upgrade() {
+                Some(service) if service.daemon.lock_or_recover().is_some() => {
+                    if let Some(alerts) = service.extension::<Alerts>() {
+                        let mut state = alerts.state.lock_or_recover();
+                        alerts.check_stalled(&service, &mut state);
+                    }
+                    service.settings().update_interval
+                }
+                _ => break,
+            };
+            thread::sleep(interval);
+        });
+    }
+
+    /// Alerts once no update completed for `STALLED_AFTER_INTERVALS`.
+    fn check_stalled<B, T>(&self, instances: &Instances<B, T>, state: &mut AlertState)
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+        B: Backend<Registration<T>> + Send + Sync + 'static,
+    {
+        let stalled_for = instances
+            .last_update_at()
+            .and_then(|at| SystemTime::now().duration_since(at).ok());
+        let limit = instances.settings().update_interval * STALLED_AFTER_INTERVALS;
+        match stalled_for {
+            Some(stalled_for) if stalled_for > limit => {
+                if !state.stalled {
+                    state.stalled = true;
+                    self.raise(Alert::DaemonStalled {
+                        instance_id: instances.instance_id,
+                        stalled_for_ms: stalled_for.as_millis() as u64,
+                    });
+                }
+            }
+            _ => state.stalled = false,
+        }
+    }
+
+    fn raise(&self, alert: Alert) {
+        warn!("Raising the alert {:?}.", alert);
+        self.sink.alert(&alert);
+    }
+}
+
+impl<B, T> Extension<B, T> for Alerts {
+    fn on_update(&self, instances: &Instances<B, T>, tick: &TickContext<T>)
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+        B: Backend<Registration<T>> + Send + Sync + 'static,
+    {
+        let instance_id = instances.instance_id;
+        let mut state = self.state.lock_or_recover();
+
+        // Extensions run even when the update failed, so a daemon whose
+        // updates keep failing or panicking is noticed right away, the
+        // watchdog noticing the daemons no longer ticking.
+        self.check_stalled(instances, &mut state);
+
+        let is_leader = tick.is_leader();
+        let draining = tick
+            .current_info()
+            .is_some_and(|info| info.status == InstanceStatus::Draining);
+        if state.was_leader && !is_leader && !draining {
+            self.raise(Alert::LeadershipLost { instance_id });
+        }
+        state.was_leader = is_leader;
+
+        if let Some(quorum) = self.quorum {
+            let members = tick.instances().len();
+            let has_quorum = members >= quorum;
+            if state.had_quorum && !has_quorum {
+                self.raise(Alert::QuorumLost {
+                    instance_id,
+                    members,
+                    quorum,
+                });
+            }
+            state.had_quorum = has_quorum;
+        }
+    }
+}
+
+/// Posts the alerts as JSON to a webhook, e.g. a Slack incoming webhook
+/// relayed by a local proxy. Only plain `http://` URLs are supported. Each
+/// alert is posted from a thread of its own, failures are logged.
+pub struct WebhookSink {
+    host: String,
+    path: String,
+}
+
+impl WebhookSink {
+    /// A sink posting to `url`, `http://host[:port][/path]`.
+    pub fn new(url: &str) -> io::Result<Self> {
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid webhook URL '{}'.", url),
+            )
+        };
+        let rest = url.strip_prefix("http://").ok_or_else(invalid)?;
+        let (host, path) = match rest.find('/') {
+            Some(slash) => (&rest[..slash], &rest[slash..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let host = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        Ok(WebhookSink {
+            host,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl AlertSink for WebhookSink {
+    fn alert(&self, alert: &Alert) {
+        let body = match serde_json::to_string(alert) {
+            Ok(body) => body,
+            Err(error) => return warn!("Error serializing the alert. Cause: {}", error),
+        };
+        let (host, path) = (self.host.clone(), self.path.clone());
+        thread::spawn(move || match post(&host, &path, &body) {
+            Ok(status) => info!("Alert posted to the webhook, answered {}.", status),
+            Err(error) => warn!("Error posting the alert to the webhook. Cause: {}", error),
+        });
+    }
+}
+
+/// Posts `body` and returns the status line of the response.
+fn post(host: &str, path: &str, body: &str) -> io::Result<String> {
+    let addr = host
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, host.to_string()))?;
+    let mut stream = TcpStream::connect_timeout(&addr, WEBHOOK_TIMEOUT)?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    )?;
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    Ok(status.trim_end().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::backends::MockBackend;
+    use crate::daemon::start_daemon;
+    use crate::models::{CommunicationErrorStrategy, LeaderStrategy};
+    use crate::tests::{mock_data_for, new_instance};
+
+    use super::*;
+
+    #[test]
+    fn should_alert_on_leadership_and_quorum_losses() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let (id, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let listings = [vec![id, other], vec![other, id], vec![id]];
+        let listed = Arc::new(Mutex::new(0));
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        let count = listed.clone();
+        backend.expect_list_active_instances().returning(move || {
+            let mut count = count.lock().unwrap();
+            *count += 1;
+            Ok(mock_data_for(listings[*count - 1].clone()))
+        });
+
+        let instance = new_instance(
+            id,
+            backend,
+            LeaderStrategy::Oldest,
+            CommunicationErrorStrategy::Error,
+        );
+        let raised = Arc::new(Mutex::new(vec![]));
+        let sink = raised.clone();
+        let alerts = Alerts::new(move |alert: &Alert| sink.lock().unwrap().push(alert.clone()))
+            .with_quorum(2);
+        for _ in 0..3 {
+            instance.update_instance_info().unwrap();
+            alerts.on_update(&instance, &instance.tick_context());
+        }
+
+        assert_eq!(
+            vec![
+                Alert::LeadershipLost { instance_id: id },
+                Alert::QuorumLost {
+                    instance_id: id,
+                    members: 1,
+                    quorum: 2
+                },
+            ],
+            *raised.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn should_alert_on_a_hung_daemon() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+        let listings = AtomicU32::new(0);
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend.expect_list_active_instances().returning(move || {
+            if listings.fetch_add(1, Ordering::SeqCst) > 0 {
+                thread::sleep(Duration::from_millis(500));
+            }
+            Ok(mock_data_for(vec![id]))
+        });
+
+        let mut instance = new_instance(
+            id,
+            backend,
+            LeaderStrategy::Oldest,
+            CommunicationErrorStrategy::Error,
+        );
+        instance.settings.get_mut().unwrap().update_interval = Duration::from_millis(20);
+        let raised = Arc::new(Mutex::new(vec![]));
+        let sink = raised.clone();
+        instance
+            .extensions
+            .register(Alerts::new(move |alert: &Alert| {
+                sink.lock().unwrap().push(alert.clone())
+            }));
+        let instance = Arc::new(instance);
+        *instance.daemon.lock().unwrap() = Some(start_daemon(&instance));
+        Alerts::watch(&instance);
+        thread::sleep(Duration::from_millis(300));
+
+        assert!(matches!(
+            raised.lock().unwrap().as_slice(),
+            [Alert::DaemonStalled { instance_id, .. }] if *instance_id == id
+        ));
+    }
+
+    #[test]
+    fn should_post_the_alerts_to_the_webhook() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hooks/alerts", listener.local_addr().unwrap());
+        let id = Uuid::new_v4();
+
+        WebhookSink::new(&url)
+            .unwrap()
+            .alert(&Alert::LeadershipLost { instance_id: id });
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = String::new();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let _ = stream.read_to_string(&mut request);
+        assert!(request.starts_with("POST /hooks/alerts HTTP/1.1\r\n"));
+        assert!(request.ends_with(&format!(
+            r#"{{"alert":"leadership_lost","instance_id":"{}"}}"#,
+            id
+        )));
+        assert!(WebhookSink::new("https://hooks.slack.com/services/x").is_err());
+    }
+}
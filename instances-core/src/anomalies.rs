@@ -0,0 +1,137 @@
+//! Detection of membership anomalies hinting at backend or network problems,
+//! see `Builder::with_anomaly_rules`.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The anomalies to look for. None by default.
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
+pub struct AnomalyRules {
+    membership_drop: Option<f64>,
+    leader_flapping: Option<(u32, Duration)>,
+}
+
+impl AnomalyRules {
+    /// Flags an update losing more than `fraction` of the members, e.g. 0.5
+    /// for more than half. A partition or a backend losing records look like
+    /// this, while instances usually leave a few at a time.
+    pub fn with_membership_drop(mut self, fraction: f64) -> Self {
+        self.membership_drop = Some(fraction);
+        self
+    }
+
+    /// Flags the leader changing more than `changes` times within `window`.
+    pub fn with_leader_flapping(mut self, changes: u32, window: Duration) -> Self {
+        self.leader_flapping = Some((changes, window));
+        self
+    }
+}
+
+/// An anomaly detected by the `AnomalyRules`, see
+/// `InstancesEvent::AnomalyDetected`.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum Anomaly {
+    /// The members went from `from` to `to` in one update.
+    MembershipDropped { from: usize, to: usize },
+    /// The leader changed `changes` times within `window`.
+    LeaderFlapping { changes: u32, window: Duration },
+}
+
+/// Applies the rules to the successive updates.
+pub(crate) struct AnomalyDetector {
+    rules: AnomalyRules,
+    members: Option<usize>,
+    leader_changes: VecDeque<Instant>,
+}
+
+impl AnomalyDetector {
+    pub(crate) fn new(rules: AnomalyRules) -> Self {
+        AnomalyDetector {
+            rules,
+            members: None,
+            leader_changes: VecDeque::new(),
+        }
+    }
+
+    /// Checks the rules against an update listing `members` instances.
+    pub(crate) fn observe(
+        &mut self,
+        members: usize,
+        leader_changed: bool,
+        now: Instant,
+    ) -> Vec<Anomaly> {
+        let mut anomalies = vec![];
+
+        if let (Some(fraction), Some(previous)) = (self.rules.membership_drop, self.members) {
+            let lost = previous.saturating_sub(members);
+            if previous > 0 && lost as f64 / previous as f64 > fraction {
+                anomalies.push(Anomaly::MembershipDropped {
+                    from: previous,
+                    to: members,
+                });
+            }
+        }
+        self.members = Some(members);
+
+        if let Some((changes, window)) = self.rules.leader_flapping {
+            while self
+                .leader_changes
+                .front()
+                .is_some_and(|at| now.duration_since(*at) > window)
+            {
+                self.leader_changes.pop_front();
+            }
+            if leader_changed {
+                self.leader_changes.push_back(now);
+                // Reported once, when the limit is first exceeded.
+                if self.leader_changes.len() == changes as usize + 1 {
+                    anomalies.push(Anomaly::LeaderFlapping {
+                        changes: changes + 1,
+                        window,
+                    });
+                }
+            }
+        }
+
+        anomalies
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_flag_losing_more_than_the_fraction_of_the_members() {
+        let mut detector = AnomalyDetector::new(AnomalyRules::default().with_membership_drop(0.5));
+        let now = Instant::now();
+
+        assert!(detector.observe(10, false, now).is_empty());
+        assert!(detector.observe(5, false, now).is_empty());
+        assert_eq!(
+            vec![Anomaly::MembershipDropped { from: 5, to: 2 }],
+            detector.observe(2, false, now)
+        );
+    }
+
+    #[test]
+    fn should_flag_the_leader_changing_too_often() {
+        let window = Duration::from_secs(60);
+        let mut detector =
+            AnomalyDetector::new(AnomalyRules::default().with_leader_flapping(2, window));
+        let start = Instant::now();
+
+        assert!(detector.observe(3, true, start).is_empty());
+        assert!(detector
+            .observe(3, true, start + Duration::from_secs(10))
+            .is_empty());
+        // The first change left the window.
+        assert!(detector
+            .observe(3, true, start + Duration::from_secs(65))
+            .is_empty());
+        assert_eq!(
+            vec![Anomaly::LeaderFlapping { changes: 3, window }],
+            detector.observe(3, true, start + Duration::from_secs(66))
+        );
+    }
+}
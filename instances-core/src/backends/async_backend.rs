@@ -0,0 +1,268 @@
+//! Backends written against async clients (Redis, DynamoDB, sqlx...).
+//!
+//! The update daemon runs the updates on a thread, where [`BlockOn`] drives
+//! the backend's futures with an [`Executor`]. With the `tokio` feature, the
+//! runtime's handle is one, and `Builder::build_async` runs the daemon as a
+//! task of the runtime, each update on its blocking pool:
+//!
+//! ```ignore
+//! let backend = BlockOn::new(RedisBackend::connect(url).await?, Handle::current());
+//! let instances = Builder::default()
+//!     .with_backend(backend)
+//!     .with_update_interval(interval)
+//!     .with_info_extractor(extractor)
+//!     .build_async();
+//! ```
+
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::backends::{Backend, Capabilities, ConnectionError, InstanceRecord};
+
+/// The async counterpart of [`Backend`], limited to the membership: wrap it
+/// in a [`BlockOn`] to use it.
+pub trait AsyncBackend<T>: Send + Sync
+where
+    T: Serialize + DeserializeOwned + Send,
+{
+    fn update_instance_info(
+        &self,
+        instance_id: Uuid,
+        data: T,
+    ) -> impl Future<Output = Result<(), ConnectionError>> + Send;
+
+    fn list_active_instances(
+        &self,
+    ) -> impl Future<Output = Result<Vec<InstanceRecord<T>>, ConnectionError>> + Send;
+
+    /// Removes the instance record, see `Backend::deregister_instance`.
+    fn deregister_instance(
+        &self,
+        _instance_id: Uuid,
+    ) -> impl Future<Output = Result<(), ConnectionError>> + Send {
+        std::future::ready(Ok(()))
+    }
+
+    fn identity(&self) -> String {
+        std::any::type_name::<Self>().to_string()
+    }
+
+    /// Only `deregister`, `heartbeat_timestamps`, `expiry` and
+    /// `designated_leaders` apply, the other capabilities need methods async
+    /// backends don't have yet.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+}
+
+/// Runs a future to completion on the calling thread.
+pub trait Executor: Send + Sync {
+    fn block_on<F: Future>(&self, future: F) -> F::Output;
+}
+
+/// Polls the future on the calling thread, parking it until woken. Enough for
+/// futures that don't need a runtime, e.g. ones completed by another thread.
+pub struct ThreadExecutor;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+impl Executor for ThreadExecutor {
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+}
+
+/// Blocks the calling thread, which must not be one of the runtime's workers,
+/// e.g. the update daemon's or a thread of the blocking pool.
+#[cfg(feature = "tokio")]
+impl Executor for tokio::runtime::Handle {
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        tokio::runtime::Handle::block_on(self, future)
+    }
+}
+
+/// A [`Backend`] running an [`AsyncBackend`] with `executor`.
+pub struct BlockOn<A, E> {
+    backend: A,
+    executor: E,
+}
+
+impl<A, E> BlockOn<A, E> {
+    pub fn new(backend: A, executor: E) -> Self {
+        BlockOn { backend, executor }
+    }
+}
+
+impl<T, A, E> Backend<T> for BlockOn<A, E>
+where
+    T: Serialize + DeserializeOwned + Send,
+    A: AsyncBackend<T>,
+    E: Executor,
+{
+    fn update_instance_info(&self, instance_id: Uuid, data: T) -> Result<(), ConnectionError> {
+        self.executor
+            .block_on(self.backend.update_instance_info(instance_id, data))
+    }
+
+    fn list_active_instances(&self) -> Result<Vec<InstanceRecord<T>>, ConnectionError> {
+        self.executor.block_on(self.backend.list_active_instances())
+    }
+
+    fn deregister_instance(&self, instance_id: Uuid) -> Result<(), ConnectionError> {
+        self.executor
+            .block_on(self.backend.deregister_instance(instance_id))
+    }
+
+    fn identity(&self) -> String {
+        self.backend.identity()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let capabilities = self.backend.capabilities();
+        Capabilities {
+            deregister: capabilities.deregister,
+            heartbeat_timestamps: capabilities.heartbeat_timestamps,
+            expiry: capabilities.expiry,
+            designated_leaders: capabilities.designated_leaders,
+            ..Capabilities::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+    use std::time::{Duration, SystemTime};
+
+    use crate::models::Registration;
+
+    use super::*;
+
+    /// Completes every call from another thread, as an async client would.
+    #[derive(Default)]
+    struct DeferredBackend {
+        records: Arc<Mutex<HashMap<Uuid, InstanceRecord<Registration<String>>>>>,
+    }
+
+    struct Deferred<R> {
+        result: Option<R>,
+        woken: Arc<AtomicBool>,
+    }
+
+    impl<R: Unpin> Future for Deferred<R> {
+        type Output = R;
+
+        fn poll(mut self: std::pin::Pin<&mut Self>, context: &mut Context<'_>) -> Poll<R> {
+            if self.woken.load(Ordering::SeqCst) {
+                return Poll::Ready(self.result.take().expect("Polled after completion."));
+            }
+            let (woken, waker) = (self.woken.clone(), context.waker().clone());
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(1));
+                woken.store(true, Ordering::SeqCst);
+                waker.wake();
+            });
+            Poll::Pending
+        }
+    }
+
+    fn deferred<R>(result: R) -> Deferred<R> {
+        Deferred {
+            result: Some(result),
+            woken: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    impl AsyncBackend<Registration<String>> for DeferredBackend {
+        fn update_instance_info(
+            &self,
+            instance_id: Uuid,
+            data: Registration<String>,
+        ) -> impl Future<Output = Result<(), ConnectionError>> + Send {
+            self.records
+                .lock()
+                .unwrap()
+                .entry(instance_id)
+                .and_modify(|r| r.data = data.clone())
+                .or_insert_with(|| InstanceRecord::new(instance_id, SystemTime::now(), data));
+            deferred(Ok(()))
+        }
+
+        fn list_active_instances(
+            &self,
+        ) -> impl Future<Output = Result<Vec<InstanceRecord<Registration<String>>>, ConnectionError>>
+               + Send {
+            deferred(Ok(self.records.lock().unwrap().values().cloned().collect()))
+        }
+
+        fn deregister_instance(
+            &self,
+            instance_id: Uuid,
+        ) -> impl Future<Output = Result<(), ConnectionError>> + Send {
+            self.records.lock().unwrap().remove(&instance_id);
+            deferred(Ok(()))
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                deregister: true,
+                counters: true,
+                ..Capabilities::default()
+            }
+        }
+    }
+
+    crate::backend_conformance!(BlockOn::new(DeferredBackend::default(), ThreadExecutor));
+
+    #[test]
+    fn should_only_advertise_the_capabilities_it_forwards() {
+        let backend = BlockOn::new(DeferredBackend::default(), ThreadExecutor);
+
+        let capabilities = Backend::<Registration<String>>::capabilities(&backend);
+
+        assert!(capabilities.deregister);
+        assert!(!capabilities.counters);
+    }
+
+    #[cfg(feature = "tokio")]
+    mod tokio_handle {
+        use std::sync::OnceLock;
+
+        use tokio::runtime::{self, Runtime};
+
+        use super::*;
+
+        fn runtime() -> &'static Runtime {
+            static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+            RUNTIME.get_or_init(|| runtime::Builder::new_current_thread().build().unwrap())
+        }
+
+        crate::backend_conformance!(BlockOn::new(
+            DeferredBackend::default(),
+            runtime().handle().clone()
+        ));
+    }
+}
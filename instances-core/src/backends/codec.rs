@@ -0,0 +1,247 @@
+//! Encoding of the registrations stored by the backends.
+//!
+//! Backends sharing a store with other instances (or other applications) read
+//! payloads they didn't write, so decoding never trusts the input: oversized
+//! payloads are rejected before parsing and a panicking `Deserialize`
+//! implementation is reported as an error instead of unwinding the daemon.
+
+use std::panic::{self, AssertUnwindSafe};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::backends::{ConnectionError, InstanceRecord};
+use crate::models::Registration;
+
+/// Largest payload, in bytes, `decode` and `decode_payload` accept.
+pub const MAX_DECODED_SIZE: usize = 4 * 1024 * 1024;
+
+pub fn encode<T>(registration: &Registration<T>) -> Result<Vec<u8>, ConnectionError>
+where
+    T: Serialize,
+{
+    serde_json::to_vec(registration).map_err(|e| ConnectionError::FailedToUpdate(e.to_string()))
+}
+
+/// Decodes a payload written by `encode`, possibly by another instance.
+pub fn decode<T>(bytes: &[u8]) -> Result<Registration<T>, ConnectionError>
+where
+    T: DeserializeOwned,
+{
+    decode_payload(bytes)
+}
+
+/// Decodes the data of a listed record, whatever the type the backend stores,
+/// with the same checks as `decode`. `None`, once reported, if it can't be:
+/// one peer's bad record mustn't fail the whole listing.
+pub fn decode_listed<T>(instance_id: Uuid, bytes: &[u8]) -> Option<T>
+where
+    T: DeserializeOwned,
+{
+    match decode_payload(bytes) {
+        Ok(data) => Some(data),
+        Err(error) => {
+            warn!(
+                "Ignoring the record of instance {}. Cause: {}",
+                instance_id, error
+            );
+            None
+        }
+    }
+}
+
+/// Decodes the records of an encoded listing, see
+/// `Backend::list_encoded_instances`, leaving out the ones that can't be.
+pub fn decode_records<T>(records: Vec<InstanceRecord<Vec<u8>>>) -> Vec<InstanceRecord<T>>
+where
+    T: DeserializeOwned,
+{
+    records
+        .into_iter()
+        .filter_map(|record| {
+            let data = decode_listed(record.id, &record.data)?;
+            Some(record.with_data(data))
+        })
+        .collect()
+}
+
+/// The envelope of a registration, without its data.
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    protocol: u32,
+}
+
+/// Reads the protocol a payload written by `encode` was written with,
+/// without decoding its data, whose shape may differ between protocols.
+pub fn decode_protocol(bytes: &[u8]) -> Result<u32, ConnectionError> {
+    decode_payload::<Envelope>(bytes).map(|envelope| envelope.protocol)
+}
+
+fn decode_payload<T>(bytes: &[u8]) -> Result<T, ConnectionError>
+where
+    T: DeserializeOwned,
+{
+    if bytes.len() > MAX_DECODED_SIZE {
+        return Err(ConnectionError::PayloadTooLarge(
+            bytes.len(),
+            MAX_DECODED_SIZE,
+        ));
+    }
+
+    match panic::catch_unwind(AssertUnwindSafe(|| serde_json::from_slice(bytes))) {
+        Ok(Ok(data)) => Ok(data),
+        Ok(Err(e)) => Err(ConnectionError::FailedToRetrieve(format!(
+            "Malformed instance payload. {}",
+            e
+        ))),
+        Err(_) => Err(ConnectionError::FailedToRetrieve(
+            "Decoding the instance payload panicked.".to_string(),
+        )),
+    }
+}
+
+/// The JSON merge patch (RFC 7396) turning `previous` into `current`, see
+/// `Backend::update_instance_delta`. `None` when a patch can't express the
+/// change: merge patches remove the members set to `null`.
+pub fn diff(previous: &Value, current: &Value) -> Option<Value> {
+    let (Value::Object(previous), Value::Object(current)) = (previous, current) else {
+        return literal(current);
+    };
+
+    let mut patch = Map::new();
+    for (key, value) in current {
+        match previous.get(key) {
+            Some(old) if old == value => {}
+            Some(old) => {
+                patch.insert(key.clone(), diff(old, value)?);
+            }
+            None => {
+                patch.insert(key.clone(), literal(value)?);
+            }
+        }
+    }
+    for key in previous.keys().filter(|key| !current.contains_key(*key)) {
+        patch.insert(key.clone(), Value::Null);
+    }
+    Some(Value::Object(patch))
+}
+
+/// `value` as a patch replacing the target, unless it has `null` members.
+fn literal(value: &Value) -> Option<Value> {
+    match value {
+        Value::Null => None,
+        Value::Object(members) => {
+            for member in members.values() {
+                literal(member)?;
+            }
+            Some(value.clone())
+        }
+        _ => Some(value.clone()),
+    }
+}
+
+/// Applies a JSON merge patch (RFC 7396) made by `diff`, for the backends
+/// implementing `Backend::update_instance_delta` on top of a plain store.
+pub fn apply_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(members) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                members.remove(key);
+            } else {
+                apply_patch(members.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Deserializer};
+
+    use crate::models::{InstanceKind, InstanceStatus};
+    use crate::PROTOCOL_VERSION;
+
+    use super::*;
+
+    #[test]
+    fn should_decode_what_was_encoded() {
+        let registration = Registration {
+            status: InstanceStatus::Active,
+            kind: InstanceKind::Member,
+            maintenance: false,
+            leader_claim: Some(3),
+            departure: None,
+            cohort: None,
+            cut_over: None,
+            leader_term: None,
+            view_hash: None,
+            version: None,
+            protocol: PROTOCOL_VERSION,
+            data: "data".to_string(),
+        };
+
+        let bytes = encode(&registration).unwrap();
+
+        assert_eq!(registration, decode::<String>(&bytes).unwrap());
+    }
+
+    #[test]
+    fn should_patch_the_previous_value_into_the_current_one() {
+        let previous = serde_json::json!({"a": 1, "b": {"c": [1, 2], "d": "x"}, "e": true});
+        let current = serde_json::json!({"a": 1, "b": {"c": [1, 3], "f": {"g": 2}}, "h": 3});
+
+        let patch = diff(&previous, &current).unwrap();
+        let mut patched = previous.clone();
+        apply_patch(&mut patched, &patch);
+
+        assert_eq!(current, patched);
+        assert_eq!(
+            serde_json::json!({"b": {"c": [1, 3], "d": null, "f": {"g": 2}}, "e": null, "h": 3}),
+            patch
+        );
+        assert_eq!(None, diff(&previous, &serde_json::json!({"a": null})));
+    }
+
+    #[derive(Debug)]
+    struct Panicking;
+
+    impl<'de> Deserialize<'de> for Panicking {
+        fn deserialize<D: Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+            panic!("boom");
+        }
+    }
+
+    #[test]
+    fn should_reject_malformed_payloads() {
+        let nested = "[".repeat(10_000);
+        let oversized = vec![b' '; MAX_DECODED_SIZE + 1];
+
+        assert!(decode::<String>(b"{\"status\":").is_err());
+        assert!(decode::<String>(nested.as_bytes()).is_err());
+        assert!(matches!(
+            decode::<String>(&oversized),
+            Err(ConnectionError::PayloadTooLarge(_, _))
+        ));
+        assert!(decode::<Panicking>(br#"{"status":"Active","data":1}"#).is_err());
+    }
+
+    #[test]
+    fn should_leave_out_the_records_that_cannot_be_decoded() {
+        let id = Uuid::new_v4();
+
+        assert_eq!(Some(3), decode_listed::<u32>(id, b"3"));
+        assert_eq!(None, decode_listed::<u32>(id, b"\"3\""));
+        assert!(decode_listed::<Panicking>(id, b"1").is_none());
+    }
+}
@@ -0,0 +1,414 @@
+//! Checks of the `Backend` contract, meant to be run through
+//! [`backend_conformance!`](crate::backend_conformance) by backend crates.
+//!
+//! Every check gets a fresh backend and panics with a description of the
+//! broken expectation.
+
+use std::thread;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::backends::{codec, Backend, BatchOperation, BatchResult, ConnectionError};
+use crate::models::{InstanceKind, InstanceStatus, Registration};
+use crate::PROTOCOL_VERSION;
+
+/// Generates a `backend_conformance` test module running every check against
+/// the backend built by `$backend`, evaluated once per test.
+///
+/// ```ignore
+/// instances_rs::backend_conformance!(RedisBackend::connect("redis://localhost").unwrap());
+/// ```
+///
+/// Backends whose records expire (`Capabilities::expiry`) also pass how long
+/// an unrefreshed record may stay listed, enabling the expiry check:
+///
+/// ```ignore
+/// instances_rs::backend_conformance!(redis_backend(), expiry = Duration::from_secs(2));
+/// ```
+///
+/// The checks write real records: point the backend at a disposable store,
+/// e.g. a container started by the test.
+#[macro_export]
+macro_rules! backend_conformance {
+    ($backend:expr, expiry = $expiry:expr) => {
+        $crate::backend_conformance!($backend);
+
+        #[cfg(test)]
+        mod backend_conformance_expiry {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[test]
+            fn should_expire_unrefreshed_records() {
+                $crate::backends::conformance::expires_unrefreshed_records(&$backend, $expiry);
+            }
+        }
+    };
+    ($backend:expr) => {
+        #[cfg(test)]
+        mod backend_conformance {
+            #[allow(unused_imports)]
+            use super::*;
+
+            #[test]
+            fn should_list_the_registered_instance() {
+                $crate::backends::conformance::lists_registered_instances(&$backend);
+            }
+
+            #[test]
+            fn should_keep_the_registration_time() {
+                $crate::backends::conformance::keeps_registration_time(&$backend);
+            }
+
+            #[test]
+            fn should_advance_the_heartbeat() {
+                $crate::backends::conformance::advances_heartbeat(&$backend);
+            }
+
+            #[test]
+            fn should_remove_deregistered_instances() {
+                $crate::backends::conformance::removes_deregistered_instances(&$backend);
+            }
+
+            #[test]
+            fn should_reject_instances_over_the_limit() {
+                $crate::backends::conformance::rejects_instances_over_the_limit(&$backend);
+            }
+
+            #[test]
+            fn should_admit_concurrent_registrations_up_to_the_limit() {
+                $crate::backends::conformance::admits_concurrent_registrations_up_to_the_limit(
+                    &$backend,
+                );
+            }
+
+            #[test]
+            fn should_compare_and_set_counters() {
+                $crate::backends::conformance::compares_and_sets_counters(&$backend);
+            }
+
+            #[test]
+            fn should_apply_deltas() {
+                $crate::backends::conformance::applies_deltas(&$backend);
+            }
+
+            #[test]
+            fn should_hold_leases_exclusively() {
+                $crate::backends::conformance::holds_leases_exclusively(&$backend);
+            }
+
+            #[test]
+            fn should_execute_batches_in_order() {
+                $crate::backends::conformance::executes_batches_in_order(&$backend);
+            }
+
+            #[test]
+            fn should_pass_the_health_check() {
+                $crate::backends::conformance::passes_health_check(&$backend);
+            }
+        }
+    };
+}
+
+fn registration(data: &str) -> Registration<String> {
+    Registration {
+        status: InstanceStatus::Active,
+        kind: InstanceKind::Member,
+        maintenance: false,
+        leader_claim: None,
+        departure: None,
+        cohort: None,
+        cut_over: None,
+        leader_term: None,
+        view_hash: None,
+        version: None,
+        protocol: PROTOCOL_VERSION,
+        data: data.to_string(),
+    }
+}
+
+/// An update is listed with the data written, and a second update replaces it.
+pub fn lists_registered_instances<B: Backend<Registration<String>>>(backend: &B) {
+    let id = Uuid::new_v4();
+
+    backend
+        .update_instance_info(id, registration("first"))
+        .unwrap();
+    backend
+        .update_instance_info(id, registration("second"))
+        .unwrap();
+
+    let listed: Vec<_> = backend
+        .list_active_instances()
+        .unwrap()
+        .into_iter()
+        .filter(|i| i.id == id)
+        .collect();
+    assert_eq!(1, listed.len(), "the instance must be listed exactly once");
+    assert_eq!(registration("second"), listed[0].data);
+}
+
+/// `registered_at` is set by the first update only, the leader election
+/// relies on it.
+pub fn keeps_registration_time<B: Backend<Registration<String>>>(backend: &B) {
+    let id = Uuid::new_v4();
+    let registered_at = |backend: &B| {
+        backend
+            .list_active_instances()
+            .unwrap()
+            .into_iter()
+            .find(|i| i.id == id)
+            .expect("the registered instance must be listed")
+            .registered_at
+    };
+
+    backend
+        .update_instance_info(id, registration("data"))
+        .unwrap();
+    let first = registered_at(backend);
+    thread::sleep(Duration::from_millis(5));
+    backend
+        .update_instance_info(id, registration("data"))
+        .unwrap();
+
+    assert_eq!(first, registered_at(backend));
+}
+
+/// Backends advertising `Capabilities::heartbeat_timestamps` list every
+/// record with `heartbeat_at`, moved forward by each update: the
+/// `StalenessPolicy` only drops records carrying one.
+pub fn advances_heartbeat<B: Backend<Registration<String>>>(backend: &B) {
+    if !backend.capabilities().heartbeat_timestamps {
+        return;
+    }
+    let id = Uuid::new_v4();
+    let heartbeat_at = |backend: &B| {
+        backend
+            .list_active_instances()
+            .unwrap()
+            .into_iter()
+            .find(|i| i.id == id)
+            .expect("the registered instance must be listed")
+            .heartbeat_at
+            .expect("the listed record must carry its heartbeat")
+    };
+
+    backend
+        .update_instance_info(id, registration("data"))
+        .unwrap();
+    let first = heartbeat_at(backend);
+    thread::sleep(Duration::from_millis(5));
+    backend
+        .update_instance_info(id, registration("data"))
+        .unwrap();
+
+    assert!(
+        first < heartbeat_at(backend),
+        "an update must advance the heartbeat"
+    );
+}
+
+/// Backends advertising `Capabilities::deregister` stop listing the instance.
+pub fn removes_deregistered_instances<B: Backend<Registration<String>>>(backend: &B) {
+    let id = Uuid::new_v4();
+
+    backend
+        .update_instance_info(id, registration("data"))
+        .unwrap();
+    backend.deregister_instance(id).unwrap();
+
+    if backend.capabilities().deregister {
+        let listed = backend.list_active_instances().unwrap();
+        assert!(
+            listed.iter().all(|i| i.id != id),
+            "a deregistered instance must not be listed"
+        );
+    }
+}
+
+/// `register_instance_if_room` fails with `ClusterFull` once the limit is
+/// reached, and still lets registered instances update.
+pub fn rejects_instances_over_the_limit<B: Backend<Registration<String>>>(backend: &B) {
+    let members = backend.list_active_instances().unwrap().len();
+    let first = Uuid::new_v4();
+    let second = Uuid::new_v4();
+
+    backend
+        .register_instance_if_room(first, registration("data"), members + 1)
+        .unwrap();
+    backend
+        .register_instance_if_room(first, registration("data"), members + 1)
+        .unwrap();
+
+    assert_eq!(
+        Err(ConnectionError::ClusterFull(members + 1)),
+        backend.register_instance_if_room(second, registration("data"), members + 1)
+    );
+}
+
+/// Instances racing to register never exceed the limit on backends advertising
+/// `Capabilities::compare_and_swap`. On the others only one of them has to
+/// get in.
+pub fn admits_concurrent_registrations_up_to_the_limit<B>(backend: &B)
+where
+    B: Backend<Registration<String>> + Sync,
+{
+    const RACING: usize = 8;
+    let members = backend.list_active_instances().unwrap().len();
+
+    let admitted = thread::scope(|scope| {
+        let racers: Vec<_> = (0..RACING)
+            .map(|_| {
+                scope.spawn(|| {
+                    backend.register_instance_if_room(
+                        Uuid::new_v4(),
+                        registration("data"),
+                        members + 1,
+                    )
+                })
+            })
+            .collect();
+        racers
+            .into_iter()
+            .filter_map(|racer| racer.join().unwrap().ok())
+            .count()
+    });
+
+    assert!(admitted >= 1, "one of the racing instances must get in");
+    if backend.capabilities().compare_and_swap {
+        assert_eq!(1, admitted, "the limit must hold under concurrency");
+    }
+}
+
+/// A record not refreshed for longer than `expiry` stops being listed.
+pub fn expires_unrefreshed_records<B: Backend<Registration<String>>>(
+    backend: &B,
+    expiry: Duration,
+) {
+    let id = Uuid::new_v4();
+
+    backend
+        .update_instance_info(id, registration("data"))
+        .unwrap();
+    thread::sleep(expiry + Duration::from_millis(100));
+
+    let listed = backend.list_active_instances().unwrap();
+    assert!(
+        listed.iter().all(|i| i.id != id),
+        "an unrefreshed record must expire"
+    );
+}
+
+/// Backends advertising `Capabilities::counters` only set a counter still
+/// holding the expected value.
+pub fn compares_and_sets_counters<B: Backend<Registration<String>>>(backend: &B) {
+    if !backend.capabilities().counters {
+        return;
+    }
+    let name = format!("conformance/{}", Uuid::new_v4());
+
+    assert_eq!(0, backend.load_counter(&name).unwrap());
+    assert!(backend.compare_and_set_counter(&name, 0, 1).unwrap());
+    assert!(
+        !backend.compare_and_set_counter(&name, 0, 2).unwrap(),
+        "a counter that changed must not be set"
+    );
+    assert_eq!(1, backend.load_counter(&name).unwrap());
+}
+
+/// Backends advertising `Capabilities::leases` give a lease to one holder at
+/// a time, until released, and keep listing the records alongside.
+pub fn holds_leases_exclusively<B: Backend<Registration<String>>>(backend: &B) {
+    if !backend.capabilities().leases {
+        return;
+    }
+    let name = format!("conformance-{}", Uuid::new_v4());
+    let ttl = Duration::from_secs(10);
+    let id = Uuid::new_v4();
+    backend
+        .update_instance_info(id, registration("data"))
+        .unwrap();
+
+    assert!(backend.try_acquire_lease(&name, "first", ttl).unwrap());
+    assert!(
+        !backend.try_acquire_lease(&name, "second", ttl).unwrap(),
+        "a held lease must not be taken by another holder"
+    );
+    assert!(
+        backend.try_acquire_lease(&name, "first", ttl).unwrap(),
+        "the holder must renew its lease"
+    );
+    backend.release_lease(&name, "second").unwrap();
+    assert!(
+        !backend.try_acquire_lease(&name, "second", ttl).unwrap(),
+        "only the holder may release a lease"
+    );
+    backend.release_lease(&name, "first").unwrap();
+    assert!(backend.try_acquire_lease(&name, "second", ttl).unwrap());
+
+    let listed = backend.list_active_instances().unwrap();
+    assert_eq!(
+        vec![id],
+        listed.iter().map(|i| i.id).collect::<Vec<_>>(),
+        "the leases must not be listed nor break the listing"
+    );
+}
+
+/// Backends advertising `Capabilities::deltas` patch the stored record, and
+/// refuse to patch a missing one.
+pub fn applies_deltas<B: Backend<Registration<String>>>(backend: &B) {
+    if !backend.capabilities().deltas {
+        return;
+    }
+    let id = Uuid::new_v4();
+    let first = serde_json::to_value(registration("first")).unwrap();
+    let second = serde_json::to_value(registration("second")).unwrap();
+    let patch = codec::diff(&first, &second).unwrap();
+
+    assert!(
+        backend.update_instance_delta(id, &patch).is_err(),
+        "a delta must not create a missing record"
+    );
+    backend
+        .update_instance_info(id, registration("first"))
+        .unwrap();
+    backend.update_instance_delta(id, &patch).unwrap();
+
+    let listed = backend.list_active_instances().unwrap();
+    let instance = listed.iter().find(|i| i.id == id);
+    assert_eq!(
+        Some(&registration("second")),
+        instance.map(|i| &i.data),
+        "the delta must be applied to the stored record"
+    );
+}
+
+/// A batch returns one result per operation, in order, and its operations
+/// see the effects of the earlier ones.
+pub fn executes_batches_in_order<B: Backend<Registration<String>>>(backend: &B) {
+    let id = Uuid::new_v4();
+
+    let results = backend.execute_batch(vec![
+        BatchOperation::Update(id, registration("data")),
+        BatchOperation::List,
+    ]);
+
+    assert_eq!(
+        2,
+        results.len(),
+        "a batch must return one result per operation"
+    );
+    assert_eq!(Ok(BatchResult::Done), results[0]);
+    match &results[1] {
+        Ok(BatchResult::Listed(listed)) => assert!(
+            listed.iter().any(|i| i.id == id),
+            "a batched listing must see the batch's earlier update"
+        ),
+        other => panic!("expected a listing, got {:?}", other),
+    }
+}
+
+pub fn passes_health_check<B: Backend<Registration<String>>>(backend: &B) {
+    backend.health_check().unwrap();
+}
@@ -0,0 +1,243 @@
+//! A backend keeping the records in the process' memory, to run several
+//! instances in one process, e.g. in integration tests.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::backends::{codec, Backend, Capabilities, ConnectionError, InstanceRecord};
+use crate::sync::RwLockExt;
+use crate::time::{Clock, SystemClock};
+
+/// Registration time, time of the last update, and serialized data.
+type Records = HashMap<Uuid, (SystemTime, SystemTime, Vec<u8>)>;
+
+/// Stores each instance's serialized data with its registration and last
+/// update times, the latter listed as the heartbeat. Clones
+/// share the records, and the leader override, so the instances built with
+/// clones of a backend see each other as members of the same cluster.
+///
+/// Nothing expires: an instance that stops without deregistering is dropped
+/// by the `StalenessPolicy` once its heartbeat is old enough.
+#[derive(Clone)]
+pub struct MemoryBackend {
+    records: Arc<RwLock<Records>>,
+    leader_override: Arc<RwLock<Option<Uuid>>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for MemoryBackend {
+    fn default() -> Self {
+        MemoryBackend {
+            records: Arc::default(),
+            leader_override: Arc::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        MemoryBackend::default()
+    }
+
+    /// The clock timing the registrations and heartbeats, the system's by
+    /// default. Shared by the clones made afterwards.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    fn write<T: Serialize>(
+        &self,
+        records: &mut Records,
+        instance_id: Uuid,
+        data: &T,
+    ) -> Result<(), ConnectionError> {
+        let data =
+            serde_json::to_vec(data).map_err(|e| ConnectionError::FailedToUpdate(e.to_string()))?;
+        let now = SystemTime::from(self.clock.now());
+        records
+            .entry(instance_id)
+            .and_modify(|(_, heartbeat_at, stored)| {
+                *heartbeat_at = now;
+                *stored = data.clone();
+            })
+            .or_insert_with(|| (now, now, data));
+        Ok(())
+    }
+}
+
+impl<T> Backend<T> for MemoryBackend
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn update_instance_info(&self, instance_id: Uuid, data: T) -> Result<(), ConnectionError> {
+        self.write(&mut self.records.write_or_recover(), instance_id, &data)
+    }
+
+    fn list_active_instances(&self) -> Result<Vec<InstanceRecord<T>>, ConnectionError> {
+        Ok(codec::decode_records(Backend::<T>::list_encoded_instances(
+            self,
+        )?))
+    }
+
+    fn list_encoded_instances(&self) -> Result<Vec<InstanceRecord<Vec<u8>>>, ConnectionError> {
+        Ok(self
+            .records
+            .read_or_recover()
+            .iter()
+            .map(|(id, (registered_at, heartbeat_at, data))| {
+                InstanceRecord::new(*id, *registered_at, data.clone())
+                    .with_heartbeat_at(*heartbeat_at)
+            })
+            .collect())
+    }
+
+    fn deregister_instance(&self, instance_id: Uuid) -> Result<(), ConnectionError> {
+        self.records.write_or_recover().remove(&instance_id);
+        Ok(())
+    }
+
+    /// Checks the room and writes under the same lock, so racing instances
+    /// never exceed `max_members`.
+    fn register_instance_if_room(
+        &self,
+        instance_id: Uuid,
+        data: T,
+        max_members: usize,
+    ) -> Result<(), ConnectionError> {
+        let mut records = self.records.write_or_recover();
+        let members = records.keys().filter(|id| **id != instance_id).count();
+        if members >= max_members {
+            return Err(ConnectionError::ClusterFull(max_members));
+        }
+        self.write(&mut records, instance_id, &data)
+    }
+
+    fn identity(&self) -> String {
+        "Memory".to_string()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            deregister: true,
+            compare_and_swap: true,
+            heartbeat_timestamps: true,
+            overrides: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn load_leader_override(&self) -> Result<Option<Uuid>, ConnectionError> {
+        Ok(*self.leader_override.read_or_recover())
+    }
+
+    fn store_leader_override(&self, leader: Option<Uuid>) -> Result<(), ConnectionError> {
+        *self.leader_override.write_or_recover() = leader;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    use crate::config::Builder;
+    use crate::ids::FixedId;
+    use crate::models::{LeaderStrategy, Registration};
+    use crate::staleness::FixedTtl;
+    use crate::time::HeartbeatTime;
+    use crate::Instances;
+
+    use super::*;
+
+    /// A clock a millisecond further at every reading.
+    struct Ticking(AtomicU64);
+
+    impl Clock for Ticking {
+        fn now(&self) -> HeartbeatTime {
+            HeartbeatTime::from_millis(self.0.fetch_add(1, Ordering::SeqCst))
+        }
+    }
+
+    /// A clock stopped at the given millisecond.
+    struct Stopped(u64);
+
+    impl Clock for Stopped {
+        fn now(&self) -> HeartbeatTime {
+            HeartbeatTime::from_millis(self.0)
+        }
+    }
+
+    crate::backend_conformance!(MemoryBackend::new());
+
+    #[test]
+    fn should_drop_instances_whose_heartbeat_is_stale() {
+        let backend = MemoryBackend::new().with_clock(Stopped(1_000));
+        let build = |id: Uuid, now: u64| -> Arc<Instances<MemoryBackend, String>> {
+            Builder::default()
+                .with_backend(backend.clone())
+                .with_id_generator(FixedId(id))
+                .with_clock(Stopped(now))
+                .with_staleness_policy(FixedTtl(Duration::from_secs(10)))
+                .with_update_interval(Duration::from_secs(1))
+                .with_info_extractor(move || id.to_string())
+                .build_without_daemon()
+                .unwrap()
+        };
+        let (gone, fresh) = (Uuid::new_v4(), Uuid::new_v4());
+
+        build(gone, 1_000).update_instance_info().unwrap();
+        let instance = build(fresh, 5_000);
+        instance.update_instance_info().unwrap();
+        assert_eq!(2, instance.list_active_instances().len());
+
+        let instance = build(fresh, 60_000);
+        instance.update_instance_info().unwrap();
+        let listed: Vec<Uuid> = instance
+            .list_active_instances()
+            .iter()
+            .map(|i| i.id)
+            .collect();
+        assert_eq!(vec![fresh], listed);
+    }
+
+    #[test]
+    fn should_share_the_records_between_clones() {
+        let now = HeartbeatTime::now().as_millis();
+        let backend = MemoryBackend::new().with_clock(Ticking(AtomicU64::new(now)));
+        let build = |id: Uuid| -> Arc<Instances<MemoryBackend, String>> {
+            Builder::default()
+                .with_backend(backend.clone())
+                .with_id_generator(FixedId(id))
+                .with_leader_strategy(LeaderStrategy::Oldest)
+                .with_update_interval(Duration::from_secs(1))
+                .with_info_extractor(move || id.to_string())
+                .build_without_daemon()
+                .unwrap()
+        };
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let instances = [build(first), build(second)];
+
+        for instance in &instances {
+            instance.update_instance_info().unwrap();
+        }
+        for instance in &instances {
+            instance.update_instance_info().unwrap();
+        }
+
+        for instance in &instances {
+            assert_eq!(2, instance.list_active_instances().len());
+            assert_eq!(Some(first), instance.current_leader().map(|l| l.id));
+        }
+        let listed: Vec<InstanceRecord<Registration<String>>> =
+            backend.list_active_instances().unwrap();
+        assert_eq!(2, listed.len());
+    }
+}
@@ -0,0 +1,941 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crossbeam_channel::Receiver;
+#[cfg(test)]
+use mockall::mock;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::time::HeartbeatTime;
+
+pub mod async_backend;
+pub mod codec;
+pub mod conformance;
+pub mod memory;
+pub mod recording;
+pub mod replica;
+#[cfg(feature = "backend-ssdp")]
+pub mod ssdp;
+
+/// Storage shared by the instances to publish and list their registrations.
+///
+/// This is the interface third-party backend crates implement. It only grows
+/// with methods having a default implementation, so a backend written against
+/// an older version keeps compiling. Run [`backend_conformance!`](crate::backend_conformance)
+/// in the backend's tests to check it honours the contract.
+pub trait Backend<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn update_instance_info(&self, instance_id: Uuid, data: T) -> Result<(), ConnectionError>;
+    fn list_active_instances(&self) -> Result<Vec<InstanceRecord<T>>, ConnectionError>;
+
+    /// Lists the records with their payloads as stored, for the instances to
+    /// decode them (see [`codec`]): the peers writing a protocol older than
+    /// `Builder::with_min_protocol` are then left out without decoding their
+    /// data, and the records that can't be decoded are left out instead of
+    /// failing the listing. Backends storing serialized payloads implement it,
+    /// and `list_active_instances` with [`codec::decode_records`] over it.
+    fn list_encoded_instances(&self) -> Result<Vec<InstanceRecord<Vec<u8>>>, ConnectionError> {
+        Err(ConnectionError::Unsupported("encoded listings"))
+    }
+
+    /// Removes the instance record. Backends that can't delete records may keep
+    /// the default and let the record expire.
+    fn deregister_instance(&self, _instance_id: Uuid) -> Result<(), ConnectionError> {
+        Ok(())
+    }
+
+    /// Registers the instance only while the cluster has fewer than `max_members`
+    /// instances, failing with `ConnectionError::ClusterFull` otherwise.
+    ///
+    /// The default implementation checks the listing before writing, so two
+    /// instances joining at the same time may both get in. Backends supporting
+    /// compare-and-swap should override it to make the check atomic.
+    fn register_instance_if_room(
+        &self,
+        instance_id: Uuid,
+        data: T,
+        max_members: usize,
+    ) -> Result<(), ConnectionError> {
+        let members = self
+            .list_active_instances()?
+            .iter()
+            .filter(|i| i.id != instance_id)
+            .count();
+
+        if members >= max_members {
+            return Err(ConnectionError::ClusterFull(max_members));
+        }
+
+        self.update_instance_info(instance_id, data)
+    }
+
+    /// Largest serialized payload, in bytes, the backend can store for a single
+    /// instance (e.g. 400KB for a DynamoDB item).
+    fn max_payload_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Shortest update interval the backend copes with, e.g. to bound DynamoDB
+    /// write costs or MySQL connection churn. Shorter configured intervals are
+    /// raised to it.
+    fn min_update_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Identifies the backend in errors and logs, ideally with its endpoint,
+    /// e.g. `Redis(redis://cache-1:6379)`. Defaults to the type name.
+    fn identity(&self) -> String {
+        std::any::type_name::<Self>().to_string()
+    }
+
+    /// What the backend supports beyond the required methods.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Checks the backend is reachable. Defaults to a listing.
+    fn health_check(&self) -> Result<(), ConnectionError> {
+        self.list_active_instances().map(|_| ())
+    }
+
+    /// Takes the lease `name` for `holder` during `ttl`, unless another holder
+    /// has it. Renews it if `holder` already has it. Returns whether `holder`
+    /// holds the lease. Backends supporting it advertise `Capabilities::leases`.
+    fn try_acquire_lease(
+        &self,
+        _name: &str,
+        _holder: &str,
+        _ttl: Duration,
+    ) -> Result<bool, ConnectionError> {
+        Err(ConnectionError::Unsupported("leases"))
+    }
+
+    /// Gives the lease `name` up, if `holder` has it.
+    fn release_lease(&self, _name: &str, _holder: &str) -> Result<(), ConnectionError> {
+        Err(ConnectionError::Unsupported("leases"))
+    }
+
+    /// Deletes the records whose last heartbeat is more than `max_age` old,
+    /// as per the backend's clock if the heartbeats are taken from it,
+    /// returning how many were deleted. Run by the leader's janitor, see
+    /// `Builder::with_janitor`. Backends supporting it advertise
+    /// `Capabilities::pruning`.
+    fn prune_expired(&self, _max_age: Duration) -> Result<usize, ConnectionError> {
+        Err(ConnectionError::Unsupported("pruning"))
+    }
+
+    /// Pushes the id of every instance that joined, left or changed status, so
+    /// the members refresh right away instead of at their next update (see
+    /// `Builder::with_push_updates`). Plain heartbeats must not be pushed.
+    /// Dropping the sender falls back to polling. Backends supporting it
+    /// advertise `Capabilities::push`.
+    fn watch_changes(&self) -> Result<Receiver<Uuid>, ConnectionError> {
+        Err(ConnectionError::Unsupported("change notifications"))
+    }
+
+    /// Applies `patch`, a JSON merge patch (RFC 7396) made by
+    /// [`codec::diff`], to the instance's record and refreshes its heartbeat
+    /// like `update_instance_info`, to write only what changed since the
+    /// previous update (see `Builder::with_delta_updates`). Fails if the
+    /// record is missing, making the instance write it in full. Backends
+    /// supporting it advertise `Capabilities::deltas`.
+    fn update_instance_delta(
+        &self,
+        _instance_id: Uuid,
+        _patch: &Value,
+    ) -> Result<(), ConnectionError> {
+        Err(ConnectionError::Unsupported("deltas"))
+    }
+
+    /// The value of the counter `name`, 0 if it was never set. Backends
+    /// supporting it advertise `Capabilities::counters`.
+    fn load_counter(&self, _name: &str) -> Result<u64, ConnectionError> {
+        Err(ConnectionError::Unsupported("counters"))
+    }
+
+    /// Sets the counter `name` to `new` if it's still `current`, atomically.
+    /// Returns whether it was set.
+    fn compare_and_set_counter(
+        &self,
+        _name: &str,
+        _current: u64,
+        _new: u64,
+    ) -> Result<bool, ConnectionError> {
+        Err(ConnectionError::Unsupported("counters"))
+    }
+
+    /// The instance an operator named the leader, if any, see
+    /// `Builder::with_leader_override`. Backends supporting it advertise
+    /// `Capabilities::overrides`.
+    fn load_leader_override(&self) -> Result<Option<Uuid>, ConnectionError> {
+        Err(ConnectionError::Unsupported("leader overrides"))
+    }
+
+    /// Names `leader` the leader of every instance honouring the overrides,
+    /// until cleared with `None`. Meant for operators, e.g. from a CLI during
+    /// an incident.
+    fn store_leader_override(&self, _leader: Option<Uuid>) -> Result<(), ConnectionError> {
+        Err(ConnectionError::Unsupported("leader overrides"))
+    }
+
+    /// Runs the operations in order, returning one result per operation. The
+    /// default runs them one by one; backends able to send them together (a
+    /// Redis pipeline, a SQL transaction...) override it and
+    /// advertise `Capabilities::batching`.
+    fn execute_batch(
+        &self,
+        operations: Vec<BatchOperation<T>>,
+    ) -> Vec<Result<BatchResult<T>, ConnectionError>> {
+        operations
+            .into_iter()
+            .map(|operation| execute_operation(self, operation))
+            .collect()
+    }
+}
+
+impl<T, B> Backend<T> for Box<B>
+where
+    T: Serialize + DeserializeOwned,
+    B: Backend<T> + ?Sized,
+{
+    fn update_instance_info(&self, instance_id: Uuid, data: T) -> Result<(), ConnectionError> {
+        (**self).update_instance_info(instance_id, data)
+    }
+
+    fn list_active_instances(&self) -> Result<Vec<InstanceRecord<T>>, ConnectionError> {
+        (**self).list_active_instances()
+    }
+
+    fn list_encoded_instances(&self) -> Result<Vec<InstanceRecord<Vec<u8>>>, ConnectionError> {
+        Backend::<T>::list_encoded_instances(&**self)
+    }
+
+    fn deregister_instance(&self, instance_id: Uuid) -> Result<(), ConnectionError> {
+        (**self).deregister_instance(instance_id)
+    }
+
+    fn register_instance_if_room(
+        &self,
+        instance_id: Uuid,
+        data: T,
+        max_members: usize,
+    ) -> Result<(), ConnectionError> {
+        (**self).register_instance_if_room(instance_id, data, max_members)
+    }
+
+    fn max_payload_size(&self) -> Option<usize> {
+        (**self).max_payload_size()
+    }
+
+    fn min_update_interval(&self) -> Option<Duration> {
+        (**self).min_update_interval()
+    }
+
+    fn identity(&self) -> String {
+        (**self).identity()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        (**self).capabilities()
+    }
+
+    fn health_check(&self) -> Result<(), ConnectionError> {
+        (**self).health_check()
+    }
+
+    fn try_acquire_lease(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, ConnectionError> {
+        (**self).try_acquire_lease(name, holder, ttl)
+    }
+
+    fn release_lease(&self, name: &str, holder: &str) -> Result<(), ConnectionError> {
+        (**self).release_lease(name, holder)
+    }
+
+    fn prune_expired(&self, max_age: Duration) -> Result<usize, ConnectionError> {
+        (**self).prune_expired(max_age)
+    }
+
+    fn watch_changes(&self) -> Result<Receiver<Uuid>, ConnectionError> {
+        (**self).watch_changes()
+    }
+
+    fn update_instance_delta(
+        &self,
+        instance_id: Uuid,
+        patch: &Value,
+    ) -> Result<(), ConnectionError> {
+        (**self).update_instance_delta(instance_id, patch)
+    }
+
+    fn load_counter(&self, name: &str) -> Result<u64, ConnectionError> {
+        (**self).load_counter(name)
+    }
+
+    fn compare_and_set_counter(
+        &self,
+        name: &str,
+        current: u64,
+        new: u64,
+    ) -> Result<bool, ConnectionError> {
+        (**self).compare_and_set_counter(name, current, new)
+    }
+
+    fn load_leader_override(&self) -> Result<Option<Uuid>, ConnectionError> {
+        (**self).load_leader_override()
+    }
+
+    fn store_leader_override(&self, leader: Option<Uuid>) -> Result<(), ConnectionError> {
+        (**self).store_leader_override(leader)
+    }
+
+    fn execute_batch(
+        &self,
+        operations: Vec<BatchOperation<T>>,
+    ) -> Vec<Result<BatchResult<T>, ConnectionError>> {
+        (**self).execute_batch(operations)
+    }
+}
+
+// The encoded listing isn't mocked: the mocked backends list through
+// `list_active_instances`.
+#[cfg(test)]
+mock! {
+    pub Backend<T: Serialize + DeserializeOwned + 'static> {}
+
+    impl<T: Serialize + DeserializeOwned + 'static> Backend<T> for Backend<T> {
+        fn update_instance_info(&self, instance_id: Uuid, data: T) -> Result<(), ConnectionError>;
+        fn list_active_instances(&self) -> Result<Vec<InstanceRecord<T>>, ConnectionError>;
+        fn deregister_instance(&self, instance_id: Uuid) -> Result<(), ConnectionError>;
+        fn register_instance_if_room(
+            &self,
+            instance_id: Uuid,
+            data: T,
+            max_members: usize,
+        ) -> Result<(), ConnectionError>;
+        fn max_payload_size(&self) -> Option<usize>;
+        fn min_update_interval(&self) -> Option<Duration>;
+        fn identity(&self) -> String;
+        fn capabilities(&self) -> Capabilities;
+        fn health_check(&self) -> Result<(), ConnectionError>;
+        fn try_acquire_lease(
+            &self,
+            name: &str,
+            holder: &str,
+            ttl: Duration,
+        ) -> Result<bool, ConnectionError>;
+        fn release_lease(&self, name: &str, holder: &str) -> Result<(), ConnectionError>;
+        fn prune_expired(&self, max_age: Duration) -> Result<usize, ConnectionError>;
+        fn watch_changes(&self) -> Result<Receiver<Uuid>, ConnectionError>;
+        fn update_instance_delta(
+            &self,
+            instance_id: Uuid,
+            patch: &Value,
+        ) -> Result<(), ConnectionError>;
+        fn load_counter(&self, name: &str) -> Result<u64, ConnectionError>;
+        fn compare_and_set_counter(
+            &self,
+            name: &str,
+            current: u64,
+            new: u64,
+        ) -> Result<bool, ConnectionError>;
+        fn load_leader_override(&self) -> Result<Option<Uuid>, ConnectionError>;
+        fn store_leader_override(&self, leader: Option<Uuid>) -> Result<(), ConnectionError>;
+        fn execute_batch(
+            &self,
+            operations: Vec<BatchOperation<T>>,
+        ) -> Vec<Result<BatchResult<T>, ConnectionError>>;
+    }
+}
+
+/// Runs a single operation of a batch through the matching `Backend` method,
+/// e.g. for backends pipelining only some kinds of operations.
+pub fn execute_operation<T, B>(
+    backend: &B,
+    operation: BatchOperation<T>,
+) -> Result<BatchResult<T>, ConnectionError>
+where
+    T: Serialize + DeserializeOwned,
+    B: Backend<T> + ?Sized,
+{
+    match operation {
+        BatchOperation::Update(instance_id, data) => backend
+            .update_instance_info(instance_id, data)
+            .map(|_| BatchResult::Done),
+        BatchOperation::List => backend.list_active_instances().map(BatchResult::Listed),
+        BatchOperation::AcquireLease { name, holder, ttl } => backend
+            .try_acquire_lease(&name, &holder, ttl)
+            .map(BatchResult::Lease),
+        BatchOperation::ReleaseLease { name, holder } => backend
+            .release_lease(&name, &holder)
+            .map(|_| BatchResult::Done),
+        BatchOperation::LoadCounter(name) => backend.load_counter(&name).map(BatchResult::Counter),
+        BatchOperation::CompareAndSetCounter { name, current, new } => backend
+            .compare_and_set_counter(&name, current, new)
+            .map(BatchResult::CounterSet),
+    }
+}
+
+/// Optional features of a backend, see `Backend::capabilities`.
+///
+/// New capabilities are added as fields defaulting to `false`, so build it from
+/// `Capabilities::default()`:
+///
+/// ```
+/// # use instances_core::backends::Capabilities;
+/// let mut capabilities = Capabilities::default();
+/// capabilities.deregister = true;
+/// ```
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// `deregister_instance` removes the record right away.
+    pub deregister: bool,
+    /// `register_instance_if_room` checks and writes atomically (compare-and-swap).
+    pub compare_and_swap: bool,
+    /// Listed records carry `heartbeat_at`.
+    pub heartbeat_timestamps: bool,
+    /// Records expire by themselves when not updated.
+    pub expiry: bool,
+    /// `try_acquire_lease` and `release_lease` are implemented.
+    pub leases: bool,
+    /// `load_counter` and `compare_and_set_counter` are implemented.
+    pub counters: bool,
+    /// `watch_changes` is implemented.
+    pub push: bool,
+    /// `prune_expired` is implemented.
+    pub pruning: bool,
+    /// `update_instance_delta` is implemented.
+    pub deltas: bool,
+    /// `execute_batch` sends the operations together, saving round trips.
+    pub batching: bool,
+    /// `load_leader_override` and `store_leader_override` are implemented.
+    pub overrides: bool,
+    /// Listed records carry `designated_leader`, see `LeaderStrategy::Backend`.
+    pub designated_leaders: bool,
+}
+
+/// An instance as stored by the backend. Fields are added over time: build it
+/// with `InstanceRecord::new` and the `with_*` methods.
+#[derive(PartialEq, Clone, Debug)]
+#[non_exhaustive]
+pub struct InstanceRecord<T> {
+    pub id: Uuid,
+    /// When the instance first registered, used to elect the leader.
+    pub registered_at: HeartbeatTime,
+    /// When the backend last stored a heartbeat from the instance, if it keeps
+    /// track of it. Used by the `StalenessPolicy` to drop dead instances.
+    pub heartbeat_at: Option<HeartbeatTime>,
+    /// Latency to the instance as observed by the backend, e.g. from the
+    /// region or the address the record was written from.
+    pub latency: Option<Duration>,
+    /// The backend designates the instance as the leader, e.g. because it
+    /// holds a lock. Only read with `LeaderStrategy::Backend`.
+    pub designated_leader: bool,
+    pub data: T,
+    /// Backend specific metadata, e.g. a DynamoDB item version or the TTL left on
+    /// a Redis key. Exposed as is in `InstanceInfo` to help debugging.
+    pub extensions: HashMap<String, Value>,
+}
+
+impl<T> InstanceRecord<T> {
+    pub fn new(id: Uuid, registered_at: impl Into<HeartbeatTime>, data: T) -> Self {
+        InstanceRecord {
+            id,
+            registered_at: registered_at.into(),
+            heartbeat_at: None,
+            latency: None,
+            designated_leader: false,
+            data,
+            extensions: HashMap::new(),
+        }
+    }
+
+    pub fn with_heartbeat_at(mut self, heartbeat_at: impl Into<HeartbeatTime>) -> Self {
+        self.heartbeat_at = Some(heartbeat_at.into());
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = Some(latency);
+        self
+    }
+
+    pub fn with_designated_leader(mut self, designated: bool) -> Self {
+        self.designated_leader = designated;
+        self
+    }
+
+    pub fn with_extension(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.extensions.insert(key.into(), value.into());
+        self
+    }
+
+    /// The record with `data` in place of its data, e.g. once decoded.
+    pub fn with_data<U>(self, data: U) -> InstanceRecord<U> {
+        InstanceRecord {
+            id: self.id,
+            registered_at: self.registered_at,
+            heartbeat_at: self.heartbeat_at,
+            latency: self.latency,
+            designated_leader: self.designated_leader,
+            data,
+            extensions: self.extensions,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BackendType {
+    Memory,
+    #[cfg(feature = "backend-mysql")]
+    MySQL,
+    #[cfg(feature = "backend-dynamodb")]
+    DynamoDB,
+    #[cfg(feature = "backend-redis")]
+    Redis,
+    #[cfg(feature = "backend-postgres")]
+    PostgreSQL,
+    #[cfg(feature = "backend-ssdp")]
+    Ssdp,
+}
+
+/// The backends this build supports, per the enabled `backend-*` features, in
+/// the order of `BackendType`.
+pub fn available() -> &'static [BackendType] {
+    AVAILABLE
+}
+
+const AVAILABLE: &[BackendType] = &[
+    BackendType::Memory,
+    #[cfg(feature = "backend-mysql")]
+    BackendType::MySQL,
+    #[cfg(feature = "backend-dynamodb")]
+    BackendType::DynamoDB,
+    #[cfg(feature = "backend-redis")]
+    BackendType::Redis,
+    #[cfg(feature = "backend-postgres")]
+    BackendType::PostgreSQL,
+    #[cfg(feature = "backend-ssdp")]
+    BackendType::Ssdp,
+];
+
+#[derive(Error, PartialEq, Debug)]
+pub enum BackendError {
+    /// `value` names no backend of this build. `missing_feature` is the cargo
+    /// feature compiling in the backend it names, if it names a known one.
+    #[error(r#"Backend implementation '{value}' not found.{} The available options are: {}{}."#, feature_hint(.missing_feature), names(.available), url_hint(.available))]
+    BackendNotFound {
+        value: String,
+        available: Vec<BackendType>,
+        missing_feature: Option<&'static str>,
+    },
+}
+
+impl BackendError {
+    /// `name`, the whole `value` or its URL scheme, names no compiled-in
+    /// backend.
+    fn not_found(value: &str, name: &str) -> Self {
+        BackendError::BackendNotFound {
+            value: value.to_owned(),
+            available: available().to_vec(),
+            missing_feature: feature_of(name),
+        }
+    }
+}
+
+fn feature_hint(feature: &Option<&'static str>) -> String {
+    match feature {
+        Some(feature) => format!(
+            r#" It requires the "{}" feature, not enabled in this build."#,
+            feature
+        ),
+        None => String::new(),
+    }
+}
+
+fn names(backends: &[BackendType]) -> String {
+    let names: Vec<String> = backends.iter().map(|b| b.to_string()).collect();
+    names.join(", ")
+}
+
+/// An example URL for the first of `backends` connecting to a server, none
+/// of them may take one.
+fn url_hint(backends: &[BackendType]) -> String {
+    backends
+        .iter()
+        .find_map(example_url)
+        .map(|url| format!(", or a URL such as {}", url))
+        .unwrap_or_default()
+}
+
+fn example_url(backend: &BackendType) -> Option<&'static str> {
+    match backend {
+        BackendType::Memory => None,
+        #[cfg(feature = "backend-mysql")]
+        BackendType::MySQL => Some("mysql://host:3306/db"),
+        #[cfg(feature = "backend-dynamodb")]
+        BackendType::DynamoDB => Some("dynamodb://table"),
+        #[cfg(feature = "backend-redis")]
+        BackendType::Redis => Some("redis://host:6379"),
+        #[cfg(feature = "backend-postgres")]
+        BackendType::PostgreSQL => Some("postgres://host/db"),
+        #[cfg(feature = "backend-ssdp")]
+        BackendType::Ssdp => None,
+    }
+}
+
+/// The feature compiling in the backend named `name`, one of the aliases
+/// `BackendType::from_str` accepts, whether it's enabled or not.
+fn feature_of(name: &str) -> Option<&'static str> {
+    match name.trim().to_lowercase().as_ref() {
+        "mysql" | "mariadb" => Some("backend-mysql"),
+        "dynamodb" | "dynamo" | "ddb" => Some("backend-dynamodb"),
+        "redis" | "rediss" => Some("backend-redis"),
+        "postgresql" | "postgres" | "pg" => Some("backend-postgres"),
+        "ssdp" => Some("backend-ssdp"),
+        _ => None,
+    }
+}
+
+/// Errors of the backend operations. Variants are added over time, so matches
+/// need a wildcard arm.
+#[derive(Error, PartialEq, Clone, Debug)]
+#[non_exhaustive]
+pub enum ConnectionError {
+    #[error(r#"Failed to update instance info. Cause: {0}"#)]
+    FailedToUpdate(String),
+    #[error(r#"Failed to retrieve instances info. Cause: {0}"#)]
+    FailedToRetrieve(String),
+    #[error(r#"Failed to deregister instance. Cause: {0}"#)]
+    FailedToDeregister(String),
+    #[error(r#"Instance payload of {0} bytes exceeds the limit of {1} bytes."#)]
+    PayloadTooLarge(usize, usize),
+    #[error(r#"The cluster already has the maximum of {0} members."#)]
+    ClusterFull(usize),
+    #[error(r#"Operation '{1}' on backend '{0}' failed. {2}"#)]
+    Failed(String, Operation, Box<ConnectionError>),
+    #[error(r#"The backend doesn't support {0}."#)]
+    Unsupported(&'static str),
+}
+
+impl ConnectionError {
+    /// The error reported by the backend, without the context it was wrapped in.
+    pub fn root_cause(&self) -> &ConnectionError {
+        match self {
+            ConnectionError::Failed(_, _, cause) => cause.root_cause(),
+            error => error,
+        }
+    }
+}
+
+/// Backend operation that failed, see `ConnectionError::Failed`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Operation {
+    Update,
+    Register,
+    List,
+    Deregister,
+    Counter,
+    Prune,
+    Override,
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Update => f.write_str("update"),
+            Operation::Register => f.write_str("register"),
+            Operation::List => f.write_str("list"),
+            Operation::Deregister => f.write_str("deregister"),
+            Operation::Counter => f.write_str("counter"),
+            Operation::Prune => f.write_str("prune"),
+            Operation::Override => f.write_str("override"),
+        }
+    }
+}
+
+/// An operation of a batch, see `Backend::execute_batch`.
+#[derive(PartialEq, Clone, Debug)]
+pub enum BatchOperation<T> {
+    Update(Uuid, T),
+    List,
+    AcquireLease {
+        name: String,
+        holder: String,
+        ttl: Duration,
+    },
+    ReleaseLease {
+        name: String,
+        holder: String,
+    },
+    LoadCounter(String),
+    CompareAndSetCounter {
+        name: String,
+        current: u64,
+        new: u64,
+    },
+}
+
+/// What a `BatchOperation` returned.
+#[derive(PartialEq, Clone, Debug)]
+pub enum BatchResult<T> {
+    /// An update or a lease release went through.
+    Done,
+    Listed(Vec<InstanceRecord<T>>),
+    /// Whether the lease is held.
+    Lease(bool),
+    /// The value of a counter.
+    Counter(u64),
+    /// Whether a compare-and-set updated the counter.
+    CounterSet(bool),
+}
+
+/// What the backend answered, as seen by `Builder::with_backend_tap`.
+#[derive(Debug)]
+pub enum BackendResponse<'a, T> {
+    /// A write or a deregistration went through.
+    Done,
+    Listed(&'a [InstanceRecord<T>]),
+    /// The value of a counter.
+    Counter(u64),
+    /// Whether a compare-and-set updated the counter.
+    CounterSet(bool),
+    /// The leader override, `None` when cleared.
+    Override(Option<Uuid>),
+}
+
+impl Display for BackendType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendType::Memory => f.write_str("Memory"),
+            #[cfg(feature = "backend-mysql")]
+            BackendType::MySQL => f.write_str("MySQL"),
+            #[cfg(feature = "backend-dynamodb")]
+            BackendType::DynamoDB => f.write_str("DynamoDB"),
+            #[cfg(feature = "backend-redis")]
+            BackendType::Redis => f.write_str("Redis"),
+            #[cfg(feature = "backend-postgres")]
+            BackendType::PostgreSQL => f.write_str("PostgreSQL"),
+            #[cfg(feature = "backend-ssdp")]
+            BackendType::Ssdp => f.write_str("SSDP"),
+        }
+    }
+}
+
+impl FromStr for BackendType {
+    type Err = BackendError;
+
+    /// Parses the name of a backend, ignoring the case, or one of its aliases
+    /// (`mem`, `mariadb`, `dynamo`...).
+    fn from_str(s: &str) -> Result<BackendType, BackendError> {
+        match s.trim().to_lowercase().as_ref() {
+            "memory" | "mem" | "in-memory" => Ok(BackendType::Memory),
+            #[cfg(feature = "backend-mysql")]
+            "mysql" | "mariadb" => Ok(BackendType::MySQL),
+            #[cfg(feature = "backend-dynamodb")]
+            "dynamodb" | "dynamo" | "ddb" => Ok(BackendType::DynamoDB),
+            #[cfg(feature = "backend-redis")]
+            "redis" | "rediss" => Ok(BackendType::Redis),
+            #[cfg(feature = "backend-postgres")]
+            "postgresql" | "postgres" | "pg" => Ok(BackendType::PostgreSQL),
+            #[cfg(feature = "backend-ssdp")]
+            "ssdp" => Ok(BackendType::Ssdp),
+            _ => Err(BackendError::not_found(s, s)),
+        }
+    }
+}
+
+/// A backend and, when given as a URL, where to reach it. Parsed from a single
+/// setting, e.g. an environment variable holding either `memory` or
+/// `redis://cache-1:6379`: the URL scheme names the backend, aliases
+/// included (`mariadb://`, `rediss://`...).
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct BackendConfig {
+    pub backend_type: BackendType,
+    /// The URL the backend type was inferred from, to connect with.
+    pub url: Option<String>,
+}
+
+impl FromStr for BackendConfig {
+    type Err = BackendError;
+
+    fn from_str(s: &str) -> Result<BackendConfig, BackendError> {
+        let s = s.trim();
+        match s.split_once("://") {
+            Some((scheme, _)) => Ok(BackendConfig {
+                backend_type: scheme
+                    .parse()
+                    .map_err(|_| BackendError::not_found(s, scheme))?,
+                url: Some(s.to_owned()),
+            }),
+            None => Ok(BackendConfig {
+                backend_type: s.parse()?,
+                url: None,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::SystemTime;
+
+    use super::*;
+
+    struct ListOnlyBackend {
+        members: Vec<Uuid>,
+        updated: Mutex<Vec<Uuid>>,
+    }
+
+    impl Backend<String> for ListOnlyBackend {
+        fn update_instance_info(
+            &self,
+            instance_id: Uuid,
+            _: String,
+        ) -> Result<(), ConnectionError> {
+            self.updated.lock().unwrap().push(instance_id);
+            Ok(())
+        }
+
+        fn list_active_instances(&self) -> Result<Vec<InstanceRecord<String>>, ConnectionError> {
+            Ok(self
+                .members
+                .iter()
+                .map(|id| InstanceRecord::new(*id, SystemTime::now(), "data".to_string()))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn should_register_while_there_is_room() {
+        let member = Uuid::new_v4();
+        let backend = ListOnlyBackend {
+            members: vec![member],
+            updated: Mutex::new(vec![]),
+        };
+
+        let id = Uuid::new_v4();
+        backend
+            .register_instance_if_room(id, "data".to_string(), 2)
+            .unwrap();
+        backend
+            .register_instance_if_room(member, "data".to_string(), 1)
+            .unwrap();
+
+        assert_eq!(vec![id, member], *backend.updated.lock().unwrap());
+    }
+
+    #[test]
+    fn should_refuse_registering_into_a_full_cluster() {
+        let backend = ListOnlyBackend {
+            members: vec![Uuid::new_v4(), Uuid::new_v4()],
+            updated: Mutex::new(vec![]),
+        };
+
+        assert_eq!(
+            Err(ConnectionError::ClusterFull(2)),
+            backend.register_instance_if_room(Uuid::new_v4(), "data".to_string(), 2)
+        );
+        assert!(backend.updated.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn should_parse_backend_aliases_and_urls() {
+        assert_eq!(Ok(BackendType::Memory), " In-Memory ".parse());
+        assert_eq!(
+            Ok(BackendConfig {
+                backend_type: BackendType::Memory,
+                url: None
+            }),
+            "MEM".parse()
+        );
+        assert_eq!(
+            Err(BackendError::BackendNotFound {
+                value: "etcd://host:2379".to_string(),
+                available: available().to_vec(),
+                missing_feature: None,
+            }),
+            "etcd://host:2379".parse::<BackendConfig>()
+        );
+        #[cfg(feature = "backend-redis")]
+        assert_eq!(
+            Ok(BackendConfig {
+                backend_type: BackendType::Redis,
+                url: Some("rediss://cache-1:6379/0".to_string())
+            }),
+            "rediss://cache-1:6379/0".parse()
+        );
+    }
+
+    #[test]
+    fn should_parse_the_name_of_every_available_backend() {
+        let error = "etcd".parse::<BackendType>().unwrap_err().to_string();
+
+        assert_eq!(Some(&BackendType::Memory), available().first());
+        for backend in available() {
+            assert_eq!(Ok(*backend), backend.to_string().parse());
+            assert!(error.contains(&backend.to_string()));
+        }
+        #[cfg(feature = "backend-ssdp")]
+        assert!(available().contains(&BackendType::Ssdp));
+    }
+
+    #[test]
+    fn should_hint_at_the_feature_of_a_known_backend() {
+        let error = BackendError::BackendNotFound {
+            value: "mariadb://db-1".to_string(),
+            available: vec![BackendType::Memory],
+            missing_feature: feature_of("MariaDB"),
+        };
+
+        assert_eq!(
+            r#"Backend implementation 'mariadb://db-1' not found. It requires the "backend-mysql" feature, not enabled in this build. The available options are: Memory."#,
+            error.to_string()
+        );
+        #[cfg(feature = "backend-redis")]
+        assert_eq!(
+            r#"Backend implementation 'etcd' not found. The available options are: Memory, Redis, or a URL such as redis://host:6379."#,
+            BackendError::BackendNotFound {
+                value: "etcd".to_string(),
+                available: vec![BackendType::Memory, BackendType::Redis],
+                missing_feature: None,
+            }
+            .to_string()
+        );
+        assert_eq!(Some("backend-ssdp"), feature_of("SSDP"));
+        assert_eq!(None, feature_of("etcd"));
+    }
+
+    #[test]
+    fn should_describe_the_failed_operation() {
+        let error = ConnectionError::Failed(
+            "Redis(redis://cache-1:6379)".to_string(),
+            Operation::List,
+            Box::new(ConnectionError::FailedToRetrieve("timeout".to_string())),
+        );
+
+        assert_eq!(
+            "Operation 'list' on backend 'Redis(redis://cache-1:6379)' failed. Failed to retrieve instances info. Cause: timeout",
+            error.to_string()
+        );
+        assert_eq!(
+            &ConnectionError::FailedToRetrieve("timeout".to_string()),
+            error.root_cause()
+        );
+    }
+}
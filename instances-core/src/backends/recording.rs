@@ -0,0 +1,353 @@
+//! Recording the calls made to a backend and replaying them, to reproduce a
+//! cluster's behaviour (e.g. leader flaps seen in production) in a test.
+//!
+//! The recording is a JSON document per line, one per call, in the order the
+//! calls were made. Only the calls shaping the membership are recorded: the
+//! updates, registrations, listings and deregistrations.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::backends::{Backend, Capabilities, ConnectionError, InstanceRecord};
+use crate::sync::MutexExt;
+use crate::time::HeartbeatTime;
+
+/// A listed record, with its data as written.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+struct RecordedInstance {
+    id: Uuid,
+    registered_at: HeartbeatTime,
+    heartbeat_at: Option<HeartbeatTime>,
+    latency: Option<Duration>,
+    #[serde(default)]
+    designated_leader: bool,
+    data: Value,
+    extensions: HashMap<String, Value>,
+}
+
+/// A call and its result, the error as its message.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+#[serde(tag = "call", rename_all = "snake_case")]
+enum Call {
+    Update {
+        instance_id: Uuid,
+        data: Value,
+        error: Option<String>,
+    },
+    Register {
+        instance_id: Uuid,
+        data: Value,
+        max_members: usize,
+        error: Option<String>,
+    },
+    List {
+        instances: Vec<RecordedInstance>,
+        error: Option<String>,
+    },
+    Deregister {
+        instance_id: Uuid,
+        error: Option<String>,
+    },
+}
+
+/// Passes every call through to `backend`, appending it with its result to a
+/// recording that a [`ReplayBackend`] plays back.
+pub struct RecordingBackend<B> {
+    backend: B,
+    recording: Mutex<LineWriter<File>>,
+}
+
+impl<B> RecordingBackend<B> {
+    /// Records the calls to `backend` in the file at `path`, replacing it.
+    pub fn new(backend: B, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(RecordingBackend {
+            backend,
+            recording: Mutex::new(LineWriter::new(File::create(path)?)),
+        })
+    }
+
+    fn record(&self, call: Call) {
+        let line = match serde_json::to_string(&call) {
+            Ok(line) => line,
+            Err(error) => return warn!("Error serializing the backend call. Cause: {}", error),
+        };
+        if let Err(error) = writeln!(self.recording.lock_or_recover(), "{}", line) {
+            warn!("Error recording the backend call. Cause: {}", error);
+        }
+    }
+}
+
+fn error_of<R>(result: &Result<R, ConnectionError>) -> Option<String> {
+    result.as_ref().err().map(|error| error.to_string())
+}
+
+impl<T, B> Backend<T> for RecordingBackend<B>
+where
+    T: Serialize + DeserializeOwned,
+    B: Backend<T>,
+{
+    fn update_instance_info(&self, instance_id: Uuid, data: T) -> Result<(), ConnectionError> {
+        let recorded = serde_json::to_value(&data).unwrap_or(Value::Null);
+        let result = self.backend.update_instance_info(instance_id, data);
+        self.record(Call::Update {
+            instance_id,
+            data: recorded,
+            error: error_of(&result),
+        });
+        result
+    }
+
+    fn list_active_instances(&self) -> Result<Vec<InstanceRecord<T>>, ConnectionError> {
+        let result = self.backend.list_active_instances();
+        let instances = result.iter().flatten().map(|i| RecordedInstance {
+            id: i.id,
+            registered_at: i.registered_at,
+            heartbeat_at: i.heartbeat_at,
+            latency: i.latency,
+            designated_leader: i.designated_leader,
+            data: serde_json::to_value(&i.data).unwrap_or(Value::Null),
+            extensions: i.extensions.clone(),
+        });
+        self.record(Call::List {
+            instances: instances.collect(),
+            error: error_of(&result),
+        });
+        result
+    }
+
+    fn deregister_instance(&self, instance_id: Uuid) -> Result<(), ConnectionError> {
+        let result = self.backend.deregister_instance(instance_id);
+        self.record(Call::Deregister {
+            instance_id,
+            error: error_of(&result),
+        });
+        result
+    }
+
+    fn register_instance_if_room(
+        &self,
+        instance_id: Uuid,
+        data: T,
+        max_members: usize,
+    ) -> Result<(), ConnectionError> {
+        let recorded = serde_json::to_value(&data).unwrap_or(Value::Null);
+        let result = self
+            .backend
+            .register_instance_if_room(instance_id, data, max_members);
+        self.record(Call::Register {
+            instance_id,
+            data: recorded,
+            max_members,
+            error: error_of(&result),
+        });
+        result
+    }
+
+    fn max_payload_size(&self) -> Option<usize> {
+        self.backend.max_payload_size()
+    }
+
+    fn min_update_interval(&self) -> Option<Duration> {
+        self.backend.min_update_interval()
+    }
+
+    fn identity(&self) -> String {
+        format!("Recording({})", self.backend.identity())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        // The calls that aren't recorded couldn't be replayed.
+        Capabilities {
+            deregister: self.backend.capabilities().deregister,
+            compare_and_swap: self.backend.capabilities().compare_and_swap,
+            heartbeat_timestamps: self.backend.capabilities().heartbeat_timestamps,
+            expiry: self.backend.capabilities().expiry,
+            designated_leaders: self.backend.capabilities().designated_leaders,
+            ..Capabilities::default()
+        }
+    }
+}
+
+/// Answers the calls with the results of a recording made by a
+/// [`RecordingBackend`], in the recorded order, so an instance running against
+/// it goes through the recorded listings again. Pair it with a `Clock`
+/// replaying the recorded times when the staleness of the records matters.
+///
+/// The writes aren't checked against the recording, they only replay their
+/// result. Once a kind of call has no recorded result left, it fails.
+pub struct ReplayBackend {
+    calls: Mutex<HashMap<&'static str, VecDeque<Call>>>,
+}
+
+impl ReplayBackend {
+    /// Loads the recording at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut calls: HashMap<_, VecDeque<_>> = HashMap::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let call: Call = serde_json::from_str(&line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            calls.entry(kind(&call)).or_default().push_back(call);
+        }
+        Ok(ReplayBackend {
+            calls: Mutex::new(calls),
+        })
+    }
+
+    fn next(&self, kind: &'static str) -> Option<Call> {
+        self.calls.lock_or_recover().get_mut(kind)?.pop_front()
+    }
+}
+
+fn kind(call: &Call) -> &'static str {
+    match call {
+        Call::Update { .. } => "update",
+        Call::Register { .. } => "register",
+        Call::List { .. } => "list",
+        Call::Deregister { .. } => "deregister",
+    }
+}
+
+fn exhausted(kind: &str) -> String {
+    format!("The recording has no {} call left.", kind)
+}
+
+impl<T> Backend<T> for ReplayBackend
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn update_instance_info(&self, _instance_id: Uuid, _data: T) -> Result<(), ConnectionError> {
+        match self.next("update") {
+            Some(Call::Update { error: None, .. }) => Ok(()),
+            Some(Call::Update {
+                error: Some(error), ..
+            }) => Err(ConnectionError::FailedToUpdate(error)),
+            _ => Err(ConnectionError::FailedToUpdate(exhausted("update"))),
+        }
+    }
+
+    fn list_active_instances(&self) -> Result<Vec<InstanceRecord<T>>, ConnectionError> {
+        match self.next("list") {
+            Some(Call::List {
+                error: None,
+                instances,
+            }) => instances
+                .into_iter()
+                .map(|i| {
+                    let data = serde_json::from_value(i.data)
+                        .map_err(|e| ConnectionError::FailedToRetrieve(e.to_string()))?;
+                    let mut record = InstanceRecord::new(i.id, i.registered_at, data)
+                        .with_designated_leader(i.designated_leader);
+                    record.heartbeat_at = i.heartbeat_at;
+                    record.latency = i.latency;
+                    record.extensions = i.extensions;
+                    Ok(record)
+                })
+                .collect(),
+            Some(Call::List {
+                error: Some(error), ..
+            }) => Err(ConnectionError::FailedToRetrieve(error)),
+            _ => Err(ConnectionError::FailedToRetrieve(exhausted("list"))),
+        }
+    }
+
+    fn deregister_instance(&self, _instance_id: Uuid) -> Result<(), ConnectionError> {
+        match self.next("deregister") {
+            Some(Call::Deregister { error: None, .. }) => Ok(()),
+            Some(Call::Deregister {
+                error: Some(error), ..
+            }) => Err(ConnectionError::FailedToDeregister(error)),
+            _ => Err(ConnectionError::FailedToDeregister(exhausted("deregister"))),
+        }
+    }
+
+    fn register_instance_if_room(
+        &self,
+        _instance_id: Uuid,
+        _data: T,
+        max_members: usize,
+    ) -> Result<(), ConnectionError> {
+        match self.next("register") {
+            Some(Call::Register { error: None, .. }) => Ok(()),
+            Some(Call::Register { error: Some(_), .. }) => {
+                Err(ConnectionError::ClusterFull(max_members))
+            }
+            _ => Err(ConnectionError::FailedToUpdate(exhausted("register"))),
+        }
+    }
+
+    fn identity(&self) -> String {
+        "Replay".to_string()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            deregister: true,
+            designated_leaders: true,
+            ..Capabilities::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::sync::Arc;
+
+    use crate::config::Builder;
+    use crate::ids::FixedId;
+    use crate::models::{LeaderStrategy, Registration};
+    use crate::testing::{SimulatedBackend, SimulatedData};
+    use crate::Instances;
+
+    use super::*;
+
+    #[test]
+    fn should_replay_the_recorded_listings() {
+        let path = env::temp_dir().join(format!("instances-rs-{}.jsonl", Uuid::new_v4()));
+        let recording = RecordingBackend::new(SimulatedBackend::default(), &path).unwrap();
+        fn build<B>(backend: B, id: Uuid) -> Arc<Instances<B, SimulatedData>>
+        where
+            B: Backend<Registration<SimulatedData>> + Send + Sync + 'static,
+        {
+            Builder::default()
+                .with_backend(backend)
+                .with_id_generator(FixedId(id))
+                .with_leader_strategy(LeaderStrategy::Oldest)
+                .with_update_interval(Duration::from_secs(1))
+                .with_info_extractor(|| "data".to_string())
+                .build_without_daemon()
+                .unwrap()
+        }
+        let id = Uuid::new_v4();
+        let recorded = build(recording, id);
+        recorded.update_instance_info().unwrap();
+        recorded.update_instance_info().unwrap();
+
+        let replayed = build(ReplayBackend::open(&path).unwrap(), id);
+        replayed.update_instance_info().unwrap();
+        replayed.update_instance_info().unwrap();
+
+        assert_eq!(Some(id), recorded.current_leader().map(|l| l.id));
+        assert_eq!(Some(id), replayed.current_leader().map(|l| l.id));
+        assert!(replayed.update_instance_info().is_err());
+        let listing: Result<Vec<InstanceRecord<Registration<SimulatedData>>>, _> =
+            ReplayBackend::open(&path).unwrap().list_active_instances();
+        assert_eq!(1, listing.unwrap().len());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+//! Listing from a read replica while writing to the primary.
+
+use std::time::Duration;
+
+use crossbeam_channel::Receiver;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::backends::{Backend, Capabilities, ConnectionError, InstanceRecord};
+use crate::time::HeartbeatTime;
+
+/// Writes the heartbeats to `primary` and lists the instances from `replica`,
+/// e.g. a read replica of a MySQL or Postgres primary, so the listings of a
+/// large cluster don't load the primary.
+///
+/// The replica lags behind the primary by up to `max_lag`. The listed
+/// heartbeats are moved forward by `max_lag` so the `StalenessPolicy` doesn't
+/// drop live instances because of it, and an instance missing its own latest
+/// write is handled by the `Consistency` setting like with any eventually
+/// consistent backend.
+pub struct ReadReplicaBackend<P, R> {
+    primary: P,
+    replica: R,
+    max_lag: Duration,
+}
+
+impl<P, R> ReadReplicaBackend<P, R> {
+    pub fn new(primary: P, replica: R, max_lag: Duration) -> Self {
+        ReadReplicaBackend {
+            primary,
+            replica,
+            max_lag,
+        }
+    }
+}
+
+impl<P, R> ReadReplicaBackend<P, R> {
+    /// Moves the listed heartbeats forward by `max_lag`.
+    fn allow_for_lag<D>(&self, mut instances: Vec<InstanceRecord<D>>) -> Vec<InstanceRecord<D>> {
+        let lag = self.max_lag.as_millis() as u64;
+        for instance in instances.iter_mut() {
+            instance.heartbeat_at = instance
+                .heartbeat_at
+                .map(|at| HeartbeatTime::from_millis(at.as_millis() + lag));
+        }
+        instances
+    }
+}
+
+impl<T, P, R> Backend<T> for ReadReplicaBackend<P, R>
+where
+    T: Serialize + DeserializeOwned,
+    P: Backend<T>,
+    R: Backend<T>,
+{
+    fn update_instance_info(&self, instance_id: Uuid, data: T) -> Result<(), ConnectionError> {
+        self.primary.update_instance_info(instance_id, data)
+    }
+
+    fn list_active_instances(&self) -> Result<Vec<InstanceRecord<T>>, ConnectionError> {
+        Ok(self.allow_for_lag(self.replica.list_active_instances()?))
+    }
+
+    fn list_encoded_instances(&self) -> Result<Vec<InstanceRecord<Vec<u8>>>, ConnectionError> {
+        Ok(self.allow_for_lag(Backend::<T>::list_encoded_instances(&self.replica)?))
+    }
+
+    fn deregister_instance(&self, instance_id: Uuid) -> Result<(), ConnectionError> {
+        self.primary.deregister_instance(instance_id)
+    }
+
+    fn register_instance_if_room(
+        &self,
+        instance_id: Uuid,
+        data: T,
+        max_members: usize,
+    ) -> Result<(), ConnectionError> {
+        // The room has to be checked against the primary, a lagging replica
+        // would let the cluster grow over the limit.
+        self.primary
+            .register_instance_if_room(instance_id, data, max_members)
+    }
+
+    fn max_payload_size(&self) -> Option<usize> {
+        self.primary.max_payload_size()
+    }
+
+    fn min_update_interval(&self) -> Option<Duration> {
+        self.primary.min_update_interval()
+    }
+
+    fn identity(&self) -> String {
+        format!(
+            "ReadReplica({}, {})",
+            self.primary.identity(),
+            self.replica.identity()
+        )
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.primary.capabilities()
+    }
+
+    fn health_check(&self) -> Result<(), ConnectionError> {
+        self.primary.health_check()?;
+        self.replica.health_check()
+    }
+
+    fn try_acquire_lease(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, ConnectionError> {
+        self.primary.try_acquire_lease(name, holder, ttl)
+    }
+
+    fn release_lease(&self, name: &str, holder: &str) -> Result<(), ConnectionError> {
+        self.primary.release_lease(name, holder)
+    }
+
+    fn prune_expired(&self, max_age: Duration) -> Result<usize, ConnectionError> {
+        self.primary.prune_expired(max_age)
+    }
+
+    fn watch_changes(&self) -> Result<Receiver<Uuid>, ConnectionError> {
+        self.replica.watch_changes()
+    }
+
+    fn update_instance_delta(
+        &self,
+        instance_id: Uuid,
+        patch: &Value,
+    ) -> Result<(), ConnectionError> {
+        self.primary.update_instance_delta(instance_id, patch)
+    }
+
+    fn load_counter(&self, name: &str) -> Result<u64, ConnectionError> {
+        self.primary.load_counter(name)
+    }
+
+    fn compare_and_set_counter(
+        &self,
+        name: &str,
+        current: u64,
+        new: u64,
+    ) -> Result<bool, ConnectionError> {
+        self.primary.compare_and_set_counter(name, current, new)
+    }
+
+    fn load_leader_override(&self) -> Result<Option<Uuid>, ConnectionError> {
+        self.primary.load_leader_override()
+    }
+
+    fn store_leader_override(&self, leader: Option<Uuid>) -> Result<(), ConnectionError> {
+        self.primary.store_leader_override(leader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockall::predicate::eq;
+
+    use crate::backends::MockBackend;
+    use crate::models::Registration;
+    use crate::tests::registration;
+
+    use super::*;
+
+    #[test]
+    fn should_write_to_the_primary_and_list_from_the_replica() {
+        let mut primary = MockBackend::<Registration<String>>::new();
+        let mut replica = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+
+        primary
+            .expect_update_instance_info()
+            .with(eq(id), eq(registration()))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        replica.expect_list_active_instances().returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                HeartbeatTime::from_millis(1_000),
+                registration(),
+            )
+            .with_heartbeat_at(HeartbeatTime::from_millis(5_000))])
+        });
+
+        let backend = ReadReplicaBackend::new(primary, replica, Duration::from_secs(2));
+        backend.update_instance_info(id, registration()).unwrap();
+        let listed = backend.list_active_instances().unwrap();
+
+        assert_eq!(
+            Some(HeartbeatTime::from_millis(7_000)),
+            listed[0].heartbeat_at
+        );
+        assert_eq!(HeartbeatTime::from_millis(1_000), listed[0].registered_at);
+    }
+}
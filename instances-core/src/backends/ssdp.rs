@@ -0,0 +1,277 @@
+//! A backend without a datastore, for appliances and home deployments: the
+//! instances announce themselves on the local network with SSDP (the UPnP
+//! discovery protocol) and each one lists the announcements it heard.
+
+use std::collections::HashMap;
+use std::io;
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::backends::{codec, Backend, Capabilities, ConnectionError, InstanceRecord};
+use crate::multicast;
+use crate::sync::MutexExt;
+use crate::time::HeartbeatTime;
+
+const SSDP_GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+/// Notification type the instances announce themselves with.
+pub const NOTIFICATION_TYPE: &str = "urn:instances-rs:service:member:1";
+/// Keeps the announcements within a single unfragmented datagram.
+const MAX_PAYLOAD: usize = 1024;
+
+/// An announcement heard, the payload still serialized.
+#[derive(PartialEq, Clone, Debug)]
+struct Announcement {
+    id: Uuid,
+    registered_at: HeartbeatTime,
+    data: String,
+}
+
+#[derive(PartialEq, Debug)]
+enum Notification {
+    Alive(Announcement),
+    ByeBye(Uuid),
+}
+
+type Heard = HashMap<Uuid, (Announcement, HeartbeatTime)>;
+
+/// Lists the instances whose `ssdp:alive` announcements were heard in the
+/// last `ttl`, so the update interval must be well below it. Each update
+/// multicasts the instance's announcement and the deregistration an
+/// `ssdp:byebye`.
+///
+/// The announcements are plain UDP: every instance must be on the same
+/// network segment, and the payloads are limited to 1KB.
+pub struct SsdpBackend<T> {
+    socket: UdpSocket,
+    group: SocketAddr,
+    ttl: Duration,
+    heard: Arc<Mutex<Heard>>,
+    /// When each instance updated from this process registered.
+    registered: Mutex<HashMap<Uuid, HeartbeatTime>>,
+    stopped: Arc<AtomicBool>,
+    _data: PhantomData<fn() -> T>,
+}
+
+impl<T> SsdpBackend<T> {
+    /// Joins the SSDP multicast group and starts listening for announcements,
+    /// sharing the SSDP port with the other instances and UPnP devices of the
+    /// host.
+    pub fn new(ttl: Duration) -> io::Result<Self> {
+        let socket = multicast::join_v4(SSDP_GROUP, SSDP_PORT)?;
+        socket.set_read_timeout(Some(Duration::from_millis(250)))?;
+
+        let heard = Arc::new(Mutex::new(HashMap::new()));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let listener = socket.try_clone()?;
+        let (seen, stop) = (heard.clone(), stopped.clone());
+        thread::spawn(move || {
+            let mut buffer = [0u8; 2048];
+            while !stop.load(Ordering::SeqCst) {
+                let len = match listener.recv_from(&mut buffer) {
+                    Ok((len, _)) => len,
+                    Err(_) => continue,
+                };
+                let mut heard = seen.lock_or_recover();
+                match parse(&String::from_utf8_lossy(&buffer[..len])) {
+                    Some(Notification::Alive(announcement)) => {
+                        heard.insert(announcement.id, (announcement, HeartbeatTime::now()));
+                    }
+                    Some(Notification::ByeBye(id)) => {
+                        heard.remove(&id);
+                    }
+                    None => {}
+                }
+            }
+        });
+
+        Ok(SsdpBackend {
+            socket,
+            group: SocketAddr::V4(SocketAddrV4::new(SSDP_GROUP, SSDP_PORT)),
+            ttl,
+            heard,
+            registered: Mutex::new(HashMap::new()),
+            stopped,
+            _data: PhantomData,
+        })
+    }
+}
+
+impl<T> Drop for SsdpBackend<T> {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<T> Backend<T> for SsdpBackend<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn update_instance_info(&self, instance_id: Uuid, data: T) -> Result<(), ConnectionError> {
+        let data = serde_json::to_string(&data)
+            .map_err(|e| ConnectionError::FailedToUpdate(e.to_string()))?;
+        if data.len() > MAX_PAYLOAD {
+            return Err(ConnectionError::PayloadTooLarge(data.len(), MAX_PAYLOAD));
+        }
+        let registered_at = *self
+            .registered
+            .lock_or_recover()
+            .entry(instance_id)
+            .or_insert_with(HeartbeatTime::now);
+        let announcement = Announcement {
+            id: instance_id,
+            registered_at,
+            data,
+        };
+
+        self.socket
+            .send_to(alive(&announcement, self.ttl).as_bytes(), self.group)
+            .map_err(|e| ConnectionError::FailedToUpdate(e.to_string()))?;
+        // Not waiting to hear the multicast back, which may be disabled.
+        self.heard
+            .lock_or_recover()
+            .insert(instance_id, (announcement, HeartbeatTime::now()));
+        Ok(())
+    }
+
+    fn list_active_instances(&self) -> Result<Vec<InstanceRecord<T>>, ConnectionError> {
+        Ok(codec::decode_records(self.list_encoded_instances()?))
+    }
+
+    fn list_encoded_instances(&self) -> Result<Vec<InstanceRecord<Vec<u8>>>, ConnectionError> {
+        let now = HeartbeatTime::now();
+        let mut heard = self.heard.lock_or_recover();
+        heard.retain(|_, (_, at)| now.saturating_duration_since(*at) <= self.ttl);
+
+        Ok(heard
+            .values()
+            .map(|(announcement, heard_at)| {
+                InstanceRecord::new(
+                    announcement.id,
+                    announcement.registered_at,
+                    announcement.data.clone().into_bytes(),
+                )
+                .with_heartbeat_at(*heard_at)
+            })
+            .collect())
+    }
+
+    fn deregister_instance(&self, instance_id: Uuid) -> Result<(), ConnectionError> {
+        self.heard.lock_or_recover().remove(&instance_id);
+        self.registered.lock_or_recover().remove(&instance_id);
+        self.socket
+            .send_to(byebye(instance_id).as_bytes(), self.group)
+            .map_err(|e| ConnectionError::FailedToDeregister(e.to_string()))?;
+        Ok(())
+    }
+
+    fn max_payload_size(&self) -> Option<usize> {
+        Some(MAX_PAYLOAD)
+    }
+
+    fn identity(&self) -> String {
+        format!("SSDP({})", self.group)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            deregister: true,
+            heartbeat_timestamps: true,
+            expiry: true,
+            ..Capabilities::default()
+        }
+    }
+}
+
+fn alive(announcement: &Announcement, ttl: Duration) -> String {
+    format!(
+        "NOTIFY * HTTP/1.1\r\nHOST: {}:{}\r\nCACHE-CONTROL: max-age={}\r\nNT: {}\r\nNTS: ssdp:alive\r\nUSN: uuid:{}::{}\r\nX-INSTANCES-REGISTERED-AT: {}\r\nX-INSTANCES-DATA: {}\r\n\r\n",
+        SSDP_GROUP,
+        SSDP_PORT,
+        ttl.as_secs(),
+        NOTIFICATION_TYPE,
+        announcement.id,
+        NOTIFICATION_TYPE,
+        announcement.registered_at.as_millis(),
+        announcement.data,
+    )
+}
+
+fn byebye(id: Uuid) -> String {
+    format!(
+        "NOTIFY * HTTP/1.1\r\nHOST: {}:{}\r\nNT: {}\r\nNTS: ssdp:byebye\r\nUSN: uuid:{}::{}\r\n\r\n",
+        SSDP_GROUP, SSDP_PORT, NOTIFICATION_TYPE, id, NOTIFICATION_TYPE,
+    )
+}
+
+/// Reads the notifications of the instances, ignoring the other devices' and
+/// the searches.
+fn parse(message: &str) -> Option<Notification> {
+    let mut lines = message.lines();
+    if !lines.next()?.starts_with("NOTIFY ") {
+        return None;
+    }
+    let headers: HashMap<String, &str> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_uppercase(), value.trim()))
+        .collect();
+    if headers.get("NT") != Some(&NOTIFICATION_TYPE) {
+        return None;
+    }
+    let usn = headers.get("USN")?.strip_prefix("uuid:")?;
+    let id = Uuid::parse_str(usn.split("::").next()?).ok()?;
+
+    match *headers.get("NTS")? {
+        "ssdp:alive" => Some(Notification::Alive(Announcement {
+            id,
+            registered_at: HeartbeatTime::from_millis(
+                headers.get("X-INSTANCES-REGISTERED-AT")?.parse().ok()?,
+            ),
+            data: headers.get("X-INSTANCES-DATA")?.to_string(),
+        })),
+        "ssdp:byebye" => Some(Notification::ByeBye(id)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_the_announcements_back() {
+        let announcement = Announcement {
+            id: Uuid::new_v4(),
+            registered_at: HeartbeatTime::from_millis(1_000),
+            data: r#"{"address":"10.0.0.1:80"}"#.to_string(),
+        };
+
+        let message = alive(&announcement, Duration::from_secs(30));
+
+        assert_eq!(
+            Some(Notification::Alive(announcement.clone())),
+            parse(&message)
+        );
+        assert_eq!(
+            Some(Notification::ByeBye(announcement.id)),
+            parse(&byebye(announcement.id))
+        );
+    }
+
+    #[test]
+    fn should_ignore_other_devices() {
+        let message = "NOTIFY * HTTP/1.1\r\nNT: upnp:rootdevice\r\nNTS: ssdp:alive\r\nUSN: uuid:2fac1234-31f8-11b4-a222-08002b34c003::upnp:rootdevice\r\n\r\n";
+        let search = "M-SEARCH * HTTP/1.1\r\nST: ssdp:all\r\n\r\n";
+
+        assert_eq!(None, parse(message));
+        assert_eq!(None, parse(search));
+    }
+}
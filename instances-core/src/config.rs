@@ -0,0 +1,1397 @@
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::anomalies::{AnomalyDetector, AnomalyRules};
+use crate::backends::{BackendConfig, BackendError, BackendResponse, ConnectionError, Operation};
+use crate::daemon::start_daemon;
+use crate::events::EventBus;
+use crate::extension::{Extension, ExtensionRegistry, TickContext};
+use crate::ids::{IdGenerator, RandomId};
+use crate::maintenance::{janitor, LeaderMaintenance, MaintenanceTask};
+use crate::models::{Consistency, InstanceInfo, InstanceKind, InstanceRole, StartupPolicy};
+use crate::random::{RandomSource, SystemRandom};
+use crate::reload::ConfigWatcher;
+use crate::snapshot;
+use crate::staleness::{FixedTtl, MissedHeartbeats, StalenessPolicy};
+use crate::sync::{modeled, MutexExt};
+use crate::time::{Clock, SystemClock};
+use crate::timings::TimingsRecorder;
+use crate::update::Ticks;
+use crate::{
+    index_by_id, Backend, BackendTap, CommunicationErrorStrategy, Extractor, Instances,
+    InstancesState, LatencyProbe, LeaderEligible, LeaderStrategy, Registration, SelfCheck,
+    Settings, ZoneOf, PROTOCOL_VERSION,
+};
+
+pub struct Builder<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    interval: Option<Duration>,
+    backend: Option<B>,
+    id_generator: Option<Box<dyn IdGenerator>>,
+    random: Option<Arc<dyn RandomSource>>,
+    clock: Option<Box<dyn Clock>>,
+    info_extractor: Option<Extractor<T>>,
+    extract_every: u32,
+    self_check: Option<SelfCheck>,
+    leader_eligible: Option<LeaderEligible<T>>,
+    leader_zone: Option<ZoneOf<T>>,
+    latency_probe: Option<LatencyProbe<T>>,
+    backend_tap: Option<BackendTap<T>>,
+    leader_strategy: Option<LeaderStrategy>,
+    error_strategy: Option<CommunicationErrorStrategy>,
+    kind: InstanceKind,
+    consistency: Option<Consistency>,
+    leadership_acknowledgment: bool,
+    staleness_policy: Option<Box<dyn StalenessPolicy>>,
+    suspicion: u32,
+    max_payload_size: Option<usize>,
+    max_members: Option<usize>,
+    snapshot_file: Option<PathBuf>,
+    dry_run: bool,
+    tombstones: bool,
+    cohort: Option<String>,
+    solo_warmup: u32,
+    require_self_visible: bool,
+    persistent_leader_term: bool,
+    view_checks: bool,
+    leader_overrides: bool,
+    data_history: usize,
+    delta_updates: bool,
+    degraded_while_backed_off: bool,
+    anomaly_rules: AnomalyRules,
+    min_protocol: u32,
+    push_updates: bool,
+    config_file: Option<PathBuf>,
+    startup_policy: Option<StartupPolicy>,
+    extensions: ExtensionRegistry<B, T>,
+    maintenance: Vec<MaintenanceTask<T>>,
+    janitor: Option<(Duration, Duration)>,
+    pre_registration: bool,
+}
+
+// Implemented by hand since deriving it would require `B: Default`.
+impl<B, T> Default for Builder<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        Builder {
+            interval: None,
+            backend: None,
+            id_generator: None,
+            random: None,
+            clock: None,
+            info_extractor: None,
+            extract_every: 1,
+            self_check: None,
+            leader_eligible: None,
+            leader_zone: None,
+            latency_probe: None,
+            backend_tap: None,
+            leader_strategy: None,
+            error_strategy: None,
+            kind: InstanceKind::default(),
+            consistency: None,
+            leadership_acknowledgment: false,
+            staleness_policy: None,
+            suspicion: 0,
+            max_payload_size: None,
+            max_members: None,
+            snapshot_file: None,
+            dry_run: false,
+            tombstones: false,
+            cohort: None,
+            solo_warmup: 0,
+            require_self_visible: false,
+            persistent_leader_term: false,
+            view_checks: false,
+            leader_overrides: false,
+            data_history: 0,
+            delta_updates: false,
+            degraded_while_backed_off: false,
+            anomaly_rules: AnomalyRules::default(),
+            min_protocol: 0,
+            push_updates: false,
+            config_file: None,
+            startup_policy: None,
+            extensions: ExtensionRegistry::default(),
+            maintenance: vec![],
+            janitor: None,
+            pre_registration: false,
+        }
+    }
+}
+
+/// The timing settings of the service in one place, to load them from the
+/// application's configuration. Missing fields take their default:
+///
+/// ```
+/// # use instances_core::config::TimingConfig;
+/// let timing: TimingConfig = serde_json::from_str(r#"{"update_interval_ms": 2000}"#).unwrap();
+/// assert_eq!(TimingConfig { update_interval_ms: 2000, ..TimingConfig::default() }, timing);
+/// ```
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct TimingConfig {
+    /// Time between updates, see `Builder::with_update_interval`. 5 seconds by
+    /// default.
+    pub update_interval_ms: u64,
+    /// Age past which an instance that stopped heart-beating is dropped, see
+    /// `FixedTtl`. By default instances are dropped once they miss three
+    /// update intervals.
+    pub ttl_ms: Option<u64>,
+    /// See `Builder::with_suspicion`. 0 by default.
+    pub suspicion: u32,
+    /// See `Builder::with_solo_warmup`. 0 by default.
+    pub solo_warmup: u32,
+    /// See `Builder::with_extractor_every`. 1 by default.
+    pub extract_every: u32,
+}
+
+impl Default for TimingConfig {
+    fn default() -> Self {
+        TimingConfig {
+            update_interval_ms: 5000,
+            ttl_ms: None,
+            suspicion: 0,
+            solo_warmup: 0,
+            extract_every: 1,
+        }
+    }
+}
+
+impl<B, T> Builder<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    pub fn with_update_interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Applies every setting of `timing`, replacing the ones set before.
+    pub fn with_timing(mut self, timing: TimingConfig) -> Self {
+        self = self
+            .with_update_interval(Duration::from_millis(timing.update_interval_ms))
+            .with_suspicion(timing.suspicion)
+            .with_solo_warmup(timing.solo_warmup)
+            .with_extractor_every(timing.extract_every);
+        self.staleness_policy = timing
+            .ttl_ms
+            .map(|ttl| Box::new(FixedTtl(Duration::from_millis(ttl))) as Box<dyn StalenessPolicy>);
+        self
+    }
+
+    pub fn with_backend(mut self, backend: B) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Starts a builder with the backend named by `config`, e.g. `memory` or
+    /// `redis://cache-1:6379`, built by `connect` from the parsed
+    /// [`BackendConfig`]. A backend this build doesn't compile in fails with
+    /// `ConfigError::Backend`, listing the available ones.
+    pub fn from_config(
+        config: &str,
+        connect: impl FnOnce(BackendConfig) -> B,
+    ) -> Result<Self, ConfigError> {
+        let config = config.parse::<BackendConfig>()?;
+        Ok(Builder::default().with_backend(connect(config)))
+    }
+
+    /// Like `from_config`, with the backend named by the environment variable
+    /// `var`.
+    pub fn from_env(
+        var: &str,
+        connect: impl FnOnce(BackendConfig) -> B,
+    ) -> Result<Self, ConfigError> {
+        let config = env::var(var).map_err(|_| ConfigError::Missing("backend"))?;
+        Self::from_config(&config, connect)
+    }
+
+    /// How the instance id is generated, random UUIDv4 by default. Time-ordered
+    /// ids (see [`TimeOrderedId`](crate::ids::TimeOrderedId)) also break ties
+    /// between instances registered at the same time when electing the leader.
+    pub fn with_id_generator(mut self, generator: impl IdGenerator + 'static) -> Self {
+        self.id_generator = Some(Box::new(generator));
+        self
+    }
+
+    /// Where the randomness comes from, the operating system by default. A
+    /// [`SeededRandom`](crate::random::SeededRandom) makes the instance
+    /// reproducible: it draws the random part of the instance id (see
+    /// `IdGenerator::generate_from`) and the jitter of the listing backoff.
+    pub fn with_random_source(mut self, random: impl RandomSource + 'static) -> Self {
+        self.random = Some(Arc::new(random));
+        self
+    }
+
+    /// Where the current time comes from, the system clock by default.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    pub fn with_info_extractor(
+        mut self,
+        extractor: impl Fn() -> T + Send + Sync + 'static,
+    ) -> Self {
+        self.info_extractor = Some(Box::new(extractor));
+        self
+    }
+
+    /// Runs the info extractor only every `updates` updates, publishing the last
+    /// extracted value in between, for info that is expensive to compute (disk
+    /// scans, metric aggregation). The heartbeat keeps its own interval.
+    pub fn with_extractor_every(mut self, updates: u32) -> Self {
+        self.extract_every = updates;
+        self
+    }
+
+    /// Checks the application's health before every update. While it fails the
+    /// instance is published as `InstanceStatus::Degraded`, so it can't be
+    /// elected leader and peers can route around it.
+    pub fn with_self_check(mut self, check: impl Fn() -> bool + Send + Sync + 'static) -> Self {
+        self.self_check = Some(Box::new(check));
+        self
+    }
+
+    /// Measures the latency to each peer on every update, e.g. from a ping
+    /// kept up to date in the background or from the distance between zones.
+    /// It runs on the update thread so it must be quick. Overrides the latency
+    /// reported by the backend unless it returns `None`.
+    pub fn with_latency_probe(
+        mut self,
+        probe: impl Fn(&InstanceInfo<T>) -> Option<Duration> + Send + Sync + 'static,
+    ) -> Self {
+        self.latency_probe = Some(Box::new(probe));
+        self
+    }
+
+    /// Shows every call the instance makes to the backend and its raw result to
+    /// `tap`, e.g. to debug a backend as a black box or to collect custom
+    /// metrics. The backend errors are the ones returned by the backend,
+    /// before they're wrapped with the operation. It runs on the calling
+    /// thread, usually the update daemon, so it must be quick.
+    pub fn with_backend_tap(
+        mut self,
+        tap: impl Fn(Operation, &Result<BackendResponse<'_, Registration<T>>, ConnectionError>)
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        self.backend_tap = Some(Box::new(tap));
+        self
+    }
+
+    /// Restricts the leadership to the instances whose data passes `predicate`,
+    /// e.g. to keep spot instances or canaries from leading. The others are
+    /// still members.
+    pub fn leader_eligible(
+        mut self,
+        predicate: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.leader_eligible = Some(Box::new(predicate));
+        self
+    }
+
+    /// Spreads the cohort leaders (see `Instances::cohort_leader`) across the
+    /// zones returned by `zone_of`, where the cohorts have members in enough
+    /// zones, so that losing a zone doesn't take down every leader at once.
+    /// Instances without a zone never conflict.
+    ///
+    /// ```ignore
+    /// builder.with_spread_cohort_leaders(|data| data.zone().map(str::to_string))
+    /// ```
+    pub fn with_spread_cohort_leaders(
+        mut self,
+        zone_of: impl Fn(&T) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.leader_zone = Some(Box::new(zone_of));
+        self
+    }
+
+    pub fn with_leader_strategy(mut self, strategy: LeaderStrategy) -> Self {
+        self.leader_strategy = Some(strategy);
+        self
+    }
+
+    pub fn with_error_strategy(mut self, strategy: CommunicationErrorStrategy) -> Self {
+        self.error_strategy = Some(strategy);
+        self
+    }
+
+    /// Registers the instance as a witness: it's counted as a member but never
+    /// becomes the leader.
+    pub fn as_witness(mut self) -> Self {
+        self.kind = InstanceKind::Witness;
+        self
+    }
+
+    /// Registers the instance as a standby: it observes the cluster but gets
+    /// neither the leadership nor partitions until `Instances::promote`.
+    pub fn as_standby(mut self) -> Self {
+        self.kind = InstanceKind::Standby;
+        self
+    }
+
+    pub fn with_consistency(mut self, consistency: Consistency) -> Self {
+        self.consistency = Some(consistency);
+        self
+    }
+
+    /// Makes a newly elected leader publish a claim first. Nobody, including the
+    /// leader itself, treats it as the leader until the claim is listed, which
+    /// keeps instances from briefly disagreeing on who leads.
+    pub fn with_leadership_acknowledgment(mut self, enabled: bool) -> Self {
+        self.leadership_acknowledgment = enabled;
+        self
+    }
+
+    /// How instances that stopped heart-beating are detected, by default once they
+    /// miss three update intervals. Only applies to backends reporting the
+    /// instances' last heartbeat.
+    pub fn with_staleness_policy(mut self, policy: impl StalenessPolicy + 'static) -> Self {
+        self.staleness_policy = Some(Box::new(policy));
+        self
+    }
+
+    /// Keeps instances missing from the listing as suspects for up to
+    /// `missed_updates` consecutive updates before removing them and electing a
+    /// new leader. Disabled by default. The grace, `missed_updates` update
+    /// intervals, must be shorter than the staleness timeout.
+    pub fn with_suspicion(mut self, missed_updates: u32) -> Self {
+        self.suspicion = missed_updates;
+        self
+    }
+
+    /// Refuses to publish payloads bigger than `bytes` once serialized. The
+    /// backend's own limit, if lower, always applies.
+    pub fn with_max_payload_size(mut self, bytes: usize) -> Self {
+        self.max_payload_size = Some(bytes);
+        self
+    }
+
+    /// Limits the cluster to `max_members` instances. Once it's full, new
+    /// instances fail to register with `ConnectionError::ClusterFull` instead of
+    /// silently joining.
+    pub fn with_max_members(mut self, max_members: usize) -> Self {
+        self.max_members = Some(max_members);
+        self
+    }
+
+    /// Persists the instances list to `path` after every successful update. With
+    /// `CommunicationErrorStrategy::UseLastInfo` the file is loaded on startup,
+    /// flagged as stale, so a restart during a backend outage still knows the
+    /// last membership (and leader).
+    pub fn with_snapshot_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.snapshot_file = Some(path.into());
+        self
+    }
+
+    /// Skips every backend write, logging it instead, while still listing the
+    /// other instances. The instance never registers, but computes the role it
+    /// would get, so a new configuration can be checked against production.
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// Makes `Instances::drain` leave a tombstone marking the instance as
+    /// `Departed` instead of deleting its record, exposed to the other instances
+    /// through `Instances::recently_departed`. Tombstones stay in the backend
+    /// until they expire or are cleaned up.
+    pub fn with_tombstones(mut self, enabled: bool) -> Self {
+        self.tombstones = enabled;
+        self
+    }
+
+    /// Tags the instance with a deployment cohort, e.g. `blue` or `green`. Each
+    /// cohort elects its own leader besides the cluster-wide one, and
+    /// `Instances::cut_over` switches the active cohort.
+    pub fn with_cohort(mut self, cohort: impl Into<String>) -> Self {
+        self.cohort = Some(cohort.into());
+        self
+    }
+
+    /// Keeps an instance that lists no other instance from leading until it was
+    /// alone for `updates` consecutive updates, giving the peers restarted
+    /// along with it (e.g. in a fleet-wide redeploy) time to show up. By default
+    /// a solo instance leads right away.
+    pub fn with_solo_warmup(mut self, updates: u32) -> Self {
+        self.solo_warmup = updates;
+        self
+    }
+
+    /// Doesn't let the instance lead before its own registration showed up in a
+    /// backend listing, instead of trusting the record assumed locally with
+    /// `Consistency::Eventual`.
+    pub fn require_self_visible(mut self, enabled: bool) -> Self {
+        self.require_self_visible = enabled;
+        self
+    }
+
+    /// Keeps a leader term in the backend, incremented (with a compare-and-set)
+    /// by every instance taking the leadership and published with its record:
+    /// see `Instances::leader_term` and `InstancesEvent::LeaderTermStarted`.
+    /// External systems can order leader transitions and fence out stale
+    /// leaders with it. The backend must support `Capabilities::counters`.
+    pub fn with_persistent_leader_term(mut self, enabled: bool) -> Self {
+        self.persistent_leader_term = enabled;
+        self
+    }
+
+    /// Honours the leader override stored in the backend, read at every
+    /// update: while set, the instance it names leads whatever the leader
+    /// strategy picks, and nobody leads while it isn't listed or isn't
+    /// eligible (e.g. draining or under maintenance). Operators set it
+    /// with `Instances::override_leader` or straight on the backend (see
+    /// `Backend::store_leader_override`) when the election picks the wrong
+    /// instance. The backend must support `Capabilities::overrides`.
+    pub fn with_leader_override(mut self, enabled: bool) -> Self {
+        self.leader_overrides = enabled;
+        self
+    }
+
+    /// Publishes a hash of the members seen by the instance with its record, to
+    /// compare the instances' views with `Instances::view_consistency`. Views
+    /// still differing after a few updates are reported with
+    /// `InstancesEvent::ViewsDiverged`, catching backend replication problems
+    /// before they split the leadership.
+    pub fn with_view_checks(mut self, enabled: bool) -> Self {
+        self.view_checks = enabled;
+        self
+    }
+
+    /// Leaves out of the membership the peers writing a wire protocol older
+    /// than `protocol`, reporting them with `InstancesEvent::IncompatiblePeer`
+    /// and `Instances::rejected_peers` instead. They still count against
+    /// `Builder::with_max_members`, as the backend lists them.
+    ///
+    /// To roll out a new protocol, first deploy the version writing it
+    /// everywhere while keeping the previous minimum, which lets both share
+    /// the cluster. Once no instance writes the old protocol, raise the
+    /// minimum. Defaults to 0, accepting every peer.
+    pub fn with_min_protocol(mut self, protocol: u32) -> Self {
+        self.min_protocol = protocol;
+        self
+    }
+
+    /// Raises `InstancesEvent::AnomalyDetected` (and counts it in
+    /// `Instances::anomalies_detected`) when an update breaks one of `rules`,
+    /// to notice backend or network problems.
+    pub fn with_anomaly_rules(mut self, rules: AnomalyRules) -> Self {
+        self.anomaly_rules = rules;
+        self
+    }
+
+    /// Publishes the instance as `Degraded` while its listing is backed off
+    /// after backend failures (see `CommunicationErrorStrategy::UseLastInfo`),
+    /// which takes it out of the leader election. Off by default: a short
+    /// backend outage would otherwise make the leader step down.
+    pub fn with_degraded_while_backed_off(mut self, enabled: bool) -> Self {
+        self.degraded_while_backed_off = enabled;
+        self
+    }
+
+    /// Writes only what changed in the registration since the previous update,
+    /// as a JSON merge patch (see `Backend::update_instance_delta`), cutting
+    /// the bandwidth used by large payloads. The record is still written in
+    /// full from time to time. Ignored if the backend doesn't support
+    /// `Capabilities::deltas`.
+    pub fn with_delta_updates(mut self, enabled: bool) -> Self {
+        self.delta_updates = enabled;
+        self
+    }
+
+    /// Keeps the last `versions` values of each peer's data with when they were
+    /// seen, to tell how a peer's state evolved before an incident. See
+    /// `Instances::data_history`.
+    pub fn with_data_history(mut self, versions: usize) -> Self {
+        self.data_history = versions;
+        self
+    }
+
+    /// Refreshes the instances as soon as the backend pushes a change (see
+    /// `Backend::watch_changes`), e.g. Redis keyspace notifications, on top of
+    /// the regular updates. Falls back to polling if the backend can't push.
+    pub fn with_push_updates(mut self, enabled: bool) -> Self {
+        self.push_updates = enabled;
+        self
+    }
+
+    /// Starts the instance in the `Joining` state, as if `pre_register` was
+    /// called before the first update. It's published as active once
+    /// `Instances::mark_ready` is called.
+    pub fn with_pre_registration(mut self) -> Self {
+        self.pre_registration = true;
+        self
+    }
+
+    /// Retries a failing first update with backoff for a while, then gives up or
+    /// keeps retrying at the update interval. Without it the first update is
+    /// simply retried on every tick.
+    pub fn with_startup_policy(mut self, policy: StartupPolicy) -> Self {
+        self.startup_policy = Some(policy);
+        self
+    }
+
+    /// Registers a subsystem sharing this service's state and update daemon,
+    /// retrieved with `Instances::extension`. Registering a second extension
+    /// of the same type replaces the first.
+    pub fn with_extension(mut self, extension: impl Extension<B, T>) -> Self {
+        self.extensions.register(extension);
+        self
+    }
+
+    /// Reports the critical conditions of the instance to an alert sink, see
+    /// [`Alerts`](crate::alerts::Alerts).
+    #[cfg(feature = "alerts")]
+    pub fn with_alerts(self, alerts: crate::alerts::Alerts) -> Self {
+        self.with_extension(alerts)
+    }
+
+    /// Makes the leader delete the records that stopped heart-beating more than
+    /// `max_age` ago, every `cadence`, so the backend's storage doesn't grow
+    /// forever in long-lived clusters. The backend must support
+    /// `Capabilities::pruning`.
+    pub fn with_janitor(mut self, cadence: Duration, max_age: Duration) -> Self {
+        self.janitor = Some((cadence, max_age));
+        self
+    }
+
+    /// Runs `task` every `interval` on the current leader only, for
+    /// housekeeping such as compactions or metrics rollups. It runs on a thread
+    /// of its own so it may be slow; a run still going when the next one is due
+    /// makes the instance skip it instead of running both at once. Another
+    /// instance taking over the leadership may start a run while the previous
+    /// leader's is still going, so tasks must tolerate that.
+    pub fn leader_maintenance(
+        mut self,
+        interval: Duration,
+        task: impl Fn(&TickContext<T>) + Send + Sync + 'static,
+    ) -> Self {
+        self.maintenance.push(MaintenanceTask::new(interval, task));
+        self
+    }
+
+    /// Watches a JSON file with settings to apply at runtime, checked before
+    /// every update. It may set `update_interval_ms`, `error_strategy`,
+    /// `suspicion` and `solo_warmup`; each reload emits
+    /// `InstancesEvent::ConfigReloaded`. A reload failing the builder's checks
+    /// is ignored with a warning, keeping the previous settings.
+    pub fn with_config_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Builds the service and starts its update daemon.
+    ///
+    /// # Panics
+    ///
+    /// If the configuration is invalid, see [`Builder::try_build`].
+    pub fn build(self) -> Arc<Instances<B, T>> {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Builds the service and starts its update daemon, failing if a required
+    /// setting is missing or the settings contradict each other.
+    pub fn try_build(self) -> Result<Arc<Instances<B, T>>, ConfigError> {
+        let service = self.build_without_daemon()?;
+
+        let daemon = start_daemon(&service);
+        *service.daemon.lock_or_recover() = Some(daemon);
+        #[cfg(feature = "alerts")]
+        crate::alerts::Alerts::watch(&service);
+
+        Ok(service)
+    }
+
+    /// Builds the service as `build` does, its update daemon a task of the
+    /// current Tokio runtime instead of a thread, see
+    /// [`start_async_daemon`](crate::daemon::start_async_daemon).
+    ///
+    /// # Panics
+    ///
+    /// If the configuration is invalid, or outside of a Tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn build_async(self) -> Arc<Instances<B, T>> {
+        self.try_build_async().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Builds the service as `try_build` does, its update daemon a task of
+    /// the current Tokio runtime instead of a thread.
+    ///
+    /// # Panics
+    ///
+    /// Outside of a Tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub fn try_build_async(self) -> Result<Arc<Instances<B, T>>, ConfigError> {
+        let service = self.build_without_daemon()?;
+
+        let daemon = crate::daemon::start_async_daemon(&service);
+        *service.daemon.lock_or_recover() = Some(daemon);
+        #[cfg(feature = "alerts")]
+        crate::alerts::Alerts::watch(&service);
+
+        Ok(service)
+    }
+
+    /// Builds the service leaving the updates to the caller.
+    pub(crate) fn build_without_daemon(self) -> Result<Arc<Instances<B, T>>, ConfigError> {
+        let interval = self
+            .interval
+            .ok_or(ConfigError::Missing("update interval"))?;
+        let backend = self.backend.ok_or(ConfigError::Missing("backend"))?;
+        let info_extractor = self
+            .info_extractor
+            .ok_or(ConfigError::Missing("info extractor"))?;
+
+        let staleness_policy = self
+            .staleness_policy
+            .unwrap_or_else(|| Box::new(MissedHeartbeats::default()));
+        let interval = checked_timing(
+            interval,
+            self.suspicion,
+            backend.min_update_interval(),
+            staleness_policy.as_ref(),
+        )?;
+
+        if self.max_members == Some(0) {
+            return Err(ConfigError::NoMembersAllowed);
+        }
+
+        if self.min_protocol > PROTOCOL_VERSION {
+            return Err(ConfigError::MinProtocolAboveCurrent(
+                self.min_protocol,
+                PROTOCOL_VERSION,
+            ));
+        }
+
+        if self.persistent_leader_term && !backend.capabilities().counters {
+            return Err(ConfigError::UnsupportedByBackend("counters"));
+        }
+        if self.leader_overrides && !backend.capabilities().overrides {
+            return Err(ConfigError::UnsupportedByBackend("leader overrides"));
+        }
+        if self.janitor.is_some() && !backend.capabilities().pruning {
+            return Err(ConfigError::UnsupportedByBackend("pruning"));
+        }
+        if self.leader_strategy == Some(LeaderStrategy::Backend)
+            && !backend.capabilities().designated_leaders
+        {
+            return Err(ConfigError::UnsupportedByBackend("designated leaders"));
+        }
+        if let Some(missing) = self
+            .extensions
+            .iter()
+            .find_map(|extension| extension.missing_capability(&backend.capabilities()))
+        {
+            return Err(ConfigError::UnsupportedByBackend(missing));
+        }
+        let delta_updates = self.delta_updates && backend.capabilities().deltas;
+
+        let max_payload_size = match (self.max_payload_size, backend.max_payload_size()) {
+            (Some(configured), Some(supported)) => Some(configured.min(supported)),
+            (configured, supported) => configured.or(supported),
+        };
+
+        let error_strategy = self
+            .error_strategy
+            .unwrap_or(CommunicationErrorStrategy::Error);
+
+        let last_snapshot = match (&self.snapshot_file, &error_strategy) {
+            (Some(path), CommunicationErrorStrategy::UseLastInfo) if path.exists() => {
+                snapshot::load(path)
+                    .map_err(|e| warn!("Error loading the instances snapshot. Cause: {}", e))
+                    .ok()
+            }
+            _ => None,
+        };
+
+        let last_leader = last_snapshot
+            .iter()
+            .flatten()
+            .find(|i| i.role == InstanceRole::Leader)
+            .cloned()
+            .map(Arc::new);
+
+        let random = self.random.unwrap_or_else(|| Arc::new(SystemRandom));
+        let id_generator = self.id_generator.unwrap_or_else(|| Box::new(RandomId));
+
+        let backend_identity = backend.identity();
+        let backend = Arc::new(backend);
+
+        let mut extensions = self.extensions;
+        let mut maintenance = self.maintenance;
+        if let Some((cadence, max_age)) = self.janitor {
+            maintenance.push(janitor(
+                backend.clone(),
+                backend_identity.clone(),
+                cadence,
+                max_age,
+            ));
+        }
+        if !maintenance.is_empty() {
+            extensions.register(LeaderMaintenance::new(maintenance));
+        }
+
+        let service = Arc::new(Instances {
+            instance_id: id_generator.generate_from(random.as_ref()),
+            id_generator,
+            random,
+            backend_identity,
+            backend,
+            clock: self.clock.unwrap_or_else(|| Box::new(SystemClock)),
+            info_extractor,
+            extract_every: u64::from(self.extract_every.max(1)),
+            self_check: self.self_check,
+            leader_eligible: self.leader_eligible,
+            leader_zone: self.leader_zone,
+            latency_probe: self.latency_probe,
+            backend_tap: self.backend_tap,
+            leader_strategy: self.leader_strategy.unwrap_or(LeaderStrategy::None),
+            kind: Mutex::new(self.kind),
+            consistency: self.consistency.unwrap_or(Consistency::Eventual),
+            leadership_acknowledgment: self.leadership_acknowledgment,
+            staleness_policy,
+            settings: RwLock::new(Settings {
+                update_interval: interval,
+                error_strategy,
+                suspicion: self.suspicion,
+                solo_warmup: self.solo_warmup,
+            }),
+            config_watcher: self.config_file.map(ConfigWatcher::new),
+            max_payload_size,
+            max_members: self.max_members,
+            snapshot_file: self.snapshot_file,
+            dry_run: self.dry_run,
+            tombstones: self.tombstones,
+            cohort: self.cohort,
+            require_self_visible: self.require_self_visible,
+            persistent_leader_term: self.persistent_leader_term,
+            push_updates: self.push_updates,
+            view_checks: self.view_checks,
+            leader_overrides: self.leader_overrides,
+            data_history_depth: self.data_history,
+            delta_updates,
+            degraded_while_backed_off: self.degraded_while_backed_off,
+            startup_policy: self.startup_policy,
+            extensions,
+
+            admitted: AtomicBool::new(false),
+            startup_failed: AtomicBool::new(false),
+            last_error: Mutex::new(None),
+            listing_backoff: Mutex::new(Default::default()),
+            draining: AtomicBool::new(false),
+            joining: AtomicBool::new(self.pre_registration),
+            maintenance: AtomicBool::new(false),
+            leader_claim: Mutex::new(None),
+            leader_term: Mutex::new(None),
+            view_hash: Mutex::new(None),
+            leader_override: Mutex::new(None),
+            diverged_updates: AtomicU32::new(0),
+            data_history: Mutex::new(HashMap::new()),
+            last_written: Mutex::new(None),
+            anomaly_detector: Mutex::new(AnomalyDetector::new(self.anomaly_rules)),
+            anomalies_detected: AtomicU64::new(0),
+            incompatible_peers: Mutex::new(HashSet::new()),
+            min_protocol: self.min_protocol,
+            queued_operations: Mutex::new(vec![]),
+            rejected_peers: Mutex::new(HashSet::new()),
+            cut_over: Mutex::new(None),
+            peers: Mutex::new(HashMap::new()),
+            heartbeats: Mutex::new(HashMap::new()),
+            last_tick: Mutex::new(None),
+            oversized_payloads: AtomicU64::new(0),
+            overruns: AtomicU64::new(0),
+            updates_completed: AtomicU64::new(0),
+            ticks: Ticks::default(),
+            solo_updates: AtomicU32::new(0),
+            seen_self: AtomicBool::new(false),
+            last_extracted: Mutex::new(None),
+            last_update_at: Mutex::new(None),
+            events: EventBus::new(),
+            timings: TimingsRecorder::new(),
+
+            state: Arc::new(modeled::RwLock::new(InstancesState {
+                tick: 0,
+                current_info: None,
+                stale: last_snapshot.is_some(),
+                self_visible: false,
+                leader: last_leader,
+                leader_epoch: 0,
+                succession: Arc::new(vec![]),
+                departed: Arc::new(vec![]),
+                cohort_leaders: Arc::new(HashMap::new()),
+                index: Arc::new(
+                    last_snapshot
+                        .as_deref()
+                        .map(index_by_id)
+                        .unwrap_or_default(),
+                ),
+                instances: Arc::new(last_snapshot.unwrap_or_default()),
+            })),
+
+            daemon: Arc::new(Mutex::new(None)),
+        });
+
+        Ok(service)
+    }
+}
+
+impl<B> Builder<B, ()>
+where
+    B: Backend<Registration<()>> + Send + Sync + 'static,
+{
+    /// For clusters that only need membership and leadership: the instances
+    /// publish no data, so no info extractor is required.
+    pub fn without_info(self) -> Self {
+        self.with_info_extractor(|| ())
+    }
+}
+
+impl<B> Builder<B, Value>
+where
+    B: Backend<Registration<Value>> + Send + Sync + 'static,
+{
+    /// Publishes what `extractor` returns under the `name` key of the instance
+    /// data, so several parts of an application can each contribute their own
+    /// section. Sections are added to the object returned by the info extractor,
+    /// if any; an extractor returning something other than an object is replaced.
+    pub fn with_info_section<S>(
+        mut self,
+        name: impl Into<String>,
+        extractor: impl Fn() -> S + Send + Sync + 'static,
+    ) -> Self
+    where
+        S: Serialize,
+    {
+        let name = name.into();
+        let base = self.info_extractor.take();
+
+        self.with_info_extractor(move || {
+            let mut data = match base.as_ref().map(|base| base()) {
+                Some(Value::Object(data)) => data,
+                _ => Map::new(),
+            };
+            let section = serde_json::to_value(extractor()).unwrap_or_else(|e| {
+                warn!(
+                    "Error serializing the '{}' info section. Cause: {}",
+                    name, e
+                );
+                Value::Null
+            });
+            data.insert(name.clone(), section);
+            Value::Object(data)
+        })
+    }
+}
+
+impl<B> Instances<B, ()>
+where
+    B: Backend<Registration<()>> + Send + Sync + 'static,
+{
+    /// Starts a data-less instance with the default settings, see
+    /// [`Builder::without_info`].
+    pub fn membership_only(backend: B, update_interval: Duration) -> Arc<Instances<B, ()>> {
+        Builder::default()
+            .with_backend(backend)
+            .with_update_interval(update_interval)
+            .without_info()
+            .build()
+    }
+}
+
+/// The update interval raised to the backend `minimum`, checked along with the
+/// suspicion grace against the staleness timeout. Shared by the builder and
+/// the configuration reloads.
+///
+/// The listing backoff's jitter needs no check: it's drawn below one update
+/// interval by construction, and isn't configurable.
+pub(crate) fn checked_timing(
+    interval: Duration,
+    suspicion: u32,
+    minimum: Option<Duration>,
+    staleness_policy: &dyn StalenessPolicy,
+) -> Result<Duration, ConfigError> {
+    if interval.is_zero() {
+        return Err(ConfigError::ZeroUpdateInterval);
+    }
+
+    let interval = match minimum {
+        Some(minimum) if interval < minimum => {
+            warn!(
+                "Update interval of {:?} is below the backend minimum, using {:?} instead.",
+                interval, minimum
+            );
+            minimum
+        }
+        _ => interval,
+    };
+
+    if let Some(timeout) = staleness_policy.timeout(interval) {
+        if timeout <= interval {
+            return Err(ConfigError::TimeoutNotAboveInterval(timeout, interval));
+        }
+        let grace = interval * suspicion;
+        if suspicion > 0 && grace >= timeout {
+            return Err(ConfigError::GraceNotBelowTimeout(grace, timeout));
+        }
+    }
+    Ok(interval)
+}
+
+#[derive(Error, PartialEq, Debug)]
+pub enum ConfigError {
+    #[error(r#"Missing required {0} configuration."#)]
+    Missing(&'static str),
+    #[error(r#"The update interval must be greater than zero."#)]
+    ZeroUpdateInterval,
+    #[error(r#"The staleness timeout of {0:?} must be longer than the update interval of {1:?}, otherwise healthy instances are dropped between heartbeats."#)]
+    TimeoutNotAboveInterval(Duration, Duration),
+    #[error(r#"The suspicion grace of {0:?} must be shorter than the staleness timeout of {1:?}, otherwise dead instances stay listed as suspects past it."#)]
+    GraceNotBelowTimeout(Duration, Duration),
+    #[error(r#"The cluster must allow at least one member."#)]
+    NoMembersAllowed,
+    #[error(r#"The configuration requires {0}, which the backend doesn't support."#)]
+    UnsupportedByBackend(&'static str),
+    #[error(r#"The minimum protocol {0} is above the protocol {1} written by this version."#)]
+    MinProtocolAboveCurrent(u32, u32),
+    /// The configured backend isn't compiled in, see `Builder::from_config`.
+    #[error(transparent)]
+    Backend(#[from] BackendError),
+}
+
+#[cfg(test)]
+mod tests {
+    use tracing_test::traced_test;
+    use uuid::Uuid;
+
+    use crate::backends::{Capabilities, MockBackend};
+    use crate::staleness::FixedTtl;
+
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "Missing required update interval configuration.")]
+    fn should_require_a_update_interval_config() {
+        let _ = Builder::default()
+            .with_backend(mock_backend())
+            .with_info_extractor(|| "data".to_string())
+            .with_error_strategy(CommunicationErrorStrategy::UseLastInfo)
+            .with_leader_strategy(LeaderStrategy::Oldest)
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing required backend configuration.")]
+    fn should_require_a_backend_config() {
+        let _ = Builder::<MockBackend<Registration<String>>, String>::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_info_extractor(|| "data".to_string())
+            .with_error_strategy(CommunicationErrorStrategy::UseLastInfo)
+            .with_leader_strategy(LeaderStrategy::Oldest)
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "Missing required info extractor configuration.")]
+    fn should_require_an_info_extractor_config() {
+        let _ = Builder::<MockBackend<Registration<String>>, String>::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(mock_backend())
+            .with_error_strategy(CommunicationErrorStrategy::UseLastInfo)
+            .with_leader_strategy(LeaderStrategy::Oldest)
+            .build();
+    }
+
+    #[test]
+    fn should_reject_a_minimum_protocol_above_the_current_one() {
+        let result = Builder::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(mock_backend())
+            .with_info_extractor(|| "data".to_string())
+            .with_min_protocol(PROTOCOL_VERSION + 1)
+            .try_build();
+
+        assert_eq!(
+            ConfigError::MinProtocolAboveCurrent(PROTOCOL_VERSION + 1, PROTOCOL_VERSION),
+            result.err().unwrap()
+        );
+    }
+
+    #[test]
+    fn should_reject_a_staleness_timeout_within_the_interval() {
+        let result = Builder::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(mock_backend())
+            .with_info_extractor(|| "data".to_string())
+            .with_staleness_policy(FixedTtl(Duration::from_secs(10)))
+            .try_build();
+
+        assert_eq!(
+            ConfigError::TimeoutNotAboveInterval(Duration::from_secs(10), Duration::from_secs(10)),
+            result.err().unwrap()
+        );
+    }
+
+    #[test]
+    fn should_reject_a_janitor_the_backend_cannot_run() {
+        let mut backend = mock_backend();
+        backend
+            .expect_capabilities()
+            .returning(Capabilities::default);
+
+        let result = Builder::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(backend)
+            .with_info_extractor(|| "data".to_string())
+            .with_janitor(Duration::from_secs(60), Duration::from_secs(600))
+            .try_build();
+
+        assert_eq!(
+            ConfigError::UnsupportedByBackend("pruning"),
+            result.err().unwrap()
+        );
+    }
+
+    #[test]
+    fn should_reject_a_leader_the_backend_cannot_designate() {
+        let mut backend = mock_backend();
+        backend
+            .expect_capabilities()
+            .returning(Capabilities::default);
+
+        let result = Builder::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(backend)
+            .with_info_extractor(|| "data".to_string())
+            .with_leader_strategy(LeaderStrategy::Backend)
+            .try_build();
+
+        assert_eq!(
+            ConfigError::UnsupportedByBackend("designated leaders"),
+            result.err().unwrap()
+        );
+    }
+
+    #[test]
+    fn should_reject_invalid_limits() {
+        let zero_interval = Builder::default()
+            .with_update_interval(Duration::ZERO)
+            .with_backend(mock_backend())
+            .with_info_extractor(|| "data".to_string())
+            .try_build();
+        let no_members = Builder::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(mock_backend())
+            .with_info_extractor(|| "data".to_string())
+            .with_max_members(0)
+            .try_build();
+
+        assert_eq!(
+            ConfigError::ZeroUpdateInterval,
+            zero_interval.err().unwrap()
+        );
+        assert_eq!(ConfigError::NoMembersAllowed, no_members.err().unwrap());
+    }
+
+    #[test]
+    fn should_reject_a_suspicion_grace_past_the_staleness_timeout() {
+        let build = |suspicion| {
+            Builder::default()
+                .with_update_interval(Duration::from_secs(1))
+                .with_backend(mock_backend())
+                .with_info_extractor(|| "data".to_string())
+                .with_staleness_policy(FixedTtl(Duration::from_secs(5)))
+                .with_suspicion(suspicion)
+                .build_without_daemon()
+        };
+
+        assert!(build(4).is_ok());
+        assert_eq!(
+            Some(ConfigError::GraceNotBelowTimeout(
+                Duration::from_secs(5),
+                Duration::from_secs(5)
+            )),
+            build(5).err()
+        );
+    }
+
+    #[test]
+    #[traced_test]
+    fn should_raise_the_interval_to_the_backend_minimum() {
+        let mut backend = MockBackend::new();
+        backend.expect_max_payload_size().returning(|| None);
+        backend
+            .expect_min_update_interval()
+            .returning(|| Some(Duration::from_secs(5)));
+        backend.expect_identity().returning(|| "mock".to_string());
+
+        let instance = Builder::default()
+            .with_update_interval(Duration::from_secs(1))
+            .with_backend(backend)
+            .with_info_extractor(|| "data".to_string())
+            .build();
+
+        assert_eq!(Duration::from_secs(5), instance.settings().update_interval);
+        assert!(logs_contain("below the backend minimum"));
+    }
+
+    #[test]
+    fn should_build_a_membership_only_instance() {
+        let mut backend = MockBackend::<Registration<()>>::new();
+        backend.expect_max_payload_size().returning(|| None);
+        backend.expect_min_update_interval().returning(|| None);
+        backend.expect_identity().returning(|| "mock".to_string());
+
+        let instance = Instances::membership_only(backend, Duration::from_secs(10));
+
+        assert_eq!((), (instance.info_extractor)());
+    }
+
+    #[test]
+    fn should_merge_the_info_sections() {
+        let mut backend = MockBackend::<Registration<Value>>::new();
+        backend.expect_max_payload_size().returning(|| None);
+        backend.expect_min_update_interval().returning(|| None);
+        backend.expect_identity().returning(|| "mock".to_string());
+
+        let instance = Builder::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(backend)
+            .with_info_extractor(|| serde_json::json!({"version": "1.0.0"}))
+            .with_info_section("web", || serde_json::json!({"port": 8080}))
+            .with_info_section("queue", || 3)
+            .build();
+
+        assert_eq!(
+            serde_json::json!({"version": "1.0.0", "web": {"port": 8080}, "queue": 3}),
+            (instance.info_extractor)()
+        );
+    }
+
+    #[test]
+    fn should_build_an_instance() {
+        let instance = Builder::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(mock_backend())
+            .with_info_extractor(|| "data".to_string())
+            .with_error_strategy(CommunicationErrorStrategy::UseLastInfo)
+            .with_leader_strategy(LeaderStrategy::Oldest)
+            .build();
+
+        assert_eq!(
+            CommunicationErrorStrategy::UseLastInfo,
+            instance.settings().error_strategy
+        );
+        assert_eq!(LeaderStrategy::Oldest, instance.leader_strategy);
+    }
+
+    #[test]
+    fn should_build_an_instance_with_defaults() {
+        let instance = Builder::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(mock_backend())
+            .with_info_extractor(|| "data".to_string())
+            .build();
+
+        assert_eq!(
+            CommunicationErrorStrategy::Error,
+            instance.settings().error_strategy
+        );
+        assert_eq!(LeaderStrategy::None, instance.leader_strategy);
+        assert_eq!(Consistency::Eventual, instance.consistency);
+    }
+
+    #[test]
+    fn should_use_the_lowest_payload_size_limit() {
+        let mut backend = MockBackend::new();
+        backend.expect_max_payload_size().returning(|| Some(400));
+        backend.expect_min_update_interval().returning(|| None);
+        backend.expect_identity().returning(|| "mock".to_string());
+
+        let instance = Builder::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(backend)
+            .with_info_extractor(|| "data".to_string())
+            .with_max_payload_size(1024)
+            .build();
+
+        assert_eq!(Some(400), instance.max_payload_size);
+    }
+
+    #[test]
+    fn should_load_a_stale_snapshot_on_startup() {
+        let path = std::env::temp_dir().join(format!("instances-rs-{}.json", Uuid::new_v4()));
+        let id = Uuid::new_v4();
+        snapshot::save(
+            &path,
+            &[crate::InstanceInfo {
+                id,
+                role: InstanceRole::Leader,
+                status: crate::models::InstanceStatus::Active,
+                kind: InstanceKind::Member,
+                maintenance: false,
+                cohort: None,
+                leader_term: None,
+                view_hash: None,
+                version: None,
+                latency: None,
+                suspect: false,
+                data: "data".to_string(),
+                extensions: Default::default(),
+            }],
+        )
+        .unwrap();
+
+        let instance = Builder::default()
+            .with_update_interval(Duration::from_secs(10))
+            .with_backend(mock_backend())
+            .with_info_extractor(|| "data".to_string())
+            .with_error_strategy(CommunicationErrorStrategy::UseLastInfo)
+            .with_snapshot_file(&path)
+            .build();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(instance.is_stale());
+        assert_eq!(id, instance.list_active_instances()[0].id);
+        assert_eq!(id, instance.current_leader().unwrap().id);
+        assert!(instance.get_instance_info().is_none());
+    }
+
+    #[test]
+    fn should_apply_the_timing_config() {
+        let timing: TimingConfig =
+            serde_json::from_str(r#"{"update_interval_ms": 2000, "ttl_ms": 1000, "suspicion": 2}"#)
+                .unwrap();
+
+        let result = Builder::default()
+            .with_backend(mock_backend())
+            .with_info_extractor(|| "data".to_string())
+            .with_timing(timing)
+            .build_without_daemon();
+
+        assert_eq!(
+            Some(ConfigError::TimeoutNotAboveInterval(
+                Duration::from_secs(1),
+                Duration::from_secs(2)
+            )),
+            result.err()
+        );
+
+        let instance = Builder::default()
+            .with_backend(mock_backend())
+            .with_info_extractor(|| "data".to_string())
+            .with_timing(TimingConfig {
+                ttl_ms: Some(6000),
+                ..timing
+            })
+            .build_without_daemon()
+            .unwrap();
+
+        assert_eq!(Duration::from_secs(2), instance.settings().update_interval);
+        assert_eq!(2, instance.settings().suspicion);
+        assert_eq!(1, instance.extract_every);
+    }
+
+    #[test]
+    fn should_report_the_backends_missing_from_the_build() {
+        let error = Builder::<MockBackend<Registration<String>>, String>::from_config(
+            "etcd://host:2379",
+            |_| unreachable!("No backend to connect to."),
+        )
+        .err()
+        .unwrap();
+
+        match error {
+            ConfigError::Backend(BackendError::BackendNotFound {
+                value,
+                available,
+                missing_feature,
+            }) => {
+                assert_eq!("etcd://host:2379", value);
+                assert_eq!(crate::backends::available(), available);
+                assert_eq!(None, missing_feature);
+            }
+            error => panic!("Unexpected error: {:?}", error),
+        }
+    }
+
+    #[cfg(not(feature = "backend-mysql"))]
+    #[test]
+    fn should_name_the_feature_of_a_backend_left_out() {
+        let error = Builder::<MockBackend<Registration<String>>, String>::from_config(
+            "mariadb://db-1",
+            |_| unreachable!("No backend to connect to."),
+        )
+        .err()
+        .unwrap();
+
+        assert!(matches!(
+            error,
+            ConfigError::Backend(BackendError::BackendNotFound {
+                missing_feature: Some("backend-mysql"),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn should_build_the_configured_backend() {
+        let var = format!("INSTANCES_BACKEND_{}", Uuid::new_v4().to_simple());
+        env::set_var(&var, "memory");
+        let mut connected = None;
+
+        let instance = Builder::from_env(&var, |config| {
+            connected = Some(config);
+            mock_backend()
+        })
+        .unwrap()
+        .with_update_interval(Duration::from_secs(10))
+        .with_info_extractor(|| "data".to_string())
+        .build_without_daemon();
+        env::remove_var(&var);
+
+        assert!(instance.is_ok());
+        assert_eq!(
+            Some(BackendConfig {
+                backend_type: crate::backends::BackendType::Memory,
+                url: None,
+            }),
+            connected
+        );
+        assert_eq!(
+            ConfigError::Missing("backend"),
+            Builder::<MockBackend<Registration<String>>, String>::from_env(&var, |_| {
+                mock_backend()
+            })
+            .err()
+            .unwrap()
+        );
+    }
+
+    fn mock_backend() -> MockBackend<Registration<String>> {
+        let mut backend = MockBackend::new();
+        backend.expect_max_payload_size().returning(|| None);
+        backend.expect_min_update_interval().returning(|| None);
+        backend.expect_identity().returning(|| "mock".to_string());
+        backend
+    }
+}
@@ -0,0 +1,880 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{select, Receiver, Sender};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{error, info, span, warn, Level};
+use uuid::Uuid;
+
+use crate::models::StartupFallback;
+use crate::sync::modeled;
+use crate::sync::MutexExt;
+use crate::{Backend, Instances, Registration};
+
+#[cfg(feature = "tokio")]
+mod tasks;
+
+#[cfg(feature = "tokio")]
+pub use tasks::start_async_daemon;
+
+/// First delay between the retries of the first update, doubled after each
+/// attempt up to the update interval.
+const STARTUP_BACKOFF: Duration = Duration::from_millis(50);
+
+pub struct UpdateDaemon {
+    stopped: StopFlag,
+    runner: Runner,
+}
+
+/// What runs the updates: a thread of the daemon's own or, with the `tokio`
+/// feature, a task of the runtime.
+enum Runner {
+    Thread {
+        stop_signal: Option<Sender<()>>,
+        handle: Option<JoinHandle<()>>,
+    },
+    #[cfg(feature = "tokio")]
+    Task {
+        task: tokio::task::JoinHandle<()>,
+        /// Held while an update runs on the runtime's blocking pool.
+        in_flight: Arc<std::sync::Mutex<()>>,
+    },
+}
+
+/// Set when the daemon is stopped and checked before every update: the stop
+/// signal only wakes the daemon up, and `select!` may pick a tick that is
+/// due at the same time.
+#[derive(Clone, Default)]
+struct StopFlag(modeled::Arc<modeled::atomic::AtomicBool>);
+
+impl StopFlag {
+    fn set(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    fn is_set(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Starts updating `service` at its update interval. The daemon only keeps a
+/// weak reference, so it stops by itself once the service is dropped.
+pub fn start_daemon<B, T>(service: &Arc<Instances<B, T>>) -> UpdateDaemon
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    let (stop_signal, stop_received) = crossbeam_channel::bounded(0);
+    let stopped = StopFlag::default();
+
+    let update_interval = service.settings().update_interval;
+    let changes = watch_changes(service);
+    let handle = spawn_daemon(
+        update_interval,
+        stopped.clone(),
+        stop_received,
+        changes,
+        Arc::downgrade(service),
+    );
+
+    UpdateDaemon {
+        stopped,
+        runner: Runner::Thread {
+            stop_signal: Some(stop_signal),
+            handle: Some(handle),
+        },
+    }
+}
+
+/// The changes pushed by the backend when `Builder::with_push_updates` is set,
+/// or a channel that never receives anything to only poll.
+fn watch_changes<B, T>(service: &Instances<B, T>) -> Receiver<Uuid>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    if !service.push_updates {
+        return crossbeam_channel::never();
+    }
+    match service.backend.watch_changes() {
+        Ok(changes) => changes,
+        Err(error) => {
+            warn!(
+                "Change notifications unavailable, polling instead. Cause: {}",
+                error
+            );
+            crossbeam_channel::never()
+        }
+    }
+}
+
+fn spawn_daemon<B, T>(
+    update_interval: Duration,
+    stopped: StopFlag,
+    stop_received: Receiver<()>,
+    mut changes: Receiver<Uuid>,
+    service: Weak<Instances<B, T>>,
+) -> JoinHandle<()>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    let mut interval = update_interval;
+
+    thread::spawn(move || {
+        let skip_update = match run_startup(&service, &stopped, &stop_received) {
+            Some(updated) => updated,
+            None => return,
+        };
+        let mut ticker = crossbeam_channel::tick(interval);
+        let own_id = match service.upgrade() {
+            Some(service) => service.instance_id,
+            None => return,
+        };
+
+        run_loop(&stopped, skip_update, |skip_update| {
+            if !skip_update {
+                let service = service.upgrade()?;
+                run_tick(&service, interval, &ticker);
+            }
+
+            let (reloaded, resume_in) = {
+                let service = service.upgrade()?;
+                (
+                    service.settings().update_interval,
+                    service.listing_resume_in(),
+                )
+            };
+            if reloaded != interval {
+                interval = reloaded;
+                ticker = crossbeam_channel::tick(interval);
+            }
+            // A backed off listing is probed as soon as it may run again
+            // instead of at the next tick.
+            let probe = match resume_in {
+                Some(resume_in) => crossbeam_channel::after(resume_in),
+                None => crossbeam_channel::never(),
+            };
+            select! {
+                recv(ticker) -> _ => Some(false),
+                recv(probe) -> _ => Some(false),
+                recv(changes) -> change => match change {
+                    // The own writes are already reflected by the update.
+                    Ok(id) if id == own_id => Some(true),
+                    // A burst of changes is handled by a single update.
+                    Ok(_) => {
+                        while changes.try_recv().is_ok() {}
+                        Some(false)
+                    }
+                    Err(_) => {
+                        warn!("Change notifications stopped, polling instead.");
+                        changes = crossbeam_channel::never();
+                        Some(true)
+                    }
+                },
+                recv(stop_received) -> _ => None,
+            }
+        });
+    })
+}
+
+/// The daemon's loop, checking `stopped` before every wake up: `wake_up`
+/// runs the update, unless told to skip it, and waits for the next one. It
+/// returns whether to skip the next update, or `None` to stop.
+fn run_loop(
+    stopped: &StopFlag,
+    mut skip_update: bool,
+    mut wake_up: impl FnMut(bool) -> Option<bool>,
+) {
+    while !stopped.is_set() {
+        match wake_up(skip_update) {
+            Some(skip_next) => skip_update = skip_next,
+            None => break,
+        }
+    }
+}
+
+/// Retries the first update following the service's `StartupPolicy`, if any.
+///
+/// Returns whether the update already ran (and the loop should wait for the
+/// next tick), or `None` if the daemon must stop.
+fn run_startup<B, T>(
+    service: &Weak<Instances<B, T>>,
+    stopped: &StopFlag,
+    stop_received: &Receiver<()>,
+) -> Option<bool>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    let policy = match service.upgrade()?.startup_policy {
+        Some(policy) => policy,
+        None => return Some(false),
+    };
+    let deadline = Instant::now() + policy.retry_for;
+    let mut backoff = STARTUP_BACKOFF;
+
+    loop {
+        if stopped.is_set() {
+            return None;
+        }
+        let service = service.upgrade()?;
+        if run_update(&service) && service.last_error.lock_or_recover().is_none() {
+            return Some(true);
+        }
+
+        if Instant::now() >= deadline {
+            return startup_fallback(&service, policy.then);
+        }
+
+        backoff = backoff.min(service.settings().update_interval);
+        drop(service);
+        select! {
+            recv(stop_received) -> _ => return None,
+            default(backoff) => {},
+        }
+        backoff *= 2;
+    }
+}
+
+/// What to do once the first update kept failing for the whole `retry_for`:
+/// `None` if the daemon must stop.
+fn startup_fallback<B, T>(service: &Instances<B, T>, then: StartupFallback) -> Option<bool>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    match then {
+        StartupFallback::GiveUp => {
+            error!("The first update kept failing, giving up.");
+            service.startup_failed.store(true, Ordering::SeqCst);
+            None
+        }
+        StartupFallback::KeepRetrying => {
+            warn!("The first update kept failing, retrying at the update interval.");
+            Some(true)
+        }
+    }
+}
+
+fn run_tick<B, T>(service: &Instances<B, T>, interval: Duration, ticker: &Receiver<Instant>)
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    let span = span!(Level::INFO, "instances-rs_update_instance_info");
+    let _guard = span.enter();
+    let started = Instant::now();
+    service.detect_clock_gap();
+    service.reload_config();
+    service.events.flush();
+    run_update(service);
+    if service.take_recovery() {
+        info!("The backend recovered, refreshing the instances info right away.");
+        run_update(service);
+    }
+    run_extensions(service);
+
+    let elapsed = started.elapsed();
+    if elapsed > interval {
+        service.record_overrun(elapsed);
+        while ticker.try_recv().is_ok() {}
+    }
+}
+
+/// Runs one update, keeping the daemon alive if it panics (e.g. in the info
+/// extractor). Failures are logged by the update and retried on the next tick.
+/// Returns whether the update ran to completion.
+fn run_update<B, T>(service: &Instances<B, T>) -> bool
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    match panic::catch_unwind(AssertUnwindSafe(|| service.update_instance_info())) {
+        Ok(_) => true,
+        Err(_) => {
+            error!("The update panicked, retrying on the next tick.");
+            false
+        }
+    }
+}
+
+/// Runs the extensions, keeping the daemon alive if one of them panics.
+fn run_extensions<B, T>(service: &Instances<B, T>)
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    if panic::catch_unwind(AssertUnwindSafe(|| service.run_extensions())).is_err() {
+        error!("An extension panicked, running it again on the next tick.");
+    }
+}
+
+impl UpdateDaemon {
+    /// Stops the daemon and waits for an in-flight update to finish, so no
+    /// write can reach the backend after this returns.
+    pub fn stop(mut self) {
+        self.stopped.set();
+        match &mut self.runner {
+            Runner::Thread {
+                stop_signal,
+                handle,
+            } => {
+                stop_signal.take();
+                if let Some(handle) = handle.take() {
+                    if handle.thread().id() != thread::current().id() {
+                        let _ = handle.join();
+                    }
+                }
+            }
+            #[cfg(feature = "tokio")]
+            Runner::Task { task, in_flight } => {
+                task.abort();
+                if !tasks::UPDATING.get() {
+                    drop(in_flight.lock_or_recover());
+                }
+            }
+        }
+    }
+}
+
+impl Drop for UpdateDaemon {
+    fn drop(&mut self) {
+        self.stopped.set();
+        match &mut self.runner {
+            Runner::Thread { stop_signal, .. } => {
+                stop_signal.take();
+            }
+            #[cfg(feature = "tokio")]
+            Runner::Task { task, .. } => task.abort(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::SystemTime;
+
+    use mockall::predicate::eq;
+    use tracing_test::traced_test;
+    use uuid::Uuid;
+
+    use crate::backends::{ConnectionError, InstanceRecord, MockBackend};
+    use crate::events::InstancesEvent;
+    use crate::extension::{Extension, TickContext};
+    use crate::models::StartupPolicy;
+    use crate::random::RandomSource;
+    use crate::tests::{new_instance, registration};
+    use crate::{CommunicationErrorStrategy, InstancesError, LeaderStrategy};
+
+    use super::*;
+
+    #[test]
+    #[traced_test]
+    fn should_execute_the_first_update_immediately() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+
+        backend
+            .expect_update_instance_info()
+            .with(eq(id), eq(registration()))
+            .returning(|_, _| Ok(()));
+
+        backend.expect_list_active_instances().returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+        let instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        );
+        instances.settings.write().unwrap().update_interval = Duration::from_secs(5);
+        let instances = Arc::new(instances);
+
+        assert!(instances.get_instance_info().is_none());
+
+        let _daemon = start_daemon(&instances);
+        instances
+            .wait_for_first_update(Duration::from_millis(100))
+            .unwrap();
+
+        assert!(instances.get_instance_info().is_some());
+        drop(_daemon);
+    }
+
+    #[test]
+    #[traced_test]
+    fn should_execute_the_update_5_times() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+
+        backend
+            .expect_update_instance_info()
+            .with(eq(id), eq(registration()))
+            .times(5)
+            .returning(|_, _| Ok(()));
+
+        backend
+            .expect_list_active_instances()
+            .times(5)
+            .returning(move || {
+                Ok(vec![InstanceRecord::new(
+                    id,
+                    SystemTime::now(),
+                    registration(),
+                )])
+            });
+
+        let instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        );
+        instances.settings.write().unwrap().update_interval = Duration::from_millis(50);
+        let instances = Arc::new(instances);
+
+        assert!(instances.get_instance_info().is_none());
+
+        let _daemon = start_daemon(&instances);
+        thread::sleep(Duration::from_millis(230));
+        drop(_daemon);
+
+        assert!(instances.get_instance_info().is_some());
+    }
+
+    #[test]
+    #[traced_test]
+    fn should_stop_once_the_service_is_dropped() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend.expect_list_active_instances().returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+        let instances = Arc::new(new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        ));
+        let daemon = start_daemon(&instances);
+        *instances.daemon.lock().unwrap() = Some(daemon);
+        instances
+            .wait_for_first_update(Duration::from_millis(100))
+            .unwrap();
+
+        let weak = instances.downgrade();
+        drop(instances);
+        thread::sleep(Duration::from_millis(30));
+
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn should_not_update_after_being_stopped_from_another_thread() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+        let updates = Arc::new(AtomicUsize::new(0));
+
+        let counter = updates.clone();
+        backend
+            .expect_update_instance_info()
+            .returning(move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            });
+        backend.expect_list_active_instances().returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+        let instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        );
+        instances.settings.write().unwrap().update_interval = Duration::from_millis(1);
+        let instances = Arc::new(instances);
+        let daemon = start_daemon(&instances);
+        thread::sleep(Duration::from_millis(20));
+
+        thread::spawn(move || daemon.stop()).join().unwrap();
+        let stopped_at = updates.load(Ordering::SeqCst);
+        thread::sleep(Duration::from_millis(20));
+
+        assert!(stopped_at > 0);
+        assert_eq!(stopped_at, updates.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    #[traced_test]
+    fn should_skip_the_ticks_missed_by_slow_updates() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+
+        backend.expect_update_instance_info().returning(|_, _| {
+            thread::sleep(Duration::from_millis(30));
+            Ok(())
+        });
+        backend
+            .expect_list_active_instances()
+            .returning(|| Ok(vec![]));
+
+        let instances = Arc::new(new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        ));
+        let events = instances.subscribe();
+
+        let daemon = start_daemon(&instances);
+        let event = events.recv_timeout(Duration::from_millis(200)).unwrap();
+        daemon.stop();
+
+        assert!(
+            matches!(event, InstancesEvent::UpdateOverrun(d) if d >= Duration::from_millis(30))
+        );
+        assert!(instances.overruns() >= 1);
+    }
+
+    /// Backs off the listing for exactly the doubled update intervals.
+    struct NoJitter;
+
+    impl RandomSource for NoJitter {
+        fn next_u64(&self) -> u64 {
+            0
+        }
+    }
+
+    #[test]
+    fn should_refresh_right_away_once_the_backend_recovers() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let mut sequence = mockall::Sequence::new();
+        let id = Uuid::new_v4();
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend
+            .expect_list_active_instances()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|| Ok(vec![]));
+        backend
+            .expect_list_active_instances()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|| Err(ConnectionError::FailedToRetrieve("error".to_string())));
+        backend
+            .expect_list_active_instances()
+            .returning(|| Ok(vec![]));
+
+        let mut instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::UseLastInfo,
+        );
+        instances.settings.get_mut().unwrap().update_interval = Duration::from_millis(100);
+        instances.random = Arc::new(NoJitter);
+        let instances = Arc::new(instances);
+
+        let daemon = start_daemon(&instances);
+        // Listing fails at 100ms and is backed off until 300ms, where it's
+        // probed and refreshed again, well before the tick at 400ms.
+        thread::sleep(Duration::from_millis(350));
+        daemon.stop();
+
+        assert_eq!(3, instances.updates_completed());
+    }
+
+    #[test]
+    fn should_keep_updating_after_a_panicking_extractor() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend.expect_list_active_instances().returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+        let mut instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        );
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        instances.info_extractor = Box::new(move || {
+            if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("extractor failure");
+            }
+            "data".to_string()
+        });
+        let instances = Arc::new(instances);
+
+        let _daemon = start_daemon(&instances);
+        // Not a fixed wait: printing the panic's backtrace takes a while.
+        instances
+            .wait_for_first_update(Duration::from_secs(5))
+            .unwrap();
+
+        assert!(instances.updates_completed() >= 1);
+        assert!(instances.get_instance_info().is_some());
+    }
+
+    #[test]
+    fn should_run_the_extensions_after_every_update() {
+        #[derive(Default)]
+        struct UpdatesSeen(std::sync::atomic::AtomicU32);
+
+        impl Extension<MockBackend<Registration<String>>, String> for UpdatesSeen {
+            fn on_update(
+                &self,
+                _: &Instances<MockBackend<Registration<String>>, String>,
+                tick: &TickContext<String>,
+            ) {
+                if tick.current_info().is_some() && tick.instances().len() == 1 {
+                    self.0.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend.expect_list_active_instances().returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+        let mut instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        );
+        instances.extensions.register(UpdatesSeen::default());
+        let instances = Arc::new(instances);
+
+        let _daemon = start_daemon(&instances);
+        thread::sleep(Duration::from_millis(50));
+
+        let seen = instances.extension::<UpdatesSeen>().unwrap();
+        assert!(seen.0.load(Ordering::SeqCst) >= 1);
+    }
+
+    #[test]
+    fn should_update_right_away_on_pushed_changes() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+        let (push, changes) = crossbeam_channel::unbounded();
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend.expect_list_active_instances().returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+        backend
+            .expect_watch_changes()
+            .times(1)
+            .return_once(move || Ok(changes));
+
+        let mut instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        );
+        instances.push_updates = true;
+        instances.settings.get_mut().unwrap().update_interval = Duration::from_secs(5);
+        let instances = Arc::new(instances);
+
+        let _daemon = start_daemon(&instances);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(1, instances.updates_completed());
+
+        push.send(id).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(1, instances.updates_completed());
+
+        push.send(Uuid::new_v4()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(2, instances.updates_completed());
+    }
+
+    #[test]
+    fn should_retry_the_first_update_with_backoff() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let mut sequence = mockall::Sequence::new();
+        let id = Uuid::new_v4();
+
+        backend
+            .expect_update_instance_info()
+            .times(2)
+            .in_sequence(&mut sequence)
+            .returning(|_, _| Err(ConnectionError::FailedToUpdate("error".to_string())));
+        backend
+            .expect_update_instance_info()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_, _| Ok(()));
+        backend
+            .expect_list_active_instances()
+            .returning(|| Ok(vec![]));
+
+        let mut instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        );
+        instances.settings.get_mut().unwrap().update_interval = Duration::from_secs(5);
+        instances.startup_policy = Some(StartupPolicy {
+            retry_for: Duration::from_secs(1),
+            then: StartupFallback::GiveUp,
+        });
+        let instances = Arc::new(instances);
+
+        let _daemon = start_daemon(&instances);
+
+        assert_eq!(
+            Ok(()),
+            instances.wait_for_first_update(Duration::from_millis(500))
+        );
+    }
+
+    #[test]
+    fn should_give_up_on_a_failing_first_update() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Err(ConnectionError::FailedToUpdate("error".to_string())));
+
+        let mut instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::UseLastInfo,
+        );
+        instances.startup_policy = Some(StartupPolicy {
+            retry_for: Duration::from_millis(100),
+            then: StartupFallback::GiveUp,
+        });
+        let instances = Arc::new(instances);
+
+        let _daemon = start_daemon(&instances);
+        let started = Instant::now();
+        let result = instances.wait_for_first_update(Duration::from_secs(5));
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(matches!(
+            result,
+            Err(InstancesError::Backend(ConnectionError::Failed(_, _, _)))
+        ));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod model_checks {
+    use loom::sync::atomic::AtomicUsize;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::*;
+
+    /// The daemon's loop, the first update stopping the daemon from its own
+    /// thread, as a callback may. `UpdateDaemon::stop` then can't join the
+    /// thread.
+    fn run(stopped: StopFlag, updates: Arc<AtomicUsize>, wake_ups: usize) {
+        let mut woken_up = 0;
+        run_loop(&stopped, false, |_| {
+            updates.fetch_add(1, Ordering::SeqCst);
+            stopped.set();
+            woken_up += 1;
+            (woken_up < wake_ups).then_some(false)
+        });
+    }
+
+    #[test]
+    fn should_not_update_again_once_stopped_from_its_own_thread() {
+        loom::model(|| {
+            let stopped = StopFlag::default();
+            let updates = Arc::new(AtomicUsize::new(0));
+
+            let daemon = {
+                let (stopped, updates) = (stopped.clone(), updates.clone());
+                thread::spawn(move || run(stopped, updates, 3))
+            };
+            stopped.set();
+            daemon.join().unwrap();
+
+            assert!(updates.load(Ordering::SeqCst) <= 1);
+        });
+    }
+
+    #[test]
+    fn should_see_what_was_written_before_the_stop() {
+        loom::model(|| {
+            let stopped = StopFlag::default();
+            let drained = Arc::new(AtomicUsize::new(0));
+
+            let daemon = {
+                let (stopped, drained) = (stopped.clone(), drained.clone());
+                thread::spawn(move || {
+                    if stopped.is_set() {
+                        assert_eq!(1, drained.load(Ordering::Relaxed));
+                    }
+                })
+            };
+            drained.store(1, Ordering::Relaxed);
+            stopped.set();
+            daemon.join().unwrap();
+        });
+    }
+}
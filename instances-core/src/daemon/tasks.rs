@@ -0,0 +1,339 @@
+//! The update daemon as a task of a Tokio runtime (feature `tokio`), ticking
+//! with `tokio::time::interval` instead of a thread of its own. `Backend` is
+//! blocking, so every update borrows a thread of the runtime's blocking pool
+//! while it runs.
+
+use std::cell::Cell;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::time::{self, Interval, MissedTickBehavior};
+use tracing::warn;
+
+use super::{
+    run_tick, run_update, startup_fallback, Runner, StopFlag, UpdateDaemon, STARTUP_BACKOFF,
+};
+use crate::sync::MutexExt;
+use crate::{Backend, Instances, Registration};
+
+thread_local! {
+    /// Whether the thread is running an update of the task, so that stopping
+    /// the daemon from it (e.g. draining from a callback) doesn't wait for
+    /// itself.
+    pub(super) static UPDATING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Starts updating `service` at its update interval, as a task of the current
+/// Tokio runtime. The daemon only keeps a weak reference, so it stops by
+/// itself once the service is dropped.
+///
+/// The changes pushed by the backend (`Builder::with_push_updates`) aren't
+/// received, the task only polls.
+///
+/// # Panics
+///
+/// Outside of a Tokio runtime, as `tokio::spawn` does.
+pub fn start_async_daemon<B, T>(service: &Arc<Instances<B, T>>) -> UpdateDaemon
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    if service.push_updates {
+        warn!("The async daemon doesn't receive the pushed changes, polling instead.");
+    }
+    let stopped = StopFlag::default();
+    let in_flight = Arc::new(Mutex::new(()));
+
+    let daemon = AsyncDaemon {
+        service: Arc::downgrade(service),
+        stopped: stopped.clone(),
+        in_flight: in_flight.clone(),
+    };
+    let task = tokio::spawn(daemon.run(service.settings().update_interval));
+
+    UpdateDaemon {
+        stopped,
+        runner: Runner::Task { task, in_flight },
+    }
+}
+
+struct AsyncDaemon<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    service: Weak<Instances<B, T>>,
+    stopped: StopFlag,
+    in_flight: Arc<Mutex<()>>,
+}
+
+impl<B, T> AsyncDaemon<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    async fn run(self, mut interval: Duration) {
+        let mut skip_update = match self.run_startup().await {
+            Some(updated) => updated,
+            None => return,
+        };
+        let mut ticker = interval_ticker(interval);
+
+        loop {
+            if !skip_update {
+                let updated = self
+                    .on_blocking_pool(move |service| {
+                        // The interval skips the missed ticks by itself.
+                        run_tick(service, interval, &crossbeam_channel::never())
+                    })
+                    .await;
+                if updated.is_none() {
+                    break;
+                }
+            }
+            skip_update = false;
+
+            let (reloaded, resume_in) = match self.service.upgrade() {
+                Some(service) => (
+                    service.settings().update_interval,
+                    service.listing_resume_in(),
+                ),
+                None => break,
+            };
+            if reloaded != interval {
+                interval = reloaded;
+                ticker = interval_ticker(interval);
+            }
+            // A backed off listing is probed as soon as it may run again
+            // instead of at the next tick.
+            match resume_in {
+                Some(resume_in) => tokio::select! {
+                    _ = ticker.tick() => {},
+                    _ = time::sleep(resume_in) => {},
+                },
+                None => {
+                    ticker.tick().await;
+                }
+            }
+        }
+    }
+
+    /// Retries the first update following the service's `StartupPolicy`, as
+    /// the daemon's thread does.
+    async fn run_startup(&self) -> Option<bool> {
+        let policy = match self.service.upgrade()?.startup_policy {
+            Some(policy) => policy,
+            None => return Some(false),
+        };
+        let deadline = Instant::now() + policy.retry_for;
+        let mut backoff = STARTUP_BACKOFF;
+
+        loop {
+            let succeeded = self
+                .on_blocking_pool(|service| {
+                    run_update(service) && service.last_error.lock_or_recover().is_none()
+                })
+                .await?;
+            if succeeded {
+                return Some(true);
+            }
+
+            let service = self.service.upgrade()?;
+            if Instant::now() >= deadline {
+                return startup_fallback(&service, policy.then);
+            }
+            backoff = backoff.min(service.settings().update_interval);
+            drop(service);
+            time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    /// Runs `work` on the runtime's blocking pool, unless the daemon was
+    /// stopped or the service dropped meanwhile, in which case it's `None`.
+    async fn on_blocking_pool<R>(
+        &self,
+        work: impl FnOnce(&Instances<B, T>) -> R + Send + 'static,
+    ) -> Option<R>
+    where
+        R: Send + 'static,
+    {
+        let (service, stopped, in_flight) = (
+            self.service.clone(),
+            self.stopped.clone(),
+            self.in_flight.clone(),
+        );
+        let update = tokio::task::spawn_blocking(move || {
+            let _in_flight = in_flight.lock_or_recover();
+            if stopped.is_set() {
+                return None;
+            }
+            let service = service.upgrade()?;
+            let _updating = Updating::enter();
+            Some(work(&service))
+        });
+        update.await.ok().flatten()
+    }
+}
+
+/// Flags the thread as running an update until dropped, even by a panic.
+struct Updating;
+
+impl Updating {
+    fn enter() -> Self {
+        UPDATING.set(true);
+        Updating
+    }
+}
+
+impl Drop for Updating {
+    fn drop(&mut self) {
+        UPDATING.set(false);
+    }
+}
+
+/// Ticks every `interval`, the first time one `interval` from now, skipping
+/// the ticks missed by slow updates.
+fn interval_ticker(interval: Duration) -> Interval {
+    let mut ticker = time::interval_at(time::Instant::now() + interval, interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    ticker
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::SystemTime;
+
+    use tokio::runtime::{self, Runtime};
+    use uuid::Uuid;
+
+    use crate::backends::{ConnectionError, InstanceRecord, MockBackend};
+    use crate::models::{StartupFallback, StartupPolicy};
+    use crate::tests::{new_instance, registration};
+    use crate::{CommunicationErrorStrategy, LeaderStrategy};
+
+    use super::*;
+
+    fn runtime() -> Runtime {
+        runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+    }
+
+    type Service = Instances<MockBackend<Registration<String>>, String>;
+
+    /// A service listing itself, counting its updates, at `interval`.
+    fn counted(interval: Duration) -> (Arc<Service>, Arc<AtomicUsize>) {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+        let updates = Arc::new(AtomicUsize::new(0));
+
+        let counter = updates.clone();
+        backend
+            .expect_update_instance_info()
+            .returning(move |_, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            });
+        backend.expect_list_active_instances().returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+        let instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        );
+        instances.settings.write().unwrap().update_interval = interval;
+        (Arc::new(instances), updates)
+    }
+
+    #[test]
+    fn should_update_at_the_interval_until_stopped() {
+        let (instances, updates) = counted(Duration::from_millis(50));
+
+        runtime().block_on(async {
+            let daemon = start_async_daemon(&instances);
+            time::sleep(Duration::from_millis(230)).await;
+            tokio::task::spawn_blocking(move || daemon.stop())
+                .await
+                .unwrap();
+            let stopped_at = updates.load(Ordering::SeqCst);
+            time::sleep(Duration::from_millis(100)).await;
+
+            assert_eq!(5, stopped_at);
+            assert_eq!(stopped_at, updates.load(Ordering::SeqCst));
+        });
+        assert!(instances.get_instance_info().is_some());
+    }
+
+    #[test]
+    fn should_stop_once_the_service_is_dropped() {
+        let (instances, _) = counted(Duration::from_millis(10));
+
+        runtime().block_on(async {
+            let daemon = start_async_daemon(&instances);
+            time::sleep(Duration::from_millis(20)).await;
+            drop(instances);
+            time::sleep(Duration::from_millis(30)).await;
+
+            match &daemon.runner {
+                Runner::Task { task, .. } => assert!(task.is_finished()),
+                Runner::Thread { .. } => unreachable!("Started as a task."),
+            }
+        });
+    }
+
+    #[test]
+    fn should_retry_the_first_update_with_backoff() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let mut sequence = mockall::Sequence::new();
+        let id = Uuid::new_v4();
+
+        backend
+            .expect_update_instance_info()
+            .times(2)
+            .in_sequence(&mut sequence)
+            .returning(|_, _| Err(ConnectionError::FailedToUpdate("error".to_string())));
+        backend
+            .expect_update_instance_info()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|_, _| Ok(()));
+        backend
+            .expect_list_active_instances()
+            .returning(|| Ok(vec![]));
+
+        let mut instances = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::Error,
+        );
+        instances.settings.get_mut().unwrap().update_interval = Duration::from_secs(5);
+        instances.startup_policy = Some(StartupPolicy {
+            retry_for: Duration::from_secs(1),
+            then: StartupFallback::GiveUp,
+        });
+        let instances = Arc::new(instances);
+
+        runtime().block_on(async {
+            let _daemon = start_async_daemon(&instances);
+            let waiting = instances.clone();
+            let first_update = tokio::task::spawn_blocking(move || {
+                waiting.wait_for_first_update(Duration::from_millis(500))
+            });
+
+            assert_eq!(Ok(()), first_update.await.unwrap());
+        });
+    }
+}
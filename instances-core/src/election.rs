@@ -0,0 +1,276 @@
+//! The leader election run on every listing.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::backends::{Backend, BackendResponse, ConnectionError, Operation};
+use crate::events::InstancesEvent;
+use crate::models::{InstanceInfo, InstanceRole, InstanceStatus, LeaderStrategy, Registration};
+use crate::sync::MutexExt;
+use crate::time::HeartbeatTime;
+use crate::InstanceRole::{Follower, Leader, Unknown};
+use crate::{Instances, Listing};
+
+/// Backend counter holding the latest leader term.
+const LEADER_TERM_COUNTER: &str = "instances-rs/leader-term";
+
+impl<B, T> Instances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    /// The leader of every cohort listed. When spreading the leaders, the
+    /// cohorts pick in name order the first instance in line from a zone no
+    /// earlier cohort leader runs in, if any.
+    pub(crate) fn cohort_leaders(&self, instances: &Listing<T>) -> HashMap<String, Uuid> {
+        let mut cohorts: Vec<&String> = instances
+            .iter()
+            .filter_map(|i| i.data.cohort.as_ref())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        cohorts.sort();
+
+        let zones: HashMap<Uuid, Option<String>> = match &self.leader_zone {
+            Some(zone_of) => instances
+                .iter()
+                .map(|i| (i.id, zone_of(&i.data.data)))
+                .collect(),
+            None => HashMap::new(),
+        };
+        let mut taken = HashSet::new();
+        cohorts
+            .into_iter()
+            .filter_map(|cohort| {
+                let members: Listing<T> = instances
+                    .iter()
+                    .filter(|i| i.data.cohort.as_ref() == Some(cohort))
+                    .cloned()
+                    .collect();
+                let succession = self.succession_order(&members);
+                let zone = |id: &Uuid| zones.get(id).cloned().flatten();
+                let leader = *succession
+                    .iter()
+                    .find(|id| zone(id).is_none_or(|z| !taken.contains(&z)))
+                    .or_else(|| succession.first())?;
+                taken.extend(zone(&leader));
+                Some((cohort.clone(), leader))
+            })
+            .collect()
+    }
+
+    pub(crate) fn add_leadership(&self, mut instances: Listing<T>) -> Vec<InstanceInfo<T>> {
+        let leader = self
+            .succession_order(&instances)
+            .first()
+            .copied()
+            .filter(|leader| !self.leadership_acknowledgment || has_claimed(&instances, leader))
+            .filter(|_| !self.in_solo_warmup())
+            .filter(|leader| *leader != self.instance_id || self.may_lead_unseen());
+
+        let mut result = Vec::with_capacity(instances.len());
+
+        while let Some(i) = instances.pop() {
+            result.push(InstanceInfo {
+                id: i.id,
+                role: self.check_leader(&leader, &i.id),
+                status: i.data.status,
+                kind: i.data.kind,
+                maintenance: i.data.maintenance,
+                cohort: i.data.cohort,
+                leader_term: i.data.leader_term,
+                latency: i.latency,
+                view_hash: i.data.view_hash,
+                version: i.data.version,
+                suspect: false,
+                data: i.data.data,
+                extensions: i.extensions,
+            })
+        }
+
+        result
+    }
+
+    /// Takes a new term from the backend when this instance becomes the
+    /// leader, and forgets it when it stops leading.
+    pub(crate) fn track_leader_term(&self, instances: &mut [InstanceInfo<T>]) {
+        let Some(current) = instances.iter_mut().find(|i| i.id == self.instance_id) else {
+            return;
+        };
+        let mut term = self.leader_term.lock_or_recover();
+        if current.role != Leader {
+            *term = None;
+        } else if term.is_none() {
+            *term = self
+                .advance_leader_term()
+                .map_err(|e| {
+                    warn!(
+                        "Error taking a new leader term, retrying on the next update. Cause: {}",
+                        e
+                    )
+                })
+                .ok();
+        }
+        current.leader_term = *term;
+    }
+
+    /// Increments the backend's leader term, racing with the other instances.
+    fn advance_leader_term(&self) -> Result<u64, ConnectionError> {
+        loop {
+            let result = self.backend.load_counter(LEADER_TERM_COUNTER);
+            let current = self
+                .tap(Operation::Counter, result, |value| {
+                    BackendResponse::Counter(*value)
+                })
+                .map_err(|e| self.backend_error(Operation::Counter, e))?;
+            let result =
+                self.backend
+                    .compare_and_set_counter(LEADER_TERM_COUNTER, current, current + 1);
+            if self
+                .tap(Operation::Counter, result, |set| {
+                    BackendResponse::CounterSet(*set)
+                })
+                .map_err(|e| self.backend_error(Operation::Counter, e))?
+            {
+                return Ok(current + 1);
+            }
+        }
+    }
+
+    /// Reads the leader override, keeping the previous one if the backend
+    /// fails, see `Builder::with_leader_override`.
+    pub(crate) fn refresh_leader_override(&self) {
+        let result = self.backend.load_leader_override();
+        let result = self
+            .tap(Operation::Override, result, |leader| {
+                BackendResponse::Override(*leader)
+            })
+            .map_err(|e| self.backend_error(Operation::Override, e));
+        let leader = match result {
+            Ok(leader) => leader,
+            Err(error) => {
+                return warn!(
+                    "Error loading the leader override, keeping the previous one. Cause: {}",
+                    error
+                )
+            }
+        };
+        let mut current = self.leader_override.lock_or_recover();
+        if *current == leader {
+            return;
+        }
+        match leader {
+            Some(id) => warn!("Instance {} named the leader by an override.", id),
+            None => info!("Leader override cleared."),
+        }
+        *current = leader;
+        drop(current);
+        self.events.emit(InstancesEvent::LeaderOverridden(leader));
+    }
+
+    /// Counts the consecutive updates in which this instance was the only one
+    /// listed, see `Builder::with_solo_warmup`.
+    pub(crate) fn track_solo_updates(&self, instances: &Listing<T>) {
+        let solo = instances.len() == 1 && instances[0].id == self.instance_id;
+        if solo {
+            self.solo_updates.fetch_add(1, Ordering::SeqCst);
+        } else {
+            self.solo_updates.store(0, Ordering::SeqCst);
+        }
+    }
+
+    /// Whether this instance may lead, see `Builder::require_self_visible`.
+    pub(crate) fn may_lead_unseen(&self) -> bool {
+        !self.require_self_visible || self.seen_self.load(Ordering::SeqCst)
+    }
+
+    /// Whether this instance is alone and must wait before leading.
+    pub(crate) fn in_solo_warmup(&self) -> bool {
+        let solo_updates = self.solo_updates.load(Ordering::SeqCst);
+        solo_updates > 0 && solo_updates <= self.settings().solo_warmup
+    }
+
+    /// Instances eligible for the leadership, ordered by the leader strategy: the
+    /// first one is the leader and the others follow in line. Ties are broken by
+    /// the instance id so every instance computes the same order.
+    pub(crate) fn succession_order(&self, instances: &Listing<T>) -> Vec<Uuid> {
+        let designated: HashSet<Uuid> = instances
+            .iter()
+            .filter(|i| i.designated_leader)
+            .map(|i| i.id)
+            .collect();
+        let mut candidates: Vec<(HeartbeatTime, Option<SystemTime>, Uuid)> = instances
+            .iter()
+            .filter(|i| is_leader_eligible(&i.data))
+            .filter(|i| {
+                self.leader_eligible
+                    .as_ref()
+                    .is_none_or(|f| f(&i.data.data))
+            })
+            .map(|i| (i.registered_at, self.id_generator.timestamp(&i.id), i.id))
+            .collect();
+
+        let overridden = *self.leader_override.lock_or_recover();
+        match self.leader_strategy {
+            LeaderStrategy::None => return vec![],
+            // The operator's choice stands until cleared, as long as it is
+            // listed and eligible: a draining or ineligible instance can't lead.
+            _ if overridden.is_some() => {
+                return candidates
+                    .into_iter()
+                    .map(|(_, _, id)| id)
+                    .filter(|id| Some(*id) == overridden)
+                    .collect();
+            }
+            LeaderStrategy::Oldest => candidates.sort(),
+            LeaderStrategy::Newest => {
+                candidates.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)))
+            }
+            // While the designated instance can't lead, e.g. it holds the
+            // lock since it joined or it's draining, the oldest eligible one
+            // does.
+            LeaderStrategy::Backend => {
+                if designated.is_empty() {
+                    return vec![];
+                }
+                candidates.sort();
+                candidates.sort_by_key(|c| !designated.contains(&c.2));
+            }
+        }
+
+        candidates.into_iter().map(|(_, _, id)| id).collect()
+    }
+
+    fn check_leader(&self, leader: &Option<Uuid>, current: &Uuid) -> InstanceRole {
+        match self.leader_strategy {
+            LeaderStrategy::None => Unknown,
+            _ => {
+                if *leader == Some(*current) {
+                    Leader
+                } else {
+                    Follower
+                }
+            }
+        }
+    }
+}
+
+fn is_leader_eligible<T>(registration: &Registration<T>) -> bool {
+    registration.status == InstanceStatus::Active
+        && registration.kind.is_member()
+        && !registration.maintenance
+}
+
+/// With the leadership acknowledgment enabled an elected instance only leads
+/// once its claim is visible to everyone.
+fn has_claimed<T>(instances: &Listing<T>, leader: &Uuid) -> bool {
+    instances
+        .iter()
+        .any(|i| i.id == *leader && i.data.leader_claim.is_some())
+}
@@ -0,0 +1,429 @@
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use uuid::Uuid;
+
+use crate::anomalies::Anomaly;
+use crate::models::{DepartureReason, IncompatiblePeer};
+use crate::partitioning::MigrationPlan;
+use crate::sync::modeled::Mutex;
+use crate::sync::MutexExt;
+
+/// Notable changes in the instance's lifecycle, see [`Instances::subscribe`](crate::Instances::subscribe).
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum InstancesEvent {
+    /// The settings were reloaded from the watched configuration file.
+    ConfigReloaded,
+    /// An update took longer than the update interval; the ticks missed in the
+    /// meantime are skipped. Holds how long the update took.
+    UpdateOverrun(Duration),
+    /// Much more time than expected passed between two updates, e.g. because
+    /// the host was suspended. The state is flagged as stale until the update
+    /// that runs right away completes. Holds the time since the previous update.
+    ClockGapDetected(Duration),
+    /// An instance listed by the previous update is gone, and why.
+    MemberLeft(Uuid, DepartureReason),
+    /// Another instance, or none, is now the leader.
+    LeaderChanged(Option<Uuid>),
+    /// The leader published the term it got from the backend, see
+    /// `Builder::with_persistent_leader_term`. Terms only grow, ordering the
+    /// leader transitions.
+    LeaderTermStarted(Uuid, u64),
+    /// The leader override read from the backend changed, `None` once
+    /// cleared, see `Builder::with_leader_override`.
+    LeaderOverridden(Option<Uuid>),
+    /// These instances kept seeing other members than the current instance
+    /// for several updates in a row, e.g. because of a backend replication
+    /// problem. See `Builder::with_view_checks`.
+    ViewsDiverged(Vec<Uuid>),
+    /// The membership or the leadership behaved abnormally, see
+    /// `Builder::with_anomaly_rules`.
+    AnomalyDetected(Anomaly),
+    /// A peer runs this version of instances-rs, incompatible with the
+    /// current instance's. Reported once per peer.
+    IncompatibleVersion(Uuid, String),
+    /// A peer writes a wire protocol older than `Builder::with_min_protocol`
+    /// and was left out of the membership. Reported once per peer.
+    IncompatiblePeer(IncompatiblePeer),
+    /// A `PartitionManager` resize is pending: the partitions changing owner
+    /// once it's completed. Emitted again if the membership changes the plan.
+    MigrationPlanned(MigrationPlan),
+    /// The subscriber's buffer was full, so this many events were dropped
+    /// before this one, see [`Instances::subscribe_bounded`](crate::Instances::subscribe_bounded).
+    Lagged(u64),
+}
+
+type EventFilter = Box<dyn Fn(&InstancesEvent) -> bool + Send + Sync>;
+
+/// How a subscriber receives the events, see
+/// [`Instances::subscribe_with`](crate::Instances::subscribe_with).
+#[derive(Default)]
+pub struct SubscriptionOptions {
+    filter: Option<EventFilter>,
+    debounce: Option<Duration>,
+    capacity: Option<usize>,
+}
+
+impl SubscriptionOptions {
+    /// Only delivers the events passing `filter`.
+    pub fn with_filter(
+        mut self,
+        filter: impl Fn(&InstancesEvent) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Coalesces the events over `window`: the first event is held for
+    /// `window` and only the latest one received meanwhile is delivered. The
+    /// held event is delivered by the next update after the window, so it can
+    /// arrive up to one update interval late.
+    pub fn with_debounce(mut self, window: Duration) -> Self {
+        self.debounce = Some(window);
+        self
+    }
+
+    /// Buffers at most `capacity` events, see
+    /// [`Instances::subscribe_bounded`](crate::Instances::subscribe_bounded).
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+}
+
+/// Fans the events out to every subscriber.
+pub(crate) struct EventBus {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+struct Subscriber {
+    sender: Sink,
+    /// Events dropped since the last one delivered.
+    missed: u64,
+    filter: Option<EventFilter>,
+    debounce: Option<Duration>,
+    /// The latest event held by the debounce and when the first was held.
+    held: Option<(InstancesEvent, Instant)>,
+}
+
+/// Where a subscriber's events go.
+enum Sink {
+    Channel(Sender<InstancesEvent>),
+    /// Tokio reports the events a slow receiver missed by itself, with
+    /// `RecvError::Lagged`.
+    #[cfg(feature = "tokio")]
+    Broadcast(tokio::sync::broadcast::Sender<InstancesEvent>),
+}
+
+impl Subscriber {
+    fn new(sender: Sink, options: SubscriptionOptions) -> Self {
+        Subscriber {
+            sender,
+            missed: 0,
+            filter: options.filter,
+            debounce: options.debounce,
+            held: None,
+        }
+    }
+
+    /// Filters and debounces the event, returning whether the subscriber is
+    /// still there.
+    fn offer(&mut self, event: InstancesEvent, now: Instant) -> bool {
+        if let Some(filter) = &self.filter {
+            if !filter(&event) {
+                return self.flush(now);
+            }
+        }
+        match self.debounce {
+            Some(_) => {
+                let since = self.held.take().map_or(now, |(_, since)| since);
+                self.held = Some((event, since));
+                self.flush(now)
+            }
+            None => self.send(event),
+        }
+    }
+
+    /// Delivers the held event once its debounce window is over.
+    fn flush(&mut self, now: Instant) -> bool {
+        match (&self.held, self.debounce) {
+            (Some((_, since)), Some(window)) if now.duration_since(*since) >= window => {
+                let (event, _) = self.held.take().expect("Checked above.");
+                self.send(event)
+            }
+            _ => true,
+        }
+    }
+
+    /// Delivers the event without blocking, returning whether the subscriber
+    /// is still there.
+    fn send(&mut self, event: InstancesEvent) -> bool {
+        match &self.sender {
+            Sink::Channel(sender) => send_counting_misses(sender, &mut self.missed, event),
+            // Only fails once every receiver is gone.
+            #[cfg(feature = "tokio")]
+            Sink::Broadcast(sender) => sender.send(event).is_ok(),
+        }
+    }
+}
+
+/// Sends the event to a channel, preceded by `InstancesEvent::Lagged` if
+/// events were `missed`, returning whether the receiver is still there.
+fn send_counting_misses(
+    sender: &Sender<InstancesEvent>,
+    missed: &mut u64,
+    event: InstancesEvent,
+) -> bool {
+    if *missed > 0 {
+        match sender.try_send(InstancesEvent::Lagged(*missed)) {
+            Ok(()) => *missed = 0,
+            Err(TrySendError::Full(_)) => {
+                *missed += 1;
+                return true;
+            }
+            Err(TrySendError::Disconnected(_)) => return false,
+        }
+    }
+    match sender.try_send(event) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            *missed += 1;
+            true
+        }
+        Err(TrySendError::Disconnected(_)) => false,
+    }
+}
+
+impl EventBus {
+    pub(crate) fn new() -> Self {
+        EventBus {
+            subscribers: Mutex::new(vec![]),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<InstancesEvent> {
+        self.subscribe_with(SubscriptionOptions::default())
+    }
+
+    pub(crate) fn subscribe_bounded(&self, capacity: usize) -> Receiver<InstancesEvent> {
+        self.subscribe_with(SubscriptionOptions::default().with_capacity(capacity))
+    }
+
+    pub(crate) fn subscribe_with(&self, options: SubscriptionOptions) -> Receiver<InstancesEvent> {
+        let (sender, receiver) = match options.capacity {
+            Some(capacity) => crossbeam_channel::bounded(capacity),
+            None => crossbeam_channel::unbounded(),
+        };
+        self.subscribers
+            .lock_or_recover()
+            .push(Subscriber::new(Sink::Channel(sender), options));
+        receiver
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn subscribe_broadcast(
+        &self,
+        capacity: usize,
+        options: SubscriptionOptions,
+    ) -> tokio::sync::broadcast::Receiver<InstancesEvent> {
+        let (sender, receiver) = tokio::sync::broadcast::channel(capacity);
+        self.subscribers
+            .lock_or_recover()
+            .push(Subscriber::new(Sink::Broadcast(sender), options));
+        receiver
+    }
+
+    /// Sends the event to the subscribers, forgetting the ones that went away.
+    /// Never blocks: subscribers with a full buffer miss the event.
+    pub(crate) fn emit(&self, event: InstancesEvent) {
+        let now = Instant::now();
+        self.subscribers
+            .lock_or_recover()
+            .retain_mut(|s| s.offer(event.clone(), now));
+    }
+
+    /// Delivers the debounced events whose window is over.
+    pub(crate) fn flush(&self) {
+        let now = Instant::now();
+        self.subscribers
+            .lock_or_recover()
+            .retain_mut(|s| s.flush(now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn should_send_events_to_every_subscriber() {
+        let bus = EventBus::new();
+        let first = bus.subscribe();
+        let second = bus.subscribe();
+        drop(bus.subscribe());
+
+        bus.emit(InstancesEvent::ConfigReloaded);
+
+        assert_eq!(Ok(InstancesEvent::ConfigReloaded), first.try_recv());
+        assert_eq!(Ok(InstancesEvent::ConfigReloaded), second.try_recv());
+        assert_eq!(2, bus.subscribers.lock().unwrap().len());
+    }
+
+    #[test]
+    fn should_deliver_events_emitted_from_several_threads() {
+        let bus = Arc::new(EventBus::new());
+        let subscribers: Vec<_> = (0..3).map(|_| bus.subscribe()).collect();
+
+        let emitters: Vec<_> = (0..4)
+            .map(|_| {
+                let bus = bus.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        bus.emit(InstancesEvent::ConfigReloaded);
+                        drop(bus.subscribe());
+                    }
+                })
+            })
+            .collect();
+        for emitter in emitters {
+            emitter.join().unwrap();
+        }
+
+        for subscriber in subscribers {
+            assert_eq!(400, subscriber.try_iter().count());
+        }
+    }
+
+    #[test]
+    fn should_report_the_events_a_slow_subscriber_missed() {
+        let bus = EventBus::new();
+        let slow = bus.subscribe_bounded(2);
+
+        for _ in 0..5 {
+            bus.emit(InstancesEvent::ConfigReloaded);
+        }
+        assert_eq!(2, slow.try_iter().count());
+
+        bus.emit(InstancesEvent::ConfigReloaded);
+
+        assert_eq!(Ok(InstancesEvent::Lagged(3)), slow.try_recv());
+        assert_eq!(Ok(InstancesEvent::ConfigReloaded), slow.try_recv());
+    }
+
+    #[test]
+    fn should_filter_and_coalesce_the_events() {
+        let bus = EventBus::new();
+        let leaders = bus.subscribe_with(
+            SubscriptionOptions::default()
+                .with_filter(|e| matches!(e, InstancesEvent::LeaderChanged(_)))
+                .with_debounce(Duration::from_millis(50)),
+        );
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        bus.emit(InstancesEvent::LeaderChanged(Some(first)));
+        bus.emit(InstancesEvent::ConfigReloaded);
+        bus.emit(InstancesEvent::LeaderChanged(Some(second)));
+        bus.flush();
+        assert!(leaders.try_recv().is_err());
+
+        std::thread::sleep(Duration::from_millis(60));
+        bus.flush();
+
+        assert_eq!(
+            vec![InstancesEvent::LeaderChanged(Some(second))],
+            leaders.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn should_report_the_lag_of_a_broadcast_receiver() {
+        use tokio::sync::broadcast::error::TryRecvError;
+
+        let bus = EventBus::new();
+        let mut slow = bus.subscribe_broadcast(2, SubscriptionOptions::default());
+
+        for millis in 0..5 {
+            bus.emit(InstancesEvent::UpdateOverrun(Duration::from_millis(millis)));
+        }
+
+        assert_eq!(Err(TryRecvError::Lagged(3)), slow.try_recv());
+        assert_eq!(
+            Ok(InstancesEvent::UpdateOverrun(Duration::from_millis(3))),
+            slow.try_recv()
+        );
+        assert_eq!(
+            Ok(InstancesEvent::UpdateOverrun(Duration::from_millis(4))),
+            slow.try_recv()
+        );
+        assert_eq!(Err(TryRecvError::Empty), slow.try_recv());
+
+        drop(slow);
+        bus.emit(InstancesEvent::ConfigReloaded);
+        assert!(bus.subscribers.lock().unwrap().is_empty());
+    }
+}
+
+#[cfg(all(test, loom))]
+mod model_checks {
+    use loom::sync::Arc;
+    use loom::thread;
+
+    use super::*;
+
+    #[test]
+    fn should_deliver_the_events_of_concurrent_emitters() {
+        loom::model(|| {
+            let bus = Arc::new(EventBus::new());
+            let subscriber = bus.subscribe();
+
+            let emitter = {
+                let bus = bus.clone();
+                thread::spawn(move || bus.emit(InstancesEvent::ConfigReloaded))
+            };
+            bus.emit(InstancesEvent::ConfigReloaded);
+            emitter.join().unwrap();
+
+            assert_eq!(2, subscriber.try_iter().count());
+        });
+    }
+
+    #[test]
+    fn should_deliver_at_most_once_to_a_concurrent_subscriber() {
+        loom::model(|| {
+            let bus = Arc::new(EventBus::new());
+            let existing = bus.subscribe();
+
+            let subscribing = {
+                let bus = bus.clone();
+                thread::spawn(move || bus.subscribe())
+            };
+            bus.emit(InstancesEvent::ConfigReloaded);
+            let late = subscribing.join().unwrap();
+
+            assert_eq!(1, existing.try_iter().count());
+            assert!(late.try_iter().count() <= 1);
+        });
+    }
+
+    #[test]
+    fn should_forget_a_subscriber_dropped_while_emitting() {
+        loom::model(|| {
+            let bus = Arc::new(EventBus::new());
+            let subscriber = bus.subscribe();
+
+            let emitter = {
+                let bus = bus.clone();
+                thread::spawn(move || bus.emit(InstancesEvent::ConfigReloaded))
+            };
+            drop(subscriber);
+            emitter.join().unwrap();
+            bus.emit(InstancesEvent::ConfigReloaded);
+
+            assert!(bus.subscribers.lock_or_recover().is_empty());
+        });
+    }
+}
@@ -0,0 +1,134 @@
+//! Subsystems composed on top of the membership (partitioning, locks, jobs,
+//! messaging...), sharing the service's state and update daemon instead of
+//! each running their own loop.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use uuid::Uuid;
+
+use crate::backends::{Backend, Capabilities};
+use crate::models::InstanceInfo;
+use crate::{InstanceRole, Instances, Registration};
+
+/// A subsystem registered with `Builder::with_extension` and retrieved with
+/// [`Instances::extension`].
+pub trait Extension<B, T>: Send + Sync + 'static {
+    /// Called by the update daemon after every update, with the service and
+    /// the state that update produced. Read the membership and the leadership
+    /// from `tick` rather than from `instances`, which may already reflect a
+    /// later update. It runs on the daemon thread, so it must be quick; a
+    /// panic is logged and doesn't stop the daemon.
+    fn on_update(&self, _instances: &Instances<B, T>, _tick: &TickContext<T>)
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+        B: Backend<Registration<T>> + Send + Sync + 'static,
+    {
+    }
+
+    /// The first capability the extension needs that the backend lacks, if
+    /// any, failing the build with `ConfigError::UnsupportedByBackend`.
+    fn missing_capability(&self, _capabilities: &Capabilities) -> Option<&'static str> {
+        None
+    }
+}
+
+/// The state produced by one update, read at once so the instances, the
+/// leader and its epoch are always consistent with each other, see
+/// [`Instances::tick_context`].
+#[derive(Clone, Debug)]
+pub struct TickContext<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    pub(crate) tick: u64,
+    pub(crate) instances: Arc<Vec<InstanceInfo<T>>>,
+    pub(crate) current_info: Option<Arc<InstanceInfo<T>>>,
+    pub(crate) leader: Option<Arc<InstanceInfo<T>>>,
+    pub(crate) leader_epoch: u64,
+    pub(crate) succession: Arc<Vec<Uuid>>,
+}
+
+impl<T> TickContext<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    /// Sequence number of the update, increasing with every update.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn instances(&self) -> &[InstanceInfo<T>] {
+        &self.instances
+    }
+
+    pub fn current_info(&self) -> Option<&InstanceInfo<T>> {
+        self.current_info.as_deref()
+    }
+
+    pub fn leader(&self) -> Option<&InstanceInfo<T>> {
+        self.leader.as_deref()
+    }
+
+    pub fn leader_epoch(&self) -> u64 {
+        self.leader_epoch
+    }
+
+    /// Whether the current instance was the leader as of this update.
+    pub fn is_leader(&self) -> bool {
+        self.current_info
+            .as_ref()
+            .is_some_and(|info| info.role == InstanceRole::Leader)
+    }
+
+    /// The instances next in line for the leadership, see
+    /// [`Instances::succession`].
+    pub fn succession(&self) -> &[Uuid] {
+        &self.succession
+    }
+}
+
+/// The extensions of a service, by type.
+pub(crate) struct ExtensionRegistry<B, T> {
+    by_type: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+    in_order: Vec<(TypeId, Arc<dyn Extension<B, T>>)>,
+}
+
+impl<B, T> Default for ExtensionRegistry<B, T> {
+    fn default() -> Self {
+        ExtensionRegistry {
+            by_type: HashMap::new(),
+            in_order: vec![],
+        }
+    }
+}
+
+impl<B, T> ExtensionRegistry<B, T>
+where
+    B: 'static,
+    T: 'static,
+{
+    /// Adds the extension, replacing a previous one of the same type.
+    pub(crate) fn register<E: Extension<B, T>>(&mut self, extension: E) {
+        let extension = Arc::new(extension);
+        let type_id = TypeId::of::<E>();
+        self.by_type.insert(type_id, extension.clone());
+        self.in_order.retain(|(id, _)| *id != type_id);
+        self.in_order.push((type_id, extension));
+    }
+
+    pub(crate) fn get<E: Extension<B, T>>(&self) -> Option<Arc<E>> {
+        self.by_type
+            .get(&TypeId::of::<E>())
+            .cloned()
+            .and_then(|extension| extension.downcast::<E>().ok())
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Arc<dyn Extension<B, T>>> {
+        self.in_order.iter().map(|(_, extension)| extension)
+    }
+}
@@ -0,0 +1,150 @@
+//! A global view over several regional clusters.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::backends::Backend;
+use crate::models::InstanceInfo;
+use crate::{Instances, Registration};
+
+/// An instance of one of the federated clusters.
+#[derive(Clone, Debug)]
+pub struct FederatedMember<T>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    pub region: String,
+    pub info: InstanceInfo<T>,
+}
+
+/// Aggregates the clusters of several regions, each tracked by its own
+/// `Instances` (typically a witness, see `Builder::as_witness`, pointed at the
+/// region's backend).
+///
+/// Every region elects its leader as usual. The global leader is the leader of
+/// the first region, in the order they were added, that has one: instances
+/// federating the same regions in the same order agree on it.
+pub struct FederatedInstances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    regions: Vec<(String, Arc<Instances<B, T>>)>,
+}
+
+impl<B, T> Default for FederatedInstances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    fn default() -> Self {
+        FederatedInstances { regions: vec![] }
+    }
+}
+
+impl<B, T> FederatedInstances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    /// Adds the cluster of `region`, after the regions added before.
+    pub fn with_region(
+        mut self,
+        region: impl Into<String>,
+        instances: Arc<Instances<B, T>>,
+    ) -> Self {
+        self.regions.push((region.into(), instances));
+        self
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = &str> {
+        self.regions.iter().map(|(region, _)| region.as_str())
+    }
+
+    /// The instances of every region, as of each region's latest update.
+    pub fn list_active_instances(&self) -> Vec<FederatedMember<T>> {
+        self.regions
+            .iter()
+            .flat_map(|(region, instances)| {
+                instances.iter().map(move |info| FederatedMember {
+                    region: region.clone(),
+                    info,
+                })
+            })
+            .collect()
+    }
+
+    /// The leader of each region that has one.
+    pub fn regional_leaders(&self) -> HashMap<String, InstanceInfo<T>> {
+        self.regions
+            .iter()
+            .filter_map(|(region, instances)| {
+                let leader = instances.current_leader()?;
+                Some((region.clone(), (*leader).clone()))
+            })
+            .collect()
+    }
+
+    /// The leader among the regional leaders.
+    pub fn global_leader(&self) -> Option<FederatedMember<T>> {
+        self.regions.iter().find_map(|(region, instances)| {
+            let leader = instances.current_leader()?;
+            Some(FederatedMember {
+                region: region.clone(),
+                info: (*leader).clone(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use crate::backends::MockBackend;
+    use crate::tests::{mock_data_for, new_instance};
+    use crate::{CommunicationErrorStrategy, LeaderStrategy};
+
+    use super::*;
+
+    fn region(
+        local: Uuid,
+        members: Vec<Uuid>,
+        strategy: LeaderStrategy,
+    ) -> Arc<Instances<MockBackend<Registration<String>>, String>> {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend
+            .expect_list_active_instances()
+            .returning(move || Ok(mock_data_for(members.clone())));
+        let instance = new_instance(local, backend, strategy, CommunicationErrorStrategy::Error);
+        instance.update_instance_info().unwrap();
+        Arc::new(instance)
+    }
+
+    #[test]
+    fn should_elect_the_global_leader_among_the_regional_ones() {
+        let (eu, eu_peer, us) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let federation = FederatedInstances::default()
+            .with_region("sa", region(Uuid::new_v4(), vec![], LeaderStrategy::None))
+            .with_region("eu", region(eu, vec![eu, eu_peer], LeaderStrategy::Oldest))
+            .with_region("us", region(us, vec![us], LeaderStrategy::Oldest));
+
+        assert_eq!(4, federation.list_active_instances().len());
+        assert_eq!(
+            HashMap::from([("eu".to_string(), eu), ("us".to_string(), us)]),
+            federation
+                .regional_leaders()
+                .into_iter()
+                .map(|(region, leader)| (region, leader.id))
+                .collect()
+        );
+        let global = federation.global_leader().unwrap();
+        assert_eq!(("eu", eu), (global.region.as_str(), global.info.id));
+    }
+}
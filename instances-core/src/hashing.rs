@@ -0,0 +1,197 @@
+//! Hash functions mapping keys to partitions and partitions to owners, see
+//! [`PartitionManager::with_hasher`](crate::partitioning::PartitionManager::with_hasher).
+//! Pick the one of the system the assignment must agree with, e.g.
+//! [`Murmur2`] to place keys as Kafka's default partitioner does.
+
+#[allow(deprecated)]
+use std::hash::{Hasher, SipHasher};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+const XXH_PRIME_1: u64 = 0x9e3779b185ebca87;
+const XXH_PRIME_2: u64 = 0xc2b2ae3d27d4eb4f;
+const XXH_PRIME_3: u64 = 0x165667b19e3779f9;
+const XXH_PRIME_4: u64 = 0x85ebca77c2b2ca63;
+const XXH_PRIME_5: u64 = 0x27d4eb2f165667c5;
+
+const MURMUR2_SEED: u32 = 0x9747b28c;
+const MURMUR2_M: u32 = 0x5bd1e995;
+
+/// A hash function stable across processes, hosts and versions: every
+/// instance must get the same value for the same key.
+pub trait KeyHasher: Send + Sync {
+    fn hash(&self, key: &[u8]) -> u64;
+}
+
+/// 64 bits FNV-1a, the default: fast and good enough for short keys.
+pub struct Fnv1a;
+
+impl KeyHasher for Fnv1a {
+    fn hash(&self, key: &[u8]) -> u64 {
+        fnv1a(key)
+    }
+}
+
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// 64 bits xxHash (XXH64) with the given seed.
+pub struct XxHash64(pub u64);
+
+impl KeyHasher for XxHash64 {
+    fn hash(&self, key: &[u8]) -> u64 {
+        xxh64(key, self.0)
+    }
+}
+
+fn xxh64(bytes: &[u8], seed: u64) -> u64 {
+    fn round(acc: u64, input: u64) -> u64 {
+        acc.wrapping_add(input.wrapping_mul(XXH_PRIME_2))
+            .rotate_left(31)
+            .wrapping_mul(XXH_PRIME_1)
+    }
+    fn merge(acc: u64, value: u64) -> u64 {
+        (acc ^ round(0, value))
+            .wrapping_mul(XXH_PRIME_1)
+            .wrapping_add(XXH_PRIME_4)
+    }
+    let u64_at = |chunk: &[u8]| u64::from_le_bytes(chunk[..8].try_into().unwrap());
+    let u32_at = |chunk: &[u8]| u32::from_le_bytes(chunk[..4].try_into().unwrap());
+
+    let mut stripes = bytes.chunks_exact(32);
+    let mut hash = if bytes.len() >= 32 {
+        let mut v = [
+            seed.wrapping_add(XXH_PRIME_1).wrapping_add(XXH_PRIME_2),
+            seed.wrapping_add(XXH_PRIME_2),
+            seed,
+            seed.wrapping_sub(XXH_PRIME_1),
+        ];
+        for stripe in &mut stripes {
+            for (lane, acc) in v.iter_mut().enumerate() {
+                *acc = round(*acc, u64_at(&stripe[lane * 8..]));
+            }
+        }
+        let hash = v[0]
+            .rotate_left(1)
+            .wrapping_add(v[1].rotate_left(7))
+            .wrapping_add(v[2].rotate_left(12))
+            .wrapping_add(v[3].rotate_left(18));
+        v.iter().fold(hash, |hash, acc| merge(hash, *acc))
+    } else {
+        seed.wrapping_add(XXH_PRIME_5)
+    };
+    hash = hash.wrapping_add(bytes.len() as u64);
+
+    let mut rest = stripes.remainder();
+    while rest.len() >= 8 {
+        hash = (hash ^ round(0, u64_at(rest)))
+            .rotate_left(27)
+            .wrapping_mul(XXH_PRIME_1)
+            .wrapping_add(XXH_PRIME_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        hash = (hash ^ (u32_at(rest) as u64).wrapping_mul(XXH_PRIME_1))
+            .rotate_left(23)
+            .wrapping_mul(XXH_PRIME_2)
+            .wrapping_add(XXH_PRIME_3);
+        rest = &rest[4..];
+    }
+    for byte in rest {
+        hash = (hash ^ (*byte as u64).wrapping_mul(XXH_PRIME_5))
+            .rotate_left(11)
+            .wrapping_mul(XXH_PRIME_1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(XXH_PRIME_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(XXH_PRIME_3);
+    hash ^ (hash >> 32)
+}
+
+/// SipHash-2-4 keyed with `k0` and `k1`.
+pub struct SipHash {
+    pub k0: u64,
+    pub k1: u64,
+}
+
+impl KeyHasher for SipHash {
+    #[allow(deprecated)]
+    fn hash(&self, key: &[u8]) -> u64 {
+        let mut hasher = SipHasher::new_with_keys(self.k0, self.k1);
+        hasher.write(key);
+        hasher.finish()
+    }
+}
+
+/// Kafka's murmur2, made positive as its default partitioner does, so that
+/// `hash % partitions` picks the same partition as a Kafka producer.
+pub struct Murmur2;
+
+impl KeyHasher for Murmur2 {
+    fn hash(&self, key: &[u8]) -> u64 {
+        (murmur2(key) & 0x7fffffff) as u64
+    }
+}
+
+fn murmur2(bytes: &[u8]) -> u32 {
+    let mut hash = MURMUR2_SEED ^ bytes.len() as u32;
+    let mut words = bytes.chunks_exact(4);
+    for word in &mut words {
+        let mut k = u32::from_le_bytes(word.try_into().unwrap()).wrapping_mul(MURMUR2_M);
+        k ^= k >> 24;
+        hash = hash.wrapping_mul(MURMUR2_M) ^ k.wrapping_mul(MURMUR2_M);
+    }
+    let tail = words.remainder();
+    if !tail.is_empty() {
+        for (position, byte) in tail.iter().enumerate() {
+            hash ^= (*byte as u32) << (8 * position);
+        }
+        hash = hash.wrapping_mul(MURMUR2_M);
+    }
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(MURMUR2_M);
+    hash ^ (hash >> 15)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_match_the_reference_implementations() {
+        assert_eq!(0xcbf29ce484222325, Fnv1a.hash(b""));
+        assert_eq!(0xaf63dc4c8601ec8c, Fnv1a.hash(b"a"));
+        assert_eq!(0xef46db3751d8e999, XxHash64(0).hash(b""));
+        assert_eq!(0x44bc2cf5ad770999, XxHash64(0).hash(b"abc"));
+        let sip = SipHash {
+            k0: 0x0706050403020100,
+            k1: 0x0f0e0d0c0b0a0908,
+        };
+        assert_eq!(0x726fdb47dd0e0e31, sip.hash(b""));
+    }
+
+    #[test]
+    fn should_hash_as_kafka_does() {
+        let cases: [(&[u8], i32); 6] = [
+            (b"21", -973932308),
+            (b"foobar", -790332482),
+            (b"a-little-bit-long-string", -985981536),
+            (b"a-little-bit-longer-string", -1486304829),
+            (
+                b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8",
+                -58897971,
+            ),
+            (b"abc", 479470107),
+        ];
+        for (key, expected) in cases {
+            assert_eq!(expected, murmur2(key) as i32);
+        }
+        assert_eq!((-973932308i32 & 0x7fffffff) as u64, Murmur2.hash(b"21"));
+    }
+}
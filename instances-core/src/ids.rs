@@ -0,0 +1,141 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use uuid::Uuid;
+
+use crate::random::RandomSource;
+
+/// Generates the id of the current instance.
+pub trait IdGenerator: Send + Sync {
+    fn generate(&self) -> Uuid;
+
+    /// Like `generate`, drawing the random part of the id from `random`, the
+    /// `Builder::with_random_source` source. Generators without one ignore it.
+    fn generate_from(&self, _random: &dyn RandomSource) -> Uuid {
+        self.generate()
+    }
+
+    /// Creation time embedded in an id made by this generator, if the format has
+    /// one. It breaks ties between instances registered at the same time when
+    /// electing the leader.
+    fn timestamp(&self, _id: &Uuid) -> Option<SystemTime> {
+        None
+    }
+}
+
+/// Random UUIDv4 ids, the default.
+pub struct RandomId;
+
+impl IdGenerator for RandomId {
+    fn generate(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+
+    fn generate_from(&self, random: &dyn RandomSource) -> Uuid {
+        random.uuid()
+    }
+}
+
+/// Always the same id, e.g. to run a `ReplayBackend` as the instance that
+/// made the recording.
+pub struct FixedId(pub Uuid);
+
+impl IdGenerator for FixedId {
+    fn generate(&self) -> Uuid {
+        self.0
+    }
+}
+
+/// Time-ordered UUIDv7 ids: they sort by creation time, which also makes logs
+/// from several instances easier to follow.
+pub struct TimeOrderedId;
+
+impl TimeOrderedId {
+    /// The current time followed by the random bits of `random_id`.
+    fn stamp(random_id: Uuid) -> Uuid {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        let mut bytes = *random_id.as_bytes();
+        bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+        bytes[6] = 0x70 | (bytes[6] & 0x0f);
+        bytes[8] = 0x80 | (bytes[8] & 0x3f);
+
+        Uuid::from_bytes(bytes)
+    }
+}
+
+impl IdGenerator for TimeOrderedId {
+    fn generate(&self) -> Uuid {
+        TimeOrderedId::stamp(Uuid::new_v4())
+    }
+
+    fn generate_from(&self, random: &dyn RandomSource) -> Uuid {
+        TimeOrderedId::stamp(random.uuid())
+    }
+
+    fn timestamp(&self, id: &Uuid) -> Option<SystemTime> {
+        if id.get_version_num() != 7 {
+            return None;
+        }
+
+        let mut millis = [0u8; 8];
+        millis[2..].copy_from_slice(&id.as_bytes()[..6]);
+        Some(UNIX_EPOCH + Duration::from_millis(u64::from_be_bytes(millis)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::random::SeededRandom;
+
+    use super::*;
+
+    #[test]
+    fn should_generate_valid_v7_ids() {
+        let id = TimeOrderedId.generate();
+
+        assert_eq!(7, id.get_version_num());
+        assert_eq!(uuid::Variant::RFC4122, id.get_variant().unwrap());
+    }
+
+    #[test]
+    fn should_embed_the_creation_time() {
+        let before = SystemTime::now() - Duration::from_millis(1);
+        let id = TimeOrderedId.generate();
+        let after = SystemTime::now();
+
+        let timestamp = TimeOrderedId.timestamp(&id).unwrap();
+
+        assert!(before <= timestamp && timestamp <= after);
+        assert!(TimeOrderedId.timestamp(&Uuid::new_v4()).is_none());
+    }
+
+    #[test]
+    fn should_draw_the_random_bits_from_the_source() {
+        let drawn = |generator: &dyn IdGenerator| {
+            let id = generator.generate_from(&SeededRandom::new(7));
+            id.as_bytes()[6..].to_vec()
+        };
+
+        assert_eq!(drawn(&RandomId), drawn(&RandomId));
+        assert_eq!(drawn(&TimeOrderedId), drawn(&TimeOrderedId));
+        assert_eq!(
+            FixedId(Uuid::nil()).generate(),
+            FixedId(Uuid::nil()).generate_from(&SeededRandom::new(7))
+        );
+    }
+
+    #[test]
+    fn should_sort_by_creation_time() {
+        let first = TimeOrderedId.generate();
+        thread::sleep(Duration::from_millis(2));
+        let second = TimeOrderedId.generate();
+
+        assert!(first < second);
+        assert!(first.to_string() < second.to_string());
+    }
+}
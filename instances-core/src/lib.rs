@@ -0,0 +1,953 @@
+extern crate core;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::anomalies::AnomalyDetector;
+use crate::backends::{
+    Backend, BackendResponse, BatchOperation, BatchResult, ConnectionError, InstanceRecord,
+    Operation,
+};
+use crate::daemon::UpdateDaemon;
+use crate::events::{EventBus, InstancesEvent, SubscriptionOptions};
+use crate::extension::{Extension, ExtensionRegistry, TickContext};
+use crate::ids::IdGenerator;
+use crate::models::{
+    CommunicationErrorStrategy, Consistency, CutOver, DataVersion, DepartedInstance, Departure,
+    DepartureReason, IncompatiblePeer, InstanceInfo, InstanceKind, InstanceRole, InstanceStatus,
+    LeaderStrategy, Registration, StartupPolicy, StateCheckpoint, ViewConsistency,
+};
+use crate::random::RandomSource;
+use crate::reload::ConfigWatcher;
+use crate::staleness::StalenessPolicy;
+use crate::sync::{modeled, MutexExt, RwLockExt};
+use crate::time::{Clock, HeartbeatTime};
+use crate::timings::{Timings, TimingsRecorder};
+use crate::update::Ticks;
+use crate::views::compare_views;
+
+#[cfg(feature = "alerts")]
+pub mod alerts;
+pub mod anomalies;
+pub mod backends;
+pub mod config;
+pub mod daemon;
+mod election;
+pub mod events;
+pub mod extension;
+pub mod federation;
+pub mod hashing;
+pub mod ids;
+mod maintenance;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+pub mod models;
+#[cfg(any(feature = "mdns", feature = "backend-ssdp"))]
+mod multicast;
+pub mod partitioning;
+pub mod random;
+mod reload;
+pub mod restart;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "signals")]
+mod signals;
+mod simple;
+mod snapshot;
+pub mod staleness;
+mod sync;
+pub mod testing;
+pub mod time;
+pub mod timings;
+mod update;
+mod views;
+
+pub use crate::simple::{BoxedBackend, SimpleInstances};
+
+/// Version of instances-rs, published with the registrations so that mixed
+/// version clusters are noticed, see `InstancesEvent::IncompatibleVersion`.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Version of the registration format written to the backend. Raised whenever
+/// older versions can't read the records anymore, see
+/// `Builder::with_min_protocol`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Produces the data the instance publishes on every update.
+type Extractor<T> = Box<dyn Fn() -> T + Send + Sync>;
+
+/// Tells whether the application is healthy, see `Builder::with_self_check`.
+type SelfCheck = Box<dyn Fn() -> bool + Send + Sync>;
+
+/// Tells whether an instance may lead, see `Builder::leader_eligible`.
+type LeaderEligible<T> = Box<dyn Fn(&T) -> bool + Send + Sync>;
+/// A subsystem's operation waiting for the next update, see `Instances::enqueue`.
+type QueuedOperation<T> = (
+    BatchOperation<Registration<T>>,
+    crossbeam_channel::Sender<Result<BatchResult<Registration<T>>, ConnectionError>>,
+);
+/// The failure domain an instance runs in, see `Builder::with_spread_cohort_leaders`.
+type ZoneOf<T> = Box<dyn Fn(&T) -> Option<String> + Send + Sync>;
+
+/// Measures the latency to a peer, see `Builder::with_latency_probe`.
+type LatencyProbe<T> = Box<dyn Fn(&InstanceInfo<T>) -> Option<Duration> + Send + Sync>;
+type BackendTap<T> = Box<
+    dyn Fn(Operation, &Result<BackendResponse<'_, Registration<T>>, ConnectionError>) + Send + Sync,
+>;
+
+/// Instances as listed by the backend.
+type Listing<T> = Vec<InstanceRecord<Registration<T>>>;
+
+/// Settings that can be changed at runtime, see `Builder::with_config_file`.
+#[derive(PartialEq, Clone, Copy, Debug)]
+struct Settings {
+    update_interval: Duration,
+    error_strategy: CommunicationErrorStrategy,
+    suspicion: u32,
+    solo_warmup: u32,
+}
+
+/// A heartbeat of another instance and when, in monotonic time, it was made.
+struct ObservedHeartbeat {
+    heartbeat_at: HeartbeatTime,
+    seen_at: Instant,
+}
+
+impl ObservedHeartbeat {
+    fn new(heartbeat_at: HeartbeatTime, age: Duration) -> Self {
+        let now = Instant::now();
+        ObservedHeartbeat {
+            heartbeat_at,
+            seen_at: now.checked_sub(age).unwrap_or(now),
+        }
+    }
+}
+
+/// Consecutive failed updates under `UseLastInfo` and when the listing may be
+/// attempted again.
+#[derive(Default)]
+struct ListingBackoff {
+    failures: u32,
+    resume_at: Option<Instant>,
+    /// Set by the first successful update after failures, until the daemon
+    /// runs the follow-up refresh.
+    recovered: bool,
+}
+
+/// Last record seen from an instance, used to keep it around while suspect.
+struct Peer<T> {
+    record: InstanceRecord<Registration<T>>,
+    missed_updates: u32,
+}
+
+/// The membership of the cluster as seen by the current instance.
+///
+/// `Instances` is `Send + Sync`, as are [`WeakInstances`], the
+/// [`UpdateDaemon`] and the event receivers: share them between threads or
+/// async runtimes, e.g. behind an `Arc`. Each update swaps the whole state at
+/// once, so a read sees either the previous or the new listing, never a mix.
+pub struct Instances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    instance_id: Uuid,
+    id_generator: Box<dyn IdGenerator>,
+    random: Arc<dyn RandomSource>,
+    backend: Arc<B>,
+    backend_identity: String,
+    clock: Box<dyn Clock>,
+    info_extractor: Extractor<T>,
+    extract_every: u64,
+    self_check: Option<SelfCheck>,
+    leader_eligible: Option<LeaderEligible<T>>,
+    leader_zone: Option<ZoneOf<T>>,
+    latency_probe: Option<LatencyProbe<T>>,
+    backend_tap: Option<BackendTap<T>>,
+    leader_strategy: LeaderStrategy,
+    kind: Mutex<InstanceKind>,
+    consistency: Consistency,
+    leadership_acknowledgment: bool,
+    staleness_policy: Box<dyn StalenessPolicy>,
+    settings: RwLock<Settings>,
+    config_watcher: Option<ConfigWatcher>,
+    max_payload_size: Option<usize>,
+    max_members: Option<usize>,
+    snapshot_file: Option<PathBuf>,
+    dry_run: bool,
+    tombstones: bool,
+    cohort: Option<String>,
+    require_self_visible: bool,
+    persistent_leader_term: bool,
+    push_updates: bool,
+    view_checks: bool,
+    leader_overrides: bool,
+    data_history_depth: usize,
+    delta_updates: bool,
+    degraded_while_backed_off: bool,
+    startup_policy: Option<StartupPolicy>,
+    extensions: ExtensionRegistry<B, T>,
+
+    admitted: AtomicBool,
+    startup_failed: AtomicBool,
+    last_error: Mutex<Option<ConnectionError>>,
+    listing_backoff: Mutex<ListingBackoff>,
+    draining: AtomicBool,
+    joining: AtomicBool,
+    maintenance: AtomicBool,
+    leader_claim: Mutex<Option<u64>>,
+    leader_term: Mutex<Option<u64>>,
+    view_hash: Mutex<Option<u64>>,
+    leader_override: Mutex<Option<Uuid>>,
+    diverged_updates: AtomicU32,
+    data_history: Mutex<HashMap<Uuid, VecDeque<DataVersion<T>>>>,
+    /// The registration last written and the deltas written since.
+    last_written: Mutex<Option<(Value, u32)>>,
+    anomaly_detector: Mutex<AnomalyDetector>,
+    anomalies_detected: AtomicU64,
+    /// The peers already reported as running an incompatible version.
+    incompatible_peers: Mutex<HashSet<Uuid>>,
+    min_protocol: u32,
+    queued_operations: Mutex<Vec<QueuedOperation<T>>>,
+    /// The peers left out for writing a protocol older than `min_protocol`.
+    rejected_peers: Mutex<HashSet<IncompatiblePeer>>,
+    cut_over: Mutex<Option<CutOver>>,
+    peers: Mutex<HashMap<Uuid, Peer<T>>>,
+    heartbeats: Mutex<HashMap<Uuid, ObservedHeartbeat>>,
+    last_tick: Mutex<Option<(Instant, HeartbeatTime)>>,
+    oversized_payloads: AtomicU64,
+    overruns: AtomicU64,
+    updates_completed: AtomicU64,
+    ticks: Ticks,
+    solo_updates: AtomicU32,
+    seen_self: AtomicBool,
+    last_extracted: Mutex<Option<T>>,
+    last_update_at: Mutex<Option<SystemTime>>,
+    events: EventBus,
+    timings: TimingsRecorder,
+
+    state: Arc<modeled::RwLock<InstancesState<T>>>,
+
+    daemon: Arc<Mutex<Option<UpdateDaemon>>>,
+}
+
+struct InstancesState<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    /// Sequence number of the update that produced this state. Concurrent
+    /// updates (the daemon and `refresh_now`) may finish out of order; results
+    /// older than the state are dropped.
+    tick: u64,
+    current_info: Option<Arc<InstanceInfo<T>>>,
+    instances: Arc<Vec<InstanceInfo<T>>>,
+    /// Position of each instance in `instances`.
+    index: Arc<HashMap<Uuid, usize>>,
+    stale: bool,
+    self_visible: bool,
+    leader: Option<Arc<InstanceInfo<T>>>,
+    leader_epoch: u64,
+    succession: Arc<Vec<Uuid>>,
+    departed: Arc<Vec<DepartedInstance>>,
+    cohort_leaders: Arc<HashMap<String, Uuid>>,
+}
+
+impl<T> InstancesState<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    fn next_leader_epoch(&self, leader: &Option<Arc<InstanceInfo<T>>>) -> u64 {
+        let current = self.leader.as_ref().map(|l| l.id);
+        if current == leader.as_ref().map(|l| l.id) {
+            self.leader_epoch
+        } else {
+            self.leader_epoch + 1
+        }
+    }
+}
+
+/// Iterator over a snapshot of the instances, see [`Instances::iter`]. Each
+/// instance is cloned as it's visited.
+pub struct InstancesIter<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    instances: Arc<Vec<InstanceInfo<T>>>,
+    next: usize,
+}
+
+impl<T> Iterator for InstancesIter<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    type Item = InstanceInfo<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let instance = self.instances.get(self.next)?.clone();
+        self.next += 1;
+        Some(instance)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.instances.len() - self.next;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for InstancesIter<T> where
+    T: Serialize + DeserializeOwned + Clone + 'static
+{
+}
+
+/// A reference to [`Instances`] that doesn't keep it alive, e.g. for callbacks
+/// or background tasks that shouldn't outlive the service.
+pub struct WeakInstances<B, T>(std::sync::Weak<Instances<B, T>>)
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static;
+
+impl<B, T> WeakInstances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    /// The service, unless it was already dropped.
+    pub fn upgrade(&self) -> Option<Arc<Instances<B, T>>> {
+        self.0.upgrade()
+    }
+}
+
+impl<B, T> Clone for WeakInstances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    fn clone(&self) -> Self {
+        WeakInstances(self.0.clone())
+    }
+}
+
+/// Fails to compile if a public handle stops being shareable between threads.
+const _: fn() = || {
+    fn assert_send_sync<S: Send + Sync>() {}
+    assert_send_sync::<SimpleInstances>();
+    assert_send_sync::<WeakInstances<BoxedBackend, Value>>();
+    assert_send_sync::<InstancesIter<Value>>();
+    assert_send_sync::<UpdateDaemon>();
+    assert_send_sync::<crossbeam_channel::Receiver<InstancesEvent>>();
+    assert_send_sync::<federation::FederatedInstances<BoxedBackend, Value>>();
+    assert_send_sync::<restart::RollingRestartCoordinator<BoxedBackend, Value>>();
+    assert_send_sync::<timings::Timings>();
+    #[cfg(feature = "server")]
+    assert_send_sync::<server::RegistryServer>();
+    #[cfg(feature = "mdns")]
+    assert_send_sync::<mdns::MdnsPublisher>();
+};
+
+impl<B, T> Instances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    /// Creates a [`WeakInstances`] to this service. The update daemon only holds
+    /// a weak reference too, so dropping the last `Arc` stops it.
+    pub fn downgrade(self: &Arc<Self>) -> WeakInstances<B, T> {
+        WeakInstances(Arc::downgrade(self))
+    }
+
+    pub fn get_instance_info(&self) -> Option<Arc<InstanceInfo<T>>> {
+        let guard = self.state.read_or_recover();
+        guard.current_info.as_ref().cloned()
+    }
+
+    pub fn instances_count(&self) -> Option<usize> {
+        let guard = self.state.read_or_recover();
+        match guard.instances.len() {
+            0 => None,
+            len => Some(len),
+        }
+    }
+
+    pub fn list_active_instances(&self) -> Arc<Vec<InstanceInfo<T>>> {
+        let guard = self.state.read_or_recover();
+        guard.instances.clone()
+    }
+
+    /// Iterates over the instances of the latest update. Later updates don't
+    /// affect an ongoing iteration, and the state isn't locked while iterating.
+    pub fn iter(&self) -> InstancesIter<T> {
+        InstancesIter {
+            instances: self.list_active_instances(),
+            next: 0,
+        }
+    }
+
+    /// The instance at `index` in the listing order of the latest update.
+    pub fn get(&self, index: usize) -> Option<InstanceInfo<T>> {
+        let guard = self.state.read_or_recover();
+        guard.instances.get(index).cloned()
+    }
+
+    pub fn get_by_id(&self, id: Uuid) -> Option<InstanceInfo<T>> {
+        let guard = self.state.read_or_recover();
+        let position = guard.index.get(&id)?;
+        guard.instances.get(*position).cloned()
+    }
+
+    /// The other instances ordered by latency, lowest first, up to `count`.
+    /// Instances with an unknown latency come last. See
+    /// `Builder::with_latency_probe`.
+    pub fn nearest(&self, count: usize) -> Vec<InstanceInfo<T>> {
+        let mut peers: Vec<_> = self.iter().filter(|i| i.id != self.instance_id).collect();
+        peers.sort_by_key(|i| (i.latency.is_none(), i.latency));
+        peers.truncate(count);
+        peers
+    }
+
+    /// The instance currently holding the leadership, if any.
+    pub fn current_leader(&self) -> Option<Arc<InstanceInfo<T>>> {
+        let guard = self.state.read_or_recover();
+        guard.leader.clone()
+    }
+
+    /// Term the current leader took from the backend, see
+    /// `Builder::with_persistent_leader_term`. Unlike `leader_epoch` it's
+    /// shared by every instance, so it can fence writes to external systems.
+    pub fn leader_term(&self) -> Option<u64> {
+        let guard = self.state.read_or_recover();
+        guard.leader.as_ref().and_then(|l| l.leader_term)
+    }
+
+    /// The latest values of the peer's data, oldest first, each with when this
+    /// instance first saw it. Empty unless enabled with
+    /// `Builder::with_data_history`, or once the peer left the listing.
+    pub fn data_history(&self, id: Uuid) -> Vec<DataVersion<T>> {
+        let history = self.data_history.lock_or_recover();
+        history
+            .get(&id)
+            .map(|versions| versions.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Compares the views of the membership published by the instances with
+    /// the current instance's, as of the latest update. See
+    /// `Builder::with_view_checks`.
+    pub fn view_consistency(&self) -> ViewConsistency {
+        let guard = self.state.read_or_recover();
+        compare_views(self.instance_id, &guard.instances)
+    }
+
+    /// Counter increased every time the leader changes, including when the
+    /// cluster ends up without one.
+    pub fn leader_epoch(&self) -> u64 {
+        let guard = self.state.read_or_recover();
+        guard.leader_epoch
+    }
+
+    /// Whether the leader changed after `epoch` was read from `leader_epoch`.
+    pub fn leader_changed_since(&self, epoch: u64) -> bool {
+        self.leader_epoch() != epoch
+    }
+
+    /// The instances next in line for the leadership, in order, excluding the
+    /// current leader. Empty when `LeaderStrategy::None` is used.
+    pub fn succession(&self) -> Arc<Vec<Uuid>> {
+        let guard = self.state.read_or_recover();
+        guard.succession.clone()
+    }
+
+    /// The state of the latest update, e.g. for a supervisor to checkpoint it
+    /// or for analysis tools. See `import_state`.
+    pub fn export_state(&self) -> StateCheckpoint<T> {
+        let guard = self.state.read_or_recover();
+        StateCheckpoint {
+            instance_id: self.instance_id,
+            instances: guard.instances.to_vec(),
+            leader: guard.leader.as_ref().map(|l| l.id),
+            leader_epoch: guard.leader_epoch,
+            succession: guard.succession.to_vec(),
+            departed: guard.departed.to_vec(),
+            cohort_leaders: guard.cohort_leaders.as_ref().clone(),
+            self_visible: guard.self_visible,
+        }
+    }
+
+    /// Restores a state exported by `export_state`, e.g. by an earlier run of
+    /// the instance, to serve it until the next update replaces it. Like a
+    /// snapshot loaded at startup, it's flagged as stale meanwhile.
+    pub fn import_state(&self, checkpoint: StateCheckpoint<T>) {
+        let instances = checkpoint.instances;
+        let index = index_by_id(&instances);
+        let find = |id: &Uuid| index.get(id).map(|p| Arc::new(instances[*p].clone()));
+        let current_info = find(&self.instance_id);
+        let leader = checkpoint.leader.as_ref().and_then(find);
+
+        let mut guard = self.state.write_or_recover();
+        *guard = InstancesState {
+            tick: guard.tick,
+            current_info,
+            leader,
+            instances: Arc::new(instances),
+            index: Arc::new(index),
+            stale: true,
+            self_visible: checkpoint.self_visible && checkpoint.instance_id == self.instance_id,
+            leader_epoch: checkpoint.leader_epoch,
+            succession: Arc::new(checkpoint.succession),
+            departed: Arc::new(checkpoint.departed),
+            cohort_leaders: Arc::new(checkpoint.cohort_leaders),
+        };
+    }
+
+    /// Whether the instances list comes from the snapshot saved by a previous run
+    /// and wasn't confirmed by the backend yet.
+    pub fn is_stale(&self) -> bool {
+        let guard = self.state.read_or_recover();
+        guard.stale
+    }
+
+    /// How many updates succeeded since the instance started.
+    pub fn updates_completed(&self) -> u64 {
+        self.updates_completed.load(Ordering::Relaxed)
+    }
+
+    /// When the latest successful update finished.
+    pub fn last_update_at(&self) -> Option<SystemTime> {
+        *self.last_update_at.lock_or_recover()
+    }
+
+    /// How many of the listed peers run a version of instances-rs incompatible
+    /// with this one.
+    pub fn incompatible_peers(&self) -> usize {
+        self.incompatible_peers.lock_or_recover().len()
+    }
+
+    /// The peers left out of the membership in the latest update for writing a
+    /// protocol older than `Builder::with_min_protocol`.
+    pub fn rejected_peers(&self) -> Vec<IncompatiblePeer> {
+        self.rejected_peers
+            .lock_or_recover()
+            .iter()
+            .copied()
+            .collect()
+    }
+
+    /// The source of randomness set with `Builder::with_random_source`, e.g.
+    /// for extensions jittering their work.
+    pub fn random(&self) -> &dyn RandomSource {
+        self.random.as_ref()
+    }
+
+    /// How many anomalies the `Builder::with_anomaly_rules` flagged.
+    pub fn anomalies_detected(&self) -> u64 {
+        self.anomalies_detected.load(Ordering::Relaxed)
+    }
+
+    /// How many updates took longer than the update interval.
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    /// How long the steps of the latest update took, plus percentiles over the
+    /// recent ones, to tell slow extractors apart from slow backends.
+    pub fn timings(&self) -> Timings {
+        self.timings.timings()
+    }
+
+    /// Receives the events emitted from now on. Dropping the receiver
+    /// unsubscribes.
+    pub fn subscribe(&self) -> crossbeam_channel::Receiver<InstancesEvent> {
+        self.events.subscribe()
+    }
+
+    /// Like `subscribe`, buffering at most `capacity` events. The daemon never
+    /// waits on a slow subscriber: events that don't fit are dropped and the
+    /// next one delivered is preceded by `InstancesEvent::Lagged` with how many
+    /// were missed.
+    pub fn subscribe_bounded(
+        &self,
+        capacity: usize,
+    ) -> crossbeam_channel::Receiver<InstancesEvent> {
+        self.events.subscribe_bounded(capacity)
+    }
+
+    /// The extension of type `E` registered with `Builder::with_extension`.
+    ///
+    /// ```ignore
+    /// let partitions = instances.extension::<PartitionManager>().unwrap();
+    /// ```
+    pub fn extension<E: Extension<B, T>>(&self) -> Option<Arc<E>> {
+        self.extensions.get::<E>()
+    }
+
+    /// The state of the latest update, taken at once: unlike successive calls
+    /// to `list_active_instances` and `current_leader` it can't mix the
+    /// membership of one update with the leadership of another.
+    pub fn tick_context(&self) -> TickContext<T> {
+        let guard = self.state.read_or_recover();
+        TickContext {
+            tick: guard.tick,
+            instances: guard.instances.clone(),
+            current_info: guard.current_info.clone(),
+            leader: guard.leader.clone(),
+            leader_epoch: guard.leader_epoch,
+            succession: guard.succession.clone(),
+        }
+    }
+
+    /// Lets every extension react to the update that just ran, all of them
+    /// seeing the same state.
+    pub(crate) fn run_extensions(&self) {
+        let tick = self.tick_context();
+        for extension in self.extensions.iter() {
+            extension.on_update(self, &tick);
+        }
+    }
+
+    /// Like `subscribe`, filtering, coalescing and buffering the events as set
+    /// in `options`, e.g. only `LeaderChanged` coalesced over 2s:
+    ///
+    /// ```ignore
+    /// let leaders = instances.subscribe_with(
+    ///     SubscriptionOptions::default()
+    ///         .with_filter(|e| matches!(e, InstancesEvent::LeaderChanged(_)))
+    ///         .with_debounce(Duration::from_secs(2)),
+    /// );
+    /// ```
+    pub fn subscribe_with(
+        &self,
+        options: SubscriptionOptions,
+    ) -> crossbeam_channel::Receiver<InstancesEvent> {
+        self.events.subscribe_with(options)
+    }
+
+    /// Like `subscribe_with`, on a Tokio broadcast channel of `capacity` to
+    /// await the events from async code. A slow receiver doesn't get
+    /// `InstancesEvent::Lagged`: once it falls `capacity` events behind, the
+    /// oldest ones are dropped and its next `recv` returns
+    /// `RecvError::Lagged` with how many were missed.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero, as `tokio::sync::broadcast::channel` does.
+    #[cfg(feature = "tokio")]
+    pub fn subscribe_broadcast(
+        &self,
+        capacity: usize,
+        options: SubscriptionOptions,
+    ) -> tokio::sync::broadcast::Receiver<InstancesEvent> {
+        self.events.subscribe_broadcast(capacity, options)
+    }
+
+    /// Queues a backend operation of a subsystem sharing the backend (locks,
+    /// counters...) to send it with the next update's. On backends supporting
+    /// `Capabilities::batching` the registration write, the listing and the
+    /// queued operations then share their round trips. The result is
+    /// delivered on the returned channel. In dry run the operation fails
+    /// right away, as nothing is written to the backend.
+    pub fn enqueue(
+        &self,
+        operation: BatchOperation<Registration<T>>,
+    ) -> crossbeam_channel::Receiver<Result<BatchResult<Registration<T>>, ConnectionError>> {
+        let (sender, receiver) = crossbeam_channel::bounded(1);
+        if self.dry_run {
+            let _ = sender.send(Err(ConnectionError::FailedToUpdate(
+                "Dry run, the queued operations aren't sent.".to_string(),
+            )));
+            return receiver;
+        }
+        self.queued_operations
+            .lock_or_recover()
+            .push((operation, sender));
+        receiver
+    }
+
+    /// Runs an update right away instead of waiting for the next tick, e.g. after
+    /// changing what the info extractor returns.
+    pub fn refresh_now(&self) -> Result<(), InstancesError> {
+        if self.draining.load(Ordering::SeqCst) {
+            return Err(InstancesError::ShuttingDown);
+        }
+        if self.daemon.lock_or_recover().is_none() {
+            return Err(InstancesError::NotStarted);
+        }
+        Ok(self.update_instance_info()?)
+    }
+
+    /// Waits until the first update succeeds.
+    ///
+    /// Fails with `InstancesError::Backend` holding the cause if the attempts
+    /// made so far errored, or with `InstancesError::Timeout` if none finished
+    /// in time. If the daemon gave up on the first update, see
+    /// `Builder::with_startup_policy`, it fails right away.
+    pub fn wait_for_first_update(&self, duration: Duration) -> Result<(), InstancesError> {
+        let end = Instant::now() + duration;
+        while Instant::now() < end && self.get_instance_info().is_none() {
+            if self.startup_failed.load(Ordering::SeqCst) {
+                if let Some(error) = self.last_error.lock_or_recover().clone() {
+                    return Err(InstancesError::Backend(error));
+                }
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        if Instant::now() < end {
+            return Ok(());
+        }
+        match self.last_error.lock_or_recover().clone() {
+            Some(error) => Err(InstancesError::Backend(error)),
+            None => Err(InstancesError::Timeout),
+        }
+    }
+
+    /// How many updates were refused because the payload exceeded the size limit.
+    pub fn oversized_payloads(&self) -> u64 {
+        self.oversized_payloads.load(Ordering::Relaxed)
+    }
+
+    /// The deployment cohort of this instance, see `Builder::with_cohort`.
+    pub fn cohort(&self) -> Option<&str> {
+        self.cohort.as_deref()
+    }
+
+    /// Makes `cohort` the active one cluster-wide. The cut-over is published
+    /// with the next update and adopted by every instance as it lists it.
+    pub fn cut_over(&self, cohort: impl Into<String>) {
+        let cohort = cohort.into();
+        info!("Cutting over to cohort {}.", cohort);
+        *self.cut_over.lock_or_recover() = Some(CutOver {
+            cohort,
+            at: self.clock.now(),
+        });
+    }
+
+    /// The cohort made active by the latest cut-over seen, if any.
+    pub fn active_cohort(&self) -> Option<String> {
+        self.cut_over
+            .lock_or_recover()
+            .as_ref()
+            .map(|c| c.cohort.clone())
+    }
+
+    /// The instances of `cohort`.
+    pub fn cohort_members(&self, cohort: &str) -> Vec<InstanceInfo<T>> {
+        let guard = self.state.read_or_recover();
+        guard
+            .instances
+            .iter()
+            .filter(|i| i.cohort.as_deref() == Some(cohort))
+            .cloned()
+            .collect()
+    }
+
+    /// The leader elected among the instances of `cohort` only, with the same
+    /// strategy as the cluster-wide leader.
+    pub fn cohort_leader(&self, cohort: &str) -> Option<InstanceInfo<T>> {
+        let guard = self.state.read_or_recover();
+        let leader = guard.cohort_leaders.get(cohort)?;
+        let position = guard.index.get(leader)?;
+        guard.instances.get(*position).cloned()
+    }
+
+    /// Publishes the instance as `Joining` right away, before the application
+    /// is ready to take work, so peers (and partitioners) can prepare for it.
+    /// Call `mark_ready` once ready. Build with `Builder::with_pre_registration`
+    /// to never be published as active before that.
+    pub fn pre_register(&self) -> Result<(), InstancesError> {
+        self.joining.store(true, Ordering::SeqCst);
+        self.refresh_now()
+    }
+
+    /// Ends the `Joining` state started by `pre_register`, publishing the
+    /// instance as active right away.
+    pub fn mark_ready(&self) -> Result<(), InstancesError> {
+        self.joining.store(false, Ordering::SeqCst);
+        self.refresh_now()
+    }
+
+    /// Turns a standby, see `Builder::as_standby`, into a member, publishing it
+    /// right away so it can take the leadership and partitions. Does nothing
+    /// for an instance that isn't a standby.
+    pub fn promote(&self) -> Result<(), InstancesError> {
+        {
+            let mut kind = self.kind.lock_or_recover();
+            if *kind != InstanceKind::Standby {
+                return Ok(());
+            }
+            *kind = InstanceKind::Member;
+        }
+        info!("Standby instance {} promoted.", self.instance_id);
+        self.refresh_now()
+    }
+
+    /// Names `leader` the leader of the instances built with
+    /// `Builder::with_leader_override`, from their next update on, until
+    /// cleared with `None`.
+    pub fn override_leader(&self, leader: Option<Uuid>) -> Result<(), InstancesError> {
+        let result = self.backend.store_leader_override(leader);
+        self.tap(Operation::Override, result, |_| BackendResponse::Done)
+            .map_err(|e| self.backend_error(Operation::Override, e))?;
+        Ok(())
+    }
+
+    /// The leader override honoured by the latest update, see
+    /// `Builder::with_leader_override`.
+    pub fn leader_override(&self) -> Option<Uuid> {
+        *self.leader_override.lock_or_recover()
+    }
+
+    /// Flags the instance as under maintenance from the next update on. It stays
+    /// visible to its peers but can't be elected leader.
+    pub fn set_maintenance(&self, maintenance: bool) {
+        self.maintenance.store(maintenance, Ordering::SeqCst);
+    }
+
+    /// Gracefully leaves the cluster, meant to be called from a SIGTERM handler or
+    /// a preStop hook.
+    ///
+    /// The instance is published as `Draining`, which removes it from the leader
+    /// election, and then waits until its own record shows up as draining plus one
+    /// update interval, giving every peer a chance to observe the change. After
+    /// that, or once `grace` expires, the daemon is stopped and the instance is
+    /// removed from the backend.
+    pub fn drain(&self, grace: Duration) -> Result<(), InstancesError> {
+        self.leave(grace, DepartureReason::Drain)
+    }
+
+    /// Drains the instance as `drain` does, recording `reason` in the tombstone.
+    pub fn leave(&self, grace: Duration, reason: DepartureReason) -> Result<(), InstancesError> {
+        let end = Instant::now() + grace;
+        if self.draining.swap(true, Ordering::SeqCst) {
+            return Err(InstancesError::ShuttingDown);
+        }
+
+        info!("Draining the instance.");
+
+        if let Err(error) = self.update_instance_info() {
+            warn!("Error publishing the draining status. Cause: {}", error);
+        }
+
+        let mut observed_at = None;
+        while !self.dry_run && Instant::now() < end {
+            match observed_at {
+                None if self.is_draining_visible() => observed_at = Some(Instant::now()),
+                Some(at) if at.elapsed() >= self.settings().update_interval => break,
+                _ => {}
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        if observed_at.is_none() && !self.dry_run {
+            warn!("Grace period expired before the draining status was observed.");
+        }
+
+        if let Some(daemon) = self.daemon.lock_or_recover().take() {
+            daemon.stop();
+        }
+
+        if self.dry_run {
+            info!("Dry run, skipping the deregistration.");
+            return Ok(());
+        }
+
+        if self.tombstones {
+            self.write_tombstone(reason)?;
+            info!("Instance tombstone written.");
+            return Ok(());
+        }
+
+        let result = self.backend.deregister_instance(self.instance_id);
+        self.tap(Operation::Deregister, result, |_| BackendResponse::Done)
+            .map_err(|e| self.backend_error(Operation::Deregister, e))?;
+
+        info!("Instance deregistered.");
+
+        Ok(())
+    }
+
+    /// Replaces the instance's record with a `Departed` one instead of deleting
+    /// it, so the other instances can tell it left gracefully.
+    fn write_tombstone(&self, reason: DepartureReason) -> Result<(), InstancesError> {
+        let tombstone = Registration {
+            status: InstanceStatus::Departed,
+            kind: *self.kind.lock_or_recover(),
+            maintenance: false,
+            leader_claim: None,
+            departure: Some(Departure {
+                at: self.clock.now(),
+                reason,
+            }),
+            cohort: self.cohort.clone(),
+            cut_over: None,
+            leader_term: None,
+            view_hash: None,
+            version: Some(VERSION.to_string()),
+            protocol: PROTOCOL_VERSION,
+            data: (self.info_extractor)(),
+        };
+        let result = self
+            .backend
+            .update_instance_info(self.instance_id, tombstone);
+        self.tap(Operation::Deregister, result, |_| BackendResponse::Done)
+            .map_err(|e| self.backend_error(Operation::Deregister, e))?;
+        Ok(())
+    }
+
+    /// Instances that left gracefully less than `window` ago, as told by their
+    /// tombstones. Instances that vanished without one most likely crashed.
+    pub fn recently_departed(&self, window: Duration) -> Vec<DepartedInstance> {
+        let now = self.clock.now();
+        let guard = self.state.read_or_recover();
+        guard
+            .departed
+            .iter()
+            .filter(|d| now.saturating_duration_since(d.departure.at) <= window)
+            .copied()
+            .collect()
+    }
+
+    fn is_draining_visible(&self) -> bool {
+        let guard = self.state.read_or_recover();
+        guard.self_visible
+            && guard
+                .current_info
+                .as_ref()
+                .map(|info| info.status == InstanceStatus::Draining)
+                .unwrap_or(false)
+    }
+}
+
+/// Position of each instance, by id.
+fn index_by_id<T>(instances: &[InstanceInfo<T>]) -> HashMap<Uuid, usize>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    instances
+        .iter()
+        .enumerate()
+        .map(|(position, i)| (i.id, position))
+        .collect()
+}
+
+#[derive(Error, PartialEq, Debug)]
+pub enum InstancesError {
+    #[error(r#"BacTimeout waiting for the first update."#)]
+    Timeout,
+    #[error(transparent)]
+    Backend(#[from] ConnectionError),
+    #[error(r#"The update daemon is not running."#)]
+    NotStarted,
+    #[error(r#"The instance is draining or already deregistered."#)]
+    ShuttingDown,
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,235 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::backends::{Backend, ConnectionError, Operation};
+use crate::extension::{Extension, TickContext};
+use crate::sync::MutexExt;
+use crate::{Instances, Registration};
+
+type Task<T> = Arc<dyn Fn(&TickContext<T>) + Send + Sync>;
+
+/// Housekeeping run by the leader only, see `Builder::leader_maintenance`.
+pub(crate) struct MaintenanceTask<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    interval: Duration,
+    task: Task<T>,
+    last_run: Mutex<Option<Instant>>,
+    running: Arc<AtomicBool>,
+}
+
+impl<T> MaintenanceTask<T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    pub(crate) fn new(
+        interval: Duration,
+        task: impl Fn(&TickContext<T>) + Send + Sync + 'static,
+    ) -> Self {
+        MaintenanceTask {
+            interval,
+            task: Arc::new(task),
+            last_run: Mutex::new(None),
+            running: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Starts the task on a thread of its own if it's due and not running.
+    fn run_if_due(&self, tick: &TickContext<T>) {
+        let mut last_run = self.last_run.lock_or_recover();
+        if last_run.is_some_and(|at| at.elapsed() < self.interval) {
+            return;
+        }
+        if self.running.swap(true, Ordering::SeqCst) {
+            warn!("Leader maintenance still running, skipping this run.");
+            return;
+        }
+        *last_run = Some(Instant::now());
+
+        let running = RunningGuard(self.running.clone());
+        let task = self.task.clone();
+        let tick = tick.clone();
+        thread::spawn(move || {
+            let _running = running;
+            task(&tick);
+        });
+    }
+}
+
+/// Clears the running flag once the task is over, even if it panicked.
+struct RunningGuard(Arc<AtomicBool>);
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Runs the maintenance tasks while the instance leads.
+pub(crate) struct LeaderMaintenance<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    tasks: Vec<MaintenanceTask<T>>,
+}
+
+impl<T> LeaderMaintenance<T>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    pub(crate) fn new(tasks: Vec<MaintenanceTask<T>>) -> Self {
+        LeaderMaintenance { tasks }
+    }
+}
+
+impl<B, T> Extension<B, T> for LeaderMaintenance<T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+{
+    fn on_update(&self, _: &Instances<B, T>, tick: &TickContext<T>)
+    where
+        B: Backend<Registration<T>> + Send + Sync + 'static,
+    {
+        if !tick.is_leader() {
+            return;
+        }
+        for task in &self.tasks {
+            task.run_if_due(tick);
+        }
+    }
+}
+
+/// Deletes the records that stopped heart-beating more than `max_age` ago,
+/// see `Builder::with_janitor`.
+pub(crate) fn janitor<B, T>(
+    backend: Arc<B>,
+    backend_identity: String,
+    cadence: Duration,
+    max_age: Duration,
+) -> MaintenanceTask<T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    MaintenanceTask::new(cadence, move |_| match backend.prune_expired(max_age) {
+        Ok(0) => {}
+        Ok(pruned) => info!("Pruned {} expired records.", pruned),
+        Err(e) => {
+            let error =
+                ConnectionError::Failed(backend_identity.clone(), Operation::Prune, Box::new(e));
+            warn!("Error pruning the expired records. {}", error);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+
+    use mockall::predicate::eq;
+    use uuid::Uuid;
+
+    use crate::backends::MockBackend;
+    use crate::tests::{mock_data_for, new_instance};
+    use crate::{CommunicationErrorStrategy, LeaderStrategy};
+
+    use super::*;
+
+    fn tick_of(
+        id: Uuid,
+        members: Vec<Uuid>,
+    ) -> (
+        Instances<MockBackend<Registration<String>>, String>,
+        TickContext<String>,
+    ) {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend
+            .expect_list_active_instances()
+            .returning(move || Ok(mock_data_for(members.clone())));
+        let instance = new_instance(
+            id,
+            backend,
+            LeaderStrategy::Oldest,
+            CommunicationErrorStrategy::Error,
+        );
+        instance.update_instance_info().unwrap();
+        let tick = instance.tick_context();
+        (instance, tick)
+    }
+
+    #[test]
+    fn should_run_the_tasks_only_on_the_leader() {
+        let id = Uuid::new_v4();
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        let maintenance =
+            LeaderMaintenance::new(vec![MaintenanceTask::new(Duration::ZERO, move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })]);
+
+        let (follower, tick) = tick_of(id, vec![Uuid::new_v4(), id]);
+        maintenance.on_update(&follower, &tick);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(0, runs.load(Ordering::SeqCst));
+
+        let (leader, tick) = tick_of(id, vec![id]);
+        maintenance.on_update(&leader, &tick);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn should_not_overlap_the_runs_of_a_task() {
+        let id = Uuid::new_v4();
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        let task = MaintenanceTask::new(Duration::ZERO, move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+        });
+        let (_leader, tick) = tick_of(id, vec![id]);
+
+        task.run_if_due(&tick);
+        thread::sleep(Duration::from_millis(10));
+        task.run_if_due(&tick);
+        thread::sleep(Duration::from_millis(60));
+        task.run_if_due(&tick);
+        thread::sleep(Duration::from_millis(10));
+
+        assert_eq!(2, runs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn should_prune_the_expired_records() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        backend
+            .expect_prune_expired()
+            .with(eq(Duration::from_secs(300)))
+            .times(1)
+            .returning(|_| Ok(2));
+        let id = Uuid::new_v4();
+        let (_leader, tick) = tick_of(id, vec![id]);
+
+        let task = janitor(
+            Arc::new(backend),
+            "mock".to_string(),
+            Duration::from_secs(60),
+            Duration::from_secs(300),
+        );
+        task.run_if_due(&tick);
+        task.run_if_due(&tick);
+        thread::sleep(Duration::from_millis(20));
+        // Checks the expectations of the backend owned by the task.
+        drop(task);
+    }
+}
@@ -0,0 +1,368 @@
+//! Publishes the instances on the local network with mDNS (RFC 6762) as
+//! DNS-SD services (RFC 6763), see [`Instances::publish_mdns`].
+//!
+//! Only what's needed to announce the instance and to notice its peers is
+//! implemented: unsolicited announcements, answers to the queries for the
+//! service, and the reading of the peers' announcements.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{debug, info};
+use uuid::Uuid;
+
+use crate::multicast;
+use crate::sync::MutexExt;
+use crate::{Backend, InstanceRole, Instances, Registration};
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+const MDNS_PORT: u16 = 5353;
+/// Service type the instances are published as.
+pub const SERVICE: &str = "_instances._tcp.local";
+const RECORD_TTL: u32 = 120;
+const ANNOUNCE_EVERY: Duration = Duration::from_secs(20);
+const POLL: Duration = Duration::from_millis(250);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+/// Set on the records owned by a single host, see RFC 6762 section 10.2.
+const CACHE_FLUSH: u16 = 0x8000;
+
+/// The mDNS publication started by [`Instances::publish_mdns`]. Dropping it
+/// stops announcing the instance.
+pub struct MdnsPublisher {
+    stopped: Arc<AtomicBool>,
+    peers: Arc<Mutex<HashMap<Uuid, Instant>>>,
+}
+
+impl MdnsPublisher {
+    /// The instances whose announcements were heard in the last `window`, a
+    /// source of peers not depending on the backend.
+    pub fn discovered_peers(&self, window: Duration) -> Vec<Uuid> {
+        self.peers
+            .lock_or_recover()
+            .iter()
+            .filter(|(_, seen)| seen.elapsed() <= window)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+}
+
+impl Drop for MdnsPublisher {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<B, T> Instances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    /// Announces the instance on the local network as
+    /// `<instance id>._instances._tcp.local`, reachable on `port` of
+    /// `<instance id>.local`, whose A and AAAA records hold the addresses of
+    /// this host, with its id and role in the TXT record. LAN tools (`avahi-browse`,
+    /// `dns-sd`) can then find the members without access to the backend.
+    ///
+    /// Binds the mDNS port, shared with the other responders of the host.
+    pub fn publish_mdns(self: &Arc<Self>, port: u16) -> io::Result<MdnsPublisher> {
+        let socket = multicast::join_v4(MDNS_GROUP, MDNS_PORT)?;
+        socket.set_read_timeout(Some(POLL))?;
+        let group = SocketAddr::V4(SocketAddrV4::new(MDNS_GROUP, MDNS_PORT));
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let peers = Arc::new(Mutex::new(HashMap::new()));
+        let service = self.downgrade();
+        let (stop, seen) = (stopped.clone(), peers.clone());
+
+        thread::spawn(move || {
+            let mut last_announced: Option<Instant> = None;
+            let mut buffer = [0u8; 9000];
+            while !stop.load(Ordering::SeqCst) {
+                let service = match service.upgrade() {
+                    Some(service) => service,
+                    None => break,
+                };
+                let id = service.instance_id;
+                let role = service
+                    .get_instance_info()
+                    .map(|info| info.role.clone())
+                    .unwrap_or(InstanceRole::Unknown);
+                drop(service);
+
+                let mut announce = last_announced.is_none_or(|at| at.elapsed() >= ANNOUNCE_EVERY);
+                if let Ok((len, _)) = socket.recv_from(&mut buffer) {
+                    match parse(&buffer[..len]) {
+                        Some(Message::Query(names)) => {
+                            announce |= names.iter().any(|n| n.eq_ignore_ascii_case(SERVICE))
+                        }
+                        Some(Message::Announcement(ids)) => {
+                            let now = Instant::now();
+                            let mut peers = seen.lock_or_recover();
+                            for peer in ids.into_iter().filter(|peer| *peer != id) {
+                                peers.insert(peer, now);
+                            }
+                        }
+                        None => {}
+                    }
+                }
+                if announce {
+                    if let Err(error) =
+                        socket.send_to(&announcement(id, role, port, &addresses()), group)
+                    {
+                        debug!("Error sending the mDNS announcement. Cause: {}", error);
+                    }
+                    last_announced = Some(Instant::now());
+                }
+            }
+        });
+        info!("Publishing the instance over mDNS as {}.", SERVICE);
+
+        Ok(MdnsPublisher { stopped, peers })
+    }
+}
+
+#[derive(PartialEq, Debug)]
+enum Message {
+    /// The names asked for.
+    Query(Vec<String>),
+    /// The ids of the instances announced.
+    Announcement(Vec<Uuid>),
+}
+
+/// The addresses the host multicasts from, one per IP version it has a route
+/// for. The sockets are only connected, nothing is sent.
+fn addresses() -> Vec<IpAddr> {
+    let local = |bind: SocketAddr, group: SocketAddr| {
+        let socket = UdpSocket::bind(bind).ok()?;
+        socket.connect(group).ok()?;
+        Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_unspecified())
+    };
+    let v4 = local(
+        (Ipv4Addr::UNSPECIFIED, 0).into(),
+        (MDNS_GROUP, MDNS_PORT).into(),
+    );
+    let v6 = local(
+        (Ipv6Addr::UNSPECIFIED, 0).into(),
+        (MDNS_GROUP_V6, MDNS_PORT).into(),
+    );
+    v4.into_iter().chain(v6).collect()
+}
+
+/// An mDNS response announcing the instance: the PTR record of the service
+/// pointing at the instance, the instance's SRV and TXT records, and the A
+/// and AAAA records of the SRV's target.
+fn announcement(id: Uuid, role: InstanceRole, port: u16, addresses: &[IpAddr]) -> Vec<u8> {
+    let instance = format!("{}.{}", id, SERVICE);
+    let host = format!("{}.local", id);
+    let role = match role {
+        InstanceRole::Leader => "leader",
+        InstanceRole::Follower => "follower",
+        InstanceRole::Unknown => "unknown",
+    };
+
+    // Header: id 0, authoritative answer, no questions, the answers.
+    let answers = 3 + addresses.len() as u16;
+    let mut packet = vec![0, 0, 0x84, 0, 0, 0];
+    packet.extend_from_slice(&answers.to_be_bytes());
+    packet.extend_from_slice(&[0, 0, 0, 0]);
+    record(&mut packet, SERVICE, TYPE_PTR, CLASS_IN, &name(&instance));
+
+    let mut srv = vec![0, 0, 0, 0];
+    srv.extend_from_slice(&port.to_be_bytes());
+    srv.extend(name(&host));
+    record(
+        &mut packet,
+        &instance,
+        TYPE_SRV,
+        CLASS_IN | CACHE_FLUSH,
+        &srv,
+    );
+
+    let mut txt = vec![];
+    for entry in [format!("id={}", id), format!("role={}", role)] {
+        txt.push(entry.len() as u8);
+        txt.extend_from_slice(entry.as_bytes());
+    }
+    record(
+        &mut packet,
+        &instance,
+        TYPE_TXT,
+        CLASS_IN | CACHE_FLUSH,
+        &txt,
+    );
+
+    for address in addresses {
+        let (kind, data) = match address {
+            IpAddr::V4(address) => (TYPE_A, address.octets().to_vec()),
+            IpAddr::V6(address) => (TYPE_AAAA, address.octets().to_vec()),
+        };
+        record(&mut packet, &host, kind, CLASS_IN | CACHE_FLUSH, &data);
+    }
+    packet
+}
+
+fn record(packet: &mut Vec<u8>, owner: &str, kind: u16, class: u16, data: &[u8]) {
+    packet.extend(name(owner));
+    packet.extend_from_slice(&kind.to_be_bytes());
+    packet.extend_from_slice(&class.to_be_bytes());
+    packet.extend_from_slice(&RECORD_TTL.to_be_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+}
+
+/// A domain name as a sequence of length-prefixed labels.
+fn name(name: &str) -> Vec<u8> {
+    let mut encoded = vec![];
+    for label in name.split('.') {
+        encoded.push(label.len() as u8);
+        encoded.extend_from_slice(label.as_bytes());
+    }
+    encoded.push(0);
+    encoded
+}
+
+/// Reads the queries and the announcements of the service, ignoring the rest.
+/// `None` for malformed packets.
+fn parse(packet: &[u8]) -> Option<Message> {
+    let header = packet.get(..12)?;
+    let is_response = header[2] & 0x80 != 0;
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+    let mut at = 12;
+
+    let mut asked = vec![];
+    for _ in 0..questions {
+        let (name, next) = read_name(packet, at)?;
+        asked.push(name);
+        at = next + 4;
+    }
+    if !is_response {
+        return Some(Message::Query(asked));
+    }
+
+    let mut announced = vec![];
+    for _ in 0..answers {
+        let (owner, next) = read_name(packet, at)?;
+        let fields = packet.get(next..next + 10)?;
+        let kind = u16::from_be_bytes([fields[0], fields[1]]);
+        let length = u16::from_be_bytes([fields[8], fields[9]]) as usize;
+        let data = packet.get(next + 10..next + 10 + length)?;
+        at = next + 10 + length;
+
+        if kind == TYPE_TXT && owner.to_ascii_lowercase().ends_with(SERVICE) {
+            announced.extend(read_txt(data).iter().find_map(|entry| {
+                let id = entry.strip_prefix("id=")?;
+                Uuid::parse_str(id).ok()
+            }));
+        }
+    }
+    Some(Message::Announcement(announced))
+}
+
+/// Reads the name at `at`, following compression pointers. Returns it with
+/// the position right after it.
+fn read_name(packet: &[u8], mut at: usize) -> Option<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+    for _ in 0..128 {
+        let length = *packet.get(at)? as usize;
+        match length {
+            0 => {
+                return Some((labels.join("."), end.unwrap_or(at + 1)));
+            }
+            pointer if pointer & 0xC0 == 0xC0 => {
+                let offset = ((pointer & 0x3F) << 8) | *packet.get(at + 1)? as usize;
+                end.get_or_insert(at + 2);
+                at = offset;
+            }
+            _ => {
+                let label = packet.get(at + 1..at + 1 + length)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                at += 1 + length;
+            }
+        }
+    }
+    None
+}
+
+fn read_txt(mut data: &[u8]) -> Vec<String> {
+    let mut entries = vec![];
+    while let Some((&length, rest)) = data.split_first() {
+        let Some(entry) = rest.get(..length as usize) else {
+            break;
+        };
+        entries.push(String::from_utf8_lossy(entry).into_owned());
+        data = &rest[length as usize..];
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_read_the_announced_instances() {
+        let id = Uuid::new_v4();
+
+        let packet = announcement(id, InstanceRole::Leader, 8080, &[]);
+
+        assert_eq!(Some(Message::Announcement(vec![id])), parse(&packet));
+    }
+
+    #[test]
+    fn should_announce_the_addresses_of_the_host() {
+        let id = Uuid::new_v4();
+        let addresses = [
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10)),
+            IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)),
+        ];
+
+        let packet = announcement(id, InstanceRole::Follower, 8080, &addresses);
+
+        assert_eq!(&[0, 5], &packet[6..8]);
+        let mut host = name(&format!("{}.local", id));
+        host.extend_from_slice(&TYPE_A.to_be_bytes());
+        let at = packet
+            .windows(host.len())
+            .position(|window| window == host)
+            .unwrap();
+        assert_eq!(&[192, 168, 1, 10], &packet[at + host.len() + 8..][..4]);
+        assert_eq!(Some(Message::Announcement(vec![id])), parse(&packet));
+    }
+
+    #[test]
+    fn should_read_the_queried_names() {
+        let mut query = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend(name(SERVICE));
+        query.extend_from_slice(&[0, 12, 0, 1]);
+
+        assert_eq!(
+            Some(Message::Query(vec![SERVICE.to_string()])),
+            parse(&query)
+        );
+        assert_eq!(None, parse(&query[..20]));
+    }
+
+    #[test]
+    fn should_follow_compressed_names() {
+        let mut packet = name("local");
+        packet.extend_from_slice(&[3, b'f', b'o', b'o', 0xC0, 0]);
+
+        assert_eq!(Some(("foo.local".to_string(), 13)), read_name(&packet, 7));
+    }
+}
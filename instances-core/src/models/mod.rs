@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::time::HeartbeatTime;
+
+#[cfg(feature = "derive")]
+pub use instances_rs_derive::InstanceData;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LeaderStrategy {
+    None,
+    Oldest,
+    Newest,
+    /// The instance the backend designates leads, see
+    /// `InstanceRecord::designated_leader`, e.g. the holder of a PostgreSQL
+    /// advisory lock. Authoritative, as it doesn't compare timestamps: no
+    /// instance leads while none is designated. While the designated one
+    /// can't lead, e.g. it's draining, the oldest eligible one does. The
+    /// others follow in line from the oldest. Needs a backend advertising
+    /// `Capabilities::designated_leaders`.
+    Backend,
+}
+
+#[derive(Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum CommunicationErrorStrategy {
+    Error,
+    UseLastInfo,
+}
+
+/// How the update daemon handles a failing first update.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct StartupPolicy {
+    /// How long to retry the first update, with exponential backoff.
+    pub retry_for: Duration,
+    /// What to do if it still fails after `retry_for`.
+    pub then: StartupFallback,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum StartupFallback {
+    /// Stops the daemon. `Instances::wait_for_first_update` reports the error.
+    GiveUp,
+    /// Keeps retrying at the update interval.
+    KeepRetrying,
+}
+
+/// How fresh the listing must be compared to the write made in the same update.
+#[derive(PartialEq, Debug)]
+pub enum Consistency {
+    /// Accepts whatever the backend lists. If the instance's own write isn't
+    /// visible yet the local registration is used in its place.
+    Eventual,
+    /// Retries the listing until the instance's own write is visible. Meant for
+    /// eventually consistent backends such as DynamoDB or S3.
+    ReadYourWrites,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum InstanceRole {
+    Leader,
+    Follower,
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub enum InstanceStatus {
+    Active,
+    /// The instance announced itself but isn't ready to take work yet, see
+    /// `Instances::pre_register`. It can't lead.
+    Joining,
+    Draining,
+    /// The instance's self-check failed. It stays listed but can't lead.
+    Degraded,
+    /// The instance left, its record is a tombstone, see `Builder::with_tombstones`.
+    Departed,
+}
+
+/// Witnesses count as members, e.g. to break ties in two-node deployments, but
+/// are never elected leader. Standbys are warm spares: they observe the cluster
+/// but get neither the leadership nor partitions until `Instances::promote`.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug, Default)]
+pub enum InstanceKind {
+    #[default]
+    Member,
+    Witness,
+    Standby,
+}
+
+impl InstanceKind {
+    pub fn is_member(&self) -> bool {
+        *self == InstanceKind::Member
+    }
+}
+
+/// What each instance publishes to the backend: the user data wrapped with the
+/// metadata the other instances need to classify it.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct Registration<T> {
+    pub status: InstanceStatus,
+    #[serde(default, skip_serializing_if = "InstanceKind::is_member")]
+    pub kind: InstanceKind,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub maintenance: bool,
+    /// Set by the elected leader when the leadership acknowledgment is enabled.
+    /// The value is the leader epoch in which the leadership was claimed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leader_claim: Option<u64>,
+    /// Set on the tombstone written when the instance leaves.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub departure: Option<Departure>,
+    /// Deployment cohort of the instance, see `Builder::with_cohort`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cohort: Option<String>,
+    /// Latest cut-over the instance knows of, republished by every instance so
+    /// it outlives the one that made it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cut_over: Option<CutOver>,
+    /// Term of the leadership held by the instance, see
+    /// `Builder::with_persistent_leader_term`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub leader_term: Option<u64>,
+    /// Hash of the membership the instance saw in its previous update, see
+    /// `Builder::with_view_checks`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub view_hash: Option<u64>,
+    /// Version of instances-rs the instance runs, see `crate::VERSION`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Wire protocol the registration is written with, see
+    /// `crate::PROTOCOL_VERSION`. Zero for the versions predating it.
+    #[serde(default)]
+    pub protocol: u32,
+    pub data: T,
+}
+
+/// Makes `cohort` the active one, see `Instances::cut_over`. The latest
+/// cut-over wins.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct CutOver {
+    pub cohort: String,
+    pub at: HeartbeatTime,
+}
+
+/// How an instance left the cluster, recorded in its tombstone.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub struct Departure {
+    pub at: HeartbeatTime,
+    #[serde(default)]
+    pub reason: DepartureReason,
+}
+
+/// Why an instance left the cluster.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+pub enum DepartureReason {
+    /// The process was asked to stop, e.g. on SIGTERM.
+    #[default]
+    Shutdown,
+    /// The instance was drained through `Instances::drain`.
+    Drain,
+    /// Another instance or an operator removed it from the cluster.
+    Evicted,
+    /// It vanished without leaving a tombstone, most likely it crashed. Only
+    /// ever detected by the other instances.
+    CrashDetected,
+}
+
+/// A value of an instance's data and when it was first seen, see
+/// `Instances::data_history`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct DataVersion<T> {
+    pub observed_at: HeartbeatTime,
+    pub data: T,
+}
+
+/// The state of an `Instances` as of its latest update, see
+/// `Instances::export_state`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StateCheckpoint<T>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    /// The instance the state was exported from.
+    pub instance_id: Uuid,
+    #[serde(bound(deserialize = ""))]
+    pub instances: Vec<InstanceInfo<T>>,
+    pub leader: Option<Uuid>,
+    pub leader_epoch: u64,
+    /// The instances next in line for the leadership, after the leader.
+    pub succession: Vec<Uuid>,
+    pub departed: Vec<DepartedInstance>,
+    pub cohort_leaders: HashMap<String, Uuid>,
+    /// Whether the instance's own record came from the backend.
+    pub self_visible: bool,
+}
+
+/// How the views of the membership published by the instances compare with
+/// the current instance's, see `Instances::view_consistency`. Instances not
+/// publishing their view yet are left out.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct ViewConsistency {
+    /// The instances seeing the same members as the current one, itself
+    /// included.
+    pub agreeing: Vec<Uuid>,
+    /// The instances seeing other members.
+    pub diverging: Vec<Uuid>,
+}
+
+impl ViewConsistency {
+    pub fn is_consistent(&self) -> bool {
+        self.diverging.is_empty()
+    }
+}
+
+/// A peer left out of the membership because it writes a wire protocol older
+/// than the minimum accepted, see `Builder::with_min_protocol`.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct IncompatiblePeer {
+    pub id: Uuid,
+    pub protocol: u32,
+}
+
+/// An instance that left the cluster gracefully, see
+/// `Instances::recently_departed`.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Copy, Debug)]
+pub struct DepartedInstance {
+    pub id: Uuid,
+    pub departure: Departure,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstanceInfo<T>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    pub id: Uuid,
+    pub role: InstanceRole,
+    pub status: InstanceStatus,
+    pub kind: InstanceKind,
+    pub maintenance: bool,
+    #[serde(default)]
+    pub cohort: Option<String>,
+    /// Term stored in the backend when the instance took the leadership, see
+    /// `Builder::with_persistent_leader_term`.
+    #[serde(default)]
+    pub leader_term: Option<u64>,
+    /// Latency to the instance, reported by the backend or measured by the
+    /// `Builder::with_latency_probe` hook. Used by `Instances::nearest`.
+    #[serde(default)]
+    pub latency: Option<Duration>,
+    /// Hash of the membership the instance published, see
+    /// `Builder::with_view_checks`.
+    #[serde(default)]
+    pub view_hash: Option<u64>,
+    /// Version of instances-rs the instance runs, unknown for the versions
+    /// not publishing it.
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Missing from the latest listings but not yet confirmed as gone.
+    #[serde(default)]
+    pub suspect: bool,
+    #[serde(deserialize_with = "T::deserialize")]
+    pub data: T,
+    /// Backend specific metadata attached to the record, see
+    /// [`InstanceRecord::extensions`](crate::backends::InstanceRecord::extensions).
+    #[serde(default)]
+    pub extensions: HashMap<String, Value>,
+}
+
+/// Instance data exposing the metadata used to weight, place and reach the
+/// instances. Every method is optional; with the `derive` feature it can be
+/// derived by marking the fields, e.g. `#[instance(zone)]`.
+pub trait InstanceData: Serialize + DeserializeOwned + Clone + Send + Sync + 'static {
+    /// Relative capacity of the instance.
+    fn weight(&self) -> Option<f64> {
+        None
+    }
+
+    /// Availability zone (or rack, region...) the instance runs in.
+    fn zone(&self) -> Option<&str> {
+        None
+    }
+
+    /// Where other instances can reach this one, e.g. `10.0.0.12:8080`.
+    fn address(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl<T> InstanceInfo<T>
+where
+    T: InstanceData,
+{
+    pub fn weight(&self) -> Option<f64> {
+        self.data.weight()
+    }
+
+    pub fn zone(&self) -> Option<&str> {
+        self.data.zone()
+    }
+
+    pub fn address(&self) -> Option<&str> {
+        self.data.address()
+    }
+}
@@ -0,0 +1,762 @@
+//! Splits a number of partitions between the active members, see
+//! [`PartitionManager`].
+
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, TryRecvError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::backends::{BatchOperation, BatchResult, Capabilities, ConnectionError};
+use crate::events::InstancesEvent;
+use crate::extension::{Extension, TickContext};
+use crate::hashing::{Fnv1a, KeyHasher};
+use crate::sync::RwLockExt;
+use crate::{Backend, InstanceStatus, Instances, Registration};
+
+type LoadOf = Box<dyn Fn(&dyn Any) -> Option<f64> + Send + Sync>;
+/// Polls the result of a queued lease operation: `None` until the update
+/// sending it ran, then whether the lease is held.
+type LeaseReply = Box<dyn Fn() -> Option<Result<bool, ConnectionError>> + Send + Sync>;
+
+struct Assignment {
+    instance_id: Option<Uuid>,
+    members: Vec<Uuid>,
+    /// The members above the load threshold, see
+    /// `PartitionManager::with_load_threshold`.
+    overloaded: BTreeSet<Uuid>,
+    partitions: u32,
+    owners: Vec<Option<Uuid>>,
+    resize: Option<Resize>,
+    /// The partitions whose lease the current instance holds, and when it
+    /// was last renewed. Only with `PartitionManager::with_handoff`.
+    held: BTreeMap<u32, Instant>,
+    /// The partitions the application is done with, to release.
+    released: BTreeSet<u32>,
+    /// The lease operations queued for the next update.
+    queued: Option<QueuedLeases>,
+}
+
+/// The lease operations of a hand-off, sent with the next update's batch,
+/// see `Instances::enqueue`.
+struct QueuedLeases {
+    queued_at: Instant,
+    acquiring: Vec<QueuedLease>,
+    releasing: Vec<QueuedLease>,
+}
+
+struct QueuedLease {
+    partition: u32,
+    reply: LeaseReply,
+    result: Option<Result<bool, ConnectionError>>,
+}
+
+/// A partition count requested with [`PartitionManager::resize`], not active
+/// yet.
+struct Resize {
+    partitions: u32,
+    plan: Option<MigrationPlan>,
+}
+
+/// The owners changing when the partition count grows, see
+/// [`PartitionManager::resize`].
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct MigrationPlan {
+    pub from_partitions: u32,
+    pub to_partitions: u32,
+    /// The partitions whose owner changes, by partition. The added ones have
+    /// no previous owner: their keys come from the partitions they hashed to
+    /// with `from_partitions`.
+    pub moves: Vec<PartitionMove>,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct PartitionMove {
+    pub partition: u32,
+    pub from: Option<Uuid>,
+    pub to: Option<Uuid>,
+}
+
+#[derive(Error, PartialEq, Debug)]
+pub enum PartitioningError {
+    #[error(r#"The partition count can only grow, from {0} to {1} requested."#)]
+    CannotShrink(u32, u32),
+}
+
+/// Assigns each partition to one of the active members by rendezvous
+/// hashing: every instance computes the same owners from the same listing,
+/// and a member joining or leaving only moves the partitions it gains or
+/// loses. Keys map to partitions as `hash(key) % partitions`. Witnesses,
+/// joining, draining and degraded instances own nothing.
+///
+/// Registered with `Builder::with_extension` and read back with
+/// `instances.extension::<PartitionManager>()`.
+pub struct PartitionManager {
+    hasher: Box<dyn KeyHasher>,
+    assignment: RwLock<Assignment>,
+    handoff: Option<Duration>,
+    load: Option<(LoadOf, f64)>,
+}
+
+impl PartitionManager {
+    /// Panics if `partitions` is zero.
+    pub fn new(partitions: u32) -> Self {
+        assert!(partitions > 0, "At least one partition is needed.");
+        PartitionManager {
+            hasher: Box::new(Fnv1a),
+            assignment: RwLock::new(Assignment {
+                instance_id: None,
+                members: vec![],
+                overloaded: BTreeSet::new(),
+                partitions,
+                owners: vec![None; partitions as usize],
+                resize: None,
+                held: BTreeMap::new(),
+                released: BTreeSet::new(),
+                queued: None,
+            }),
+            handoff: None,
+            load: None,
+        }
+    }
+
+    /// The hash function placing the keys and the partitions, [`Fnv1a`] by
+    /// default. Every instance must use the same one; match it with the
+    /// system the keys come from to keep their mapping, e.g.
+    /// [`Murmur2`](crate::hashing::Murmur2) for Kafka's partitions.
+    pub fn with_hasher(mut self, hasher: impl KeyHasher + 'static) -> Self {
+        self.hasher = Box::new(hasher);
+        self
+    }
+
+    /// Hands the partitions over through the backend's leases (see
+    /// `Capabilities::leases`), so that no two instances own a partition at
+    /// once during a rebalance. Each owner holds the lease `partition-<n>`
+    /// of its partitions, renewed for `ttl` with every update's batch (see
+    /// `Instances::enqueue`), the results applying on the next: a partition
+    /// moving to another member stays in [`PartitionManager::releasing_partitions`]
+    /// until the application confirms it's done with it calling
+    /// [`PartitionManager::release`], and is only owned by the new member
+    /// once the lease was released, or expired if it never is.
+    ///
+    /// `ttl` must span a few update intervals, or leases lapse between two
+    /// renewals. Building the service fails if the backend doesn't support
+    /// leases.
+    pub fn with_handoff(mut self, ttl: Duration) -> Self {
+        self.handoff = Some(ttl);
+        self
+    }
+
+    /// Leaves the members whose load, read from their data by `load_of`, is
+    /// above `threshold` out of the new assignments: the partitions they own
+    /// stay theirs, but those moving or added go to the next member in line.
+    /// When every member is overloaded the load is ignored.
+    ///
+    /// `T` must be the data of the instances the manager is registered
+    /// with, members with other data count as not loaded.
+    pub fn with_load_threshold<T: 'static>(
+        mut self,
+        threshold: f64,
+        load_of: impl Fn(&T) -> f64 + Send + Sync + 'static,
+    ) -> Self {
+        let load_of: LoadOf = Box::new(move |data| data.downcast_ref::<T>().map(&load_of));
+        self.load = Some((load_of, threshold));
+        self
+    }
+
+    /// The members left out of the new assignments as of the latest update,
+    /// see [`PartitionManager::with_load_threshold`].
+    pub fn overloaded_members(&self) -> Vec<Uuid> {
+        let assignment = self.assignment.read_or_recover();
+        assignment.overloaded.iter().copied().collect()
+    }
+
+    /// The active partition count.
+    pub fn partitions(&self) -> u32 {
+        self.assignment.read_or_recover().partitions
+    }
+
+    pub fn partition_for(&self, key: &[u8]) -> u32 {
+        (self.hasher.hash(key) % self.partitions() as u64) as u32
+    }
+
+    /// The member owning `partition` as of the latest update, `None` without
+    /// active members or out of range.
+    pub fn owner(&self, partition: u32) -> Option<Uuid> {
+        let assignment = self.assignment.read_or_recover();
+        assignment.owners.get(partition as usize).copied().flatten()
+    }
+
+    pub fn owner_of(&self, key: &[u8]) -> Option<Uuid> {
+        self.owner(self.partition_for(key))
+    }
+
+    /// The partitions owned by the current instance. With the handoff, only
+    /// the ones whose lease it holds.
+    pub fn owned_partitions(&self) -> Vec<u32> {
+        let assignment = self.assignment.read_or_recover();
+        self.assigned(&assignment)
+            .into_iter()
+            .filter(|p| self.handoff.is_none() || assignment.held.contains_key(p))
+            .collect()
+    }
+
+    /// The partitions assigned to the current instance whose previous owner
+    /// didn't release them yet, see [`PartitionManager::with_handoff`].
+    pub fn pending_partitions(&self) -> Vec<u32> {
+        let assignment = self.assignment.read_or_recover();
+        self.assigned(&assignment)
+            .into_iter()
+            .filter(|p| self.handoff.is_some() && !assignment.held.contains_key(p))
+            .collect()
+    }
+
+    /// The partitions moved to another member that the current instance
+    /// still holds, until it calls [`PartitionManager::release`].
+    pub fn releasing_partitions(&self) -> Vec<u32> {
+        let assignment = self.assignment.read_or_recover();
+        let assigned = self.assigned(&assignment);
+        assignment
+            .held
+            .keys()
+            .copied()
+            .filter(|p| !assigned.contains(p) && !assignment.released.contains(p))
+            .collect()
+    }
+
+    /// Confirms the application stopped processing `partition`: its lease
+    /// is released on the next update, letting the new owner take it.
+    pub fn release(&self, partition: u32) {
+        let mut assignment = self.assignment.write_or_recover();
+        if assignment.held.contains_key(&partition) {
+            assignment.released.insert(partition);
+        }
+    }
+
+    fn assigned(&self, assignment: &Assignment) -> Vec<u32> {
+        let Some(instance_id) = assignment.instance_id else {
+            return vec![];
+        };
+        (0..assignment.partitions)
+            .filter(|p| assignment.owners[*p as usize] == Some(instance_id))
+            .collect()
+    }
+
+    /// Grows the partition count to `partitions`. The current assignment
+    /// stays active: from the next update on, the owners changing are
+    /// published as an [`InstancesEvent::MigrationPlanned`], again whenever
+    /// the membership changes the plan, and the new count applies once
+    /// [`PartitionManager::complete_resize`] is called, after the
+    /// applications moved their state. Every instance must be resized alike.
+    pub fn resize(&self, partitions: u32) -> Result<(), PartitioningError> {
+        let mut assignment = self.assignment.write_or_recover();
+        if partitions <= assignment.partitions {
+            return Err(PartitioningError::CannotShrink(
+                assignment.partitions,
+                partitions,
+            ));
+        }
+        assignment.resize = Some(Resize {
+            partitions,
+            plan: None,
+        });
+        Ok(())
+    }
+
+    /// The plan of the pending resize, as of the latest update.
+    pub fn migration_plan(&self) -> Option<MigrationPlan> {
+        let assignment = self.assignment.read_or_recover();
+        assignment.resize.as_ref().and_then(|r| r.plan.clone())
+    }
+
+    /// Activates the pending resize, returning whether there was one.
+    pub fn complete_resize(&self) -> bool {
+        let mut assignment = self.assignment.write_or_recover();
+        let Some(resize) = assignment.resize.take() else {
+            return false;
+        };
+        assignment.owners = self.assign(resize.partitions, &assignment);
+        assignment.partitions = resize.partitions;
+        true
+    }
+
+    /// The owner of every partition, by partition.
+    pub fn assignment(&self) -> Vec<Option<Uuid>> {
+        self.assignment.read_or_recover().owners.clone()
+    }
+
+    /// The owner of each of `partitions`: the member ranking first for it,
+    /// skipping the overloaded ones but its current owner.
+    fn assign(&self, partitions: u32, assignment: &Assignment) -> Vec<Option<Uuid>> {
+        (0..partitions)
+            .map(|partition| {
+                let mut ranked: Vec<&Uuid> = assignment.members.iter().collect();
+                ranked.sort_by_key(|id| std::cmp::Reverse((self.score(partition, id), **id)));
+                let current = assignment.owners.get(partition as usize).copied().flatten();
+                ranked
+                    .iter()
+                    .find(|id| !assignment.overloaded.contains(id) || Some(***id) == current)
+                    .or(ranked.first())
+                    .map(|id| **id)
+            })
+            .collect()
+    }
+
+    /// The plan of the pending resize, if it changed since it was last
+    /// published.
+    fn plan_resize(&self, assignment: &mut Assignment) -> Option<MigrationPlan> {
+        let to_partitions = assignment.resize.as_ref()?.partitions;
+        let moves = self
+            .assign(to_partitions, assignment)
+            .into_iter()
+            .enumerate()
+            .map(|(partition, to)| PartitionMove {
+                partition: partition as u32,
+                from: assignment.owners.get(partition).copied().flatten(),
+                to,
+            })
+            .filter(|m| m.from != m.to)
+            .collect();
+        let plan = MigrationPlan {
+            from_partitions: assignment.partitions,
+            to_partitions,
+            moves,
+        };
+        let resize = assignment.resize.as_mut()?;
+        if resize.plan.as_ref() == Some(&plan) {
+            return None;
+        }
+        resize.plan = Some(plan.clone());
+        Some(plan)
+    }
+
+    /// Renews the leases of the partitions assigned to the current instance,
+    /// taking the ones their previous owner released, and releases the ones
+    /// the application confirmed. The operations join the next update's
+    /// batch, their results being applied on the update after it.
+    fn hand_off<B, T>(&self, instances: &Instances<B, T>, ttl: Duration)
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+        B: Backend<Registration<T>> + Send + Sync + 'static,
+    {
+        let mut assignment = self.assignment.write_or_recover();
+        let Some(instance_id) = assignment.instance_id else {
+            return;
+        };
+        let mut sent = true;
+        if let Some(mut queued) = assignment.queued.take() {
+            sent = apply_leases(&mut assignment, &mut queued);
+            if !sent {
+                assignment.queued = Some(queued);
+            }
+        }
+        // Leases not renewed within their TTL may have been taken over.
+        let now = Instant::now();
+        assignment
+            .held
+            .retain(|_, renewed_at| now.duration_since(*renewed_at) < ttl);
+        if !sent {
+            return;
+        }
+
+        let acquiring = self.assigned(&assignment);
+        // A partition assigned back needs no release anymore.
+        assignment.released.retain(|p| !acquiring.contains(p));
+        let releasing: Vec<u32> = assignment.released.iter().copied().collect();
+        let holder = instance_id.to_string();
+        let queued = QueuedLeases {
+            queued_at: now,
+            acquiring: acquiring
+                .into_iter()
+                .map(|p| {
+                    let operation = BatchOperation::AcquireLease {
+                        name: lease_name(p),
+                        holder: holder.clone(),
+                        ttl,
+                    };
+                    QueuedLease::new(p, instances.enqueue(operation))
+                })
+                .collect(),
+            releasing: releasing
+                .into_iter()
+                .map(|p| {
+                    let operation = BatchOperation::ReleaseLease {
+                        name: lease_name(p),
+                        holder: holder.clone(),
+                    };
+                    QueuedLease::new(p, instances.enqueue(operation))
+                })
+                .collect(),
+        };
+        assignment.queued = Some(queued);
+    }
+
+    fn score(&self, partition: u32, member: &Uuid) -> u64 {
+        let mut key = [0u8; 20];
+        key[..4].copy_from_slice(&partition.to_be_bytes());
+        key[4..].copy_from_slice(member.as_bytes());
+        self.hasher.hash(&key)
+    }
+}
+
+impl<B, T> Extension<B, T> for PartitionManager {
+    fn on_update(&self, instances: &Instances<B, T>, tick: &TickContext<T>)
+    where
+        T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+        B: Backend<Registration<T>> + Send + Sync + 'static,
+    {
+        let active: Vec<_> = tick
+            .instances()
+            .iter()
+            .filter(|i| i.kind.is_member() && i.status == InstanceStatus::Active)
+            .collect();
+        let overloaded = match &self.load {
+            Some((load_of, threshold)) => active
+                .iter()
+                .filter(|i| load_of(&i.data as &dyn Any).is_some_and(|load| load > *threshold))
+                .map(|i| i.id)
+                .collect(),
+            None => BTreeSet::new(),
+        };
+        let plan = {
+            let mut assignment = self.assignment.write_or_recover();
+            assignment.instance_id = tick.current_info().map(|info| info.id);
+            assignment.members = active.iter().map(|i| i.id).collect();
+            assignment.overloaded = overloaded;
+            assignment.owners = self.assign(assignment.partitions, &assignment);
+            self.plan_resize(&mut assignment)
+        };
+        if let Some(plan) = plan {
+            instances
+                .events
+                .emit(InstancesEvent::MigrationPlanned(plan));
+        }
+        if let Some(ttl) = self.handoff {
+            self.hand_off(instances, ttl);
+        }
+    }
+
+    fn missing_capability(&self, capabilities: &Capabilities) -> Option<&'static str> {
+        (self.handoff.is_some() && !capabilities.leases).then_some("leases")
+    }
+}
+
+fn lease_name(partition: u32) -> String {
+    format!("partition-{}", partition)
+}
+
+impl QueuedLease {
+    fn new<T: Send + 'static>(
+        partition: u32,
+        receiver: Receiver<Result<BatchResult<T>, ConnectionError>>,
+    ) -> Self {
+        let reply: LeaseReply = Box::new(move || match receiver.try_recv() {
+            Ok(Ok(BatchResult::Lease(held))) => Some(Ok(held)),
+            Ok(Ok(_)) => Some(Ok(false)),
+            Ok(Err(error)) => Some(Err(error)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(ConnectionError::FailedToUpdate(
+                "The queued lease operation was dropped.".to_string(),
+            ))),
+        });
+        QueuedLease {
+            partition,
+            reply,
+            result: None,
+        }
+    }
+}
+
+/// Applies the results of the queued lease operations, returning whether
+/// they were all sent. Until then they stay queued, e.g. while the updates
+/// fail.
+fn apply_leases(assignment: &mut Assignment, queued: &mut QueuedLeases) -> bool {
+    for lease in queued
+        .acquiring
+        .iter_mut()
+        .chain(queued.releasing.iter_mut())
+    {
+        if lease.result.is_none() {
+            lease.result = (lease.reply)();
+        }
+    }
+    let mut leases = queued.acquiring.iter().chain(queued.releasing.iter());
+    if leases.any(|lease| lease.result.is_none()) {
+        return false;
+    }
+    for lease in queued.acquiring.drain(..) {
+        match lease.result {
+            Some(Ok(true)) => {
+                assignment.held.insert(lease.partition, queued.queued_at);
+            }
+            Some(Err(error)) => {
+                warn!(
+                    "Error renewing the lease of the partition {}. Cause: {}",
+                    lease.partition, error
+                );
+                assignment.held.remove(&lease.partition);
+            }
+            _ => {
+                assignment.held.remove(&lease.partition);
+            }
+        }
+    }
+    for lease in queued.releasing.drain(..) {
+        match lease.result {
+            Some(Err(error)) => warn!(
+                "Error releasing the lease of the partition {}. Cause: {}",
+                lease.partition, error
+            ),
+            _ => {
+                assignment.held.remove(&lease.partition);
+                assignment.released.remove(&lease.partition);
+            }
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::backends::memory::MemoryBackend;
+    use crate::backends::MockBackend;
+    use crate::config::{Builder, ConfigError};
+    use crate::hashing::Murmur2;
+    use crate::models::{CommunicationErrorStrategy, LeaderStrategy};
+    use crate::testing::SimulatedBackend;
+    use crate::tests::{mock_data_for, new_instance};
+
+    use super::*;
+
+    #[test]
+    fn should_only_move_the_partitions_of_the_member_leaving() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let ids = vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let listings = Arc::new(Mutex::new(vec![ids[..2].to_vec(), ids.clone()]));
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        let remaining = listings.clone();
+        backend
+            .expect_list_active_instances()
+            .returning(move || Ok(mock_data_for(remaining.lock().unwrap().pop().unwrap())));
+
+        let instance = new_instance(
+            ids[0],
+            backend,
+            LeaderStrategy::Oldest,
+            CommunicationErrorStrategy::Error,
+        );
+        let partitions = PartitionManager::new(64);
+
+        instance.update_instance_info().unwrap();
+        partitions.on_update(&instance, &instance.tick_context());
+        let before = partitions.assignment();
+        instance.update_instance_info().unwrap();
+        partitions.on_update(&instance, &instance.tick_context());
+        let after = partitions.assignment();
+
+        for id in &ids {
+            assert!(before.contains(&Some(*id)));
+        }
+        for (before, after) in before.iter().zip(&after) {
+            if *before != Some(ids[2]) {
+                assert_eq!(before, after);
+            }
+        }
+        assert!(!after.contains(&Some(ids[2])));
+        assert_eq!(
+            partitions.owned_partitions(),
+            (0..64)
+                .filter(|p| after[*p as usize] == Some(ids[0]))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn should_plan_the_migration_before_growing() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let ids = vec![Uuid::new_v4(), Uuid::new_v4()];
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        let listed = ids.clone();
+        backend
+            .expect_list_active_instances()
+            .returning(move || Ok(mock_data_for(listed.clone())));
+
+        let instance = new_instance(
+            ids[0],
+            backend,
+            LeaderStrategy::Oldest,
+            CommunicationErrorStrategy::Error,
+        );
+        let events = instance.subscribe();
+        let partitions = PartitionManager::new(4);
+        let update = || {
+            instance.update_instance_info().unwrap();
+            partitions.on_update(&instance, &instance.tick_context());
+        };
+
+        update();
+        let before = partitions.assignment();
+        assert_eq!(
+            Err(PartitioningError::CannotShrink(4, 2)),
+            partitions.resize(2)
+        );
+        partitions.resize(8).unwrap();
+        update();
+        update();
+
+        let plan = partitions.migration_plan().unwrap();
+        assert_eq!((4, 8), (plan.from_partitions, plan.to_partitions));
+        assert_eq!(
+            (4..8).collect::<Vec<_>>(),
+            plan.moves.iter().map(|m| m.partition).collect::<Vec<_>>()
+        );
+        assert!(plan.moves.iter().all(|m| m.from.is_none()));
+        assert_eq!(before, partitions.assignment());
+        let planned: Vec<_> = events
+            .try_iter()
+            .filter(|e| matches!(e, InstancesEvent::MigrationPlanned(..)))
+            .collect();
+        assert_eq!(
+            vec![InstancesEvent::MigrationPlanned(plan.clone())],
+            planned
+        );
+
+        assert!(partitions.complete_resize());
+        assert_eq!(8, partitions.partitions());
+        let after = partitions.assignment();
+        assert_eq!(before[..], after[..4]);
+        for m in &plan.moves {
+            assert_eq!(m.to, after[m.partition as usize]);
+        }
+        assert!(!partitions.complete_resize());
+    }
+
+    #[test]
+    fn should_wait_for_the_previous_owner_to_release_the_partitions() {
+        let backend = SimulatedBackend::default();
+        let join = || {
+            Builder::default()
+                .with_backend(backend.clone())
+                .with_update_interval(Duration::from_secs(1))
+                .with_info_extractor(|| "data".to_string())
+                .with_extension(PartitionManager::new(16).with_handoff(Duration::from_secs(10)))
+                .build_without_daemon()
+                .unwrap()
+        };
+        let update = |instance: &Arc<Instances<SimulatedBackend, String>>| {
+            instance.update_instance_info().unwrap();
+            instance.run_extensions();
+            instance.extension::<PartitionManager>().unwrap()
+        };
+
+        // The lease operations queued by an update are sent with the next.
+        let first = join();
+        assert!(update(&first).owned_partitions().is_empty());
+        assert_eq!(16, update(&first).owned_partitions().len());
+        let second = join();
+        let moving = update(&second).pending_partitions();
+        assert!(!moving.is_empty());
+        assert!(update(&second).owned_partitions().is_empty());
+
+        let partitions = update(&first);
+        assert_eq!(moving, partitions.releasing_partitions());
+        assert_eq!(16 - moving.len(), partitions.owned_partitions().len());
+        for partition in &moving {
+            partitions.release(*partition);
+        }
+        update(&first);
+        update(&first);
+        assert!(partitions.releasing_partitions().is_empty());
+
+        assert_eq!(moving, update(&second).owned_partitions());
+        assert!(update(&second).pending_partitions().is_empty());
+    }
+
+    #[test]
+    fn should_reject_a_handoff_the_backend_cannot_lease() {
+        let result = Builder::default()
+            .with_backend(MemoryBackend::new())
+            .with_update_interval(Duration::from_secs(1))
+            .with_info_extractor(|| "data".to_string())
+            .with_extension(PartitionManager::new(16).with_handoff(Duration::from_secs(10)))
+            .build_without_daemon();
+
+        assert_eq!(
+            ConfigError::UnsupportedByBackend("leases"),
+            result.err().unwrap()
+        );
+    }
+
+    #[test]
+    fn should_leave_the_overloaded_members_out_of_new_assignments() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let ids = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        let with_loads = |loads: &[&str]| {
+            let mut listing = mock_data_for(ids[..loads.len()].to_vec());
+            for (record, load) in listing.iter_mut().zip(loads) {
+                record.data.data = load.to_string();
+            }
+            listing
+        };
+        let listings = Arc::new(Mutex::new(vec![
+            with_loads(&["0.5", "0.5", "0.5"]),
+            with_loads(&["0.5", "0.5", "0.9"]),
+            with_loads(&["0.5", "0.5"]),
+        ]));
+
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend
+            .expect_list_active_instances()
+            .returning(move || Ok(listings.lock().unwrap().pop().unwrap()));
+
+        let instance = new_instance(
+            ids[0],
+            backend,
+            LeaderStrategy::Oldest,
+            CommunicationErrorStrategy::Error,
+        );
+        let partitions = PartitionManager::new(32)
+            .with_load_threshold(0.8, |data: &String| data.parse().unwrap_or(0.0));
+        let update = || {
+            instance.update_instance_info().unwrap();
+            partitions.on_update(&instance, &instance.tick_context());
+            partitions.assignment()
+        };
+
+        let before = update();
+        assert_eq!(before, update());
+        assert_eq!(vec![ids[2]], partitions.overloaded_members());
+        let after = update();
+        assert!(after.contains(&Some(ids[2])));
+        assert!(partitions.overloaded_members().is_empty());
+    }
+
+    #[test]
+    fn should_place_the_keys_with_the_chosen_hasher() {
+        let partitions = PartitionManager::new(12).with_hasher(Murmur2);
+
+        // Kafka's default partitioner sends the key "21" to partition
+        // toPositive(-973932308) % 12.
+        assert_eq!(
+            ((-973932308i32 & 0x7fffffff) % 12) as u32,
+            partitions.partition_for(b"21")
+        );
+        assert_eq!(None, partitions.owner_of(b"21"));
+    }
+}
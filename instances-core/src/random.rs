@@ -0,0 +1,107 @@
+//! Source of the randomness used by the instances, replaceable by a seeded one
+//! to make simulations and property tests deterministic, see
+//! `Builder::with_random_source`. It draws the instance ids and the jitter of
+//! the listing backoff. The partition assignment doesn't use it: rendezvous
+//! hashing must give every member the same result, so it's deterministic by
+//! design. Nothing in the crate selects instances by `weight` at random;
+//! applications doing so can draw from `Instances::random`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use uuid::Uuid;
+
+const GOLDEN_GAMMA: u64 = 0x9e3779b97f4a7c15;
+
+pub trait RandomSource: Send + Sync {
+    fn next_u64(&self) -> u64;
+
+    /// A value uniformly distributed in `[0, 1)`.
+    fn next_f64(&self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A value uniformly distributed in `[0, bound)`, zero if `bound` is zero.
+    fn below(&self, bound: u64) -> u64 {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as u64
+    }
+
+    /// A random UUIDv4.
+    fn uuid(&self) -> Uuid {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.next_u64().to_le_bytes());
+        bytes[8..].copy_from_slice(&self.next_u64().to_le_bytes());
+        bytes[6] = 0x40 | (bytes[6] & 0x0f);
+        bytes[8] = 0x80 | (bytes[8] & 0x3f);
+        Uuid::from_bytes(bytes)
+    }
+}
+
+/// The operating system's randomness, the default.
+pub struct SystemRandom;
+
+impl RandomSource for SystemRandom {
+    fn next_u64(&self) -> u64 {
+        let id = Uuid::new_v4();
+        let b = id.as_bytes();
+        u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[10], b[11]])
+    }
+
+    fn uuid(&self) -> Uuid {
+        Uuid::new_v4()
+    }
+}
+
+/// A SplitMix64 generator: the same seed always yields the same values, also
+/// when shared between threads (in a nondeterministic order then).
+pub struct SeededRandom {
+    state: AtomicU64,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        SeededRandom {
+            state: AtomicU64::new(seed),
+        }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_u64(&self) -> u64 {
+        let mut z = self
+            .state
+            .fetch_add(GOLDEN_GAMMA, Ordering::Relaxed)
+            .wrapping_add(GOLDEN_GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_repeat_the_values_of_a_seed() {
+        let draw = |seed| {
+            let random = SeededRandom::new(seed);
+            (0..4).map(|_| random.next_u64()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(draw(7), draw(7));
+        assert_ne!(draw(7), draw(8));
+    }
+
+    #[test]
+    fn should_draw_within_the_bounds() {
+        let random = SeededRandom::new(1);
+
+        for _ in 0..100 {
+            assert!(random.below(3) < 3);
+            assert!((0.0..1.0).contains(&random.next_f64()));
+        }
+        assert_eq!(0, random.below(0));
+        assert_eq!(4, random.uuid().get_version_num());
+        assert_eq!(uuid::Variant::RFC4122, random.uuid().get_variant().unwrap());
+    }
+}
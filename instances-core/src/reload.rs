@@ -0,0 +1,85 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Deserialize;
+
+use crate::models::CommunicationErrorStrategy;
+use crate::sync::MutexExt;
+
+/// Contents of the watched configuration file. Missing settings keep their
+/// current value.
+#[derive(Deserialize, PartialEq, Debug)]
+pub(crate) struct SettingsFile {
+    pub update_interval_ms: Option<u64>,
+    pub error_strategy: Option<CommunicationErrorStrategy>,
+    pub suspicion: Option<u32>,
+    pub solo_warmup: Option<u32>,
+}
+
+/// Re-reads a JSON configuration file whenever its modification time changes.
+pub(crate) struct ConfigWatcher {
+    path: PathBuf,
+    modified: Mutex<Option<SystemTime>>,
+}
+
+impl ConfigWatcher {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        ConfigWatcher {
+            path,
+            modified: Mutex::new(None),
+        }
+    }
+
+    /// The file contents if it changed since the last call.
+    pub(crate) fn poll(&self) -> io::Result<Option<SettingsFile>> {
+        let modified = fs::metadata(&self.path)?.modified()?;
+        let mut last = self.modified.lock_or_recover();
+        if *last == Some(modified) {
+            return Ok(None);
+        }
+        *last = Some(modified);
+
+        let content = fs::read(&self.path)?;
+        serde_json::from_slice(&content)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    #[test]
+    fn should_only_read_the_file_when_it_changes() {
+        let path = env::temp_dir().join(format!("instances-rs-{}.json", Uuid::new_v4()));
+        fs::write(
+            &path,
+            r#"{"suspicion": 2, "error_strategy": "UseLastInfo"}"#,
+        )
+        .unwrap();
+        let watcher = ConfigWatcher::new(path.clone());
+
+        let first = watcher.poll().unwrap();
+        let second = watcher.poll().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            Some(SettingsFile {
+                update_interval_ms: None,
+                error_strategy: Some(CommunicationErrorStrategy::UseLastInfo),
+                suspicion: Some(2),
+                solo_warmup: None,
+            }),
+            first
+        );
+        assert_eq!(None, second);
+    }
+}
@@ -0,0 +1,145 @@
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::info;
+
+use crate::backends::{Backend, ConnectionError};
+use crate::sync::MutexExt;
+use crate::{Instances, Registration};
+
+const SLOT_LEASE_PREFIX: &str = "instances-rs/restart-slot/";
+
+/// Keeps at most `slots` instances restarting at a time, e.g. during a rolling
+/// deployment or a config rollout, using leases of the backend (see
+/// `Capabilities::leases`).
+///
+/// An instance calls [`request_restart_slot`](Self::request_restart_slot)
+/// before shutting down and [`release_restart_slot`](Self::release_restart_slot)
+/// once it rejoined, e.g. after `Instances::wait_for_first_update`. The holder
+/// must be stable across the restart (the host or pod name, not the instance
+/// id) to release the slot it took. Slots of instances that never come back
+/// free up once their lease expires.
+pub struct RollingRestartCoordinator<B, T>
+where
+    T: Serialize + DeserializeOwned,
+    B: Backend<Registration<T>>,
+{
+    backend: Arc<B>,
+    holder: String,
+    slots: usize,
+    ttl: Duration,
+    held: Mutex<Option<usize>>,
+    data: PhantomData<fn() -> T>,
+}
+
+impl<B, T> RollingRestartCoordinator<B, T>
+where
+    T: Serialize + DeserializeOwned,
+    B: Backend<Registration<T>>,
+{
+    pub fn new(backend: Arc<B>, holder: impl Into<String>, slots: usize, ttl: Duration) -> Self {
+        RollingRestartCoordinator {
+            backend,
+            holder: holder.into(),
+            slots,
+            ttl,
+            held: Mutex::new(None),
+            data: PhantomData,
+        }
+    }
+
+    /// Takes one of the restart slots, returning whether one was free. Calling
+    /// it again while holding a slot renews it, or looks for another one if
+    /// the slot was lost meanwhile.
+    pub fn request_restart_slot(&self) -> Result<bool, ConnectionError> {
+        let mut held = self.held.lock_or_recover();
+        if let Some(slot) = held.take() {
+            if self
+                .backend
+                .try_acquire_lease(&slot_lease(slot), &self.holder, self.ttl)?
+            {
+                *held = Some(slot);
+                return Ok(true);
+            }
+            info!("Lost restart slot {}.", slot);
+        }
+
+        for slot in 0..self.slots {
+            if self
+                .backend
+                .try_acquire_lease(&slot_lease(slot), &self.holder, self.ttl)?
+            {
+                info!("Took restart slot {}.", slot);
+                *held = Some(slot);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Gives back the slot taken by `request_restart_slot`, before the restart
+    /// or after it (the slot held isn't known anymore then, so every slot is
+    /// released for the holder).
+    pub fn release_restart_slot(&self) -> Result<(), ConnectionError> {
+        for slot in 0..self.slots {
+            self.backend
+                .release_lease(&slot_lease(slot), &self.holder)?;
+        }
+        *self.held.lock_or_recover() = None;
+        Ok(())
+    }
+}
+
+fn slot_lease(slot: usize) -> String {
+    format!("{}{}", SLOT_LEASE_PREFIX, slot)
+}
+
+impl<B, T> Instances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    /// A `RollingRestartCoordinator` sharing this service's backend.
+    pub fn restart_coordinator(
+        &self,
+        holder: impl Into<String>,
+        slots: usize,
+        ttl: Duration,
+    ) -> RollingRestartCoordinator<B, T> {
+        RollingRestartCoordinator::new(self.backend.clone(), holder, slots, ttl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::SimulatedBackend;
+
+    use super::*;
+
+    #[test]
+    fn should_limit_the_instances_restarting_at_a_time() {
+        let backend = Arc::new(SimulatedBackend::default());
+        let ttl = Duration::from_secs(60);
+        let coordinator =
+            |holder: &str| RollingRestartCoordinator::new(backend.clone(), holder, 2, ttl);
+        let (first, second, third) = (coordinator("a"), coordinator("b"), coordinator("c"));
+
+        assert_eq!(Ok(true), first.request_restart_slot());
+        assert_eq!(Ok(true), second.request_restart_slot());
+        assert_eq!(Ok(false), third.request_restart_slot());
+        assert_eq!(Ok(true), first.request_restart_slot());
+
+        // After the restart a new coordinator of the same holder releases it.
+        coordinator("a").release_restart_slot().unwrap();
+        assert_eq!(Ok(true), third.request_restart_slot());
+        assert_eq!(Ok(false), first.request_restart_slot());
+
+        // Having lost its slot, it takes the next one freed.
+        second.release_restart_slot().unwrap();
+        assert_eq!(Ok(true), first.request_restart_slot());
+        assert_eq!(Ok(false), second.request_restart_slot());
+    }
+}
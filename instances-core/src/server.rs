@@ -0,0 +1,266 @@
+//! Serves the membership over HTTP to clients that aren't members (CLIs,
+//! sidecars...), see [`Instances::serve`].
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{Backend, Instances, Registration, WeakInstances};
+
+const ACCEPT_POLL: Duration = Duration::from_millis(50);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+/// The longest request line read, the rest is ignored.
+const MAX_REQUEST_LINE: u64 = 8 * 1024;
+/// The connections answered at a time, the others wait to be accepted.
+const MAX_CONNECTIONS: usize = 16;
+
+/// The server started by [`Instances::serve`]. Dropping it stops the server.
+pub struct RegistryServer {
+    local_addr: SocketAddr,
+    stopped: Arc<AtomicBool>,
+}
+
+impl RegistryServer {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for RegistryServer {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<B, T> Instances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    /// Serves the latest listing over HTTP on `addr`, as JSON:
+    ///
+    /// - `GET /instances`: every instance.
+    /// - `GET /leader`: the leader, or a 404 without one.
+    ///
+    /// Each connection is answered on its own thread, 16 at a time at most,
+    /// the others waiting to be accepted, from the state of the latest
+    /// update: the backend is never queried. The server stops once the
+    /// returned handle or the service is dropped.
+    pub fn serve(self: &Arc<Self>, addr: impl ToSocketAddrs) -> io::Result<RegistryServer> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let local_addr = listener.local_addr()?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let service = self.downgrade();
+
+        let stop = stopped.clone();
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        thread::spawn(move || {
+            info!("Serving the instances on {}.", local_addr);
+            while !stop.load(Ordering::SeqCst) && service.upgrade().is_some() {
+                let Some(connection) = Connection::start(&in_flight) else {
+                    thread::sleep(ACCEPT_POLL);
+                    continue;
+                };
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let service = service.clone();
+                        thread::spawn(move || {
+                            if let Err(error) = handle(stream, &service) {
+                                debug!("Error answering a registry request. Cause: {}", error);
+                            }
+                            drop(connection);
+                        });
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+                    Err(e) => debug!("Error accepting a registry connection. Cause: {}", e),
+                }
+            }
+        });
+
+        Ok(RegistryServer {
+            local_addr,
+            stopped,
+        })
+    }
+}
+
+/// A connection being answered, counted in the connections in flight until
+/// dropped.
+struct Connection(Arc<AtomicUsize>);
+
+impl Connection {
+    /// `None` if `MAX_CONNECTIONS` are already in flight.
+    fn start(in_flight: &Arc<AtomicUsize>) -> Option<Self> {
+        in_flight
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < MAX_CONNECTIONS).then_some(count + 1)
+            })
+            .ok()?;
+        Some(Connection(in_flight.clone()))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn handle<B, T>(mut stream: TcpStream, service: &WeakInstances<B, T>) -> io::Result<()>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new((&stream).take(MAX_REQUEST_LINE)).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+
+    let service = match service.upgrade() {
+        Some(service) => service,
+        None => return respond(&mut stream, "503 Service Unavailable", None),
+    };
+    let body = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/instances")) => {
+            Some(serde_json::to_vec(&*service.list_active_instances())?)
+        }
+        (Some("GET"), Some("/leader")) => match service.current_leader() {
+            Some(leader) => Some(serde_json::to_vec(&*leader)?),
+            None => return respond(&mut stream, "404 Not Found", None),
+        },
+        _ => return respond(&mut stream, "404 Not Found", None),
+    };
+    respond(&mut stream, "200 OK", body)
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: Option<Vec<u8>>) -> io::Result<()> {
+    let body = body.unwrap_or_default();
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use uuid::Uuid;
+
+    use crate::backends::MockBackend;
+    use crate::tests::{mock_data_for, new_instance};
+    use crate::{CommunicationErrorStrategy, LeaderStrategy};
+
+    use super::*;
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn should_serve_the_instances_and_the_leader() {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let id = Uuid::new_v4();
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend
+            .expect_list_active_instances()
+            .returning(move || Ok(mock_data_for(vec![id])));
+        let instance = Arc::new(new_instance(
+            id,
+            backend,
+            LeaderStrategy::Oldest,
+            CommunicationErrorStrategy::Error,
+        ));
+        instance.update_instance_info().unwrap();
+
+        let server = instance.serve("127.0.0.1:0").unwrap();
+
+        let instances = get(server.local_addr(), "/instances");
+        assert!(instances.starts_with("HTTP/1.1 200 OK"));
+        assert!(instances.contains(&id.to_string()));
+        let leader = get(server.local_addr(), "/leader");
+        assert!(leader.contains(&format!(r#""id":"{}""#, id)));
+        assert!(get(server.local_addr(), "/other").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn should_answer_while_a_connection_is_idle() {
+        let instance = Arc::new(new_instance(
+            Uuid::new_v4(),
+            MockBackend::<Registration<String>>::new(),
+            LeaderStrategy::Oldest,
+            CommunicationErrorStrategy::Error,
+        ));
+        let server = instance.serve("127.0.0.1:0").unwrap();
+
+        let _idle = TcpStream::connect(server.local_addr()).unwrap();
+
+        assert!(get(server.local_addr(), "/instances").starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[test]
+    fn should_hold_the_connections_beyond_the_limit() {
+        let instance = Arc::new(new_instance(
+            Uuid::new_v4(),
+            MockBackend::<Registration<String>>::new(),
+            LeaderStrategy::Oldest,
+            CommunicationErrorStrategy::Error,
+        ));
+        let server = instance.serve("127.0.0.1:0").unwrap();
+
+        let idle: Vec<_> = (0..MAX_CONNECTIONS)
+            .map(|_| TcpStream::connect(server.local_addr()).unwrap())
+            .collect();
+
+        let mut waiting = TcpStream::connect(server.local_addr()).unwrap();
+        write!(waiting, "GET /instances HTTP/1.1\r\n\r\n").unwrap();
+        waiting
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        let mut response = String::new();
+        assert!(waiting.read_to_string(&mut response).is_err());
+        assert!(response.is_empty());
+
+        drop(idle);
+        waiting
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        waiting.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+    }
+
+    #[test]
+    fn should_stop_once_the_service_is_dropped() {
+        let instance = Arc::new(new_instance(
+            Uuid::new_v4(),
+            MockBackend::<Registration<String>>::new(),
+            LeaderStrategy::Oldest,
+            CommunicationErrorStrategy::Error,
+        ));
+        let server = instance.serve("127.0.0.1:0").unwrap();
+
+        drop(instance);
+        thread::sleep(ACCEPT_POLL * 4);
+
+        assert!(TcpStream::connect(server.local_addr()).is_err());
+    }
+}
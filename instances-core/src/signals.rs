@@ -0,0 +1,48 @@
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use signal_hook::low_level::emulate_default_handler;
+use tracing::{error, info};
+
+use crate::models::DepartureReason;
+use crate::{Backend, Instances, Registration};
+
+impl<B, T> Instances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    /// Drains the instance when the process receives SIGTERM or SIGINT.
+    ///
+    /// Once the drain finishes the signal's default action is restored and
+    /// performed, so the process terminates as it would without the handler.
+    pub fn install_signal_handlers(self: &Arc<Self>, grace: Duration) -> io::Result<()> {
+        let mut signals = Signals::new([SIGTERM, SIGINT])?;
+        let service = self.downgrade();
+
+        thread::spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                info!("Received signal {}, draining the instance.", signal);
+
+                if let Some(service) = service.upgrade() {
+                    if let Err(error) = service.leave(grace, DepartureReason::Shutdown) {
+                        error!("Error draining the instance. Cause: {}", error);
+                    }
+                }
+
+                signals.handle().close();
+                if let Err(error) = emulate_default_handler(signal) {
+                    error!("Error terminating after the drain. Cause: {}", error);
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::backends::Backend;
+use crate::config::Builder;
+use crate::{Instances, Registration};
+
+/// Any backend storing JSON payloads.
+pub type BoxedBackend = Box<dyn Backend<Registration<Value>> + Send + Sync>;
+
+/// [`Instances`] without generic parameters, publishing JSON data.
+pub type SimpleInstances = Instances<BoxedBackend, Value>;
+
+impl SimpleInstances {
+    /// Starts an instance with the default settings. Use [`Builder`] with a
+    /// [`BoxedBackend`] to customize the rest.
+    pub fn simple(
+        backend: impl Backend<Registration<Value>> + Send + Sync + 'static,
+        update_interval: Duration,
+        info_extractor: fn() -> Value,
+    ) -> Arc<SimpleInstances> {
+        Builder::<BoxedBackend, Value>::default()
+            .with_backend(Box::new(backend))
+            .with_update_interval(update_interval)
+            .with_info_extractor(info_extractor)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::backends::MockBackend;
+
+    use super::*;
+
+    #[test]
+    fn should_publish_json_data() {
+        let mut backend = MockBackend::<Registration<Value>>::new();
+        backend.expect_max_payload_size().returning(|| None);
+        backend.expect_min_update_interval().returning(|| None);
+        backend.expect_identity().returning(|| "mock".to_string());
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend
+            .expect_list_active_instances()
+            .returning(|| Ok(vec![]));
+
+        let instances =
+            SimpleInstances::simple(backend, Duration::from_secs(10), || json!({"port": 8080}));
+        instances
+            .wait_for_first_update(Duration::from_millis(100))
+            .unwrap();
+
+        assert_eq!(
+            json!({"port": 8080}),
+            instances.get_instance_info().unwrap().data
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::InstanceInfo;
+
+/// Reads the instances list saved by [`save`].
+pub(crate) fn load<T>(path: &Path) -> io::Result<Vec<InstanceInfo<T>>>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    let content = fs::read(path)?;
+    serde_json::from_slice(&content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Writes the instances list to `path`. The content goes to a temporary file
+/// first so a crash mid-write never leaves a truncated snapshot behind.
+pub(crate) fn save<T>(path: &Path, instances: &[InstanceInfo<T>]) -> io::Result<()>
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    let content = serde_json::to_vec(instances)?;
+    let temporary = path.with_extension("tmp");
+    fs::write(&temporary, content)?;
+    fs::rename(&temporary, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use uuid::Uuid;
+
+    use crate::models::{InstanceKind, InstanceRole, InstanceStatus};
+
+    use super::*;
+
+    #[test]
+    fn should_load_the_saved_snapshot() {
+        let path = env::temp_dir().join(format!("instances-rs-{}.json", Uuid::new_v4()));
+        let id = Uuid::new_v4();
+
+        save(
+            &path,
+            &[InstanceInfo {
+                id,
+                role: InstanceRole::Leader,
+                status: InstanceStatus::Active,
+                kind: InstanceKind::Member,
+                maintenance: false,
+                cohort: None,
+                leader_term: None,
+                view_hash: None,
+                version: None,
+                latency: None,
+                suspect: false,
+                data: "data".to_string(),
+                extensions: Default::default(),
+            }],
+        )
+        .unwrap();
+
+        let loaded = load::<String>(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(1, loaded.len());
+        assert_eq!(id, loaded[0].id);
+        assert_eq!(InstanceRole::Leader, loaded[0].role);
+        assert_eq!("data".to_string(), loaded[0].data);
+    }
+
+    #[test]
+    fn should_fail_loading_a_missing_snapshot() {
+        let path = env::temp_dir().join(format!("instances-rs-{}.json", Uuid::new_v4()));
+
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            load::<String>(&path).unwrap_err().kind()
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::sync::MutexExt;
+use uuid::Uuid;
+
+/// Decides when an instance that stopped heart-beating is considered gone.
+///
+/// It's consulted on every update for each listed instance whose backend
+/// reports the time of its last heartbeat. Stale instances are dropped from the
+/// listing before the leader is elected.
+pub trait StalenessPolicy: Send + Sync {
+    fn is_stale(&self, instance_id: Uuid, age: Duration, update_interval: Duration) -> bool;
+
+    /// Age past which an instance is always stale, if the policy has a fixed
+    /// one. Used to validate the configuration.
+    fn timeout(&self, _update_interval: Duration) -> Option<Duration> {
+        None
+    }
+}
+
+/// Drops instances whose last heartbeat is older than a fixed TTL.
+pub struct FixedTtl(pub Duration);
+
+impl StalenessPolicy for FixedTtl {
+    fn is_stale(&self, _: Uuid, age: Duration, _: Duration) -> bool {
+        age > self.0
+    }
+
+    fn timeout(&self, _: Duration) -> Option<Duration> {
+        Some(self.0)
+    }
+}
+
+/// Drops instances that missed a number of consecutive update intervals.
+pub struct MissedHeartbeats(pub u32);
+
+impl StalenessPolicy for MissedHeartbeats {
+    fn is_stale(&self, _: Uuid, age: Duration, update_interval: Duration) -> bool {
+        age > update_interval * self.0
+    }
+
+    fn timeout(&self, update_interval: Duration) -> Option<Duration> {
+        Some(update_interval * self.0)
+    }
+}
+
+impl Default for MissedHeartbeats {
+    fn default() -> Self {
+        MissedHeartbeats(3)
+    }
+}
+
+/// Learns how often each instance actually heartbeats and drops it once it's
+/// silent for `tolerance` times that period. Instances running slower than the
+/// configured interval (e.g. overloaded ones) get more slack than with
+/// [`MissedHeartbeats`].
+pub struct Adaptive {
+    tolerance: f64,
+    observed: Mutex<HashMap<Uuid, Observation>>,
+}
+
+struct Observation {
+    last_age: Duration,
+    last_heartbeat: Instant,
+    period: Option<Duration>,
+}
+
+impl Adaptive {
+    pub fn new(tolerance: f64) -> Self {
+        Adaptive {
+            tolerance,
+            observed: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl StalenessPolicy for Adaptive {
+    fn is_stale(&self, instance_id: Uuid, age: Duration, update_interval: Duration) -> bool {
+        let mut observed = self.observed.lock_or_recover();
+        let now = Instant::now();
+
+        let observation = observed.entry(instance_id).or_insert(Observation {
+            last_age: age,
+            last_heartbeat: now,
+            period: None,
+        });
+
+        if age < observation.last_age {
+            let period = now.duration_since(observation.last_heartbeat);
+            observation.period = Some(match observation.period {
+                Some(previous) => (previous + period) / 2,
+                None => period,
+            });
+            observation.last_heartbeat = now;
+        }
+        observation.last_age = age;
+
+        let period = observation
+            .period
+            .map_or(update_interval, |p| p.max(update_interval));
+        age > period.mul_f64(self.tolerance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn should_drop_instances_older_than_the_ttl() {
+        let policy = FixedTtl(Duration::from_secs(1));
+
+        assert!(!policy.is_stale(Uuid::new_v4(), Duration::from_millis(1000), INTERVAL));
+        assert!(policy.is_stale(Uuid::new_v4(), Duration::from_millis(1001), INTERVAL));
+    }
+
+    #[test]
+    fn should_drop_instances_missing_heartbeats() {
+        let policy = MissedHeartbeats(3);
+
+        assert!(!policy.is_stale(Uuid::new_v4(), Duration::from_millis(300), INTERVAL));
+        assert!(policy.is_stale(Uuid::new_v4(), Duration::from_millis(301), INTERVAL));
+    }
+
+    #[test]
+    fn should_adapt_to_the_observed_heartbeat_period() {
+        let policy = Adaptive::new(2.0);
+        let id = Uuid::new_v4();
+
+        assert!(!policy.is_stale(id, Duration::from_millis(150), INTERVAL));
+        assert!(policy.is_stale(id, Duration::from_millis(250), INTERVAL));
+
+        thread::sleep(Duration::from_millis(300));
+        assert!(!policy.is_stale(id, Duration::from_millis(10), INTERVAL));
+        assert!(!policy.is_stale(id, Duration::from_millis(550), INTERVAL));
+    }
+}
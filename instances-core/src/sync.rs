@@ -0,0 +1,100 @@
+//! Lock access recovering from poisoning.
+//!
+//! A panic while holding a lock (e.g. in a user callback) poisons it, and
+//! unwrapping would then make every later call panic too. The state behind
+//! the locks is always replaced as a whole, so the last value written is
+//! consistent and safe to keep using.
+
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// The primitives of the state shared by the threads the loom models check
+/// (`RUSTFLAGS="--cfg loom" cargo test -p instances-core --lib model_checks`):
+/// loom's under `cfg(loom)`, std's otherwise.
+pub(crate) mod modeled {
+    #[cfg(loom)]
+    pub(crate) use loom::sync::{atomic, Arc, Mutex, RwLock};
+    #[cfg(not(loom))]
+    pub(crate) use std::sync::{atomic, Arc, Mutex, RwLock};
+}
+
+pub(crate) trait MutexExt<T> {
+    type Guard<'a>
+    where
+        Self: 'a;
+
+    fn lock_or_recover(&self) -> Self::Guard<'_>;
+}
+
+impl<T> MutexExt<T> for Mutex<T> {
+    type Guard<'a>
+        = MutexGuard<'a, T>
+    where
+        T: 'a;
+
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(loom)]
+impl<T> MutexExt<T> for loom::sync::Mutex<T> {
+    type Guard<'a>
+        = loom::sync::MutexGuard<'a, T>
+    where
+        T: 'a;
+
+    fn lock_or_recover(&self) -> loom::sync::MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) trait RwLockExt<T> {
+    type ReadGuard<'a>
+    where
+        Self: 'a;
+    type WriteGuard<'a>
+    where
+        Self: 'a;
+
+    fn read_or_recover(&self) -> Self::ReadGuard<'_>;
+    fn write_or_recover(&self) -> Self::WriteGuard<'_>;
+}
+
+impl<T> RwLockExt<T> for RwLock<T> {
+    type ReadGuard<'a>
+        = RwLockReadGuard<'a, T>
+    where
+        T: 'a;
+    type WriteGuard<'a>
+        = RwLockWriteGuard<'a, T>
+    where
+        T: 'a;
+
+    fn read_or_recover(&self) -> RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_or_recover(&self) -> RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(loom)]
+impl<T> RwLockExt<T> for loom::sync::RwLock<T> {
+    type ReadGuard<'a>
+        = loom::sync::RwLockReadGuard<'a, T>
+    where
+        T: 'a;
+    type WriteGuard<'a>
+        = loom::sync::RwLockWriteGuard<'a, T>
+    where
+        T: 'a;
+
+    fn read_or_recover(&self) -> loom::sync::RwLockReadGuard<'_, T> {
+        self.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_or_recover(&self) -> loom::sync::RwLockWriteGuard<'_, T> {
+        self.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,427 @@
+//! A deterministic model of a cluster to test leader election, also usable to
+//! check custom configurations (leader strategy, eligibility, ...).
+//!
+//! The instances share a [`SimulatedBackend`] where each one may run with a
+//! skewed clock and see the other instances' writes a number of ticks late.
+//! Nothing runs in the background: every [`Simulation::tick`] updates each
+//! instance once, in the order they joined.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::backends::{codec, Backend, Capabilities, ConnectionError, InstanceRecord};
+use crate::config::Builder;
+use crate::models::{LeaderStrategy, Registration};
+use crate::random::SeededRandom;
+use crate::sync::MutexExt;
+use crate::time::{Clock, HeartbeatTime};
+use crate::Instances;
+
+/// Data published by the simulated instances.
+pub type SimulatedData = String;
+
+type Record = InstanceRecord<Registration<SimulatedData>>;
+
+#[derive(Default)]
+struct Store {
+    records: HashMap<Uuid, Record>,
+    /// The records at the end of the previous ticks, most recent first.
+    history: VecDeque<Vec<Record>>,
+    /// Lease holders and when their lease expires.
+    leases: HashMap<String, (String, Instant)>,
+    counters: HashMap<String, u64>,
+    leader_override: Option<Uuid>,
+}
+
+/// One instance's connection to the shared store. Clones connect to the same
+/// store, e.g. to run several instances in one process.
+#[derive(Clone)]
+pub struct SimulatedBackend {
+    store: Arc<Mutex<Store>>,
+    clock: SkewedClock,
+    delay: usize,
+}
+
+impl Default for SimulatedBackend {
+    /// A backend with a store of its own, without skew nor delay.
+    fn default() -> Self {
+        SimulatedBackend {
+            store: Arc::default(),
+            clock: SkewedClock(0),
+            delay: 0,
+        }
+    }
+}
+
+impl Backend<Registration<SimulatedData>> for SimulatedBackend {
+    fn update_instance_info(
+        &self,
+        instance_id: Uuid,
+        data: Registration<SimulatedData>,
+    ) -> Result<(), ConnectionError> {
+        let now = self.clock.now();
+        let mut store = self.store.lock_or_recover();
+        store
+            .records
+            .entry(instance_id)
+            .and_modify(|r| r.data = data.clone())
+            .or_insert_with(|| InstanceRecord::new(instance_id, now, data));
+        Ok(())
+    }
+
+    fn list_active_instances(&self) -> Result<Vec<Record>, ConnectionError> {
+        let store = self.store.lock_or_recover();
+        if self.delay == 0 {
+            return Ok(store.records.values().cloned().collect());
+        }
+        let snapshot = store
+            .history
+            .get(self.delay - 1)
+            .or_else(|| store.history.back());
+        Ok(snapshot.cloned().unwrap_or_default())
+    }
+
+    fn deregister_instance(&self, instance_id: Uuid) -> Result<(), ConnectionError> {
+        self.store.lock_or_recover().records.remove(&instance_id);
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            deregister: true,
+            leases: true,
+            counters: true,
+            deltas: true,
+            overrides: true,
+            ..Capabilities::default()
+        }
+    }
+
+    fn try_acquire_lease(
+        &self,
+        name: &str,
+        holder: &str,
+        ttl: Duration,
+    ) -> Result<bool, ConnectionError> {
+        let now = Instant::now();
+        let mut store = self.store.lock_or_recover();
+        match store.leases.get(name) {
+            Some((current, expires_at)) if current != holder && *expires_at > now => Ok(false),
+            _ => {
+                store
+                    .leases
+                    .insert(name.to_string(), (holder.to_string(), now + ttl));
+                Ok(true)
+            }
+        }
+    }
+
+    fn release_lease(&self, name: &str, holder: &str) -> Result<(), ConnectionError> {
+        let mut store = self.store.lock_or_recover();
+        if store
+            .leases
+            .get(name)
+            .is_some_and(|(current, _)| current == holder)
+        {
+            store.leases.remove(name);
+        }
+        Ok(())
+    }
+
+    fn update_instance_delta(
+        &self,
+        instance_id: Uuid,
+        patch: &Value,
+    ) -> Result<(), ConnectionError> {
+        let mut store = self.store.lock_or_recover();
+        let record = store.records.get_mut(&instance_id).ok_or_else(|| {
+            ConnectionError::FailedToUpdate(format!("No record for instance {}.", instance_id))
+        })?;
+        let mut data = serde_json::to_value(&record.data)
+            .map_err(|e| ConnectionError::FailedToUpdate(e.to_string()))?;
+        codec::apply_patch(&mut data, patch);
+        record.data = serde_json::from_value(data)
+            .map_err(|e| ConnectionError::FailedToUpdate(e.to_string()))?;
+        Ok(())
+    }
+
+    fn load_counter(&self, name: &str) -> Result<u64, ConnectionError> {
+        let store = self.store.lock_or_recover();
+        Ok(store.counters.get(name).copied().unwrap_or(0))
+    }
+
+    fn compare_and_set_counter(
+        &self,
+        name: &str,
+        current: u64,
+        new: u64,
+    ) -> Result<bool, ConnectionError> {
+        let mut store = self.store.lock_or_recover();
+        let counter = store.counters.entry(name.to_string()).or_insert(0);
+        if *counter != current {
+            return Ok(false);
+        }
+        *counter = new;
+        Ok(true)
+    }
+
+    fn load_leader_override(&self) -> Result<Option<Uuid>, ConnectionError> {
+        Ok(self.store.lock_or_recover().leader_override)
+    }
+
+    fn store_leader_override(&self, leader: Option<Uuid>) -> Result<(), ConnectionError> {
+        self.store.lock_or_recover().leader_override = leader;
+        Ok(())
+    }
+}
+
+/// The system clock shifted by a fixed offset, in milliseconds.
+#[derive(Clone, Copy)]
+pub struct SkewedClock(pub i64);
+
+impl Clock for SkewedClock {
+    fn now(&self) -> HeartbeatTime {
+        let now = SystemTime::now();
+        let offset = Duration::from_millis(self.0.unsigned_abs());
+        if self.0 >= 0 {
+            (now + offset).into()
+        } else {
+            (now - offset).into()
+        }
+    }
+}
+
+type Configure = Box<
+    dyn Fn(Builder<SimulatedBackend, SimulatedData>) -> Builder<SimulatedBackend, SimulatedData>,
+>;
+
+pub struct Simulation {
+    store: Arc<Mutex<Store>>,
+    configure: Configure,
+    instances: Vec<Arc<Instances<SimulatedBackend, SimulatedData>>>,
+    max_delay: usize,
+    seed: Option<u64>,
+    joined: u64,
+}
+
+impl Simulation {
+    /// A cluster electing its leader with `strategy`.
+    pub fn new(strategy: LeaderStrategy) -> Self {
+        Simulation::with_builder(move |builder| builder.with_leader_strategy(strategy))
+    }
+
+    /// A cluster whose instances are configured by `configure`. The backend,
+    /// clock, update interval and info extractor are set by the simulation.
+    pub fn with_builder(
+        configure: impl Fn(Builder<SimulatedBackend, SimulatedData>) -> Builder<SimulatedBackend, SimulatedData>
+            + 'static,
+    ) -> Self {
+        Simulation {
+            store: Arc::new(Mutex::new(Store::default())),
+            configure: Box::new(configure),
+            instances: vec![],
+            max_delay: 0,
+            seed: None,
+            joined: 0,
+        }
+    }
+
+    /// Seeds the randomness of the instances, ids included, so that the same
+    /// steps always play out the same way.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Adds an instance whose clock is `skew_ms` off and that sees the other
+    /// instances' writes `delay` ticks late.
+    pub fn join(&mut self, skew_ms: i64, delay: usize) -> Uuid {
+        let backend = SimulatedBackend {
+            store: self.store.clone(),
+            clock: SkewedClock(skew_ms),
+            delay,
+        };
+        let mut builder = Builder::default()
+            .with_backend(backend)
+            .with_clock(SkewedClock(skew_ms))
+            .with_update_interval(Duration::from_secs(1))
+            .with_info_extractor(|| "simulated".to_string());
+        if let Some(seed) = self.seed {
+            builder = builder.with_random_source(SeededRandom::new(seed.wrapping_add(self.joined)));
+        }
+        self.joined += 1;
+        let instance = (self.configure)(builder)
+            .build_without_daemon()
+            .expect("The simulated instance configuration is invalid.");
+
+        self.max_delay = self.max_delay.max(delay);
+        self.instances.push(instance.clone());
+        instance.instance_id
+    }
+
+    /// Removes the instance from the cluster and the backend.
+    pub fn leave(&mut self, instance_id: Uuid) {
+        self.instances.retain(|i| i.instance_id != instance_id);
+        self.store.lock_or_recover().records.remove(&instance_id);
+    }
+
+    /// Updates every instance once.
+    pub fn tick(&mut self) {
+        for instance in &self.instances {
+            let _ = instance.update_instance_info();
+        }
+
+        let mut store = self.store.lock_or_recover();
+        let snapshot = store.records.values().cloned().collect();
+        store.history.push_front(snapshot);
+        store.history.truncate(self.max_delay.max(1));
+    }
+
+    /// How many ticks without changes the instances need to agree again.
+    pub fn settle_ticks(&self) -> usize {
+        self.max_delay + 2
+    }
+
+    pub fn instances(&self) -> Vec<Uuid> {
+        self.instances.iter().map(|i| i.instance_id).collect()
+    }
+
+    /// The leader and leader epoch each instance currently sees.
+    pub fn leaders(&self) -> HashMap<Uuid, (Option<Uuid>, u64)> {
+        self.instances
+            .iter()
+            .map(|i| {
+                let leader = i.current_leader().map(|l| l.id);
+                (i.instance_id, (leader, i.leader_epoch()))
+            })
+            .collect()
+    }
+
+    /// Whether every instance sees the same leader.
+    pub fn converged(&self) -> bool {
+        let leaders = self.leaders();
+        let mut seen = leaders.values().map(|(leader, _)| leader);
+        match seen.next() {
+            Some(first) => seen.all(|leader| leader == first),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    enum Step {
+        Join { skew_ms: i64, delay: usize },
+        Leave(usize),
+        Tick,
+    }
+
+    fn step() -> impl Strategy<Value = Step> {
+        prop_oneof![
+            (-5_000i64..5_000, 0usize..3)
+                .prop_map(|(skew_ms, delay)| Step::Join { skew_ms, delay }),
+            any::<usize>().prop_map(Step::Leave),
+            Just(Step::Tick),
+            Just(Step::Tick),
+        ]
+    }
+
+    crate::backend_conformance!(SimulatedBackend::default());
+
+    #[test]
+    fn should_take_a_new_leader_term_on_every_leadership_change() {
+        let mut simulation = Simulation::with_builder(|builder| {
+            builder
+                .with_leader_strategy(LeaderStrategy::Oldest)
+                .with_persistent_leader_term(true)
+        });
+        // Registered a second earlier, so it's the oldest from the first tick.
+        let first = simulation.join(-1000, 0);
+        simulation.join(0, 0);
+        for _ in 0..simulation.settle_ticks() {
+            simulation.tick();
+        }
+        assert!(simulation
+            .instances
+            .iter()
+            .all(|i| i.leader_term() == Some(1)));
+
+        simulation.leave(first);
+        for _ in 0..simulation.settle_ticks() {
+            simulation.tick();
+        }
+
+        assert_eq!(Some(2), simulation.instances[0].leader_term());
+    }
+
+    #[test]
+    fn should_replay_a_seeded_simulation() {
+        let joined = |seed| {
+            let mut simulation = Simulation::new(LeaderStrategy::Oldest).with_seed(seed);
+            (0..3).map(|_| simulation.join(0, 0)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(joined(42), joined(42));
+        assert_ne!(joined(42), joined(43));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn should_elect_a_single_leader_per_epoch_and_converge(
+            steps in prop::collection::vec(step(), 1..40),
+            newest in any::<bool>(),
+        ) {
+            let strategy = if newest { LeaderStrategy::Newest } else { LeaderStrategy::Oldest };
+            let mut simulation = Simulation::new(strategy);
+            let mut elected: HashMap<(Uuid, u64), Option<Uuid>> = HashMap::new();
+
+            let mut check = |simulation: &Simulation| {
+                for (observer, (leader, epoch)) in simulation.leaders() {
+                    let previous = elected.entry((observer, epoch)).or_insert(leader);
+                    prop_assert_eq!(*previous, leader, "two leaders in epoch {}", epoch);
+                }
+                Ok(())
+            };
+
+            for step in steps {
+                match step {
+                    Step::Join { skew_ms, delay } => {
+                        simulation.join(skew_ms, delay);
+                    }
+                    Step::Leave(index) => {
+                        let instances = simulation.instances();
+                        if !instances.is_empty() {
+                            simulation.leave(instances[index % instances.len()]);
+                        }
+                    }
+                    Step::Tick => simulation.tick(),
+                }
+                check(&simulation)?;
+            }
+
+            for _ in 0..simulation.settle_ticks() {
+                simulation.tick();
+                check(&simulation)?;
+            }
+
+            prop_assert!(simulation.converged());
+            let instances = simulation.instances();
+            for (leader, _) in simulation.leaders().values() {
+                prop_assert!(leader.is_some_and(|l| instances.contains(&l)));
+            }
+        }
+    }
+}
@@ -0,0 +1,2886 @@
+use std::ops::{Add, Deref, Sub};
+use std::time::Duration;
+
+use mockall::predicate::eq;
+use tracing_test::traced_test;
+
+use crate::backends::memory::MemoryBackend;
+use crate::backends::{Capabilities, MockBackend};
+use crate::config::Builder;
+use crate::ids::{FixedId, RandomId, TimeOrderedId};
+use crate::random::{SeededRandom, SystemRandom};
+use crate::staleness::{FixedTtl, MissedHeartbeats, StalenessPolicy};
+use crate::time::{Clock, HeartbeatTime, SystemClock};
+use crate::update::READ_YOUR_WRITES_ATTEMPTS;
+use crate::views::{compatible_versions, VIEW_DIVERGENCE_UPDATES};
+use crate::InstanceRole::{Follower, Leader, Unknown};
+
+use super::*;
+
+#[test]
+fn should_not_return_any_info_before_any_update() {
+    let backend = MockBackend::<Registration<String>>::new();
+
+    let instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+
+    assert!(instance.get_instance_info().is_none());
+    assert!(instance.instances_count().is_none());
+    assert_eq!(0, instance.list_active_instances().len());
+}
+
+#[test]
+fn should_return_info_after_update_success() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(registration()))
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+
+    instance.update_instance_info().unwrap();
+
+    validate(instance.get_instance_info(), id, Unknown);
+
+    assert_eq!(1, instance.instances_count().unwrap());
+
+    let instances = instance.list_active_instances();
+    let single_instance = instances.deref().first().unwrap();
+    assert_eq!(id, single_instance.id);
+    assert_eq!(Unknown, single_instance.role);
+    assert_eq!("data".to_string(), single_instance.data);
+}
+
+#[test]
+fn should_expose_the_backend_extensions() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )
+            .with_extension("ttl_ms", 1500)])
+        });
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+
+    instance.update_instance_info().unwrap();
+
+    let info = instance.get_instance_info().unwrap();
+    assert_eq!(
+        Some(&serde_json::json!(1500)),
+        info.extensions.get("ttl_ms")
+    );
+}
+
+#[test]
+#[traced_test]
+fn should_drop_stale_instances_before_electing_the_leader() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let dead = Uuid::new_v4();
+    let now = SystemTime::now();
+    let started = now.sub(Duration::from_secs(60));
+
+    backend
+        .expect_update_instance_info()
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(move || {
+            Ok(vec![
+                InstanceRecord::new(dead, started, registration())
+                    .with_heartbeat_at(now.sub(Duration::from_secs(1))),
+                InstanceRecord::new(id, now, registration()).with_heartbeat_at(now),
+            ])
+        });
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+
+    instance.update_instance_info().unwrap();
+
+    assert_eq!(1, instance.instances_count().unwrap());
+    validate(instance.get_instance_info(), id, Leader);
+}
+
+#[test]
+fn should_not_expire_instances_when_the_wall_clock_jumps() {
+    struct SteppedClock(AtomicBool);
+
+    impl Clock for SteppedClock {
+        fn now(&self) -> HeartbeatTime {
+            let now = SystemTime::now();
+            if self.0.swap(true, Ordering::SeqCst) {
+                (now + Duration::from_secs(3600)).into()
+            } else {
+                now.into()
+            }
+        }
+    }
+
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let other = Uuid::new_v4();
+    let heartbeat = SystemTime::now();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend.expect_list_active_instances().returning(move || {
+        Ok(vec![
+            InstanceRecord::new(other, heartbeat, registration()).with_heartbeat_at(heartbeat),
+            InstanceRecord::new(id, heartbeat, registration()),
+        ])
+    });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.clock = Box::new(SteppedClock(AtomicBool::new(false)));
+
+    instance.update_instance_info().unwrap();
+    instance.update_instance_info().unwrap();
+
+    assert_eq!(2, instance.instances_count().unwrap());
+}
+
+#[test]
+#[traced_test]
+fn should_keep_missing_instances_as_suspects() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let mut sequence = mockall::Sequence::new();
+    let id = Uuid::new_v4();
+    let leader = Uuid::new_v4();
+    let started = SystemTime::now();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || {
+            Ok(vec![
+                InstanceRecord::new(leader, started, registration()),
+                InstanceRecord::new(id, started.add(Duration::from_secs(1)), registration()),
+            ])
+        });
+
+    backend
+        .expect_list_active_instances()
+        .times(3)
+        .in_sequence(&mut sequence)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                started.add(Duration::from_secs(1)),
+                registration(),
+            )])
+        });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.settings.get_mut().unwrap().suspicion = 2;
+
+    instance.update_instance_info().unwrap();
+    assert_eq!(leader, instance.current_leader().unwrap().id);
+    assert!(!instance.current_leader().unwrap().suspect);
+
+    for _ in 0..2 {
+        instance.update_instance_info().unwrap();
+        assert_eq!(2, instance.instances_count().unwrap());
+        assert_eq!(leader, instance.current_leader().unwrap().id);
+        assert!(instance.current_leader().unwrap().suspect);
+    }
+
+    instance.update_instance_info().unwrap();
+    assert_eq!(1, instance.instances_count().unwrap());
+    validate(instance.get_instance_info(), id, Leader);
+}
+
+#[test]
+fn should_let_the_event_filters_read_the_instances() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .returning(move || Ok(mock_data_for(vec![id])));
+    let instance = Arc::new(new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    ));
+    let reading = Arc::downgrade(&instance);
+    let events =
+        instance.subscribe_with(SubscriptionOptions::default().with_filter(move |event| {
+            let leader = reading.upgrade().and_then(|i| i.current_leader());
+            *event == InstancesEvent::LeaderChanged(leader.map(|l| l.id))
+        }));
+
+    let updating = instance.clone();
+    let (done, updated) = crossbeam_channel::bounded(1);
+    std::thread::spawn(move || done.send(updating.update_instance_info()));
+
+    assert_eq!(Ok(Ok(())), updated.recv_timeout(Duration::from_secs(5)));
+    assert_eq!(
+        Ok(InstancesEvent::LeaderChanged(Some(id))),
+        events.try_recv()
+    );
+}
+
+#[test]
+fn should_correctly_select_leader_when_disabled() {
+    let id1 = Uuid::new_v4();
+    let id2 = Uuid::new_v4();
+    let id3 = Uuid::new_v4();
+
+    let data = mock_data_for(vec![id1, id2, id3]);
+
+    let instance = instance_service_for(LeaderStrategy::None);
+
+    let result = instance.add_leadership(data);
+
+    assert_eq!(Unknown, result.iter().find(|i| i.id == id1).unwrap().role);
+    assert_eq!(Unknown, result.iter().find(|i| i.id == id2).unwrap().role);
+    assert_eq!(Unknown, result.iter().find(|i| i.id == id3).unwrap().role);
+}
+
+#[test]
+fn should_correctly_select_leader_for_newest() {
+    let id1 = Uuid::new_v4();
+    let id2 = Uuid::new_v4();
+    let id3 = Uuid::new_v4();
+
+    let data = mock_data_for(vec![id1, id2, id3]);
+
+    let instance = instance_service_for(LeaderStrategy::Newest);
+
+    let result = instance.add_leadership(data);
+
+    assert_eq!(Follower, result.iter().find(|i| i.id == id1).unwrap().role);
+    assert_eq!(Follower, result.iter().find(|i| i.id == id2).unwrap().role);
+    assert_eq!(Leader, result.iter().find(|i| i.id == id3).unwrap().role);
+}
+
+#[test]
+fn should_correctly_select_leader_for_oldest() {
+    let id1 = Uuid::new_v4();
+    let id2 = Uuid::new_v4();
+    let id3 = Uuid::new_v4();
+
+    let data = mock_data_for(vec![id1, id2, id3]);
+
+    let instance = instance_service_for(LeaderStrategy::Oldest);
+
+    let result = instance.add_leadership(data);
+
+    assert_eq!(Leader, result.iter().find(|i| i.id == id1).unwrap().role);
+    assert_eq!(Follower, result.iter().find(|i| i.id == id2).unwrap().role);
+    assert_eq!(Follower, result.iter().find(|i| i.id == id3).unwrap().role);
+}
+
+#[test]
+fn should_order_the_succession_by_the_leader_strategy() {
+    let id1 = Uuid::new_v4();
+    let id2 = Uuid::new_v4();
+    let id3 = Uuid::new_v4();
+
+    let mut data = mock_data_for(vec![id1, id2, id3]);
+    data[1].data.status = InstanceStatus::Draining;
+
+    assert_eq!(
+        vec![id1, id3],
+        instance_service_for(LeaderStrategy::Oldest).succession_order(&data)
+    );
+    assert_eq!(
+        vec![id3, id1],
+        instance_service_for(LeaderStrategy::Newest).succession_order(&data)
+    );
+    assert!(instance_service_for(LeaderStrategy::None)
+        .succession_order(&data)
+        .is_empty());
+}
+
+#[test]
+fn should_only_elect_the_instance_the_backend_designates() {
+    let id1 = Uuid::new_v4();
+    let id2 = Uuid::new_v4();
+    let id3 = Uuid::new_v4();
+    let service = instance_service_for(LeaderStrategy::Backend);
+
+    let mut data = mock_data_for(vec![id1, id2, id3]);
+    assert!(service.succession_order(&data).is_empty());
+
+    data[2].designated_leader = true;
+    assert_eq!(vec![id3, id1, id2], service.succession_order(&data));
+    let result = service.add_leadership(data);
+    assert_eq!(Leader, result.iter().find(|i| i.id == id3).unwrap().role);
+    assert_eq!(Follower, result.iter().find(|i| i.id == id1).unwrap().role);
+}
+
+#[test]
+fn should_elect_the_oldest_while_the_designated_instance_cannot_lead() {
+    let id1 = Uuid::new_v4();
+    let id2 = Uuid::new_v4();
+    let id3 = Uuid::new_v4();
+    let service = instance_service_for(LeaderStrategy::Backend);
+
+    let mut data = mock_data_for(vec![id1, id2, id3]);
+    data[2].designated_leader = true;
+    data[2].data.status = InstanceStatus::Joining;
+    assert_eq!(vec![id1, id2], service.succession_order(&data));
+
+    data[2].data.status = InstanceStatus::Active;
+    assert_eq!(vec![id3, id1, id2], service.succession_order(&data));
+}
+
+#[test]
+fn should_only_elect_eligible_instances() {
+    let id1 = Uuid::new_v4();
+    let id2 = Uuid::new_v4();
+
+    let mut data = mock_data_for(vec![id1, id2]);
+    data[0].data.data = "canary".to_string();
+
+    let mut service = instance_service_for(LeaderStrategy::Oldest);
+    service.leader_eligible = Some(Box::new(|data: &String| data != "canary"));
+
+    assert_eq!(vec![id2], service.succession_order(&data));
+}
+
+#[test]
+fn should_spread_the_cohort_leaders_across_zones() {
+    let ids: Vec<_> = (0..4).map(|_| Uuid::new_v4()).collect();
+    let mut data = mock_data_for(ids.clone());
+    for (record, (cohort, zone)) in
+        data.iter_mut()
+            .zip([("a", "z1"), ("a", "z2"), ("b", "z1"), ("b", "z2")])
+    {
+        record.data.cohort = Some(cohort.to_string());
+        record.data.data = zone.to_string();
+    }
+
+    let mut service = instance_service_for(LeaderStrategy::Oldest);
+    let leaders = service.cohort_leaders(&data);
+    assert_eq!((ids[0], ids[2]), (leaders["a"], leaders["b"]));
+
+    service.leader_zone = Some(Box::new(|zone: &String| Some(zone.clone())));
+    let leaders = service.cohort_leaders(&data);
+    assert_eq!((ids[0], ids[3]), (leaders["a"], leaders["b"]));
+
+    data.remove(3);
+    assert_eq!(ids[2], service.cohort_leaders(&data)["b"]);
+}
+
+#[test]
+fn should_break_succession_ties_by_id() {
+    let now = SystemTime::now();
+    let mut ids = vec![Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+    let data = ids
+        .iter()
+        .map(|id| InstanceRecord::new(*id, now, registration()))
+        .collect();
+
+    ids.sort();
+
+    assert_eq!(
+        ids,
+        instance_service_for(LeaderStrategy::Newest).succession_order(&data)
+    );
+}
+
+#[test]
+fn should_break_succession_ties_by_the_id_timestamp() {
+    let now = SystemTime::now();
+    let older = TimeOrderedId.generate();
+    thread::sleep(Duration::from_millis(2));
+    let newer = TimeOrderedId.generate();
+    let data = vec![
+        InstanceRecord::new(older, now, registration()),
+        InstanceRecord::new(newer, now, registration()),
+    ];
+
+    let mut service = instance_service_for(LeaderStrategy::Newest);
+    service.id_generator = Box::new(TimeOrderedId);
+
+    assert_eq!(vec![newer, older], service.succession_order(&data));
+}
+
+#[test]
+fn should_only_lead_after_the_claim_is_visible() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let mut sequence = mockall::Sequence::new();
+    let id = Uuid::new_v4();
+    let other = Uuid::new_v4();
+    let started = SystemTime::now();
+    let claimed = Registration {
+        leader_claim: Some(1),
+        ..registration()
+    };
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(registration()))
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || {
+            Ok(vec![
+                InstanceRecord::new(id, started, registration()),
+                InstanceRecord::new(other, started.add(Duration::from_secs(1)), registration()),
+            ])
+        });
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(claimed.clone()))
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || {
+            Ok(vec![
+                InstanceRecord::new(id, started, claimed.clone()),
+                InstanceRecord::new(other, started.add(Duration::from_secs(1)), registration()),
+            ])
+        });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.leadership_acknowledgment = true;
+
+    instance.update_instance_info().unwrap();
+    validate(instance.get_instance_info(), id, Follower);
+    assert!(instance.current_leader().is_none());
+
+    instance.update_instance_info().unwrap();
+    validate(instance.get_instance_info(), id, Leader);
+    assert_eq!(id, instance.current_leader().unwrap().id);
+}
+
+#[test]
+#[traced_test]
+fn should_return_old_info_after_update_failure() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(registration()))
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(registration()))
+        .times(1)
+        .returning(|_, _| Err(ConnectionError::FailedToUpdate("error".to_string())));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::UseLastInfo,
+    );
+
+    instance.update_instance_info().unwrap();
+
+    validate(instance.get_instance_info(), id, Unknown);
+
+    instance.update_instance_info().unwrap();
+
+    validate(instance.get_instance_info(), id, Unknown);
+}
+
+#[test]
+#[traced_test]
+fn should_back_off_the_listing_while_the_backend_fails() {
+    for degraded_while_backed_off in [false, true] {
+        back_off_the_listing(degraded_while_backed_off);
+    }
+}
+
+fn back_off_the_listing(degraded_while_backed_off: bool) {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let mut sequence = mockall::Sequence::new();
+    let id = Uuid::new_v4();
+    let backed_off = Registration {
+        status: match degraded_while_backed_off {
+            true => InstanceStatus::Degraded,
+            false => InstanceStatus::Active,
+        },
+        ..registration()
+    };
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(registration()))
+        .times(2)
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(backed_off))
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || Ok(mock_data_for(vec![id])));
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(|| Err(ConnectionError::FailedToRetrieve("error".to_string())));
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::UseLastInfo,
+    );
+    let instance = Instances {
+        degraded_while_backed_off,
+        ..instance
+    };
+
+    instance.update_instance_info().unwrap();
+    instance.update_instance_info().unwrap();
+    assert!(instance.is_listing_backed_off());
+
+    instance.update_instance_info().unwrap();
+    assert!(instance.get_instance_info().is_some());
+}
+
+#[test]
+fn should_jitter_the_listing_backoff_with_the_random_source() {
+    let resume_in = |seed| {
+        let mut backend = MockBackend::<Registration<String>>::new();
+        let mut sequence = mockall::Sequence::new();
+        let id = Uuid::new_v4();
+        backend
+            .expect_update_instance_info()
+            .returning(|_, _| Ok(()));
+        backend
+            .expect_list_active_instances()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(move || Ok(mock_data_for(vec![id])));
+        backend
+            .expect_list_active_instances()
+            .times(1)
+            .in_sequence(&mut sequence)
+            .returning(|| Err(ConnectionError::FailedToRetrieve("error".to_string())));
+
+        let mut instance = new_instance(
+            id,
+            backend,
+            LeaderStrategy::None,
+            CommunicationErrorStrategy::UseLastInfo,
+        );
+        instance.settings.get_mut().unwrap().update_interval = Duration::from_secs(1);
+        instance.random = Arc::new(SeededRandom::new(seed));
+        instance.update_instance_info().unwrap();
+        instance.update_instance_info().unwrap();
+        instance.listing_resume_in().unwrap()
+    };
+
+    let (first, second) = (resume_in(1), resume_in(2));
+
+    for delay in [first, second] {
+        assert!(delay > Duration::from_millis(1900) && delay <= Duration::from_secs(3));
+    }
+    assert!(first.abs_diff(second) > Duration::from_millis(10));
+}
+
+#[test]
+#[traced_test]
+fn should_return_error_after_update_failure_and_state_reset() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(registration()))
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(registration()))
+        .times(1)
+        .returning(|_, _| Err(ConnectionError::FailedToUpdate("error".to_string())));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+
+    instance.update_instance_info().unwrap();
+
+    validate(instance.get_instance_info(), id, Unknown);
+
+    let result = instance.update_instance_info();
+
+    assert_eq!(
+        Err(ConnectionError::Failed(
+            "mock".to_string(),
+            Operation::Update,
+            Box::new(ConnectionError::FailedToUpdate("error".to_string()))
+        )),
+        result
+    );
+
+    assert!(instance.get_instance_info().is_none());
+}
+
+#[test]
+fn should_fail_waiting_on_timeout() {
+    let backend = MockBackend::<Registration<String>>::new();
+
+    let instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+
+    assert!(instance
+        .wait_for_first_update(Duration::from_millis(5))
+        .is_err());
+    assert!(instance.get_instance_info().is_none());
+}
+
+#[test]
+fn should_resume_after_first_update() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(registration()))
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+
+    assert!(instance
+        .wait_for_first_update(Duration::from_millis(5))
+        .is_err());
+    assert!(instance.get_instance_info().is_none());
+
+    instance.update_instance_info().unwrap();
+
+    assert!(instance
+        .wait_for_first_update(Duration::from_millis(5))
+        .is_ok());
+    assert!(instance.get_instance_info().is_some());
+}
+
+#[test]
+fn should_not_elect_draining_instances() {
+    let id1 = Uuid::new_v4();
+    let id2 = Uuid::new_v4();
+
+    let mut data = mock_data_for(vec![id1, id2]);
+    data[0].data.status = InstanceStatus::Draining;
+
+    let instance = instance_service_for(LeaderStrategy::Oldest);
+
+    let result = instance.add_leadership(data);
+
+    assert_eq!(Follower, result.iter().find(|i| i.id == id1).unwrap().role);
+    assert_eq!(Leader, result.iter().find(|i| i.id == id2).unwrap().role);
+}
+
+#[test]
+fn should_not_elect_witnesses() {
+    let id1 = Uuid::new_v4();
+    let id2 = Uuid::new_v4();
+
+    let mut data = mock_data_for(vec![id1, id2]);
+    data[0].data.kind = InstanceKind::Witness;
+
+    let instance = instance_service_for(LeaderStrategy::Oldest);
+
+    let result = instance.add_leadership(data);
+
+    let witness = result.iter().find(|i| i.id == id1).unwrap();
+    assert_eq!(Follower, witness.role);
+    assert_eq!(InstanceKind::Witness, witness.kind);
+    assert_eq!(Leader, result.iter().find(|i| i.id == id2).unwrap().role);
+    assert_eq!(2, result.len());
+}
+
+#[test]
+fn should_follow_the_leader_override_until_cleared() {
+    let backend = MemoryBackend::new();
+    let overrides = Arc::new(Mutex::new(vec![]));
+    let build = |id: Uuid| {
+        let overrides = overrides.clone();
+        Builder::default()
+            .with_backend(backend.clone())
+            .with_id_generator(FixedId(id))
+            .with_leader_strategy(LeaderStrategy::Oldest)
+            .with_leader_override(true)
+            .with_backend_tap(move |operation, response| {
+                if let Ok(BackendResponse::Override(leader)) = response {
+                    overrides.lock().unwrap().push((operation, *leader));
+                }
+            })
+            .with_update_interval(Duration::from_secs(1))
+            .with_info_extractor(|| "data".to_string())
+            .build_without_daemon()
+            .unwrap()
+    };
+    let (oldest, newest) = (Uuid::new_v4(), Uuid::new_v4());
+    let instances = [build(oldest), build(newest)];
+    let update = || {
+        for instance in &instances {
+            instance.update_instance_info().unwrap();
+        }
+    };
+    let leaders = || {
+        instances
+            .each_ref()
+            .map(|i| i.current_leader().map(|l| l.id))
+    };
+    update();
+
+    instances[0].override_leader(Some(newest)).unwrap();
+    update();
+    assert_eq!([Some(newest); 2], leaders());
+    assert_eq!(Some(newest), instances[1].leader_override());
+    assert!(overrides
+        .lock()
+        .unwrap()
+        .contains(&(Operation::Override, Some(newest))));
+
+    instances[1].set_maintenance(true);
+    update();
+    update();
+    assert_eq!([None; 2], leaders());
+    instances[1].set_maintenance(false);
+    update();
+    update();
+    assert_eq!([Some(newest); 2], leaders());
+
+    Backend::<Registration<String>>::deregister_instance(&backend, newest).unwrap();
+    instances[0].update_instance_info().unwrap();
+    assert_eq!(None, instances[0].current_leader().map(|l| l.id));
+
+    instances[0].override_leader(None).unwrap();
+    instances[0].update_instance_info().unwrap();
+    assert_eq!(Some(oldest), instances[0].current_leader().map(|l| l.id));
+}
+
+#[test]
+fn should_only_elect_a_standby_once_promoted() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let written = Arc::new(Mutex::new(None));
+
+    let store = written.clone();
+    backend
+        .expect_update_instance_info()
+        .times(2)
+        .returning(move |_, data| {
+            *store.lock().unwrap() = Some(data);
+            Ok(())
+        });
+    let listed = written.clone();
+    backend
+        .expect_list_active_instances()
+        .times(2)
+        .returning(move || {
+            let data = listed.lock().unwrap().clone().unwrap();
+            Ok(vec![InstanceRecord::new(id, SystemTime::now(), data)])
+        });
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    *instance.kind.lock().unwrap() = InstanceKind::Standby;
+
+    instance.update_instance_info().unwrap();
+    assert_eq!(
+        InstanceKind::Standby,
+        instance.get_instance_info().unwrap().kind
+    );
+    assert!(instance.current_leader().is_none());
+
+    // Without a daemon the promotion is published by the next update.
+    assert_eq!(Err(InstancesError::NotStarted), instance.promote());
+    instance.update_instance_info().unwrap();
+    assert_eq!(
+        InstanceKind::Member,
+        instance.get_instance_info().unwrap().kind
+    );
+    assert_eq!(Some(id), instance.current_leader().map(|l| l.id));
+}
+
+#[test]
+fn should_publish_the_maintenance_flag() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let in_maintenance = Registration {
+        maintenance: true,
+        ..registration()
+    };
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(in_maintenance.clone()))
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                in_maintenance.clone(),
+            )])
+        });
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+
+    instance.set_maintenance(true);
+    instance.update_instance_info().unwrap();
+
+    let info = instance.get_instance_info().unwrap();
+    assert!(info.maintenance);
+    assert_eq!(Follower, info.role);
+    assert!(instance.current_leader().is_none());
+}
+
+#[test]
+#[traced_test]
+fn should_publish_draining_status_before_deregistering() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let draining = Registration {
+        status: InstanceStatus::Draining,
+        ..registration()
+    };
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(draining.clone()))
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                draining.clone(),
+            )])
+        });
+
+    backend
+        .expect_deregister_instance()
+        .with(eq(id))
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+
+    instance.drain(Duration::from_secs(1)).unwrap();
+
+    let info = instance.get_instance_info().unwrap();
+    assert_eq!(InstanceStatus::Draining, info.status);
+    assert_eq!(Follower, info.role);
+}
+
+#[test]
+#[traced_test]
+fn should_deregister_when_drain_grace_expires() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .times(1)
+        .returning(|_, _| Err(ConnectionError::FailedToUpdate("error".to_string())));
+
+    backend
+        .expect_deregister_instance()
+        .with(eq(id))
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+
+    instance.drain(Duration::from_millis(20)).unwrap();
+
+    assert!(logs_contain("Grace period expired"));
+    assert_eq!(
+        Err(InstancesError::ShuttingDown),
+        instance.drain(Duration::from_millis(20))
+    );
+    assert_eq!(Err(InstancesError::ShuttingDown), instance.refresh_now());
+}
+
+#[test]
+#[traced_test]
+fn should_leave_and_report_tombstones() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let mut sequence = mockall::Sequence::new();
+    let id = Uuid::new_v4();
+    let gone = Uuid::new_v4();
+    let crashed = Uuid::new_v4();
+    let tombstone = Registration {
+        status: InstanceStatus::Departed,
+        departure: Some(Departure {
+            at: HeartbeatTime::now(),
+            reason: DepartureReason::Evicted,
+        }),
+        ..registration()
+    };
+
+    backend
+        .expect_update_instance_info()
+        .withf(|_, data| data.status != InstanceStatus::Departed)
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_update_instance_info()
+        .withf(move |instance_id, data| {
+            *instance_id == id
+                && data.status == InstanceStatus::Departed
+                && data.departure.map(|d| d.reason) == Some(DepartureReason::Drain)
+        })
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || Ok(mock_data_for(vec![id, gone, crashed])));
+    backend
+        .expect_list_active_instances()
+        .times(2)
+        .in_sequence(&mut sequence)
+        .returning(move || {
+            Ok(vec![
+                InstanceRecord::new(id, SystemTime::now(), registration()),
+                InstanceRecord::new(gone, SystemTime::now(), tombstone.clone()),
+            ])
+        });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.tombstones = true;
+    let events = instance.subscribe();
+
+    instance.update_instance_info().unwrap();
+    instance.update_instance_info().unwrap();
+
+    assert_eq!(Some(1), instance.instances_count());
+    let departed = instance.recently_departed(Duration::from_secs(60));
+    assert_eq!(
+        vec![gone],
+        departed.iter().map(|d| d.id).collect::<Vec<_>>()
+    );
+    let left: HashSet<_> = events
+        .try_iter()
+        .filter(|e| matches!(e, InstancesEvent::MemberLeft(..)))
+        .collect();
+    assert_eq!(
+        HashSet::from([
+            InstancesEvent::MemberLeft(gone, DepartureReason::Evicted),
+            InstancesEvent::MemberLeft(crashed, DepartureReason::CrashDetected),
+        ]),
+        left
+    );
+
+    instance.drain(Duration::from_millis(20)).unwrap();
+}
+
+#[test]
+fn should_access_the_instances_by_index_and_id() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let other = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .returning(move || Ok(mock_data_for(vec![id, other])));
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    assert_eq!(0, instance.iter().len());
+
+    instance.update_instance_info().unwrap();
+    let iter = instance.iter();
+    instance.update_instance_info().unwrap();
+
+    let ids: Vec<Uuid> = iter.map(|i| i.id).collect();
+    assert_eq!(HashSet::from([id, other]), ids.iter().copied().collect());
+    assert_eq!(Some(ids[1]), instance.get(1).map(|i| i.id));
+    assert!(instance.get(2).is_none());
+    assert_eq!(Follower, instance.get_by_id(other).unwrap().role);
+    assert!(instance.get_by_id(Uuid::new_v4()).is_none());
+}
+
+#[test]
+fn should_wait_before_leading_alone() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let mut sequence = mockall::Sequence::new();
+    let id = Uuid::new_v4();
+    let other = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .times(3)
+        .in_sequence(&mut sequence)
+        .returning(move || Ok(mock_data_for(vec![id])));
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || Ok(mock_data_for(vec![id, other])));
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.settings.get_mut().unwrap().solo_warmup = 2;
+
+    for _ in 0..2 {
+        instance.update_instance_info().unwrap();
+        assert!(instance.current_leader().is_none());
+    }
+    instance.update_instance_info().unwrap();
+    assert_eq!(id, instance.current_leader().unwrap().id);
+
+    instance.update_instance_info().unwrap();
+    assert_eq!(id, instance.current_leader().unwrap().id);
+}
+
+#[test]
+fn should_lead_only_once_seen_in_a_listing() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let mut sequence = mockall::Sequence::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(|| Ok(vec![]));
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || Ok(mock_data_for(vec![id])));
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.require_self_visible = true;
+
+    instance.update_instance_info().unwrap();
+    assert!(instance.current_leader().is_none());
+
+    instance.update_instance_info().unwrap();
+    assert_eq!(id, instance.current_leader().unwrap().id);
+}
+
+#[test]
+fn should_reuse_the_extracted_info_between_extractions() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let extracted = Arc::new(AtomicU32::new(0));
+    let written = Arc::new(Mutex::new(vec![]));
+
+    let sink = written.clone();
+    backend
+        .expect_update_instance_info()
+        .returning(move |_, data| {
+            sink.lock().unwrap().push(data.data);
+            Ok(())
+        });
+    backend
+        .expect_list_active_instances()
+        .returning(move || Ok(mock_data_for(vec![id])));
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    let counter = extracted.clone();
+    instance.info_extractor =
+        Box::new(move || format!("scan {}", counter.fetch_add(1, Ordering::SeqCst)));
+    instance.extract_every = 3;
+
+    for _ in 0..4 {
+        instance.update_instance_info().unwrap();
+    }
+
+    assert_eq!(2, extracted.load(Ordering::SeqCst));
+    assert_eq!(
+        vec!["scan 0", "scan 0", "scan 0", "scan 1"],
+        *written.lock().unwrap()
+    );
+}
+
+#[test]
+fn should_take_the_tick_context_from_a_single_update() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let other = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .returning(move || Ok(mock_data_for(vec![id, other])));
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.update_instance_info().unwrap();
+    instance.update_instance_info().unwrap();
+
+    let tick = instance.tick_context();
+
+    assert_eq!(2, tick.tick());
+    assert_eq!(2, tick.instances().len());
+    assert_eq!(id, tick.leader().unwrap().id);
+    assert!(tick.is_leader());
+    assert_eq!(instance.leader_epoch(), tick.leader_epoch());
+}
+
+#[test]
+fn should_report_views_diverging_for_several_updates() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let (id, agreeing, diverging) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let view = |hash| Registration {
+        view_hash: Some(hash),
+        ..registration()
+    };
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend.expect_list_active_instances().returning(move || {
+        let now = SystemTime::now();
+        Ok(vec![
+            InstanceRecord::new(id, now, view(1)),
+            InstanceRecord::new(agreeing, now, view(1)),
+            InstanceRecord::new(diverging, now, view(2)),
+        ])
+    });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.view_checks = true;
+    let events = instance.subscribe();
+    for _ in 0..VIEW_DIVERGENCE_UPDATES - 1 {
+        instance.update_instance_info().unwrap();
+    }
+    let diverged = |e: &InstancesEvent| matches!(e, InstancesEvent::ViewsDiverged(_));
+    assert!(!events.try_iter().any(|e| diverged(&e)));
+
+    instance.update_instance_info().unwrap();
+
+    let consistency = instance.view_consistency();
+    assert!(!consistency.is_consistent());
+    assert_eq!(vec![diverging], consistency.diverging);
+    assert_eq!(2, consistency.agreeing.len());
+    assert_eq!(
+        vec![InstancesEvent::ViewsDiverged(vec![diverging])],
+        events.try_iter().filter(diverged).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn should_report_peers_running_incompatible_versions_once() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let (id, same, newer, unknown) = (
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+    );
+    let version = |version: Option<&str>| Registration {
+        version: version.map(str::to_string),
+        ..registration()
+    };
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend.expect_list_active_instances().returning(move || {
+        let now = SystemTime::now();
+        Ok(vec![
+            InstanceRecord::new(id, now, registration()),
+            InstanceRecord::new(same, now, version(Some(VERSION))),
+            InstanceRecord::new(newer, now, version(Some("99.0.0"))),
+            InstanceRecord::new(unknown, now, version(None)),
+        ])
+    });
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    let events = instance.subscribe();
+    instance.update_instance_info().unwrap();
+    instance.update_instance_info().unwrap();
+
+    assert_eq!(1, instance.incompatible_peers());
+    assert_eq!(
+        vec![InstancesEvent::IncompatibleVersion(
+            newer,
+            "99.0.0".to_string()
+        )],
+        events
+            .try_iter()
+            .filter(|e| matches!(e, InstancesEvent::IncompatibleVersion(..)))
+            .collect::<Vec<_>>()
+    );
+    assert!(compatible_versions("0.1.0", "0.1.7"));
+    assert!(!compatible_versions("0.1.0", "0.2.0"));
+    assert!(compatible_versions("1.2.0", "1.9.3"));
+}
+
+#[test]
+fn should_batch_the_queued_operations_with_the_update() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend.expect_capabilities().returning(|| Capabilities {
+        batching: true,
+        ..Capabilities::default()
+    });
+    backend.expect_update_instance_info().never();
+    backend.expect_list_active_instances().never();
+    backend
+        .expect_execute_batch()
+        .withf(|operations| {
+            matches!(
+                operations.as_slice(),
+                [
+                    BatchOperation::Update(..),
+                    BatchOperation::List,
+                    BatchOperation::LoadCounter(name),
+                ] if name == "jobs"
+            )
+        })
+        .times(1)
+        .returning(move |_| {
+            vec![
+                Ok(BatchResult::Done),
+                Ok(BatchResult::Listed(mock_data_for(vec![id]))),
+                Ok(BatchResult::Counter(3)),
+            ]
+        });
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    let counter = instance.enqueue(BatchOperation::LoadCounter("jobs".to_string()));
+    instance.update_instance_info().unwrap();
+
+    assert_eq!(Ok(Ok(BatchResult::Counter(3))), counter.try_recv());
+    assert_eq!(Some(1), instance.instances_count());
+}
+
+#[test]
+fn should_fail_the_operations_a_batch_returned_no_result_for() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend.expect_capabilities().returning(|| Capabilities {
+        batching: true,
+        ..Capabilities::default()
+    });
+    backend
+        .expect_execute_batch()
+        .times(1)
+        .returning(|_| vec![Ok(BatchResult::Done)]);
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    let counter = instance.enqueue(BatchOperation::LoadCounter("jobs".to_string()));
+
+    assert!(instance.update_instance_info().is_err());
+    assert!(matches!(counter.try_recv(), Ok(Err(_))));
+}
+
+#[test]
+fn should_fail_the_queued_operations_in_dry_run() {
+    let instance = Builder::default()
+        .with_backend(MemoryBackend::new())
+        .with_update_interval(Duration::from_secs(1))
+        .with_info_extractor(|| "data".to_string())
+        .dry_run(true)
+        .build_without_daemon()
+        .unwrap();
+
+    let counter = instance.enqueue(BatchOperation::LoadCounter("jobs".to_string()));
+    instance.update_instance_info().unwrap();
+
+    assert!(matches!(counter.try_recv(), Ok(Err(_))));
+}
+
+#[test]
+fn should_never_expose_a_partial_state_to_concurrent_readers() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let (id, older, newer) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let with_newer = Arc::new(AtomicBool::new(false));
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    let toggle = with_newer.clone();
+    backend.expect_list_active_instances().returning(move || {
+        let now = SystemTime::now();
+        let mut listing = vec![
+            InstanceRecord::new(id, now - Duration::from_secs(2), registration()),
+            InstanceRecord::new(older, now - Duration::from_secs(1), registration()),
+        ];
+        if !toggle.fetch_xor(true, Ordering::SeqCst) {
+            listing.push(InstanceRecord::new(newer, now, registration()));
+        }
+        Ok(listing)
+    });
+
+    let instance = Arc::new(new_instance(
+        id,
+        backend,
+        LeaderStrategy::Newest,
+        CommunicationErrorStrategy::Error,
+    ));
+    instance.update_instance_info().unwrap();
+
+    let readers: Vec<_> = (0..4)
+        .map(|_| {
+            let instance = instance.clone();
+            thread::spawn(move || {
+                for _ in 0..500 {
+                    let state = instance.export_state();
+                    let expected_leader = match state.instances.len() {
+                        2 => older,
+                        3 => newer,
+                        len => panic!("{} instances listed", len),
+                    };
+                    assert_eq!(Some(expected_leader), state.leader);
+                }
+            })
+        })
+        .collect();
+    for _ in 0..200 {
+        instance.update_instance_info().unwrap();
+    }
+
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
+
+#[test]
+#[traced_test]
+fn should_leave_out_peers_below_the_minimum_protocol() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let (id, current, legacy) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend.expect_list_active_instances().returning(move || {
+        let now = SystemTime::now();
+        let legacy_record = Registration {
+            protocol: 0,
+            ..registration()
+        };
+        Ok(vec![
+            InstanceRecord::new(legacy, now - Duration::from_secs(2), legacy_record),
+            InstanceRecord::new(id, now - Duration::from_secs(1), registration()),
+            InstanceRecord::new(current, now, registration()),
+        ])
+    });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.min_protocol = PROTOCOL_VERSION;
+    let events = instance.subscribe();
+    instance.update_instance_info().unwrap();
+    instance.update_instance_info().unwrap();
+
+    let rejected = IncompatiblePeer {
+        id: legacy,
+        protocol: 0,
+    };
+    assert_eq!(vec![rejected], instance.rejected_peers());
+    let mut listed: Vec<_> = instance
+        .list_active_instances()
+        .iter()
+        .map(|i| i.id)
+        .collect();
+    listed.sort();
+    let mut expected = vec![id, current];
+    expected.sort();
+    assert_eq!(expected, listed);
+    assert_eq!(Some(id), instance.current_leader().map(|l| l.id));
+    assert_eq!(
+        vec![InstancesEvent::IncompatiblePeer(rejected)],
+        events
+            .try_iter()
+            .filter(|e| matches!(e, InstancesEvent::IncompatiblePeer(..)))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn should_leave_out_old_peers_and_bad_records_without_decoding_their_data() {
+    let backend = MemoryBackend::new();
+    let (id, legacy, broken) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    // A legacy peer whose data had another shape, and a peer whose data
+    // doesn't decode.
+    let legacy_record = serde_json::json!({"status": "Active", "data": [1, 2]});
+    let broken_record =
+        serde_json::json!({"status": "Active", "protocol": PROTOCOL_VERSION, "data": [3]});
+    Backend::<Value>::update_instance_info(&backend, legacy, legacy_record).unwrap();
+    Backend::<Value>::update_instance_info(&backend, broken, broken_record).unwrap();
+    let instance = Builder::default()
+        .with_backend(backend.clone())
+        .with_id_generator(FixedId(id))
+        .with_min_protocol(PROTOCOL_VERSION)
+        .with_update_interval(Duration::from_secs(1))
+        .with_info_extractor(|| "data".to_string())
+        .build_without_daemon()
+        .unwrap();
+    let events = instance.subscribe();
+
+    instance.update_instance_info().unwrap();
+
+    let rejected = IncompatiblePeer {
+        id: legacy,
+        protocol: 0,
+    };
+    assert_eq!(vec![rejected], instance.rejected_peers());
+    let listed: Vec<_> = instance
+        .list_active_instances()
+        .iter()
+        .map(|i| i.id)
+        .collect();
+    assert_eq!(vec![id], listed);
+    assert!(events
+        .try_iter()
+        .any(|e| e == InstancesEvent::IncompatiblePeer(rejected)));
+}
+
+#[test]
+fn should_restore_an_exported_state() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let (id, other) = (Uuid::new_v4(), Uuid::new_v4());
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .returning(move || Ok(mock_data_for(vec![other, id])));
+
+    let exporter = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    exporter.update_instance_info().unwrap();
+    let checkpoint = serde_json::to_string(&exporter.export_state()).unwrap();
+
+    let restored = new_instance(
+        id,
+        MockBackend::<Registration<String>>::new(),
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    restored.import_state(serde_json::from_str(&checkpoint).unwrap());
+
+    assert!(restored.is_stale());
+    assert_eq!(2, restored.list_active_instances().len());
+    assert_eq!(Some(other), restored.current_leader().map(|l| l.id));
+    assert_eq!(exporter.leader_epoch(), restored.leader_epoch());
+    assert_eq!(Some(id), restored.get_instance_info().map(|i| i.id));
+    assert_eq!(Some(other), restored.get_by_id(other).map(|i| i.id));
+}
+
+#[test]
+fn should_keep_the_latest_versions_of_the_peers_data() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let (id, other) = (Uuid::new_v4(), Uuid::new_v4());
+    let versions = ["v0", "v0", "v1", "v2"];
+    let listings = Arc::new(AtomicU64::new(0));
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend.expect_list_active_instances().returning(move || {
+        let version = versions[listings.fetch_add(1, Ordering::SeqCst) as usize];
+        let now = SystemTime::now();
+        Ok(vec![
+            InstanceRecord::new(id, now, registration()),
+            InstanceRecord::new(
+                other,
+                now,
+                Registration {
+                    data: version.to_string(),
+                    ..registration()
+                },
+            ),
+        ])
+    });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.data_history_depth = 2;
+    for _ in 0..versions.len() {
+        instance.update_instance_info().unwrap();
+    }
+
+    let history: Vec<_> = instance
+        .data_history(other)
+        .into_iter()
+        .map(|version| version.data)
+        .collect();
+    assert_eq!(vec!["v1", "v2"], history);
+    assert!(instance.data_history(id).is_empty());
+}
+
+#[test]
+fn should_write_the_changes_after_the_first_update() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .times(1)
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_update_instance_delta()
+        .withf(|_, patch| *patch == serde_json::json!({}))
+        .times(2)
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .returning(move || Ok(mock_data_for(vec![id])));
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.delta_updates = true;
+    for _ in 0..3 {
+        instance.update_instance_info().unwrap();
+    }
+}
+
+#[test]
+fn should_show_the_backend_calls_to_the_tap() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let (id, other) = (Uuid::new_v4(), Uuid::new_v4());
+    let failed = Arc::new(AtomicBool::new(false));
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    let fail = failed.clone();
+    backend.expect_list_active_instances().returning(move || {
+        if fail.load(Ordering::SeqCst) {
+            Err(ConnectionError::FailedToRetrieve("timeout".to_string()))
+        } else {
+            Ok(mock_data_for(vec![id, other]))
+        }
+    });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    let calls = Arc::new(Mutex::new(vec![]));
+    let seen = calls.clone();
+    instance.backend_tap = Some(Box::new(move |operation, result| {
+        let outcome = match result {
+            Ok(BackendResponse::Listed(listed)) => format!("listed {}", listed.len()),
+            Ok(response) => format!("{:?}", response),
+            Err(error) => error.to_string(),
+        };
+        seen.lock()
+            .unwrap()
+            .push(format!("{} {}", operation, outcome));
+    }));
+    instance.update_instance_info().unwrap();
+    failed.store(true, Ordering::SeqCst);
+    assert!(instance.update_instance_info().is_err());
+
+    assert_eq!(
+        vec![
+            "update Done",
+            "list listed 2",
+            "update Done",
+            "list Failed to retrieve instances info. Cause: timeout",
+        ],
+        *calls.lock().unwrap()
+    );
+}
+
+#[test]
+fn should_order_the_peers_by_latency() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let (id, far, near, probed, unknown) = (
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+        Uuid::new_v4(),
+    );
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend.expect_list_active_instances().returning(move || {
+        let now = SystemTime::now();
+        Ok(vec![
+            InstanceRecord::new(id, now, registration()),
+            InstanceRecord::new(unknown, now, registration()),
+            InstanceRecord::new(far, now, registration()).with_latency(Duration::from_millis(80)),
+            InstanceRecord::new(near, now, registration()).with_latency(Duration::from_millis(5)),
+            InstanceRecord::new(probed, now, registration())
+                .with_latency(Duration::from_millis(90)),
+        ])
+    });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.latency_probe = Some(Box::new(move |info| {
+        (info.id == probed).then(|| Duration::from_millis(20))
+    }));
+    instance.update_instance_info().unwrap();
+
+    let nearest: Vec<_> = instance.nearest(10).iter().map(|i| i.id).collect();
+    assert_eq!(vec![near, probed, far, unknown], nearest);
+    assert_eq!(
+        vec![near],
+        instance.nearest(1).iter().map(|i| i.id).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn should_not_lead_while_joining() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let published = Arc::new(Mutex::new(vec![]));
+
+    let sink = published.clone();
+    backend
+        .expect_update_instance_info()
+        .returning(move |_, data| {
+            sink.lock().unwrap().push(data.status);
+            Ok(())
+        });
+    backend
+        .expect_list_active_instances()
+        .returning(|| Ok(vec![]));
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.joining.store(true, Ordering::SeqCst);
+    instance.update_instance_info().unwrap();
+    assert!(instance.current_leader().is_none());
+
+    instance.joining.store(false, Ordering::SeqCst);
+    instance.update_instance_info().unwrap();
+    assert_eq!(id, instance.current_leader().unwrap().id);
+    assert_eq!(
+        vec![InstanceStatus::Joining, InstanceStatus::Active],
+        *published.lock().unwrap()
+    );
+}
+
+// Loom's locks aren't poisoned.
+#[cfg(not(loom))]
+#[test]
+fn should_recover_from_poisoned_locks() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .returning(move || Ok(mock_data_for(vec![id])));
+
+    let instance = Arc::new(new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    ));
+    instance.update_instance_info().unwrap();
+
+    let poisoner = instance.clone();
+    let _ = thread::spawn(move || {
+        let _state = poisoner.state.write().unwrap();
+        let _error = poisoner.last_error.lock().unwrap();
+        panic!("panic while holding the locks");
+    })
+    .join();
+    assert!(instance.state.is_poisoned());
+
+    assert_eq!(id, instance.get_instance_info().unwrap().id);
+    instance.update_instance_info().unwrap();
+    assert_eq!(Some(1), instance.instances_count());
+    assert_eq!(id, instance.current_leader().unwrap().id);
+}
+
+#[test]
+fn should_drop_updates_finishing_after_a_later_one() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let mut sequence = mockall::Sequence::new();
+    let id = Uuid::new_v4();
+    let other = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || {
+            thread::sleep(Duration::from_millis(100));
+            Ok(mock_data_for(vec![id, other]))
+        });
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || Ok(mock_data_for(vec![id])));
+
+    let path = std::env::temp_dir().join(format!("instances-rs-{}.json", id));
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.snapshot_file = Some(path.clone());
+    let instance = Arc::new(instance);
+
+    let slow = {
+        let instance = instance.clone();
+        thread::spawn(move || instance.update_instance_info())
+    };
+    thread::sleep(Duration::from_millis(30));
+    instance.update_instance_info().unwrap();
+    slow.join().unwrap().unwrap();
+
+    assert_eq!(Some(1), instance.instances_count());
+    assert_eq!(1, instance.updates_completed());
+    let saved = snapshot::load::<String>(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(1, saved.len(), "the dropped update must not be saved");
+}
+
+#[test]
+fn should_ignore_instances_listed_twice() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let other = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .returning(move || Ok(mock_data_for(vec![id, other, other])));
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+
+    instance.update_instance_info().unwrap();
+
+    assert_eq!(Some(2), instance.instances_count());
+    assert_eq!(other, instance.get_by_id(other).unwrap().id);
+}
+
+#[test]
+fn should_elect_cohort_leaders_and_adopt_cut_overs() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let (green, newer_green) = (Uuid::new_v4(), Uuid::new_v4());
+    let started = SystemTime::now().sub(Duration::from_secs(10));
+    let in_cohort = |cohort: &str| Registration {
+        cohort: Some(cohort.to_string()),
+        ..registration()
+    };
+    let cut_over = CutOver {
+        cohort: "green".to_string(),
+        at: HeartbeatTime::now(),
+    };
+
+    let published = cut_over.clone();
+    backend
+        .expect_update_instance_info()
+        .withf(|_, data| data.cut_over.is_none())
+        .times(1)
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_update_instance_info()
+        .withf(move |_, data| data.cut_over.as_ref() == Some(&published))
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    let listed = cut_over.clone();
+    backend.expect_list_active_instances().returning(move || {
+        Ok(vec![
+            InstanceRecord::new(id, started, in_cohort("blue")),
+            InstanceRecord::new(
+                green,
+                started.add(Duration::from_secs(1)),
+                in_cohort("green"),
+            ),
+            InstanceRecord::new(
+                newer_green,
+                started.add(Duration::from_secs(2)),
+                Registration {
+                    cut_over: Some(listed.clone()),
+                    ..in_cohort("green")
+                },
+            ),
+        ])
+    });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.cohort = Some("blue".to_string());
+
+    instance.update_instance_info().unwrap();
+    instance.update_instance_info().unwrap();
+
+    assert_eq!(Some("green".to_string()), instance.active_cohort());
+    assert_eq!(2, instance.cohort_members("green").len());
+    assert_eq!(id, instance.cohort_leader("blue").unwrap().id);
+    assert_eq!(green, instance.cohort_leader("green").unwrap().id);
+    assert_eq!(id, instance.current_leader().unwrap().id);
+}
+
+#[test]
+fn should_reload_the_settings_from_the_config_file() {
+    let path = std::env::temp_dir().join(format!("instances-rs-{}.json", Uuid::new_v4()));
+    std::fs::write(
+        &path,
+        r#"{"update_interval_ms": 250, "error_strategy": "UseLastInfo"}"#,
+    )
+    .unwrap();
+
+    let mut backend = MockBackend::<Registration<String>>::new();
+    backend.expect_min_update_interval().returning(|| None);
+    let mut instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.config_watcher = Some(ConfigWatcher::new(path.clone()));
+    let events = instance.subscribe();
+
+    instance.reload_config();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(
+        Settings {
+            update_interval: Duration::from_millis(250),
+            error_strategy: CommunicationErrorStrategy::UseLastInfo,
+            suspicion: 0,
+            solo_warmup: 0,
+        },
+        instance.settings()
+    );
+    assert_eq!(Ok(InstancesEvent::ConfigReloaded), events.try_recv());
+}
+
+/// An instance watching a configuration file with `contents` and reloading
+/// it once, on a backend accepting update intervals from `minimum`.
+fn reloaded(
+    contents: &str,
+    minimum: Duration,
+    staleness_policy: impl StalenessPolicy + 'static,
+) -> Instances<MockBackend<Registration<String>>, String> {
+    let path = std::env::temp_dir().join(format!("instances-rs-{}.json", Uuid::new_v4()));
+    std::fs::write(&path, contents).unwrap();
+
+    let mut backend = MockBackend::<Registration<String>>::new();
+    backend
+        .expect_min_update_interval()
+        .returning(move || Some(minimum));
+    let mut instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.staleness_policy = Box::new(staleness_policy);
+    instance.config_watcher = Some(ConfigWatcher::new(path.clone()));
+    instance.reload_config();
+    std::fs::remove_file(&path).unwrap();
+    instance
+}
+
+#[test]
+fn should_raise_the_reloaded_interval_to_the_backend_minimum() {
+    let instance = reloaded(
+        r#"{"update_interval_ms": 5, "suspicion": 2}"#,
+        Duration::from_millis(100),
+        MissedHeartbeats::default(),
+    );
+
+    assert_eq!(
+        Duration::from_millis(100),
+        instance.settings().update_interval
+    );
+    assert_eq!(2, instance.settings().suspicion);
+}
+
+#[test]
+#[traced_test]
+fn should_keep_the_settings_when_the_reloaded_ones_are_invalid() {
+    let zero = reloaded(
+        r#"{"update_interval_ms": 0, "suspicion": 2}"#,
+        Duration::from_millis(1),
+        MissedHeartbeats::default(),
+    );
+    let above_timeout = reloaded(
+        r#"{"update_interval_ms": 100, "solo_warmup": 3}"#,
+        Duration::from_millis(1),
+        FixedTtl(Duration::from_millis(100)),
+    );
+
+    let grace_past_timeout = reloaded(
+        r#"{"suspicion": 3}"#,
+        Duration::from_millis(1),
+        MissedHeartbeats::default(),
+    );
+
+    for instance in [zero, above_timeout, grace_past_timeout] {
+        assert_eq!(
+            Settings {
+                update_interval: Duration::from_millis(10),
+                error_strategy: CommunicationErrorStrategy::Error,
+                suspicion: 0,
+                solo_warmup: 0,
+            },
+            instance.settings()
+        );
+    }
+    assert!(logs_contain("Ignoring the reloaded configuration"));
+}
+
+#[test]
+fn should_reload_the_solo_warmup() {
+    let instance = reloaded(
+        r#"{"solo_warmup": 3}"#,
+        Duration::from_millis(1),
+        MissedHeartbeats::default(),
+    );
+
+    assert_eq!(3, instance.settings().solo_warmup);
+}
+
+#[test]
+fn should_detect_gaps_between_ticks() {
+    let instance = instance_service_for(LeaderStrategy::None);
+    let events = instance.subscribe();
+
+    assert_eq!(None, instance.detect_clock_gap());
+    assert_eq!(None, instance.detect_clock_gap());
+
+    let resumed = SystemTime::now() - Duration::from_secs(60);
+    *instance.last_tick.lock().unwrap() = Some((Instant::now(), resumed.into()));
+
+    let gap = instance.detect_clock_gap().unwrap();
+    assert!(gap >= Duration::from_secs(60));
+    assert!(instance.is_stale());
+    assert_eq!(Ok(InstancesEvent::ClockGapDetected(gap)), events.try_recv());
+}
+
+#[test]
+fn should_report_why_the_first_update_is_missing() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Err(ConnectionError::FailedToUpdate("error".to_string())));
+
+    let instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::UseLastInfo,
+    );
+
+    assert_eq!(
+        Err(InstancesError::Timeout),
+        instance.wait_for_first_update(Duration::from_millis(10))
+    );
+
+    instance.update_instance_info().unwrap();
+
+    let error = match instance.wait_for_first_update(Duration::from_millis(10)) {
+        Err(InstancesError::Backend(error)) => error,
+        result => panic!("Unexpected result {:?}", result),
+    };
+    assert_eq!(
+        &ConnectionError::FailedToUpdate("error".to_string()),
+        error.root_cause()
+    );
+}
+
+#[test]
+fn should_count_the_completed_updates() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let mut sequence = mockall::Sequence::new();
+
+    backend
+        .expect_update_instance_info()
+        .times(2)
+        .in_sequence(&mut sequence)
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_update_instance_info()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(|_, _| Err(ConnectionError::FailedToUpdate("error".to_string())));
+    backend
+        .expect_list_active_instances()
+        .returning(|| Ok(vec![]));
+
+    let instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::UseLastInfo,
+    );
+    let started = SystemTime::now() - Duration::from_millis(1);
+
+    assert_eq!(0, instance.updates_completed());
+    assert_eq!(None, instance.last_update_at());
+
+    instance.update_instance_info().unwrap();
+    instance.update_instance_info().unwrap();
+    let last_update_at = instance.last_update_at();
+    instance.update_instance_info().unwrap();
+
+    assert_eq!(2, instance.updates_completed());
+    assert!(last_update_at.unwrap() >= started);
+    assert_eq!(last_update_at, instance.last_update_at());
+}
+
+#[test]
+fn should_publish_a_failing_self_check_as_degraded() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let degraded = Registration {
+        status: InstanceStatus::Degraded,
+        ..registration()
+    };
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(degraded.clone()))
+        .times(1)
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                degraded.clone(),
+            )])
+        });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.self_check = Some(Box::new(|| false));
+
+    instance.update_instance_info().unwrap();
+
+    validate(instance.get_instance_info(), id, Follower);
+    assert!(instance.current_leader().is_none());
+}
+
+#[test]
+fn should_record_the_tick_timings() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .returning(|| Ok(vec![]));
+
+    let mut instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.info_extractor = Box::new(|| {
+        thread::sleep(Duration::from_millis(5));
+        "data".to_string()
+    });
+
+    assert_eq!(None, instance.timings().latest);
+
+    instance.update_instance_info().unwrap();
+
+    let timings = instance.timings();
+    assert!(timings.latest.unwrap().extractor >= Duration::from_millis(5));
+    assert_eq!(timings.latest.unwrap().extractor, timings.extractor.p99);
+}
+
+#[test]
+fn should_let_the_extractor_set_the_published_metadata() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+    backend
+        .expect_list_active_instances()
+        .returning(|| Ok(vec![]));
+
+    let service = Arc::new(std::sync::OnceLock::<WeakInstances<_, String>>::new());
+    let mut instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    let extracting = service.clone();
+    instance.info_extractor = Box::new(move || {
+        if let Some(instance) = extracting.get().and_then(WeakInstances::upgrade) {
+            instance.cut_over("green");
+        }
+        "data".to_string()
+    });
+    let instance = Arc::new(instance);
+    assert!(service.set(instance.downgrade()).is_ok());
+
+    instance.update_instance_info().unwrap();
+
+    assert_eq!(Some("green".to_string()), instance.active_cohort());
+}
+
+#[test]
+fn should_refresh_only_while_the_daemon_runs() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Err(ConnectionError::FailedToUpdate("error".to_string())));
+
+    let instance = Arc::new(new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    ));
+
+    assert_eq!(Err(InstancesError::NotStarted), instance.refresh_now());
+
+    let daemon = crate::daemon::start_daemon(&instance);
+    *instance.daemon.lock().unwrap() = Some(daemon);
+
+    assert!(matches!(
+        instance.refresh_now(),
+        Err(InstancesError::Backend(ConnectionError::Failed(
+            _,
+            Operation::Update,
+            _
+        )))
+    ));
+}
+
+#[test]
+#[traced_test]
+fn should_refuse_payloads_over_the_size_limit() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    backend.expect_update_instance_info().never();
+
+    let mut instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.max_payload_size = Some(8);
+
+    assert_eq!(
+        Err(ConnectionError::PayloadTooLarge(64, 8)),
+        instance.update_instance_info()
+    );
+    assert_eq!(1, instance.oversized_payloads());
+}
+
+#[test]
+fn should_only_check_the_cluster_size_before_joining() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_register_instance_if_room()
+        .with(eq(id), eq(registration()), eq(3))
+        .times(1)
+        .returning(|_, _, _| Ok(()));
+
+    backend
+        .expect_update_instance_info()
+        .with(eq(id), eq(registration()))
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(2)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.max_members = Some(3);
+
+    instance.update_instance_info().unwrap();
+    instance.update_instance_info().unwrap();
+}
+
+#[test]
+#[traced_test]
+fn should_not_join_a_full_cluster() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+
+    backend
+        .expect_register_instance_if_room()
+        .times(2)
+        .returning(|_, _, max| Err(ConnectionError::ClusterFull(max)));
+
+    let mut instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.max_members = Some(1);
+
+    assert_eq!(
+        &ConnectionError::ClusterFull(1),
+        instance.update_instance_info().unwrap_err().root_cause()
+    );
+    assert_eq!(
+        &ConnectionError::ClusterFull(1),
+        instance.update_instance_info().unwrap_err().root_cause()
+    );
+    assert!(instance.get_instance_info().is_none());
+}
+
+#[test]
+fn should_save_a_snapshot_after_update_success() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let path = std::env::temp_dir().join(format!("instances-rs-{}.json", id));
+
+    backend
+        .expect_update_instance_info()
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::UseLastInfo,
+    );
+    instance.snapshot_file = Some(path.clone());
+
+    instance.update_instance_info().unwrap();
+
+    let saved = snapshot::load::<String>(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(1, saved.len());
+    assert_eq!(id, saved[0].id);
+    assert!(!instance.is_stale());
+}
+
+#[test]
+fn should_use_the_local_registration_while_the_write_is_not_visible() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .returning(|| Ok(vec![]));
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+
+    instance.update_instance_info().unwrap();
+
+    validate(instance.get_instance_info(), id, Unknown);
+}
+
+#[test]
+#[traced_test]
+fn should_only_list_instances_in_dry_run() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let id = Uuid::new_v4();
+    let other = Uuid::new_v4();
+
+    backend.expect_list_active_instances().returning(move || {
+        Ok(vec![InstanceRecord::new(
+            other,
+            SystemTime::now() - Duration::from_secs(10),
+            registration(),
+        )])
+    });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.consistency = Consistency::ReadYourWrites;
+    instance.dry_run = true;
+
+    instance.update_instance_info().unwrap();
+    instance.drain(Duration::from_secs(1)).unwrap();
+
+    validate(instance.get_instance_info(), id, Follower);
+    assert_eq!(Some(other), instance.current_leader().map(|l| l.id));
+    assert!(logs_contain("Dry run, skipping the update"));
+}
+
+#[test]
+fn should_retry_the_listing_until_the_write_is_visible() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let mut sequence = mockall::Sequence::new();
+    let id = Uuid::new_v4();
+
+    backend
+        .expect_update_instance_info()
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(2)
+        .in_sequence(&mut sequence)
+        .returning(|| Ok(vec![]));
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || {
+            Ok(vec![InstanceRecord::new(
+                id,
+                SystemTime::now(),
+                registration(),
+            )])
+        });
+
+    let mut instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.consistency = Consistency::ReadYourWrites;
+
+    instance.update_instance_info().unwrap();
+
+    validate(instance.get_instance_info(), id, Unknown);
+}
+
+#[test]
+#[traced_test]
+fn should_fail_when_the_write_never_becomes_visible() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+
+    backend
+        .expect_update_instance_info()
+        .times(1)
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(READ_YOUR_WRITES_ATTEMPTS as usize + 1)
+        .returning(|| Ok(vec![]));
+
+    let mut instance = new_instance(
+        Uuid::new_v4(),
+        backend,
+        LeaderStrategy::None,
+        CommunicationErrorStrategy::Error,
+    );
+    instance.consistency = Consistency::ReadYourWrites;
+
+    assert!(instance.update_instance_info().is_err());
+    assert!(instance.get_instance_info().is_none());
+}
+
+#[test]
+fn should_track_the_current_leader() {
+    let mut backend = MockBackend::<Registration<String>>::new();
+    let mut sequence = mockall::Sequence::new();
+    let id = Uuid::new_v4();
+    let other = Uuid::new_v4();
+    let started = SystemTime::now().sub(Duration::from_secs(10));
+
+    backend
+        .expect_update_instance_info()
+        .returning(|_, _| Ok(()));
+
+    backend
+        .expect_list_active_instances()
+        .times(2)
+        .in_sequence(&mut sequence)
+        .returning(move || {
+            Ok(vec![
+                InstanceRecord::new(id, started, registration()),
+                InstanceRecord::new(other, started.add(Duration::from_secs(1)), registration()),
+            ])
+        });
+
+    backend
+        .expect_list_active_instances()
+        .times(1)
+        .in_sequence(&mut sequence)
+        .returning(move || Ok(vec![InstanceRecord::new(other, started, registration())]));
+
+    let instance = new_instance(
+        id,
+        backend,
+        LeaderStrategy::Oldest,
+        CommunicationErrorStrategy::Error,
+    );
+
+    assert!(instance.current_leader().is_none());
+
+    instance.update_instance_info().unwrap();
+    let epoch = instance.leader_epoch();
+    assert_eq!(id, instance.current_leader().unwrap().id);
+
+    instance.update_instance_info().unwrap();
+    assert!(!instance.leader_changed_since(epoch));
+
+    instance.update_instance_info().unwrap();
+    assert!(instance.leader_changed_since(epoch));
+    assert_eq!(other, instance.current_leader().unwrap().id);
+}
+
+fn instance_service_for(
+    leader_strategy: LeaderStrategy,
+) -> Instances<MockBackend<Registration<String>>, String> {
+    new_instance(
+        Uuid::new_v4(),
+        MockBackend::<Registration<String>>::new(),
+        leader_strategy,
+        CommunicationErrorStrategy::Error,
+    )
+}
+
+pub(crate) fn new_instance(
+    instance_id: Uuid,
+    backend: MockBackend<Registration<String>>,
+    leader_strategy: LeaderStrategy,
+    error_strategy: CommunicationErrorStrategy,
+) -> Instances<MockBackend<Registration<String>>, String> {
+    Instances {
+        instance_id,
+        id_generator: Box::new(RandomId),
+        random: Arc::new(SystemRandom),
+        backend: Arc::new(backend),
+        backend_identity: "mock".to_string(),
+        clock: Box::new(SystemClock),
+        info_extractor: Box::new(|| "data".to_string()),
+        extract_every: 1,
+        self_check: None,
+        leader_eligible: None,
+        leader_zone: None,
+        latency_probe: None,
+        backend_tap: None,
+        leader_strategy,
+        kind: Mutex::new(InstanceKind::Member),
+        consistency: Consistency::Eventual,
+        leadership_acknowledgment: false,
+        staleness_policy: Box::new(MissedHeartbeats::default()),
+        settings: RwLock::new(Settings {
+            update_interval: Duration::from_millis(10),
+            error_strategy,
+            suspicion: 0,
+            solo_warmup: 0,
+        }),
+        config_watcher: None,
+        max_payload_size: None,
+        max_members: None,
+        snapshot_file: None,
+        dry_run: false,
+        tombstones: false,
+        cohort: None,
+        require_self_visible: false,
+        persistent_leader_term: false,
+        push_updates: false,
+        view_checks: false,
+        leader_overrides: false,
+        data_history_depth: 0,
+        delta_updates: false,
+        degraded_while_backed_off: false,
+        startup_policy: None,
+        extensions: ExtensionRegistry::default(),
+        admitted: AtomicBool::new(false),
+        startup_failed: AtomicBool::new(false),
+        last_error: Mutex::new(None),
+        listing_backoff: Mutex::new(ListingBackoff::default()),
+        draining: AtomicBool::new(false),
+        joining: AtomicBool::new(false),
+        maintenance: AtomicBool::new(false),
+        leader_claim: Mutex::new(None),
+        leader_term: Mutex::new(None),
+        view_hash: Mutex::new(None),
+        leader_override: Mutex::new(None),
+        diverged_updates: AtomicU32::new(0),
+        data_history: Mutex::new(HashMap::new()),
+        last_written: Mutex::new(None),
+        anomaly_detector: Mutex::new(AnomalyDetector::new(Default::default())),
+        anomalies_detected: AtomicU64::new(0),
+        incompatible_peers: Mutex::new(HashSet::new()),
+        min_protocol: 0,
+        queued_operations: Mutex::new(vec![]),
+        rejected_peers: Mutex::new(HashSet::new()),
+        cut_over: Mutex::new(None),
+        peers: Mutex::new(HashMap::new()),
+        heartbeats: Mutex::new(HashMap::new()),
+        last_tick: Mutex::new(None),
+        oversized_payloads: AtomicU64::new(0),
+        overruns: AtomicU64::new(0),
+        updates_completed: AtomicU64::new(0),
+        ticks: Ticks::default(),
+        solo_updates: AtomicU32::new(0),
+        seen_self: AtomicBool::new(false),
+        last_extracted: Mutex::new(None),
+        last_update_at: Mutex::new(None),
+        events: EventBus::new(),
+        timings: TimingsRecorder::new(),
+        state: new_state(),
+        daemon: Arc::new(Mutex::new(None)),
+    }
+}
+
+fn new_state() -> Arc<modeled::RwLock<InstancesState<String>>> {
+    Arc::new(modeled::RwLock::new(InstancesState {
+        current_info: None,
+        tick: 0,
+        instances: Arc::new(Vec::new()),
+        index: Arc::new(HashMap::new()),
+        stale: false,
+        self_visible: false,
+        leader: None,
+        leader_epoch: 0,
+        succession: Arc::new(vec![]),
+        departed: Arc::new(vec![]),
+        cohort_leaders: Arc::new(HashMap::new()),
+    }))
+}
+
+pub(crate) fn registration() -> Registration<String> {
+    Registration {
+        status: InstanceStatus::Active,
+        kind: InstanceKind::Member,
+        maintenance: false,
+        leader_claim: None,
+        departure: None,
+        cohort: None,
+        cut_over: None,
+        leader_term: None,
+        view_hash: None,
+        version: Some(VERSION.to_string()),
+        protocol: PROTOCOL_VERSION,
+        data: "data".to_string(),
+    }
+}
+
+pub(crate) fn mock_data_for(ids: Vec<Uuid>) -> Listing<String> {
+    ids.iter()
+        .enumerate()
+        .map(|(i, id)| {
+            InstanceRecord::new(
+                *id,
+                SystemTime::now().add(Duration::from_secs(i as u64)),
+                registration(),
+            )
+        })
+        .collect()
+}
+
+fn validate(info: Option<Arc<InstanceInfo<String>>>, id: Uuid, role: InstanceRole) {
+    match info {
+        None => panic!("Should return a valid value"),
+        Some(current) => {
+            assert_eq!(id, current.id);
+            assert_eq!(role, current.role);
+            assert_eq!("data".to_string(), current.data);
+        }
+    }
+}
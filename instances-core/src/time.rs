@@ -0,0 +1,104 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// A wall clock time as exchanged with the backends, in milliseconds since the
+/// Unix epoch.
+///
+/// Clocks of different hosts drift apart, so comparisons between times taken
+/// by different instances should allow for some skew, see
+/// [`HeartbeatTime::approx_eq`] and [`HeartbeatTime::is_before`].
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+#[serde(transparent)]
+pub struct HeartbeatTime(u64);
+
+impl HeartbeatTime {
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    pub fn from_millis(millis: u64) -> Self {
+        HeartbeatTime(millis)
+    }
+
+    pub fn as_millis(&self) -> u64 {
+        self.0
+    }
+
+    /// Time elapsed since `earlier`, or zero if `earlier` is actually later
+    /// (e.g. it was taken by a host with a clock running ahead).
+    pub fn saturating_duration_since(&self, earlier: HeartbeatTime) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Whether both times are at most `tolerance` apart.
+    pub fn approx_eq(&self, other: HeartbeatTime, tolerance: Duration) -> bool {
+        self.0.abs_diff(other.0) <= tolerance.as_millis() as u64
+    }
+
+    /// Whether this time is before `other` by more than `tolerance`.
+    pub fn is_before(&self, other: HeartbeatTime, tolerance: Duration) -> bool {
+        other.saturating_duration_since(*self) > tolerance
+    }
+}
+
+impl From<SystemTime> for HeartbeatTime {
+    fn from(time: SystemTime) -> Self {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        HeartbeatTime(since_epoch.as_millis() as u64)
+    }
+}
+
+impl From<HeartbeatTime> for SystemTime {
+    fn from(time: HeartbeatTime) -> Self {
+        UNIX_EPOCH + Duration::from_millis(time.0)
+    }
+}
+
+/// Source of the current time, replaceable e.g. to use a host's synchronized
+/// clock or to control time in tests.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> HeartbeatTime;
+}
+
+/// The system's wall clock, the default.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> HeartbeatTime {
+        HeartbeatTime::now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_convert_from_and_to_system_time() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_650_000_000_123);
+
+        assert_eq!(1_650_000_000_123, HeartbeatTime::from(time).as_millis());
+        assert_eq!(time, SystemTime::from(HeartbeatTime::from(time)));
+        assert_eq!(
+            "1650000000123",
+            serde_json::to_string(&HeartbeatTime::from(time)).unwrap()
+        );
+    }
+
+    #[test]
+    fn should_compare_tolerating_skew() {
+        let time = HeartbeatTime::from_millis(10_000);
+        let later = HeartbeatTime::from_millis(10_400);
+
+        assert_eq!(
+            Duration::from_millis(400),
+            later.saturating_duration_since(time)
+        );
+        assert_eq!(Duration::ZERO, time.saturating_duration_since(later));
+        assert!(time.approx_eq(later, Duration::from_millis(500)));
+        assert!(!time.approx_eq(later, Duration::from_millis(300)));
+        assert!(time.is_before(later, Duration::from_millis(300)));
+        assert!(!time.is_before(later, Duration::from_millis(500)));
+    }
+}
@@ -0,0 +1,111 @@
+use crate::sync::MutexExt;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How many ticks the percentiles are computed over.
+const WINDOW: usize = 100;
+
+/// Time spent in each step of a single update.
+#[derive(PartialEq, Clone, Copy, Default, Debug)]
+pub struct TickTimings {
+    /// Running the info extractor.
+    pub extractor: Duration,
+    /// Serializing the registration.
+    pub serialize: Duration,
+    /// Writing the registration to the backend.
+    pub write: Duration,
+    /// Listing the instances, including the read-your-writes retries.
+    pub listing: Duration,
+}
+
+#[derive(PartialEq, Clone, Copy, Default, Debug)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+/// The latest tick timings and the percentiles over the last ticks, see
+/// [`Instances::timings`](crate::Instances::timings).
+#[derive(PartialEq, Clone, Default, Debug)]
+pub struct Timings {
+    pub latest: Option<TickTimings>,
+    pub extractor: Percentiles,
+    pub serialize: Percentiles,
+    pub write: Percentiles,
+    pub listing: Percentiles,
+}
+
+pub(crate) struct TimingsRecorder {
+    ticks: Mutex<VecDeque<TickTimings>>,
+}
+
+impl TimingsRecorder {
+    pub(crate) fn new() -> Self {
+        TimingsRecorder {
+            ticks: Mutex::new(VecDeque::with_capacity(WINDOW)),
+        }
+    }
+
+    pub(crate) fn record(&self, timings: TickTimings) {
+        let mut ticks = self.ticks.lock_or_recover();
+        if ticks.len() == WINDOW {
+            ticks.pop_front();
+        }
+        ticks.push_back(timings);
+    }
+
+    pub(crate) fn timings(&self) -> Timings {
+        let ticks = self.ticks.lock_or_recover();
+        Timings {
+            latest: ticks.back().copied(),
+            extractor: percentiles(ticks.iter().map(|t| t.extractor)),
+            serialize: percentiles(ticks.iter().map(|t| t.serialize)),
+            write: percentiles(ticks.iter().map(|t| t.write)),
+            listing: percentiles(ticks.iter().map(|t| t.listing)),
+        }
+    }
+}
+
+fn percentiles(durations: impl Iterator<Item = Duration>) -> Percentiles {
+    let mut durations: Vec<Duration> = durations.collect();
+    if durations.is_empty() {
+        return Percentiles::default();
+    }
+    durations.sort();
+
+    let rank = |p: f64| durations[((p * durations.len() as f64).ceil() as usize).max(1) - 1];
+    Percentiles {
+        p50: rank(0.5),
+        p90: rank(0.9),
+        p99: rank(0.99),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn should_compute_percentiles_over_the_window() {
+        let recorder = TimingsRecorder::new();
+        for i in 1..=WINDOW as u64 + 10 {
+            recorder.record(TickTimings {
+                write: Duration::from_millis(i),
+                ..TickTimings::default()
+            });
+        }
+
+        let timings = recorder.timings();
+
+        assert_eq!(
+            Some(Duration::from_millis(110)),
+            timings.latest.map(|t| t.write)
+        );
+        assert_eq!(Duration::from_millis(60), timings.write.p50);
+        assert_eq!(Duration::from_millis(100), timings.write.p90);
+        assert_eq!(Duration::from_millis(109), timings.write.p99);
+        assert_eq!(Percentiles::default(), timings.listing);
+    }
+}
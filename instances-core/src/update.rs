@@ -0,0 +1,1039 @@
+//! The update tick: writing the registration, listing the instances and
+//! applying the listing, with the backoff, staleness and suspicion handling.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::backends::{
+    codec, Backend, BackendResponse, BatchOperation, BatchResult, ConnectionError, InstanceRecord,
+    Operation,
+};
+use crate::config::checked_timing;
+use crate::events::InstancesEvent;
+use crate::models::{
+    CommunicationErrorStrategy, Consistency, DepartedInstance, DepartureReason, IncompatiblePeer,
+    InstanceInfo, InstanceStatus, Registration,
+};
+use crate::sync::modeled::atomic::AtomicU64;
+use crate::sync::{modeled, MutexExt, RwLockExt};
+use crate::timings::TickTimings;
+use crate::InstanceRole::Leader;
+use crate::{
+    index_by_id, snapshot, Instances, InstancesState, Listing, ListingBackoff, ObservedHeartbeat,
+    Peer, Settings, PROTOCOL_VERSION, VERSION,
+};
+
+/// A tick arriving this many update intervals late means the process was
+/// suspended or the clock jumped.
+const CLOCK_GAP_INTERVALS: u32 = 3;
+pub(crate) const READ_YOUR_WRITES_ATTEMPTS: u32 = 5;
+const READ_YOUR_WRITES_BACKOFF: Duration = Duration::from_millis(20);
+/// While the backend keeps failing under `UseLastInfo` the listing is retried
+/// after 2, 4, 8... update intervals, up to 2^`MAX_LISTING_BACKOFF_EXPONENT`,
+/// plus up to one interval of jitter.
+const MAX_LISTING_BACKOFF_EXPONENT: u32 = 5;
+/// With delta updates, the record is still written in full every this many
+/// updates, in case the backend lost it.
+const FULL_WRITE_EVERY: u32 = 10;
+
+/// Numbers the updates in the order they start. Concurrent updates (the
+/// daemon and `refresh_now`) may finish out of order: the listing of an update
+/// is only applied if no later one was applied first.
+#[derive(Default)]
+pub(crate) struct Ticks {
+    started: AtomicU64,
+    /// Latest tick whose listing is being applied: the listings of earlier
+    /// ticks finishing afterwards are dropped before they have any effect.
+    applied: AtomicU64,
+}
+
+impl Ticks {
+    /// The number of the update starting.
+    pub(crate) fn start(&self) -> u64 {
+        self.started.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Whether the listing of `tick` may be applied, i.e. no later tick's
+    /// was. Holding the state lock, the update still checks the state's own
+    /// tick: a later update may have passed here first and swapped it.
+    pub(crate) fn apply(&self, tick: u64) -> bool {
+        self.applied.fetch_max(tick, Ordering::SeqCst) <= tick
+    }
+}
+
+/// What an update listed.
+struct Listed<T> {
+    instances: Listing<T>,
+    /// The peers left out, their data unread, for writing a protocol older
+    /// than `min_protocol`.
+    incompatible: Vec<IncompatiblePeer>,
+    /// Whether the instance's own record came from the backend.
+    self_visible: bool,
+}
+
+impl<B, T> Instances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    fn current_status(&self) -> InstanceStatus {
+        if self.draining.load(Ordering::SeqCst) {
+            return InstanceStatus::Draining;
+        }
+        if self.joining.load(Ordering::SeqCst) {
+            return InstanceStatus::Joining;
+        }
+        if self.degraded_while_backed_off && self.is_listing_backed_off() {
+            return InstanceStatus::Degraded;
+        }
+        match &self.self_check {
+            Some(check) if !check() => {
+                warn!("The self-check failed, publishing the instance as degraded.");
+                InstanceStatus::Degraded
+            }
+            _ => InstanceStatus::Active,
+        }
+    }
+
+    /// Runs the extractor on every `extract_every`th update, reusing the last
+    /// value in between.
+    fn extract_info(&self, tick: u64) -> T {
+        let mut last = self.last_extracted.lock_or_recover();
+        match last.as_ref() {
+            Some(data) if !(tick - 1).is_multiple_of(self.extract_every) => data.clone(),
+            _ => {
+                let data = (self.info_extractor)();
+                if self.extract_every > 1 {
+                    *last = Some(data.clone());
+                }
+                data
+            }
+        }
+    }
+
+    pub(crate) fn update_instance_info(&self) -> Result<(), ConnectionError> {
+        let tick = self.ticks.start();
+        let mut timings = TickTimings::default();
+        let started = Instant::now();
+        // Read before running the extractor, not to hold their locks while
+        // it runs: it may set them.
+        let kind = *self.kind.lock_or_recover();
+        let leader_claim = *self.leader_claim.lock_or_recover();
+        let cut_over = self.cut_over.lock_or_recover().clone();
+        let leader_term = *self.leader_term.lock_or_recover();
+        let view_hash = *self.view_hash.lock_or_recover();
+        let data = Registration {
+            status: self.current_status(),
+            kind,
+            maintenance: self.maintenance.load(Ordering::SeqCst),
+            leader_claim,
+            departure: None,
+            cohort: self.cohort.clone(),
+            cut_over,
+            leader_term,
+            view_hash,
+            version: Some(VERSION.to_string()),
+            protocol: PROTOCOL_VERSION,
+            data: self.extract_info(tick),
+        };
+        timings.extractor = started.elapsed();
+        if self.is_listing_backed_off() {
+            self.heartbeat(data, &mut timings);
+            self.timings.record(timings);
+            return Ok(());
+        }
+        let listed = self.update_instance_info_and_retrieve(data, &mut timings);
+        self.timings.record(timings);
+
+        match listed {
+            Ok(Listed {
+                mut instances,
+                incompatible,
+                self_visible,
+            }) => {
+                if !self.ticks.apply(tick) {
+                    debug!("Update {} finished after a later one, dropping it.", tick);
+                    return Ok(());
+                }
+                drop_duplicates(&mut instances);
+                self.reject_old_protocols(&mut instances, incompatible);
+                let departed = self.take_tombstones(&mut instances);
+                let suspects = self.keep_suspects(&mut instances);
+                self.adopt_latest_cut_over(&instances);
+                let cohort_leaders = self.cohort_leaders(&instances);
+                if self_visible {
+                    self.seen_self.store(true, Ordering::SeqCst);
+                }
+                if self.leader_overrides {
+                    self.refresh_leader_override();
+                }
+                let succession = self.succession_order(&instances);
+                self.track_solo_updates(&instances);
+                let mut instances = self.add_leadership(instances);
+                if self.persistent_leader_term {
+                    self.track_leader_term(&mut instances);
+                }
+                for instance in instances.iter_mut() {
+                    instance.suspect = suspects.contains(&instance.id);
+                    if let Some(probe) = &self.latency_probe {
+                        if instance.id != self.instance_id {
+                            instance.latency = probe(instance).or(instance.latency);
+                        }
+                    }
+                }
+                self.check_versions(&instances);
+
+                let index = index_by_id(&instances);
+                let current = index
+                    .get(&self.instance_id)
+                    .map(|position| instances[*position].clone())
+                    .expect("The listing always contains the current instance.");
+
+                let leader = instances
+                    .iter()
+                    .find(|i| i.role == Leader)
+                    .cloned()
+                    .map(Arc::new);
+
+                let swapped = swap_state(&self.state, tick, |state| {
+                    // Only the listing applied is saved and recorded, so they
+                    // never go back to an earlier one.
+                    if let Some(path) = &self.snapshot_file {
+                        if let Err(error) = snapshot::save(path, &instances) {
+                            warn!("Error saving the instances snapshot. Cause: {}", error);
+                        }
+                    }
+                    if self.data_history_depth > 0 {
+                        self.record_data_history(&instances);
+                    }
+                    // Emitted once the lock is released: the subscribers'
+                    // filters may read the state.
+                    let mut events = self.departures(&state.instances, &index, &departed);
+                    if self.view_checks {
+                        events.extend(self.check_views(&instances));
+                    }
+                    let leader_id = leader.as_ref().map(|l| l.id);
+                    let leader_changed = state.leader.as_ref().map(|l| l.id) != leader_id;
+                    if leader_changed {
+                        events.push(InstancesEvent::LeaderChanged(leader_id));
+                    }
+                    events.extend(self.detect_anomalies(instances.len(), leader_changed));
+                    let previous_term = state.leader.as_ref().and_then(|l| l.leader_term);
+                    if let Some((id, term)) =
+                        leader.as_ref().and_then(|l| Some((l.id, l.leader_term?)))
+                    {
+                        if previous_term != Some(term) {
+                            info!("Instance {} started the leader term {}.", id, term);
+                            events.push(InstancesEvent::LeaderTermStarted(id, term));
+                        }
+                    }
+
+                    if self.leadership_acknowledgment {
+                        let elected = succession.first() == Some(&self.instance_id)
+                            && !self.in_solo_warmup()
+                            && self.may_lead_unseen();
+                        let mut claim = self.leader_claim.lock_or_recover();
+                        *claim = match *claim {
+                            Some(epoch) if elected => Some(epoch),
+                            None if elected => Some(state.leader_epoch + 1),
+                            _ => None,
+                        };
+                    }
+
+                    *state = InstancesState {
+                        tick,
+                        instances: Arc::new(instances),
+                        index: Arc::new(index),
+                        current_info: Some(Arc::new(current)),
+                        stale: false,
+                        self_visible,
+                        leader_epoch: state.next_leader_epoch(&leader),
+                        leader,
+                        succession: Arc::new(succession.into_iter().skip(1).collect()),
+                        departed: Arc::new(departed),
+                        cohort_leaders: Arc::new(cohort_leaders),
+                    };
+                    events
+                });
+                let Some(events) = swapped else {
+                    debug!("Update {} finished after a later one, dropping it.", tick);
+                    return Ok(());
+                };
+                for event in events {
+                    self.events.emit(event);
+                }
+
+                *self.last_error.lock_or_recover() = None;
+                {
+                    let mut backoff = self.listing_backoff.lock_or_recover();
+                    *backoff = ListingBackoff {
+                        recovered: backoff.failures > 0,
+                        ..ListingBackoff::default()
+                    };
+                }
+                *self.last_update_at.lock_or_recover() = Some(self.clock.now().into());
+                self.updates_completed.fetch_add(1, Ordering::Relaxed);
+
+                info!("Instances info updated successfully.");
+
+                Ok(())
+            }
+            Err(error) => {
+                *self.last_error.lock_or_recover() = Some(error.clone());
+                match self.settings().error_strategy {
+                    CommunicationErrorStrategy::Error => {
+                        error!("Error updating the instances info. Cause: {}", error);
+
+                        swap_state(&self.state, tick, |state| {
+                            *state = InstancesState {
+                                tick,
+                                instances: Arc::new(vec![]),
+                                index: Arc::new(HashMap::new()),
+                                current_info: None,
+                                stale: false,
+                                self_visible: false,
+                                leader: None,
+                                leader_epoch: state.next_leader_epoch(&None),
+                                succession: Arc::new(vec![]),
+                                departed: Arc::new(vec![]),
+                                cohort_leaders: Arc::new(HashMap::new()),
+                            };
+                        });
+
+                        Err(error)
+                    }
+                    CommunicationErrorStrategy::UseLastInfo => {
+                        warn!("Error updating the instances info, the old data will be used. Cause: {}", error);
+                        if let Some(delay) = self.back_off_listing() {
+                            warn!(
+                                "Only heart-beating for the next {:?} before listing again.",
+                                delay
+                            );
+                        }
+                        Ok(())
+                    }
+                }
+            }
+        }
+    }
+
+    fn update_instance_info_and_retrieve(
+        &self,
+        data: Registration<T>,
+        timings: &mut TickTimings,
+    ) -> Result<Listed<T>, ConnectionError> {
+        self.check_payload_size(&data, timings)?;
+        if self.dry_run {
+            info!(
+                "Dry run, skipping the update of instance {} with {}.",
+                self.instance_id,
+                serde_json::to_string(&data).unwrap_or_default()
+            );
+            let started = Instant::now();
+            let mut listed = self.list_instances_seeing_own_write(data, None)?;
+            timings.listing = started.elapsed();
+            self.drop_stale_instances(&mut listed.instances);
+            listed.self_visible = false;
+            return Ok(listed);
+        }
+        let admitting = self.max_members.is_some() && !self.admitted.load(Ordering::SeqCst);
+        if !admitting
+            && !self.delta_updates
+            && !self.queued_operations.lock_or_recover().is_empty()
+            && self.backend.capabilities().batching
+        {
+            return self.update_and_retrieve_in_batch(data, timings);
+        }
+
+        let started = Instant::now();
+        match self.max_members {
+            Some(max_members) if !self.admitted.load(Ordering::SeqCst) => {
+                let result = self.backend.register_instance_if_room(
+                    self.instance_id,
+                    data.clone(),
+                    max_members,
+                );
+                self.tap(Operation::Register, result, |_| BackendResponse::Done)
+                    .map_err(|e| self.backend_error(Operation::Register, e))?;
+                self.admitted.store(true, Ordering::SeqCst);
+            }
+            _ => self
+                .write_registration(data.clone())
+                .map_err(|e| self.backend_error(Operation::Update, e))?,
+        }
+        timings.write = started.elapsed();
+
+        let started = Instant::now();
+        let mut listed = self.list_instances_seeing_own_write(data, None)?;
+        timings.listing = started.elapsed();
+        self.drop_stale_instances(&mut listed.instances);
+        self.execute_queued(vec![]);
+        Ok(listed)
+    }
+
+    /// Writes the registration, lists the instances and runs the queued
+    /// operations in a single batch.
+    fn update_and_retrieve_in_batch(
+        &self,
+        data: Registration<T>,
+        timings: &mut TickTimings,
+    ) -> Result<Listed<T>, ConnectionError> {
+        let started = Instant::now();
+        let membership = vec![
+            BatchOperation::Update(self.instance_id, data.clone()),
+            BatchOperation::List,
+        ];
+        let mut results = self.execute_queued(membership).into_iter();
+        timings.write = started.elapsed();
+
+        let written = match results.next() {
+            Some(Ok(BatchResult::Done)) => Ok(()),
+            Some(Err(error)) => Err(error),
+            _ => Err(missing_result("update")),
+        };
+        self.tap(Operation::Update, written, |_| BackendResponse::Done)
+            .map_err(|e| self.backend_error(Operation::Update, e))?;
+        let listed = match results.next() {
+            Some(Ok(BatchResult::Listed(listed))) => Ok(listed),
+            Some(Err(error)) => Err(error),
+            _ => Err(missing_result("listing")),
+        };
+
+        let started = Instant::now();
+        let mut listed = self.list_instances_seeing_own_write(data, Some(listed))?;
+        timings.listing = started.elapsed();
+        self.drop_stale_instances(&mut listed.instances);
+        Ok(listed)
+    }
+
+    /// Runs `operations` then the queued operations in one batch, delivering
+    /// the results of the queued ones and returning the others.
+    fn execute_queued(
+        &self,
+        mut operations: Vec<BatchOperation<Registration<T>>>,
+    ) -> Vec<Result<BatchResult<Registration<T>>, ConnectionError>> {
+        let queued = std::mem::take(&mut *self.queued_operations.lock_or_recover());
+        if operations.is_empty() && queued.is_empty() {
+            return vec![];
+        }
+        let own = operations.len();
+        let (queued, senders): (Vec<_>, Vec<_>) = queued.into_iter().unzip();
+        operations.extend(queued);
+        let mut results = self.backend.execute_batch(operations);
+        let mut queued = results.split_off(own.min(results.len())).into_iter();
+        for sender in senders {
+            let _ = sender.send(
+                queued
+                    .next()
+                    .unwrap_or_else(|| Err(missing_result("operation"))),
+            );
+        }
+        results
+    }
+
+    /// Keeps the latest of the cut-overs listed and the one known locally.
+    fn adopt_latest_cut_over(&self, instances: &Listing<T>) {
+        let mut current = self.cut_over.lock_or_recover();
+        let latest = instances
+            .iter()
+            .filter_map(|i| i.data.cut_over.as_ref())
+            .chain(current.as_ref())
+            .max_by_key(|c| c.at)
+            .cloned();
+        if latest != *current {
+            if let Some(cut_over) = &latest {
+                info!("Cohort {} is now the active one.", cut_over.cohort);
+            }
+            *current = latest;
+        }
+    }
+
+    /// `MemberLeft` for the instances of `previous` missing from `current`,
+    /// with the reason from their tombstone if they left one.
+    fn departures(
+        &self,
+        previous: &[InstanceInfo<T>],
+        current: &HashMap<Uuid, usize>,
+        departed: &[DepartedInstance],
+    ) -> Vec<InstancesEvent> {
+        let mut events = vec![];
+        for instance in previous {
+            if current.contains_key(&instance.id) {
+                continue;
+            }
+            let reason = departed
+                .iter()
+                .find(|d| d.id == instance.id)
+                .map(|d| d.departure.reason)
+                .unwrap_or(DepartureReason::CrashDetected);
+            info!("Instance {} left the cluster ({:?}).", instance.id, reason);
+            events.push(InstancesEvent::MemberLeft(instance.id, reason));
+        }
+        events
+    }
+
+    /// Removes the tombstones from the listing, returning the departures they
+    /// record. Departed instances are forgotten right away instead of being
+    /// kept as suspects.
+    fn take_tombstones(&self, instances: &mut Listing<T>) -> Vec<DepartedInstance> {
+        let mut departed = vec![];
+        instances.retain(|i| match (i.data.status, i.data.departure) {
+            (InstanceStatus::Departed, Some(departure)) if i.id != self.instance_id => {
+                departed.push(DepartedInstance {
+                    id: i.id,
+                    departure,
+                });
+                false
+            }
+            _ => true,
+        });
+
+        let mut peers = self.peers.lock_or_recover();
+        for instance in &departed {
+            peers.remove(&instance.id);
+        }
+        departed
+    }
+
+    /// Keeps instances that went missing in the listing as suspects until they are
+    /// absent for more than `suspicion` consecutive updates, so a latency spike
+    /// in the backend doesn't evict them (and move the leadership) right away.
+    fn keep_suspects(&self, instances: &mut Listing<T>) -> HashSet<Uuid> {
+        let suspicion = self.settings().suspicion;
+        if suspicion == 0 {
+            return HashSet::new();
+        }
+
+        let mut peers = self.peers.lock_or_recover();
+        let listed: HashSet<Uuid> = instances.iter().map(|i| i.id).collect();
+
+        peers.retain(|id, peer| {
+            if listed.contains(id) {
+                return true;
+            }
+            peer.missed_updates += 1;
+            if peer.missed_updates > suspicion {
+                info!("Instance {} confirmed as gone.", id);
+            }
+            peer.missed_updates <= suspicion
+        });
+
+        let suspects: HashSet<Uuid> = peers
+            .keys()
+            .filter(|id| !listed.contains(id))
+            .copied()
+            .collect();
+        for id in &suspects {
+            debug!(
+                "Instance {} missing from the listing, keeping it as suspect.",
+                id
+            );
+            instances.push(peers[id].record.clone());
+        }
+
+        for instance in instances.iter() {
+            if !suspects.contains(&instance.id) {
+                peers.insert(
+                    instance.id,
+                    Peer {
+                        record: instance.clone(),
+                        missed_updates: 0,
+                    },
+                );
+            }
+        }
+
+        suspects
+    }
+
+    /// Drops the instances the `StalenessPolicy` considers gone.
+    ///
+    /// The heartbeat age is measured with the monotonic clock from the moment a
+    /// new heartbeat was first listed; the wall clock only estimates the age of
+    /// heartbeats seen for the first time. NTP steps or a suspended host thus
+    /// don't age (or rejuvenate) the other instances.
+    fn drop_stale_instances(&self, instances: &mut Listing<T>) {
+        let update_interval = self.settings().update_interval;
+        let now = self.clock.now();
+        let mut heartbeats = self.heartbeats.lock_or_recover();
+        let listed: HashSet<Uuid> = instances.iter().map(|i| i.id).collect();
+        heartbeats.retain(|id, _| listed.contains(id));
+
+        instances.retain(|i| {
+            let age = match i.heartbeat_at {
+                Some(heartbeat_at) if i.id != self.instance_id => {
+                    let observed = heartbeats
+                        .entry(i.id)
+                        .and_modify(|o| {
+                            if o.heartbeat_at != heartbeat_at {
+                                *o = ObservedHeartbeat::new(heartbeat_at, Duration::ZERO);
+                            }
+                        })
+                        .or_insert_with(|| {
+                            let age = now.saturating_duration_since(heartbeat_at);
+                            ObservedHeartbeat::new(heartbeat_at, age)
+                        });
+                    observed.seen_at.elapsed()
+                }
+                _ => return true,
+            };
+
+            let stale = self.staleness_policy.is_stale(i.id, age, update_interval);
+            if stale {
+                debug!(
+                    "Dropping stale instance {} (last heartbeat {:?} ago).",
+                    i.id, age
+                );
+            }
+            !stale
+        });
+    }
+
+    /// Whether the listing is on hold after repeated failures, see
+    /// `back_off_listing`.
+    pub(crate) fn is_listing_backed_off(&self) -> bool {
+        match self.listing_backoff.lock_or_recover().resume_at {
+            Some(resume_at) => Instant::now() < resume_at,
+            None => false,
+        }
+    }
+
+    /// Time left until the backed off listing may be attempted again.
+    pub(crate) fn listing_resume_in(&self) -> Option<Duration> {
+        let backoff = self.listing_backoff.lock_or_recover();
+        let now = Instant::now();
+        backoff
+            .resume_at
+            .filter(|resume_at| *resume_at > now)
+            .map(|resume_at| resume_at - now)
+    }
+
+    /// Whether the backend just recovered from failures, clearing the flag.
+    pub(crate) fn take_recovery(&self) -> bool {
+        std::mem::take(&mut self.listing_backoff.lock_or_recover().recovered)
+    }
+
+    /// Puts the listing on hold for an exponentially growing number of update
+    /// intervals, so a struggling backend only gets the heartbeats meanwhile.
+    /// The jitter, drawn from the `RandomSource`, keeps the instances that
+    /// failed together from listing again all at once.
+    /// Nothing is put on hold before the first successful update, as there is
+    /// no last info to use yet.
+    fn back_off_listing(&self) -> Option<Duration> {
+        self.state.read_or_recover().current_info.as_ref()?;
+        let mut backoff = self.listing_backoff.lock_or_recover();
+        backoff.failures += 1;
+        let exponent = backoff.failures.min(MAX_LISTING_BACKOFF_EXPONENT);
+        let interval = self.settings().update_interval;
+        let delay = interval * 2u32.pow(exponent) + interval.mul_f64(self.random.next_f64());
+        backoff.resume_at = Some(Instant::now() + delay);
+        Some(delay)
+    }
+
+    /// Writes the registration without listing, while the listing is backed off.
+    fn heartbeat(&self, data: Registration<T>, timings: &mut TickTimings) {
+        if self.dry_run || (self.max_members.is_some() && !self.admitted.load(Ordering::SeqCst)) {
+            return;
+        }
+
+        let started = Instant::now();
+        let result = self
+            .write_registration(data)
+            .map_err(|e| self.backend_error(Operation::Update, e));
+        timings.write = started.elapsed();
+
+        match result {
+            Ok(()) => debug!("Heartbeat sent, the listing is backed off."),
+            Err(error) => {
+                warn!("Error sending the heartbeat. Cause: {}", error);
+                *self.last_error.lock_or_recover() = Some(error);
+            }
+        }
+    }
+
+    /// Shows the result of a backend call to the `Builder::with_backend_tap`
+    /// observer, passing it through.
+    pub(crate) fn tap<R>(
+        &self,
+        operation: Operation,
+        result: Result<R, ConnectionError>,
+        response: impl FnOnce(&R) -> BackendResponse<'_, Registration<T>>,
+    ) -> Result<R, ConnectionError> {
+        let Some(tap) = &self.backend_tap else {
+            return result;
+        };
+        match result {
+            Ok(value) => {
+                tap(operation, &Ok(response(&value)));
+                Ok(value)
+            }
+            Err(error) => {
+                tap(operation, &Err(error.clone()));
+                Err(error)
+            }
+        }
+    }
+
+    /// Writes the registration, only the changes since the previous write with
+    /// `Builder::with_delta_updates`.
+    fn write_registration(&self, data: Registration<T>) -> Result<(), ConnectionError> {
+        if !self.delta_updates {
+            let result = self.backend.update_instance_info(self.instance_id, data);
+            return self.tap(Operation::Update, result, |_| BackendResponse::Done);
+        }
+        let current = serde_json::to_value(&data).ok();
+        let mut last = self.last_written.lock_or_recover();
+
+        if let (Some(current), Some((previous, deltas))) = (&current, last.as_mut()) {
+            let patch = codec::diff(previous, current).filter(|_| *deltas < FULL_WRITE_EVERY);
+            if let Some(patch) = patch {
+                let result = self.backend.update_instance_delta(self.instance_id, &patch);
+                match self.tap(Operation::Update, result, |_| BackendResponse::Done) {
+                    Ok(()) => {
+                        *previous = current.clone();
+                        *deltas += 1;
+                        return Ok(());
+                    }
+                    Err(error) => debug!(
+                        "Error writing the delta, writing the registration in full. Cause: {}",
+                        error
+                    ),
+                }
+            }
+        }
+
+        *last = None;
+        let result = self.backend.update_instance_info(self.instance_id, data);
+        self.tap(Operation::Update, result, |_| BackendResponse::Done)?;
+        *last = current.map(|current| (current, 0));
+        Ok(())
+    }
+
+    /// Lists the instances making sure the current one is part of the result,
+    /// also telling whether its record actually came from the backend.
+    ///
+    /// With `Consistency::ReadYourWrites` the listing is retried until the write
+    /// that was just made is visible. With `Consistency::Eventual` the local
+    /// registration fills in for the missing record. The first attempt uses
+    /// `listed` if given, e.g. a listing made in a batch.
+    fn list_instances_seeing_own_write(
+        &self,
+        data: Registration<T>,
+        mut listed: Option<Result<Listing<T>, ConnectionError>>,
+    ) -> Result<Listed<T>, ConnectionError> {
+        let mut attempt = 0;
+        loop {
+            let result = match listed.take() {
+                Some(listed) => listed.map(|instances| (instances, vec![])),
+                None => self.list_decoded(),
+            };
+            let (mut instances, incompatible) = self
+                .tap(Operation::List, result, |(listed, _)| {
+                    BackendResponse::Listed(listed)
+                })
+                .map_err(|e| self.backend_error(Operation::List, e))?;
+
+            let visible = instances
+                .iter()
+                .any(|i| i.id == self.instance_id && i.data.status == data.status);
+            if visible {
+                return Ok(Listed {
+                    instances,
+                    incompatible,
+                    self_visible: true,
+                });
+            }
+
+            match self.consistency {
+                _ if self.dry_run => {
+                    instances.retain(|i| i.id != self.instance_id);
+                    instances.push(InstanceRecord::new(
+                        self.instance_id,
+                        self.clock.now(),
+                        data,
+                    ));
+                    return Ok(Listed {
+                        instances,
+                        incompatible,
+                        self_visible: false,
+                    });
+                }
+                Consistency::Eventual => {
+                    instances.retain(|i| i.id != self.instance_id);
+                    instances.push(InstanceRecord::new(
+                        self.instance_id,
+                        self.clock.now(),
+                        data,
+                    ));
+                    return Ok(Listed {
+                        instances,
+                        incompatible,
+                        self_visible: false,
+                    });
+                }
+                Consistency::ReadYourWrites if attempt < READ_YOUR_WRITES_ATTEMPTS => {
+                    attempt += 1;
+                    thread::sleep(READ_YOUR_WRITES_BACKOFF * attempt);
+                }
+                Consistency::ReadYourWrites => {
+                    return Err(ConnectionError::FailedToRetrieve(
+                        "the instance's own registration is not visible yet".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Checks how long it's been since the previous tick, by both the monotonic
+    /// and the wall clock. The monotonic clock doesn't advance while the host is
+    /// suspended, the wall clock does. A gap much longer than the update
+    /// interval marks the state as stale until the update that follows.
+    pub(crate) fn detect_clock_gap(&self) -> Option<Duration> {
+        let now = (Instant::now(), self.clock.now());
+        let last = self.last_tick.lock_or_recover().replace(now)?;
+
+        let gap = now
+            .0
+            .duration_since(last.0)
+            .max(now.1.saturating_duration_since(last.1));
+        if gap <= self.settings().update_interval * CLOCK_GAP_INTERVALS {
+            return None;
+        }
+
+        warn!(
+            "{:?} passed since the last update, the instances info is stale.",
+            gap
+        );
+        self.state.write_or_recover().stale = true;
+        self.events.emit(InstancesEvent::ClockGapDetected(gap));
+        Some(gap)
+    }
+
+    fn detect_anomalies(&self, members: usize, leader_changed: bool) -> Vec<InstancesEvent> {
+        let anomalies = self.anomaly_detector.lock_or_recover().observe(
+            members,
+            leader_changed,
+            Instant::now(),
+        );
+        anomalies
+            .into_iter()
+            .map(|anomaly| {
+                self.anomalies_detected.fetch_add(1, Ordering::Relaxed);
+                warn!("Membership anomaly detected: {:?}.", anomaly);
+                InstancesEvent::AnomalyDetected(anomaly)
+            })
+            .collect()
+    }
+
+    pub(crate) fn record_overrun(&self, duration: Duration) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+        warn!(
+            "The update took {:?}, longer than the update interval. Skipping the missed ticks.",
+            duration
+        );
+        self.events.emit(InstancesEvent::UpdateOverrun(duration));
+    }
+
+    pub(crate) fn settings(&self) -> Settings {
+        *self.settings.read_or_recover()
+    }
+
+    /// Applies the watched configuration file if it changed since the last call.
+    pub(crate) fn reload_config(&self) {
+        let watcher = match &self.config_watcher {
+            Some(watcher) => watcher,
+            None => return,
+        };
+
+        let file = match watcher.poll() {
+            Ok(Some(file)) => file,
+            Ok(None) => return,
+            Err(error) => {
+                warn!("Error reading the configuration file. Cause: {}", error);
+                return;
+            }
+        };
+
+        {
+            let mut settings = self.settings.write_or_recover();
+            let mut reloaded = *settings;
+            if let Some(millis) = file.update_interval_ms {
+                reloaded.update_interval = Duration::from_millis(millis);
+            }
+            if let Some(strategy) = file.error_strategy {
+                reloaded.error_strategy = strategy;
+            }
+            if let Some(suspicion) = file.suspicion {
+                reloaded.suspicion = suspicion;
+            }
+            if let Some(solo_warmup) = file.solo_warmup {
+                reloaded.solo_warmup = solo_warmup;
+            }
+            match checked_timing(
+                reloaded.update_interval,
+                reloaded.suspicion,
+                self.backend.min_update_interval(),
+                self.staleness_policy.as_ref(),
+            ) {
+                Ok(interval) => reloaded.update_interval = interval,
+                Err(error) => {
+                    warn!(
+                        "Ignoring the reloaded configuration, keeping {:?}. Cause: {}",
+                        *settings, error
+                    );
+                    return;
+                }
+            }
+            *settings = reloaded;
+            info!("Configuration reloaded: {:?}.", *settings);
+        }
+
+        self.events.emit(InstancesEvent::ConfigReloaded);
+    }
+
+    pub(crate) fn backend_error(
+        &self,
+        operation: Operation,
+        error: ConnectionError,
+    ) -> ConnectionError {
+        ConnectionError::Failed(self.backend_identity.clone(), operation, Box::new(error))
+    }
+
+    fn check_payload_size(
+        &self,
+        data: &Registration<T>,
+        timings: &mut TickTimings,
+    ) -> Result<(), ConnectionError> {
+        let started = Instant::now();
+        let size = serde_json::to_vec(data)
+            .map_err(|e| ConnectionError::FailedToUpdate(e.to_string()))?
+            .len();
+        timings.serialize = started.elapsed();
+
+        let limit = match self.max_payload_size {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        if size > limit {
+            self.oversized_payloads.fetch_add(1, Ordering::Relaxed);
+            error!(size, limit, "The instance payload exceeds the size limit.");
+            return Err(ConnectionError::PayloadTooLarge(size, limit));
+        }
+
+        Ok(())
+    }
+}
+
+/// Replaces the state with the one of the update `tick` through `swap`,
+/// holding the lock, unless a later tick's already replaced it: two updates
+/// may pass `Ticks::apply` in order and still take the lock in the other.
+/// Returns what `swap` returned, if it ran.
+fn swap_state<T, R>(
+    state: &modeled::RwLock<InstancesState<T>>,
+    tick: u64,
+    swap: impl FnOnce(&mut InstancesState<T>) -> R,
+) -> Option<R>
+where
+    T: Serialize + DeserializeOwned + Clone + 'static,
+{
+    let mut guard = state.write_or_recover();
+    if guard.tick > tick {
+        return None;
+    }
+    Some(swap(&mut guard))
+}
+
+/// The error of an operation a batch returned no result for.
+fn missing_result(operation: &str) -> ConnectionError {
+    ConnectionError::FailedToRetrieve(format!(
+        "The batch returned no result for the {}.",
+        operation
+    ))
+}
+
+/// Keeps the first record of every instance, in case the backend lists one
+/// twice (e.g. while migrating a record between keys).
+fn drop_duplicates<T>(instances: &mut Listing<T>) {
+    let mut seen = HashSet::with_capacity(instances.len());
+    instances.retain(|i| {
+        let first = seen.insert(i.id);
+        if !first {
+            debug!(
+                "Instance {} listed more than once, ignoring the copy.",
+                i.id
+            );
+        }
+        first
+    });
+}
+
+#[cfg(all(test, loom))]
+mod model_checks {
+    use loom::thread;
+
+    use super::*;
+
+    /// An empty state, its leader epoch derived from `tick` only.
+    fn state(tick: u64) -> InstancesState<String> {
+        InstancesState {
+            tick,
+            current_info: None,
+            instances: Arc::new(vec![]),
+            index: Arc::new(HashMap::new()),
+            stale: false,
+            self_visible: false,
+            leader: None,
+            leader_epoch: tick * 10,
+            succession: Arc::new(vec![]),
+            departed: Arc::new(vec![]),
+            cohort_leaders: Arc::new(HashMap::new()),
+        }
+    }
+
+    /// The end of an update, the listing in between left out.
+    fn update(ticks: &Ticks, current: &modeled::RwLock<InstancesState<String>>) {
+        let tick = ticks.start();
+        if !ticks.apply(tick) {
+            return;
+        }
+        swap_state(current, tick, |current| *current = state(tick));
+    }
+
+    fn read(current: &modeled::RwLock<InstancesState<String>>) -> (u64, u64) {
+        let current = current.read_or_recover();
+        (current.tick, current.leader_epoch)
+    }
+
+    #[test]
+    fn should_keep_the_latest_update_and_never_go_back() {
+        loom::model(|| {
+            let ticks = modeled::Arc::new(Ticks::default());
+            let current = modeled::Arc::new(modeled::RwLock::new(state(0)));
+
+            let updaters: Vec<_> = (0..2)
+                .map(|_| {
+                    let (ticks, current) = (ticks.clone(), current.clone());
+                    thread::spawn(move || update(&ticks, &current))
+                })
+                .collect();
+            let first = read(&current);
+            let second = read(&current);
+            for updater in updaters {
+                updater.join().unwrap();
+            }
+
+            assert_eq!(first.0 * 10, first.1);
+            assert!(second.0 >= first.0);
+            assert_eq!((2, 20), read(&current));
+        });
+    }
+}
@@ -0,0 +1,211 @@
+//! Checks of the peers: protocol and version compatibility, the views of
+//! the membership and the history of their data.
+
+use std::sync::atomic::Ordering;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::backends::{codec, Backend, ConnectionError};
+use crate::events::InstancesEvent;
+use crate::models::{DataVersion, IncompatiblePeer, InstanceInfo, Registration, ViewConsistency};
+use crate::sync::MutexExt;
+use crate::{hashing, Instances, Listing, VERSION};
+
+/// Consecutive updates the views must differ for before it's reported, letting
+/// the joins and departures reach every instance.
+pub(crate) const VIEW_DIVERGENCE_UPDATES: u32 = 3;
+
+impl<B, T> Instances<B, T>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    B: Backend<Registration<T>> + Send + Sync + 'static,
+{
+    /// Adds the peers' data that changed since the previous update to their
+    /// history, forgetting the peers no longer listed.
+    pub(crate) fn record_data_history(&self, instances: &[InstanceInfo<T>]) {
+        let now = self.clock.now();
+        let mut history = self.data_history.lock_or_recover();
+        history.retain(|id, _| instances.iter().any(|i| i.id == *id));
+
+        for instance in instances.iter().filter(|i| i.id != self.instance_id) {
+            let versions = history.entry(instance.id).or_default();
+            let changed = versions.back().is_none_or(|latest| {
+                serde_json::to_value(&latest.data).ok() != serde_json::to_value(&instance.data).ok()
+            });
+            if changed {
+                versions.push_back(DataVersion {
+                    observed_at: now,
+                    data: instance.data.clone(),
+                });
+                if versions.len() > self.data_history_depth {
+                    versions.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Lists the instances. When the backend lists the records encoded, the
+    /// protocol of each is read first: the peers writing one older than
+    /// `min_protocol` are left out, returned apart, without decoding their
+    /// data, whose shape may have changed since. The records that can't be
+    /// decoded are left out too.
+    pub(crate) fn list_decoded(
+        &self,
+    ) -> Result<(Listing<T>, Vec<IncompatiblePeer>), ConnectionError> {
+        let records = match self.backend.list_encoded_instances() {
+            Err(ConnectionError::Unsupported(_)) => {
+                return Ok((self.backend.list_active_instances()?, vec![]))
+            }
+            result => result?,
+        };
+        let mut incompatible = vec![];
+        let records = records
+            .into_iter()
+            .filter(|record| match codec::decode_protocol(&record.data) {
+                Ok(protocol) if protocol < self.min_protocol && record.id != self.instance_id => {
+                    incompatible.push(IncompatiblePeer {
+                        id: record.id,
+                        protocol,
+                    });
+                    false
+                }
+                // Reported when decoding the record fails likewise.
+                _ => true,
+            })
+            .collect();
+        Ok((codec::decode_records(records), incompatible))
+    }
+
+    /// Leaves out the peers writing a protocol older than `min_protocol`,
+    /// reporting each once along with the ones `list_decoded` left out.
+    pub(crate) fn reject_old_protocols(
+        &self,
+        instances: &mut Listing<T>,
+        mut incompatible: Vec<IncompatiblePeer>,
+    ) {
+        instances.retain(|i| {
+            if i.id == self.instance_id || i.data.protocol >= self.min_protocol {
+                return true;
+            }
+            incompatible.push(IncompatiblePeer {
+                id: i.id,
+                protocol: i.data.protocol,
+            });
+            false
+        });
+
+        let mut rejected = self.rejected_peers.lock_or_recover();
+        let previous = std::mem::take(&mut *rejected);
+        let mut newly_rejected = vec![];
+        for peer in incompatible {
+            if rejected.insert(peer) && !previous.contains(&peer) {
+                warn!(
+                    "Instance {} writes protocol {}, older than the minimum of {}. Ignoring it.",
+                    peer.id, peer.protocol, self.min_protocol
+                );
+                newly_rejected.push(peer);
+            }
+        }
+        drop(rejected);
+        for peer in newly_rejected {
+            self.events.emit(InstancesEvent::IncompatiblePeer(peer));
+        }
+    }
+
+    /// Reports the peers running a version of instances-rs incompatible with
+    /// this one, once each.
+    pub(crate) fn check_versions(&self, instances: &[InstanceInfo<T>]) {
+        let mut reported = self.incompatible_peers.lock_or_recover();
+        reported.retain(|id| instances.iter().any(|i| i.id == *id));
+        let mut events = vec![];
+        for instance in instances {
+            let Some(version) = &instance.version else {
+                continue;
+            };
+            if !compatible_versions(VERSION, version) && reported.insert(instance.id) {
+                warn!(
+                    "Instance {} runs instances-rs {}, incompatible with {}.",
+                    instance.id, version, VERSION
+                );
+                events.push(InstancesEvent::IncompatibleVersion(
+                    instance.id,
+                    version.clone(),
+                ));
+            }
+        }
+        drop(reported);
+        for event in events {
+            self.events.emit(event);
+        }
+    }
+
+    /// Keeps the hash of the view to publish on the next update, and returns
+    /// the event reporting the instances whose published view kept differing
+    /// from ours, to emit once the state is swapped.
+    pub(crate) fn check_views(&self, instances: &[InstanceInfo<T>]) -> Option<InstancesEvent> {
+        *self.view_hash.lock_or_recover() = Some(view_hash(instances));
+
+        let diverging = compare_views(self.instance_id, instances).diverging;
+        if diverging.is_empty() {
+            self.diverged_updates.store(0, Ordering::SeqCst);
+        } else if self.diverged_updates.fetch_add(1, Ordering::SeqCst) + 1
+            == VIEW_DIVERGENCE_UPDATES
+        {
+            warn!(
+                "The instances {:?} see other members than this instance.",
+                diverging
+            );
+            return Some(InstancesEvent::ViewsDiverged(diverging));
+        }
+        None
+    }
+}
+
+/// Whether two versions of instances-rs can share a cluster: same major
+/// version, or same minor version before 1.0, as semver goes.
+pub(crate) fn compatible_versions(ours: &str, theirs: &str) -> bool {
+    let significant = |version: &str| {
+        let mut parts = version.split('.');
+        match parts.next() {
+            Some("0") => ("0".to_string(), parts.next().unwrap_or("").to_string()),
+            major => (major.unwrap_or("").to_string(), String::new()),
+        }
+    };
+    significant(ours) == significant(theirs)
+}
+
+/// FNV-1a hash of the ids of the instances, in a stable order so that every
+/// instance seeing the same members gets the same hash.
+fn view_hash<T>(instances: &[InstanceInfo<T>]) -> u64
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    let mut ids: Vec<_> = instances.iter().map(|i| i.id).collect();
+    ids.sort();
+    let bytes: Vec<u8> = ids.iter().flat_map(|id| *id.as_bytes()).collect();
+    hashing::fnv1a(&bytes)
+}
+
+pub(crate) fn compare_views<T>(instance_id: Uuid, instances: &[InstanceInfo<T>]) -> ViewConsistency
+where
+    T: Serialize + DeserializeOwned + Clone,
+{
+    let own = instances
+        .iter()
+        .find(|i| i.id == instance_id)
+        .and_then(|i| i.view_hash);
+    let mut consistency = ViewConsistency::default();
+    if let Some(own) = own {
+        for instance in instances {
+            match instance.view_hash {
+                Some(hash) if hash == own => consistency.agreeing.push(instance.id),
+                Some(_) => consistency.diverging.push(instance.id),
+                None => {}
+            }
+        }
+    }
+    consistency
+}
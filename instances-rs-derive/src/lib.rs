@@ -0,0 +1,101 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident};
+
+/// Implements `instances_rs::models::InstanceData`.
+///
+/// Fields can be marked to feed the instance metadata:
+///
+/// - `#[instance(weight)]`: a number convertible into `f64`.
+/// - `#[instance(zone)]` and `#[instance(address)]`: anything implementing
+///   `AsRef<str>`.
+///
+/// The struct must still derive `Serialize`, `Deserialize` and `Clone`.
+#[proc_macro_derive(InstanceData, attributes(instance))]
+pub fn derive_instance_data(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct Marked {
+    weight: Option<Ident>,
+    zone: Option<Ident>,
+    address: Option<Ident>,
+}
+
+fn expand(input: DeriveInput) -> Result<TokenStream2, Error> {
+    let marked = match &input.data {
+        Data::Struct(data) => marked_fields(&data.fields)?,
+        _ => Marked::default(),
+    };
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    let weight = marked.weight.map(|field| {
+        quote! {
+            fn weight(&self) -> ::core::option::Option<f64> {
+                ::core::option::Option::Some(::core::convert::Into::<f64>::into(
+                    ::core::clone::Clone::clone(&self.#field),
+                ))
+            }
+        }
+    });
+    let zone = marked.zone.map(|field| {
+        quote! {
+            fn zone(&self) -> ::core::option::Option<&str> {
+                ::core::option::Option::Some(::core::convert::AsRef::<str>::as_ref(&self.#field))
+            }
+        }
+    });
+    let address = marked.address.map(|field| {
+        quote! {
+            fn address(&self) -> ::core::option::Option<&str> {
+                ::core::option::Option::Some(::core::convert::AsRef::<str>::as_ref(&self.#field))
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::instances_rs::models::InstanceData for #name #type_generics #where_clause {
+            #weight
+            #zone
+            #address
+        }
+    })
+}
+
+fn marked_fields(fields: &Fields) -> Result<Marked, Error> {
+    let mut marked = Marked::default();
+
+    for field in fields {
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("instance")) {
+            attr.parse_nested_meta(|meta| {
+                let slot = if meta.path.is_ident("weight") {
+                    &mut marked.weight
+                } else if meta.path.is_ident("zone") {
+                    &mut marked.zone
+                } else if meta.path.is_ident("address") {
+                    &mut marked.address
+                } else {
+                    return Err(meta.error("expected `weight`, `zone` or `address`"));
+                };
+
+                let ident = field
+                    .ident
+                    .clone()
+                    .ok_or_else(|| meta.error("only named fields can be marked"))?;
+                if slot.replace(ident).is_some() {
+                    return Err(meta.error("marked on more than one field"));
+                }
+                Ok(())
+            })?;
+        }
+    }
+
+    Ok(marked)
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "derive")]
+
+use instances_rs::models::InstanceData;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, InstanceData)]
+struct Node {
+    #[instance(address)]
+    endpoint: String,
+    #[instance(zone)]
+    zone: String,
+    #[instance(weight)]
+    cores: u32,
+    version: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, InstanceData)]
+struct Plain {
+    name: String,
+}
+
+#[test]
+fn should_derive_the_marked_fields() {
+    let node = Node {
+        endpoint: "10.0.0.12:8080".to_string(),
+        zone: "eu-west-1a".to_string(),
+        cores: 4,
+        version: "1.2.0".to_string(),
+    };
+
+    assert_eq!(Some("10.0.0.12:8080"), node.address());
+    assert_eq!(Some("eu-west-1a"), node.zone());
+    assert_eq!(Some(4.0), node.weight());
+    assert_eq!("1.2.0", node.version);
+}
+
+#[test]
+fn should_derive_without_marked_fields() {
+    let plain = Plain {
+        name: "plain".to_string(),
+    };
+
+    assert_eq!(None, plain.address());
+    assert_eq!(None, plain.zone());
+    assert_eq!(None, plain.weight());
+    assert_eq!("plain", plain.name);
+}
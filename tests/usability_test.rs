@@ -3,27 +3,37 @@ use std::time::{Duration, SystemTime};
 
 use uuid::Uuid;
 
-use instances_rs::backends::{Backend, ConnectionError};
+use instances_rs::backends::{Backend, ConnectionError, InstanceRecord};
 use instances_rs::config::Builder;
-use instances_rs::models::InstanceRole;
+use instances_rs::models::{InstanceRole, Registration};
 
 #[derive(Default)]
 struct InMemoryBackend {
     instance_id: Arc<Mutex<Option<Uuid>>>,
-    data: Arc<Mutex<Option<String>>>,
+    data: Arc<Mutex<Option<Registration<String>>>>,
 }
 
-impl Backend<String> for InMemoryBackend {
-    fn update_instance_info(&self, instance_id: Uuid, data: String) -> Result<(), ConnectionError> {
+impl Backend<Registration<String>> for InMemoryBackend {
+    fn update_instance_info(
+        &self,
+        instance_id: Uuid,
+        data: Registration<String>,
+    ) -> Result<(), ConnectionError> {
         *self.instance_id.lock().unwrap() = Some(instance_id);
         *self.data.lock().unwrap() = Some(data);
         Ok(())
     }
 
-    fn list_active_instances(&self) -> Result<Vec<(Uuid, SystemTime, String)>, ConnectionError> {
+    fn list_active_instances(
+        &self,
+    ) -> Result<Vec<InstanceRecord<Registration<String>>>, ConnectionError> {
         let instance_id = (*self.instance_id.lock().unwrap()).unwrap();
         let data = self.data.lock().unwrap().clone().unwrap();
-        Ok(vec![(instance_id, SystemTime::now(), data)])
+        Ok(vec![InstanceRecord::new(
+            instance_id,
+            SystemTime::now(),
+            data,
+        )])
     }
 }
 